@@ -0,0 +1,8 @@
+/* The Secure Partition Manager (SPM) has the first 320 KiB of flash and the
+ * first 64 KiB of RAM. The next 64 KiB of RAM is shared with the modem (see
+ * `NrfxlibBuilder::shared_memory_base`). */
+MEMORY
+{
+  FLASH : ORIGIN = 0x00050000, LENGTH = 768K
+  RAM : ORIGIN = 0x20020000, LENGTH = 128K
+}
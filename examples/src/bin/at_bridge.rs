@@ -0,0 +1,129 @@
+//! # AT bridge example for nrfxlib
+//!
+//! Passes AT commands typed into the RTT terminal to the modem, and prints
+//! the responses - and any URCs that arrive in between. Handy for trying
+//! out commands before writing a helper for them. Build and flash with:
+//!
+//! ```text
+//! cargo run --release --features at-bridge --bin at_bridge
+//! ```
+//!
+//! then type e.g. `AT+CFUN=1` and `AT+CEREG=5` into the terminal.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../../../README.md)
+//! for more details.
+
+#![no_std]
+#![no_main]
+
+#[path = "../common.rs"]
+mod common;
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use cortex_m_rt::entry;
+use log::error;
+use nrfxlib::at::AtSocket;
+use nrfxlib::Error;
+use panic_halt as _;
+use rtt_target::rprintln;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The longest command we take from the terminal
+const MAX_LINE_LEN: usize = 256;
+
+/// How long we sleep when there's nothing to do
+const IDLE_MS: u32 = 10;
+
+//******************************************************************************
+// Public Functions
+//******************************************************************************
+
+#[entry]
+fn main() -> ! {
+	if let Err(e) = run() {
+		error!("Bridge stopped: {:?}", e);
+	}
+	loop {
+		cortex_m::asm::wfi();
+	}
+}
+
+//******************************************************************************
+// Private Functions
+//******************************************************************************
+
+fn run() -> Result<(), Error> {
+	let mut input = common::init()?;
+	let mut socket = AtSocket::new()?;
+	let mut line: heapless::Vec<u8, MAX_LINE_LEN> = heapless::Vec::new();
+	let mut buffer = [0u8; MAX_LINE_LEN];
+	rprintln!("AT bridge ready");
+	loop {
+		let count = input.read(&mut buffer);
+		for &byte in &buffer[0..count] {
+			match byte {
+				b'\r' | b'\n' => {
+					if !line.is_empty() {
+						send(&mut socket, &line);
+						line.clear();
+					}
+				}
+				_ => {
+					if line.push(byte).is_err() {
+						rprintln!("Too long - dropped");
+						line.clear();
+					}
+				}
+			}
+		}
+		// Anything the modem sends while we're not waiting for a response
+		// is a URC
+		match socket.recv(&mut buffer) {
+			Ok(Some(length)) => print_lines(&buffer[0..length]),
+			Ok(None) => {}
+			Err(e) => rprintln!("Read failed: {:?}", e),
+		}
+		if count == 0 {
+			common::sleep_ms(IDLE_MS);
+		}
+	}
+}
+
+/// Send one command, and print what comes back.
+fn send(socket: &mut AtSocket, line: &[u8]) {
+	let command = match core::str::from_utf8(line) {
+		Ok(command) => command.trim(),
+		Err(_) => {
+			rprintln!("Not UTF-8 - dropped");
+			return;
+		}
+	};
+	rprintln!("> {}", command);
+	let result = socket
+		.send_command(command)
+		.and_then(|()| socket.poll_response(|res| rprintln!("{}", res)));
+	match result {
+		Ok(()) => rprintln!("OK"),
+		Err(e) => rprintln!("{:?}", e),
+	}
+}
+
+fn print_lines(data: &[u8]) {
+	if let Ok(text) = core::str::from_utf8(data) {
+		for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+			rprintln!("{}", line);
+		}
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
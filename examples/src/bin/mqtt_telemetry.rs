@@ -0,0 +1,186 @@
+//! # MQTT telemetry example for nrfxlib
+//!
+//! Connects to an MQTT broker over TLS and publishes the signal strength
+//! once a minute. The MQTT packets (CONNECT and QoS 0 PUBLISH, from MQTT
+//! 3.1.1) are built by hand, to keep the example to nrfxlib alone.
+//!
+//! The broker's CA certificate must already be stored in the modem under
+//! `SECURITY_TAG` - see `tls_credentials`. Build and flash with:
+//!
+//! ```text
+//! cargo run --release --features mqtt-telemetry --bin mqtt_telemetry
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../../../README.md)
+//! for more details.
+
+#![no_std]
+#![no_main]
+
+#[path = "../common.rs"]
+mod common;
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use core::fmt::Write;
+use cortex_m_rt::entry;
+use log::{error, info, warn};
+use nrfxlib::modem;
+use nrfxlib::tls::{PeerVerification, TlsSocket, Version};
+use nrfxlib::Error;
+use panic_halt as _;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Enough for any packet we send.
+type Packet = heapless::Vec<u8, 256>;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The broker
+const BROKER: (&str, u16) = ("mqtt.example.com", 8883);
+
+/// Where the broker's CA certificate is stored
+const SECURITY_TAG: u32 = 42;
+
+/// Who we say we are
+const CLIENT_ID: &str = "nrfxlib-example";
+
+/// Where we publish
+const TOPIC: &str = "nrfxlib/example/rsrp";
+
+/// How long the broker waits without hearing from us, in seconds. We publish
+/// well within it.
+const KEEP_ALIVE_S: u16 = 300;
+
+/// How often we publish
+const PUBLISH_INTERVAL_MS: u32 = 60_000;
+
+/// MQTT control packet types (in the top four bits)
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+
+//******************************************************************************
+// Public Functions
+//******************************************************************************
+
+#[entry]
+fn main() -> ! {
+	if let Err(e) = run() {
+		error!("Telemetry stopped: {:?}", e);
+	}
+	loop {
+		cortex_m::asm::wfi();
+	}
+}
+
+//******************************************************************************
+// Private Functions
+//******************************************************************************
+
+fn run() -> Result<(), Error> {
+	common::init()?;
+	modem::on()?;
+	modem::wait_for_lte()?;
+
+	let socket = TlsSocket::new(PeerVerification::Enabled, &[SECURITY_TAG], Version::Tls1v2)?;
+	socket.connect(BROKER.0, BROKER.1)?;
+	write_all(&socket, &connect_packet()?)?;
+	let mut connack = [0u8; 4];
+	let length = socket.recv_wait(&mut connack)?;
+	if length != 4 || connack[0] != CONNACK || connack[3] != 0 {
+		warn!("Broker refused us: {:02x?}", &connack[0..length]);
+		return Err(Error::UnrecognisedValue);
+	}
+	info!("Connected to {}", BROKER.0);
+
+	loop {
+		let quality = modem::get_signal_quality()?;
+		let mut payload: heapless::String<16> = heapless::String::new();
+		match quality.rsrp_dbm {
+			Some(rsrp) => write!(payload, "{}", rsrp),
+			None => write!(payload, "unknown"),
+		}
+		.map_err(|_| Error::BufferFull)?;
+		write_all(&socket, &publish_packet(TOPIC, payload.as_bytes())?)?;
+		info!("Published RSRP {}", payload);
+		common::sleep_ms(PUBLISH_INTERVAL_MS);
+	}
+}
+
+/// A CONNECT packet: clean session, no will, no username or password.
+fn connect_packet() -> Result<Packet, Error> {
+	let mut body = Packet::new();
+	push_str(&mut body, "MQTT")?;
+	// Protocol level 4 (3.1.1), clean session
+	extend(&mut body, &[4, 0x02])?;
+	extend(&mut body, &KEEP_ALIVE_S.to_be_bytes())?;
+	push_str(&mut body, CLIENT_ID)?;
+	packet(CONNECT, &body)
+}
+
+/// A QoS 0 PUBLISH packet (which has no packet identifier).
+fn publish_packet(topic: &str, payload: &[u8]) -> Result<Packet, Error> {
+	let mut body = Packet::new();
+	push_str(&mut body, topic)?;
+	extend(&mut body, payload)?;
+	packet(PUBLISH, &body)
+}
+
+/// Put the fixed header (type and remaining length) in front of `body`.
+fn packet(packet_type: u8, body: &[u8]) -> Result<Packet, Error> {
+	let mut packet = Packet::new();
+	extend(&mut packet, &[packet_type])?;
+	// The remaining length is a varint, seven bits at a time
+	let mut remaining = body.len();
+	loop {
+		let mut byte = (remaining % 128) as u8;
+		remaining /= 128;
+		if remaining > 0 {
+			byte |= 0x80;
+		}
+		extend(&mut packet, &[byte])?;
+		if remaining == 0 {
+			break;
+		}
+	}
+	extend(&mut packet, body)?;
+	Ok(packet)
+}
+
+/// Append a length-prefixed UTF-8 string.
+fn push_str(buffer: &mut Packet, text: &str) -> Result<(), Error> {
+	extend(buffer, &(text.len() as u16).to_be_bytes())?;
+	extend(buffer, text.as_bytes())
+}
+
+fn extend(buffer: &mut Packet, data: &[u8]) -> Result<(), Error> {
+	buffer
+		.extend_from_slice(data)
+		.map_err(|_| Error::BufferFull)
+}
+
+/// Keep writing until all of `data` has gone.
+fn write_all(socket: &TlsSocket, mut data: &[u8]) -> Result<(), Error> {
+	while !data.is_empty() {
+		let written = socket.write(data)?;
+		if written == 0 {
+			return Err(Error::Disconnected);
+		}
+		data = &data[written..];
+	}
+	Ok(())
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
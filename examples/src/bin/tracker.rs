@@ -0,0 +1,99 @@
+//! # Asset tracker example for nrfxlib
+//!
+//! Takes a GNSS fix every five minutes and sends it to a UDP server, with
+//! `tracker::track_and_report`. Between reports the modem is in PSM, which
+//! also gives the GNSS module the radio time it needs.
+//!
+//! Build and flash with:
+//!
+//! ```text
+//! cargo run --release --features tracker --bin tracker
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../../../README.md)
+//! for more details.
+
+#![no_std]
+#![no_main]
+
+#[path = "../common.rs"]
+mod common;
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use core::time::Duration;
+use cortex_m_rt::entry;
+use log::{error, info};
+use nrfxlib::gnss::GnssSocket;
+use nrfxlib::modem::{self, PsmConfig, SystemMode};
+use nrfxlib::tracker::{self, TrackerConfig};
+use nrfxlib::udp::UdpSocket;
+use nrfxlib::Error;
+use panic_halt as _;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// Where the reports go
+const SERVER: (&str, u16) = ("tracking.example.com", 4242);
+
+/// How often we report
+const REPORT_INTERVAL_MS: u32 = 5 * 60_000;
+
+//******************************************************************************
+// Public Functions
+//******************************************************************************
+
+#[entry]
+fn main() -> ! {
+	if let Err(e) = run() {
+		error!("Tracker stopped: {:?}", e);
+	}
+	loop {
+		cortex_m::asm::wfi();
+	}
+}
+
+//******************************************************************************
+// Private Functions
+//******************************************************************************
+
+fn run() -> Result<(), Error> {
+	common::init()?;
+	modem::set_system_mode(SystemMode::LteMAndGnss)?;
+	modem::set_psm(Some(PsmConfig {
+		periodic_tau: Duration::from_secs(60 * 60),
+		active_time: Duration::from_secs(10),
+	}))?;
+	modem::on()?;
+	modem::wait_for_lte()?;
+	info!("Registered: {:?}", modem::last_registration_status());
+
+	let gnss = GnssSocket::new()?;
+	let mut server = UdpSocket::new()?;
+	server.connect(SERVER.0, SERVER.1)?;
+
+	let config = TrackerConfig {
+		report_interval_ms: REPORT_INTERVAL_MS,
+		..TrackerConfig::new(common::now_ms, common::sleep_ms)
+	};
+	loop {
+		let report = tracker::track_and_report(&gnss, &mut server, &config);
+		info!(
+			"Fix {:?} in {} ms, sent {:?}, slept {} ms",
+			report.fix.map(|fix| fix.source),
+			report.fix_time_ms,
+			report.sent,
+			report.slept_ms
+		);
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,133 @@
+//! # Board glue for the nrfxlib examples
+//!
+//! Everything an nRF9160 application needs before it can use nrfxlib: the
+//! EGU1, EGU2 and IPC interrupt handlers, a millisecond clock (from SysTick)
+//! and logging over RTT. Each example includes this file with `#[path]`.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../../README.md)
+//! for more details.
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::peripheral::{syst::SystClkSource, NVIC};
+use cortex_m_rt::exception;
+use nrf9160_pac::{interrupt, Interrupt};
+use rtt_target::{rprintln, rtt_init, DownChannel};
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Sends `log` records to the RTT terminal, with the uptime.
+struct RttLogger;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The CPU runs at 64 MHz, so SysTick counts this many cycles a millisecond.
+const CYCLES_PER_MS: u32 = 64_000;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// Milliseconds since `init`.
+static UPTIME_MS: AtomicU32 = AtomicU32::new(0);
+
+static LOGGER: RttLogger = RttLogger;
+
+//******************************************************************************
+// Public Functions
+//******************************************************************************
+
+/// Set up RTT, logging and the clock, then start nrfxlib. Returns the RTT
+/// down channel, for examples which read from the host.
+pub fn init() -> Result<DownChannel, nrfxlib::Error> {
+	let channels = rtt_init! {
+		up: {
+			0: { size: 1024, name: "Terminal" }
+		}
+		down: {
+			0: { size: 256, name: "Terminal" }
+		}
+	};
+	rtt_target::set_print_channel(channels.up.0);
+	if log::set_logger(&LOGGER).is_ok() {
+		log::set_max_level(log::LevelFilter::Debug);
+	}
+
+	// We're the only user of the core peripherals, and only called once
+	let mut core = unsafe { cortex_m::Peripherals::steal() };
+	core.SYST.set_clock_source(SystClkSource::Core);
+	core.SYST.set_reload(CYCLES_PER_MS - 1);
+	core.SYST.clear_current();
+	core.SYST.enable_counter();
+	core.SYST.enable_interrupt();
+
+	// libmodem enables IPC itself; the EGUs are up to us
+	unsafe {
+		NVIC::unmask(Interrupt::EGU1);
+		NVIC::unmask(Interrupt::EGU2);
+	}
+	nrfxlib::NrfxlibBuilder::new().init()?;
+	Ok(channels.down.0)
+}
+
+/// Milliseconds since `init`. Wraps after 49 days.
+pub fn now_ms() -> u32 {
+	UPTIME_MS.load(Ordering::Relaxed)
+}
+
+/// Sleep (in WFI, woken by each SysTick) for at least `ms` milliseconds.
+pub fn sleep_ms(ms: u32) {
+	let start = now_ms();
+	while now_ms().wrapping_sub(start) < ms {
+		cortex_m::asm::wfi();
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl log::Log for RttLogger {
+	fn enabled(&self, _metadata: &log::Metadata) -> bool {
+		true
+	}
+
+	fn log(&self, record: &log::Record) {
+		rprintln!("{:>9} {:<5} {}", now_ms(), record.level(), record.args());
+	}
+
+	fn flush(&self) {}
+}
+
+#[exception]
+fn SysTick() {
+	UPTIME_MS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[interrupt]
+fn EGU1() {
+	nrfxlib::application_irq_handler();
+}
+
+#[interrupt]
+fn EGU2() {
+	nrfxlib::trace_irq_handler();
+}
+
+#[interrupt]
+fn IPC() {
+	nrfxlib::ipc_irq_handler();
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
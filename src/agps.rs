@@ -0,0 +1,316 @@
+//! # GNSS assistance expiry tracking for nrfxlib
+//!
+//! Assistance (AGPS) data injected into the GNSS module goes stale:
+//! ephemerides after a few hours, the almanac after a few weeks. Once it has,
+//! time to first fix gets much worse. An `AgpsTracker` remembers when each
+//! kind of assistance data was injected, and says when it is next due for a
+//! refresh - so a device can fetch new data before its fixes degrade, rather
+//! than after.
+//!
+//! Tell the tracker about each injection with `injected`, and about each
+//! `GnssData::Agps` request from the GNSS module with `requested`. Then
+//! either ask `next_refresh_due` when to wake up, or call `poll` now and then
+//! to have the refresh hook called for anything which is due.
+//!
+//! Times are in seconds, from whatever counter you like (e.g. an RTC), as
+//! the validity windows are too long for a millisecond `u32`.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use nrfxlib_sys as sys;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// The kinds of assistance data the GNSS module accepts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AgpsKind {
+	/// GPS UTC parameters
+	UtcParameters,
+	/// Satellite ephemerides
+	Ephemerides,
+	/// The satellite almanac
+	Almanac,
+	/// Klobuchar model ionospheric corrections
+	KlobucharIonosphere,
+	/// NeQuick model ionospheric corrections
+	NequickIonosphere,
+	/// GPS system time and satellite time of week
+	SystemTime,
+	/// An approximate location
+	Location,
+	/// Satellite integrity
+	Integrity,
+}
+
+/// Which kind of assistance data is due for a refresh, and when.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RefreshDue {
+	/// The kind of data
+	pub kind: AgpsKind,
+	/// How many seconds until it is due. Zero means it is due now.
+	pub in_s: u32,
+}
+
+/// Tracks when each kind of assistance data was injected, and when it needs
+/// refreshing.
+#[derive(Debug, Clone)]
+pub struct AgpsTracker {
+	entries: [Entry; NUM_KINDS],
+	refresh_hook: Option<fn(AgpsKind)>,
+}
+
+/// What we know about one kind of assistance data.
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+	/// Refresh this long after injecting it
+	refresh_after_s: u32,
+	/// When it was last injected
+	injected_s: Option<u32>,
+	/// The GNSS module has asked for it since then
+	requested: bool,
+	/// The refresh hook has been called for it since then
+	notified: bool,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// Every `AgpsKind`, in the same order as `AgpsTracker::entries`.
+const KINDS: [AgpsKind; NUM_KINDS] = [
+	AgpsKind::UtcParameters,
+	AgpsKind::Ephemerides,
+	AgpsKind::Almanac,
+	AgpsKind::KlobucharIonosphere,
+	AgpsKind::NequickIonosphere,
+	AgpsKind::SystemTime,
+	AgpsKind::Location,
+	AgpsKind::Integrity,
+];
+
+/// The number of `AgpsKind`s.
+const NUM_KINDS: usize = 8;
+
+/// Seconds in an hour.
+const HOUR_S: u32 = 60 * 60;
+
+/// Seconds in a day.
+const DAY_S: u32 = 24 * HOUR_S;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl AgpsKind {
+	/// Get the `NRF_GNSS_AGPS_*` type used when injecting this kind of
+	/// data with `nrf_sendto`.
+	pub fn as_type(self) -> sys::nrf_gnss_agps_data_type_t {
+		let value = match self {
+			AgpsKind::UtcParameters => sys::NRF_GNSS_AGPS_UTC_PARAMETERS,
+			AgpsKind::Ephemerides => sys::NRF_GNSS_AGPS_EPHEMERIDES,
+			AgpsKind::Almanac => sys::NRF_GNSS_AGPS_ALMANAC,
+			AgpsKind::KlobucharIonosphere => sys::NRF_GNSS_AGPS_KLOBUCHAR_IONOSPHERIC_CORRECTION,
+			AgpsKind::NequickIonosphere => sys::NRF_GNSS_AGPS_NEQUICK_IONOSPHERIC_CORRECTION,
+			AgpsKind::SystemTime => sys::NRF_GNSS_AGPS_GPS_SYSTEM_CLOCK_AND_TOWS,
+			AgpsKind::Location => sys::NRF_GNSS_AGPS_LOCATION,
+			AgpsKind::Integrity => sys::NRF_GNSS_AGPS_INTEGRITY,
+		};
+		value as sys::nrf_gnss_agps_data_type_t
+	}
+
+	/// How long after injection we refresh this kind of data, unless told
+	/// otherwise with `AgpsTracker::set_refresh_after`. These are on the
+	/// cautious side - ephemerides are good for about four hours, and we
+	/// refresh them after two.
+	pub fn default_refresh_after_s(self) -> u32 {
+		match self {
+			AgpsKind::Ephemerides => 2 * HOUR_S,
+			AgpsKind::Almanac | AgpsKind::UtcParameters => 7 * DAY_S,
+			AgpsKind::KlobucharIonosphere | AgpsKind::NequickIonosphere => DAY_S,
+			AgpsKind::SystemTime | AgpsKind::Location | AgpsKind::Integrity => HOUR_S,
+		}
+	}
+
+	/// Where this kind is kept in `AgpsTracker::entries`.
+	fn index(self) -> usize {
+		self as usize
+	}
+}
+
+impl AgpsTracker {
+	/// Create a tracker which hasn't seen any injections, using each kind's
+	/// `default_refresh_after_s`.
+	pub fn new() -> AgpsTracker {
+		let mut entries = [Entry {
+			refresh_after_s: 0,
+			injected_s: None,
+			requested: false,
+			notified: false,
+		}; NUM_KINDS];
+		for (entry, kind) in entries.iter_mut().zip(KINDS.iter()) {
+			entry.refresh_after_s = kind.default_refresh_after_s();
+		}
+		AgpsTracker {
+			entries,
+			refresh_hook: None,
+		}
+	}
+
+	/// Change how long after injection a kind of data is refreshed.
+	pub fn set_refresh_after(&mut self, kind: AgpsKind, refresh_after_s: u32) {
+		self.entry_mut(kind).refresh_after_s = refresh_after_s;
+	}
+
+	/// Set a function for `poll` to call when a kind of data is due, or
+	/// `None` for no hook. It is called once per kind, until that kind is
+	/// injected again.
+	pub fn set_refresh_hook(&mut self, hook: Option<fn(AgpsKind)>) {
+		self.refresh_hook = hook;
+	}
+
+	/// Record that this kind of data was injected at `now_s`.
+	pub fn injected(&mut self, kind: AgpsKind, now_s: u32) {
+		let entry = self.entry_mut(kind);
+		entry.injected_s = Some(now_s);
+		entry.requested = false;
+		entry.notified = false;
+	}
+
+	/// Record a `GnssData::Agps` request from the GNSS module. Everything it
+	/// asks for is due now, whenever it was injected.
+	pub fn requested(&mut self, request: &sys::nrf_gnss_agps_data_frame_t) {
+		let flag = |bit: u32| request.data_flags & (1 << bit) != 0;
+		let wanted = [
+			(AgpsKind::Ephemerides, request.sv_mask_ephe != 0),
+			(AgpsKind::Almanac, request.sv_mask_alm != 0),
+			(
+				AgpsKind::UtcParameters,
+				flag(sys::NRF_GNSS_AGPS_GPS_UTC_REQUEST),
+			),
+			(
+				AgpsKind::KlobucharIonosphere,
+				flag(sys::NRF_GNSS_AGPS_KLOBUCHAR_REQUEST),
+			),
+			(
+				AgpsKind::NequickIonosphere,
+				flag(sys::NRF_GNSS_AGPS_NEQUICK_REQUEST),
+			),
+			(
+				AgpsKind::SystemTime,
+				flag(sys::NRF_GNSS_AGPS_SYS_TIME_AND_SV_TOW_REQUEST),
+			),
+			(
+				AgpsKind::Location,
+				flag(sys::NRF_GNSS_AGPS_POSITION_REQUEST),
+			),
+			(
+				AgpsKind::Integrity,
+				flag(sys::NRF_GNSS_AGPS_INTEGRITY_REQUEST),
+			),
+		];
+		for (kind, wanted) in wanted.iter() {
+			if *wanted {
+				self.entry_mut(*kind).requested = true;
+			}
+		}
+	}
+
+	/// Is this kind of data still good at `now_s`? False if it has never been
+	/// injected, or it has been requested since.
+	pub fn is_fresh(&self, kind: AgpsKind, now_s: u32) -> bool {
+		matches!(self.due_in(kind, now_s), Some(in_s) if in_s > 0)
+	}
+
+	/// Find the kind of data which is due for a refresh soonest. Returns
+	/// `None` if nothing has been injected or requested.
+	pub fn next_refresh_due(&self, now_s: u32) -> Option<RefreshDue> {
+		KINDS
+			.iter()
+			.filter_map(|&kind| {
+				self.due_in(kind, now_s)
+					.map(|in_s| RefreshDue { kind, in_s })
+			})
+			.min_by_key(|due| due.in_s)
+	}
+
+	/// Call the refresh hook for every kind of data which is due at `now_s`
+	/// and which it hasn't been called for yet. Returns the next refresh
+	/// due, as `next_refresh_due` does.
+	pub fn poll(&mut self, now_s: u32) -> Option<RefreshDue> {
+		if let Some(hook) = self.refresh_hook {
+			for &kind in KINDS.iter() {
+				if self.due_in(kind, now_s) == Some(0) && !self.entry_mut(kind).notified {
+					self.entry_mut(kind).notified = true;
+					hook(kind);
+				}
+			}
+		}
+		self.next_refresh_due(now_s)
+	}
+}
+
+impl Default for AgpsTracker {
+	fn default() -> AgpsTracker {
+		AgpsTracker::new()
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl AgpsTracker {
+	/// The entry for this kind of data.
+	#[allow(clippy::indexing_slicing)] // `entries` has one for every `AgpsKind`
+	fn entry(&self, kind: AgpsKind) -> &Entry {
+		&self.entries[kind.index()]
+	}
+
+	/// The entry for this kind of data, to change.
+	#[allow(clippy::indexing_slicing)] // `entries` has one for every `AgpsKind`
+	fn entry_mut(&mut self, kind: AgpsKind) -> &mut Entry {
+		&mut self.entries[kind.index()]
+	}
+
+	/// How many seconds until this kind is due, if it is being tracked.
+	fn due_in(&self, kind: AgpsKind, now_s: u32) -> Option<u32> {
+		let entry = self.entry(kind);
+		if entry.requested {
+			return Some(0);
+		}
+		let injected_s = entry.injected_s?;
+		let age_s = now_s.wrapping_sub(injected_s);
+		Some(entry.refresh_after_s.saturating_sub(age_s))
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
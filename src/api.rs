@@ -17,25 +17,56 @@
 // Imports
 //******************************************************************************
 
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Waker;
+use cortex_m::interrupt::Mutex;
 use nrfxlib_sys as sys;
 
 //******************************************************************************
 // Types
 //******************************************************************************
 
-// None
+/// How the application wants to be told that the library may have data ready
+/// for it (i.e. that `application_irq_handler` has run).
+///
+/// Whichever you choose, the flag read by `take_data_ready` is also set.
+#[derive(Debug, Copy, Clone)]
+pub enum DataReadyNotification {
+	/// Only set a flag, which the application polls with `take_data_ready`.
+	/// This is the default.
+	Flag,
+	/// Call this function. It is called in interrupt context, so it must be
+	/// quick.
+	Callback(fn()),
+	/// Wake the wakers given to `register_data_ready_waker`, for use with an
+	/// async executor (see `asynch`).
+	Waker,
+}
 
 //******************************************************************************
 // Constants
 //******************************************************************************
 
-// None
+/// How many wakers `register_data_ready_waker` holds at once - that is, how
+/// many tasks can be waiting for sockets at the same time without being
+/// woken early.
+pub const MAX_WAKERS: usize = 8;
 
 //******************************************************************************
 // Global Variables
 //******************************************************************************
 
-// None
+/// Set whenever the library may have data ready for us.
+static DATA_READY: AtomicBool = AtomicBool::new(false);
+
+/// How to tell the application that data may be ready.
+static NOTIFICATION: Mutex<RefCell<DataReadyNotification>> =
+	Mutex::new(RefCell::new(DataReadyNotification::Flag));
+
+/// The wakers to wake in `DataReadyNotification::Waker` mode.
+static WAKERS: Mutex<RefCell<heapless::Vec<Waker, MAX_WAKERS>>> =
+	Mutex::new(RefCell::new(heapless::Vec::new()));
 
 //******************************************************************************
 // Macros
@@ -53,6 +84,7 @@ pub fn application_irq_handler() {
 	unsafe {
 		sys::nrf_modem_os_application_irq_handler();
 	}
+	notify_data_ready();
 }
 
 /// Trampoline into the BSD library function `bsd_os_trace_irq_handler`. You
@@ -70,11 +102,62 @@ pub fn ipc_irq_handler() {
 	}
 }
 
+/// Choose how the application is told that data may be ready. You would
+/// usually call this once, before `init()`.
+pub fn set_data_ready_notification(notification: DataReadyNotification) {
+	cortex_m::interrupt::free(|cs| {
+		*NOTIFICATION.borrow(cs).borrow_mut() = notification;
+	});
+}
+
+/// Has the library had data ready for us since we last asked? Clears the
+/// flag.
+pub fn take_data_ready() -> bool {
+	DATA_READY.swap(false, Ordering::AcqRel)
+}
+
+/// Add a waker to wake when data may be ready, in
+/// `DataReadyNotification::Waker` mode. Call this from your future's `poll`
+/// each time it returns `Poll::Pending`. Every waker registered is woken
+/// (once) the next time there may be data, so several tasks can wait on
+/// different sockets.
+///
+/// If `MAX_WAKERS` are already waiting, the waker is woken straight away,
+/// so its task polls again rather than being forgotten.
+pub fn register_data_ready_waker(waker: &Waker) {
+	let full = cortex_m::interrupt::free(|cs| {
+		let mut wakers = WAKERS.borrow(cs).borrow_mut();
+		if wakers.iter().any(|existing| existing.will_wake(waker)) {
+			return false;
+		}
+		wakers.push(waker.clone()).is_err()
+	});
+	if full {
+		waker.wake_by_ref();
+	}
+}
+
 //******************************************************************************
 // Private Functions and Impl on Private Types
 //******************************************************************************
 
-// None
+/// Tell the application that data may be ready, in whichever way it asked.
+pub(crate) fn notify_data_ready() {
+	DATA_READY.store(true, Ordering::Release);
+	let notification = cortex_m::interrupt::free(|cs| *NOTIFICATION.borrow(cs).borrow());
+	match notification {
+		DataReadyNotification::Flag => {}
+		DataReadyNotification::Callback(function) => function(),
+		DataReadyNotification::Waker => {
+			let wakers = cortex_m::interrupt::free(|cs| {
+				core::mem::take(&mut *WAKERS.borrow(cs).borrow_mut())
+			});
+			for waker in wakers {
+				waker.wake();
+			}
+		}
+	}
+}
 
 //******************************************************************************
 // End of File
@@ -0,0 +1,461 @@
+//! # Async sockets for nrfxlib
+//!
+//! Everything else in this crate blocks (or spins on `wfe`) until the modem
+//! answers, which doesn't mix with an async executor such as Embassy. An
+//! `AsyncSocket` wraps a `TcpSocket`, `UdpSocket`, `TlsSocket` or
+//! `DtlsSocket` and gives it `async` methods instead, which only ever use
+//! the sockets' non-blocking calls. While one waits it parks its task, and
+//! the task is woken when `application_irq_handler` next runs (which
+//! libmodem triggers from the IPC interrupt when the modem has something for
+//! us). For that, choose `DataReadyNotification::Waker` before `init()`:
+//!
+//! ```ignore
+//! nrfxlib::set_data_ready_notification(DataReadyNotification::Waker);
+//! nrfxlib::init()?;
+//! // ... in a task
+//! let socket = AsyncSocket::connect(TcpSocket::new()?, "example.com", 80).await?;
+//! socket.write_all(b"GET / HTTP/1.0\r\n\r\n").await?;
+//! let n = socket.recv(&mut buffer).await?;
+//! ```
+//!
+//! DNS lookups have no non-blocking form in libmodem, so `connect` still
+//! blocks while the hostname is looked up.
+//!
+//! With the `embedded-nal-async` feature, `TcpConnector` implements
+//! `embedded_nal_async::TcpConnect`, `UdpConnector` implements
+//! `embedded_nal_async::UdpStack` (connected sockets only), and
+//! `AsyncSocket` implements the `embedded-io-async` `Read` and `Write`
+//! traits.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::dtls::DtlsSocket;
+use crate::raw::{ConnectStatus, Pollable, Socket};
+use crate::tcp::TcpSocket;
+use crate::tls::TlsSocket;
+#[cfg(feature = "embedded-nal-async")]
+use crate::udp::UdpSocket;
+use crate::Error;
+use core::ops::Deref;
+use core::task::Poll;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A socket with `async` methods. See the module documentation.
+#[derive(Debug)]
+pub struct AsyncSocket<S> {
+	socket: S,
+}
+
+/// Sockets which can connect to a hostname without blocking, with
+/// `connect_start` and `connect_poll`.
+pub trait ConnectNonBlocking {
+	/// Start connecting, as the socket's own `connect_start`.
+	fn connect_start(&self, hostname: &str, port: u16) -> Result<ConnectStatus, Error>;
+	/// See how the connect is going, as the socket's own `connect_poll`.
+	fn connect_poll(&self) -> Result<ConnectStatus, Error>;
+}
+
+/// Makes TCP connections to IPv4 addresses, for `embedded-nal-async`.
+#[cfg(feature = "embedded-nal-async")]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TcpConnector;
+
+/// Makes connected UDP sockets to IPv4 addresses, for `embedded-nal-async`,
+/// on the PDN connection with the given context ID (0 is the default one).
+///
+/// `UdpStack::connect` reports the local address the peer will see: the
+/// context's IPv4 address (from `pdn::addresses`, so this sends an
+/// `AT+CGPADDR` and briefly blocks) and a port from `UdpSocket::bind_random`.
+/// A carrier NAT may still rewrite both, but nothing on the device can
+/// know that.
+///
+/// The modem can't send a datagram from any address but the one its PDN
+/// connection has, so there is nothing useful to bind an unconnected socket
+/// to: `bind_single` and `bind_multiple` fail with `Error::BadDataFormat`.
+#[cfg(feature = "embedded-nal-async")]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct UdpConnector {
+	cid: u8,
+}
+
+/// The unconnected UDP socket `UdpConnector` would give, if it could give
+/// one. See `UdpConnector`.
+#[cfg(feature = "embedded-nal-async")]
+#[derive(Debug)]
+pub enum UnboundUdp {}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl<S> AsyncSocket<S>
+where
+	S: Deref<Target = Socket>,
+{
+	/// Wrap a socket which is already connected (or a UDP socket, whose
+	/// `connect` doesn't wait).
+	pub fn new(socket: S) -> Self {
+		AsyncSocket { socket }
+	}
+
+	/// Get the socket back.
+	pub fn into_inner(self) -> S {
+		self.socket
+	}
+
+	/// Get at the socket, e.g. to set options on it.
+	pub fn get_ref(&self) -> &S {
+		&self.socket
+	}
+
+	/// Receive some data, waiting until there is some. The errors are as for
+	/// `Socket::recv`.
+	pub async fn recv(&self, buf: &mut [u8]) -> Result<usize, Error> {
+		ready(|| self.socket.recv(buf)).await
+	}
+
+	/// Send some data, waiting until there is room for at least some of it.
+	/// Returns how much was sent.
+	pub async fn send(&self, buf: &[u8]) -> Result<usize, Error> {
+		ready(|| self.socket.send(buf)).await
+	}
+
+	/// Send all of `buf`, waiting for room as often as we need to.
+	pub async fn write_all(&self, mut buf: &[u8]) -> Result<(), Error> {
+		while !buf.is_empty() {
+			let sent = self.send(buf).await?;
+			buf = buf.get(sent..).unwrap_or(&[]);
+		}
+		Ok(())
+	}
+}
+
+impl<S> AsyncSocket<S>
+where
+	S: Deref<Target = Socket> + ConnectNonBlocking,
+{
+	/// Connect a new TCP, TLS or DTLS socket to a hostname, waiting for the
+	/// connection (including any handshake) without blocking.
+	pub async fn connect(socket: S, hostname: &str, port: u16) -> Result<Self, Error> {
+		if socket.connect_start(hostname, port)? == ConnectStatus::InProgress {
+			connected(|| socket.connect_poll()).await?;
+		}
+		Ok(AsyncSocket::new(socket))
+	}
+}
+
+impl<S> Pollable for AsyncSocket<S>
+where
+	S: Pollable,
+{
+	/// Get the underlying socket ID for this socket.
+	fn get_fd(&self) -> i32 {
+		self.socket.get_fd()
+	}
+}
+
+impl ConnectNonBlocking for TcpSocket {
+	fn connect_start(&self, hostname: &str, port: u16) -> Result<ConnectStatus, Error> {
+		TcpSocket::connect_start(self, hostname, port)
+	}
+
+	fn connect_poll(&self) -> Result<ConnectStatus, Error> {
+		TcpSocket::connect_poll(self)
+	}
+}
+
+impl ConnectNonBlocking for TlsSocket {
+	fn connect_start(&self, hostname: &str, port: u16) -> Result<ConnectStatus, Error> {
+		TlsSocket::connect_start(self, hostname, port)
+	}
+
+	fn connect_poll(&self) -> Result<ConnectStatus, Error> {
+		TlsSocket::connect_poll(self)
+	}
+}
+
+impl ConnectNonBlocking for DtlsSocket {
+	fn connect_start(&self, hostname: &str, port: u16) -> Result<ConnectStatus, Error> {
+		DtlsSocket::connect_start(self, hostname, port)
+	}
+
+	fn connect_poll(&self) -> Result<ConnectStatus, Error> {
+		DtlsSocket::connect_poll(self)
+	}
+}
+
+#[cfg(feature = "embedded-nal-async")]
+impl<S> embedded_io_async::ErrorType for AsyncSocket<S> {
+	type Error = Error;
+}
+
+#[cfg(feature = "embedded-nal-async")]
+impl<S> embedded_io_async::Read for AsyncSocket<S>
+where
+	S: Deref<Target = Socket>,
+{
+	/// Receive some data. The peer closing the connection is reported as
+	/// end-of-file (`Ok(0)`), as `embedded-io` expects.
+	async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		match self.recv(buf).await {
+			Err(Error::Disconnected) => Ok(0),
+			result => result,
+		}
+	}
+}
+
+#[cfg(feature = "embedded-nal-async")]
+impl<S> embedded_io_async::Write for AsyncSocket<S>
+where
+	S: Deref<Target = Socket>,
+{
+	async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+		self.send(buf).await
+	}
+
+	/// The modem sends as soon as it can, so there's nothing to flush.
+	async fn flush(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+}
+
+#[cfg(feature = "embedded-nal-async")]
+impl embedded_nal_async::TcpConnect for TcpConnector {
+	type Error = Error;
+	type Connection<'a> = AsyncSocket<TcpSocket>;
+
+	/// Connect to an IPv4 address. IPv6 addresses give
+	/// `Error::BadDataFormat`, as our TCP sockets are IPv4 only.
+	async fn connect(
+		&self,
+		remote: embedded_nal_async::SocketAddr,
+	) -> Result<AsyncSocket<TcpSocket>, Error> {
+		let addr = sockaddr_in(remote)?;
+		let socket = TcpSocket::new()?;
+		if socket.connect_start_addr(&addr)? == ConnectStatus::InProgress {
+			connected(|| socket.connect_poll()).await?;
+		}
+		Ok(AsyncSocket::new(socket))
+	}
+}
+
+#[cfg(feature = "embedded-nal-async")]
+impl UdpConnector {
+	/// Make UDP sockets on the default PDN connection (context 0).
+	pub const fn new() -> UdpConnector {
+		UdpConnector { cid: 0 }
+	}
+
+	/// Make UDP sockets on the PDN connection with this context ID (see
+	/// `pdn::configure`).
+	pub const fn with_context(cid: u8) -> UdpConnector {
+		UdpConnector { cid }
+	}
+}
+
+#[cfg(feature = "embedded-nal-async")]
+impl embedded_nal_async::UdpStack for UdpConnector {
+	type Error = Error;
+	type Connected = AsyncSocket<UdpSocket>;
+	type UniquelyBound = UnboundUdp;
+	type MultiplyBound = UnboundUdp;
+
+	/// Connect to an IPv4 address, from `local`'s port (or a random one if
+	/// that is zero). `local`'s address must be unspecified or the
+	/// context's own. IPv6 addresses give `Error::BadDataFormat`, as do
+	/// other local addresses, and `Error::LinkDown` means the context has
+	/// no IPv4 address.
+	async fn connect_from(
+		&self,
+		local: embedded_nal_async::SocketAddr,
+		remote: embedded_nal_async::SocketAddr,
+	) -> Result<(embedded_nal_async::SocketAddr, AsyncSocket<UdpSocket>), Error> {
+		let local = match local {
+			embedded_nal_async::SocketAddr::V4(local) => local,
+			embedded_nal_async::SocketAddr::V6(_) => return Err(Error::BadDataFormat),
+		};
+		let addr = sockaddr_in(remote)?;
+		let ip = crate::pdn::addresses(self.cid)?
+			.ipv4
+			.ok_or(Error::LinkDown)?;
+		let ip = embedded_nal_async::Ipv4Addr::from(ip.octets());
+		if !local.ip().is_unspecified() && *local.ip() != ip {
+			return Err(Error::BadDataFormat);
+		}
+		let socket = UdpSocket::new()?;
+		if self.cid != 0 {
+			socket.bind_to_pdn(crate::pdn::pdn_id(self.cid)?)?;
+		}
+		let port = match local.port() {
+			0 => socket.bind_random()?,
+			port => socket.bind(port).map(|_| port)?,
+		};
+		// A UDP connect only sets the peer, so it never waits
+		if socket.connect_start(&addr)? == ConnectStatus::InProgress {
+			connected(|| socket.connect_poll(&addr)).await?;
+		}
+		let local = embedded_nal_async::SocketAddrV4::new(ip, port);
+		Ok((local.into(), AsyncSocket::new(socket)))
+	}
+
+	async fn bind_single(
+		&self,
+		_local: embedded_nal_async::SocketAddr,
+	) -> Result<(embedded_nal_async::SocketAddr, UnboundUdp), Error> {
+		Err(Error::BadDataFormat)
+	}
+
+	async fn bind_multiple(
+		&self,
+		_local: embedded_nal_async::SocketAddr,
+	) -> Result<UnboundUdp, Error> {
+		Err(Error::BadDataFormat)
+	}
+}
+
+#[cfg(feature = "embedded-nal-async")]
+impl embedded_nal_async::ConnectedUdp for AsyncSocket<UdpSocket> {
+	type Error = Error;
+
+	/// Send `data` as one datagram, waiting until there is room for it.
+	async fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+		let sent = AsyncSocket::send(self, data).await?;
+		if sent == data.len() {
+			Ok(())
+		} else {
+			Err(Error::WriteError)
+		}
+	}
+
+	/// Receive one datagram, waiting until there is one. Any of it which
+	/// doesn't fit in `buffer` is lost.
+	async fn receive_into(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+		self.recv(buffer).await
+	}
+}
+
+#[cfg(feature = "embedded-nal-async")]
+impl embedded_nal_async::UnconnectedUdp for UnboundUdp {
+	type Error = Error;
+
+	async fn send(
+		&mut self,
+		_local: embedded_nal_async::SocketAddr,
+		_remote: embedded_nal_async::SocketAddr,
+		_data: &[u8],
+	) -> Result<(), Error> {
+		match *self {}
+	}
+
+	async fn receive_into(
+		&mut self,
+		_buffer: &mut [u8],
+	) -> Result<
+		(
+			usize,
+			embedded_nal_async::SocketAddr,
+			embedded_nal_async::SocketAddr,
+		),
+		Error,
+	> {
+		match *self {}
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Call `f` until it stops returning `Ok(None)`, parking the task in
+/// between until there may be news from the modem.
+async fn ready<T, F>(mut f: F) -> Result<T, Error>
+where
+	F: FnMut() -> Result<Option<T>, Error>,
+{
+	core::future::poll_fn(|cx| {
+		// Register first, so news arriving while `f` runs still wakes us
+		crate::api::register_data_ready_waker(cx.waker());
+		match f() {
+			Ok(Some(value)) => Poll::Ready(Ok(value)),
+			Ok(None) => Poll::Pending,
+			Err(e) => Poll::Ready(Err(e)),
+		}
+	})
+	.await
+}
+
+/// Wait for a connect to finish, calling `connect_poll` to see how it is
+/// going.
+async fn connected<F>(connect_poll: F) -> Result<(), Error>
+where
+	F: Fn() -> Result<ConnectStatus, Error>,
+{
+	ready(|| match connect_poll()? {
+		ConnectStatus::InProgress => Ok(None),
+		ConnectStatus::Connected => Ok(Some(())),
+	})
+	.await
+}
+
+/// Check we may connect to `remote`, and turn it into a libmodem socket
+/// address. IPv6 addresses give `Error::BadDataFormat`, as our TCP and UDP
+/// sockets are IPv4 only.
+#[cfg(feature = "embedded-nal-async")]
+fn sockaddr_in(
+	remote: embedded_nal_async::SocketAddr,
+) -> Result<crate::sys::nrf_sockaddr_in, Error> {
+	let remote = match remote {
+		embedded_nal_async::SocketAddr::V4(remote) => remote,
+		embedded_nal_async::SocketAddr::V6(_) => return Err(Error::BadDataFormat),
+	};
+	let octets = remote.ip().octets();
+	let ip = crate::net::Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]);
+	crate::destinations::check_address(ip.into(), remote.port())?;
+	Ok(crate::sys::nrf_sockaddr_in {
+		sin_len: core::mem::size_of::<crate::sys::nrf_sockaddr_in>() as u8,
+		sin_family: crate::sys::NRF_AF_INET as i32,
+		sin_port: crate::raw::htons(remote.port()),
+		sin_addr: crate::sys::nrf_in_addr {
+			// `s_addr` holds the octets in network order
+			s_addr: u32::from_ne_bytes(octets),
+		},
+	})
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
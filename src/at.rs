@@ -18,27 +18,82 @@
 // Imports
 //******************************************************************************
 
-use crate::{raw::*, AtError, Error};
+#[cfg(target_arch = "arm")]
+use crate::{raw::*, transcript::Direction};
+use crate::{AtError, Error};
+#[cfg(target_arch = "arm")]
+use core::cell::RefCell;
+#[cfg(target_arch = "arm")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(target_arch = "arm")]
+use cortex_m::interrupt::Mutex;
 
 //******************************************************************************
 // Types
 //******************************************************************************
 
 /// Represents a connection to the modem using AT Commands.
+#[cfg(target_arch = "arm")]
 #[derive(Debug)]
 pub struct AtSocket(Socket);
 
+/// Passes a response through to a sink, holding back the start of each line
+/// until we know it isn't the final response. See `AtSocket::poll_response_to`.
+#[cfg(all(feature = "embedded-io", target_arch = "arm"))]
+struct ResponseStream<'w, W: ?Sized> {
+	sink: &'w mut W,
+	/// The current line, while it's short enough to be a final response
+	held: heapless::Vec<u8, MAX_FINAL_RESPONSE_LEN>,
+	/// The current line is too long to be a final response, so the rest of
+	/// it goes straight through
+	streaming: bool,
+}
+
+/// The unsolicited result code (URC) subscriptions made through this crate.
+///
+/// Each value is the level most recently requested (e.g. `2` for
+/// `AT+CEREG=2`), where `0` means not subscribed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Subscriptions {
+	/// Network registration reports (`AT+CEREG`)
+	pub cereg: u8,
+	/// Signalling connection status reports (`AT+CSCON`)
+	pub cscon: u8,
+	/// Packet domain event reports (`AT+CGEREP`)
+	pub cgerep: u8,
+	/// Modem domain event reports (`AT%MDMEV`)
+	pub mdmev: u8,
+	/// Network error code reports (`AT+CNEC`). This is a bit mask: `8` for
+	/// EMM causes, `16` for ESM causes.
+	pub cnec: u8,
+}
+
 //******************************************************************************
 // Constants
 //******************************************************************************
 
-// None
+/// The longest final response line, with its line ending, e.g.
+/// `+CME ERROR: 65535\r\n`.
+#[cfg(all(feature = "embedded-io", target_arch = "arm"))]
+const MAX_FINAL_RESPONSE_LEN: usize = 32;
 
 //******************************************************************************
 // Global Variables
 //******************************************************************************
 
-// None
+/// The URC subscriptions we have sent to the modem.
+#[cfg(target_arch = "arm")]
+static SUBSCRIPTIONS: Mutex<RefCell<Subscriptions>> = Mutex::new(RefCell::new(Subscriptions {
+	cereg: 0,
+	cscon: 0,
+	cgerep: 0,
+	mdmev: 0,
+	cnec: 0,
+}));
+
+/// The number of AT commands sent that have not yet had a final response.
+#[cfg(target_arch = "arm")]
+static PENDING_COMMANDS: AtomicUsize = AtomicUsize::new(0);
 
 //******************************************************************************
 // Macros
@@ -50,6 +105,7 @@ pub struct AtSocket(Socket);
 // Public Functions and Impl on Public Types
 //******************************************************************************
 
+#[cfg(target_arch = "arm")]
 impl AtSocket {
 	/// Create a new AT socket.
 	pub fn new() -> Result<AtSocket, Error> {
@@ -59,7 +115,36 @@ impl AtSocket {
 
 	/// Send an AT command to the modem
 	pub fn send_command(&self, command: &str) -> Result<(), Error> {
-		self.0.write(command.as_bytes()).map(|_count| ())
+		self.0.write(command.as_bytes())?;
+		crate::transcript::record(Direction::ToModem, command);
+		crate::nvm::record(command);
+		PENDING_COMMANDS.fetch_add(1, Ordering::Relaxed);
+		record_subscription(command);
+		Ok(())
+	}
+
+	/// Send an AT command formatted straight into the socket, a piece at a
+	/// time, rather than built in a buffer first - for commands too long to
+	/// buffer, such as `AT%CMNG` with a certificate. The command isn't added
+	/// to the AT transcript, so keys sent this way stay out of it.
+	pub(crate) fn send_command_fmt(&mut self, command: core::fmt::Arguments) -> Result<(), Error> {
+		core::fmt::Write::write_fmt(&mut self.0, command)?;
+		PENDING_COMMANDS.fetch_add(1, Ordering::Relaxed);
+		Ok(())
+	}
+
+	/// Perform a non-blocking read on the socket, as `Socket::recv`. Anything
+	/// received is added to the AT transcript (see `transcript`).
+	pub fn recv(&self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+		let result = self.0.recv(buf)?;
+		if let Some(length) = result {
+			if let Ok(s) = response_text(buf.get(0..length).unwrap_or(&[])) {
+				for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+					crate::transcript::record(Direction::FromModem, line);
+				}
+			}
+		}
+		Ok(result)
 	}
 
 	/// Read from the AT socket until we get something that indicates the command has completed.
@@ -68,48 +153,92 @@ impl AtSocket {
 	/// ERROR:xxx`. These are mapped to a Rust `Result` type.
 	///
 	/// Any other data received is deemed to be a command result and passed to the given fn `callback_function`.
+	///
+	/// Call this once for each command sent with `send_command`, so that
+	/// `pending_commands` stays right.
 	pub fn poll_response<F>(&mut self, mut callback_function: F) -> Result<(), Error>
+	where
+		F: FnMut(&str),
+	{
+		let result = self.read_response(&mut callback_function);
+		PENDING_COMMANDS.fetch_sub(1, Ordering::Relaxed);
+		result
+	}
+
+	/// Read from the AT socket until the command has completed, as
+	/// `poll_response` does, but write everything before the final response
+	/// into `sink`, exactly as the modem sent it (line endings included).
+	/// Only one read (a `pool` block) and the start of one line are held in
+	/// memory at a time, however long the response is.
+	///
+	/// The response isn't added to the AT transcript, except for the final
+	/// response. If the sink fails, this returns `Error::WriteError` and the
+	/// rest of the response is left unread.
+	#[cfg(feature = "embedded-io")]
+	pub fn poll_response_to<W>(&mut self, sink: &mut W) -> Result<(), Error>
+	where
+		W: embedded_io::Write + ?Sized,
+	{
+		let result = self.stream_response(sink);
+		PENDING_COMMANDS.fetch_sub(1, Ordering::Relaxed);
+		result
+	}
+
+	/// Read until we get a final response. See `poll_response_to`.
+	#[cfg(feature = "embedded-io")]
+	fn stream_response<W>(&mut self, sink: &mut W) -> Result<(), Error>
+	where
+		W: embedded_io::Write + ?Sized,
+	{
+		let mut stream = ResponseStream {
+			sink,
+			held: heapless::Vec::new(),
+			streaming: false,
+		};
+		let mut buf = crate::pool::Buffer::take()?;
+		let timeout_ms = crate::timeouts::current().at_ms;
+		loop {
+			let length = loop {
+				match self.0.recv(&mut buf)? {
+					None => wait_readable(&self.0, timeout_ms)?,
+					Some(n) => break n,
+				}
+			};
+			let data = buf.get(0..length).unwrap_or(&[]);
+			if let Some(result) = stream.feed(data.strip_suffix(&[0]).unwrap_or(data))? {
+				stream.sink.flush().map_err(|_| Error::WriteError)?;
+				return result;
+			}
+		}
+	}
+
+	/// Read lines until we get a final response. See `poll_response`.
+	fn read_response<F>(&mut self, callback_function: &mut F) -> Result<(), Error>
 	where
 		F: FnMut(&str),
 	{
 		let result;
+		let mut buf = crate::pool::Buffer::take()?;
+		let timeout_ms = crate::timeouts::current().at_ms;
 		'outer: loop {
-			let mut buf = [0u8; 256];
 			let length = 'inner: loop {
 				match self.recv(&mut buf)? {
 					None => {
-						// EAGAIN
+						// EAGAIN - wait for more (see `timeouts`)
+						wait_readable(&self.0, timeout_ms)?;
 					}
 					Some(n) => break 'inner n,
 				};
 			};
-			let s = unsafe { core::str::from_utf8_unchecked(&buf[0..length - 1]) };
+			let s = response_text(buf.get(0..length).unwrap_or(&[]))?;
 			for line in s.lines() {
 				let line = line.trim();
-				match line {
-					"OK" => {
-						result = Ok(());
-						break 'outer;
-					}
-					"ERROR" => {
-						result = Err(Error::AtError(AtError::Error));
-						break 'outer;
-					}
-					err if err.starts_with("+CME ERROR:") => {
-						let num_str = &err[11..];
-						let value = num_str.trim().parse().unwrap_or(-1);
-						result = Err(Error::AtError(AtError::CmeError(value)));
-						break 'outer;
-					}
-					err if err.starts_with("+CMS ERROR:") => {
-						let num_str = &err[11..];
-						let value = num_str.trim().parse().unwrap_or(-1);
-						result = Err(Error::AtError(AtError::CmsError(value)));
+				match parse_final_response(line) {
+					Some(final_result) => {
+						result = final_result;
 						break 'outer;
 					}
-					data => {
-						callback_function(data);
-					}
+					None => callback_function(line),
 				}
 			}
 		}
@@ -117,6 +246,7 @@ impl AtSocket {
 	}
 }
 
+#[cfg(target_arch = "arm")]
 impl Pollable for AtSocket {
 	/// Get the underlying socket ID for this socket.
 	fn get_fd(&self) -> i32 {
@@ -124,12 +254,14 @@ impl Pollable for AtSocket {
 	}
 }
 
+#[cfg(target_arch = "arm")]
 impl core::ops::DerefMut for AtSocket {
 	fn deref_mut(&mut self) -> &mut Socket {
 		&mut self.0
 	}
 }
 
+#[cfg(target_arch = "arm")]
 impl core::ops::Deref for AtSocket {
 	type Target = Socket;
 	fn deref(&self) -> &Socket {
@@ -137,11 +269,38 @@ impl core::ops::Deref for AtSocket {
 	}
 }
 
+/// Turn what was read from an AT socket into text. The modem ends each read
+/// with a NUL, which is removed. Returns `Error::BadDataFormat` if it isn't
+/// UTF-8 - the modem shouldn't send anything else, but we don't trust it to.
+pub fn response_text(data: &[u8]) -> Result<&str, Error> {
+	let data = data.strip_suffix(&[0]).unwrap_or(data);
+	core::str::from_utf8(data).map_err(|_| Error::BadDataFormat)
+}
+
+/// See whether a line (already trimmed) is the final response to an AT
+/// command: `OK`, `ERROR`, `+CME ERROR: <n>` or `+CMS ERROR: <n>`. Returns
+/// `None` for anything else. An error number we can't read is given as -1.
+pub fn parse_final_response(line: &str) -> Option<Result<(), Error>> {
+	let error_number = |n: &str| n.trim().parse().unwrap_or(-1);
+	match line {
+		"OK" => Some(Ok(())),
+		"ERROR" => Some(Err(Error::AtError(AtError::Error))),
+		_ => {
+			let error = match line.strip_prefix("+CME ERROR:") {
+				Some(n) => AtError::CmeError(error_number(n)),
+				None => AtError::CmsError(error_number(line.strip_prefix("+CMS ERROR:")?)),
+			};
+			Some(Err(Error::AtError(error)))
+		}
+	}
+}
+
 /// Sends an AT command to the modem and calls the given closure with any
 /// indications received. Indications have any whitespace or newlines trimmed.
 ///
 /// Creates and destroys a new NRF_AF_LTE/NRF_PROTO_AT socket. Will block
 /// until we get 'OK' or some sort of error response from the modem.
+#[cfg(target_arch = "arm")]
 pub fn send_at_command<F>(command: &str, function: F) -> Result<(), Error>
 where
 	F: FnMut(&str),
@@ -151,11 +310,127 @@ where
 	skt.poll_response(function)
 }
 
+/// Sends an AT command to the modem and writes the response, up to the
+/// final `OK` or error, into `sink` as it arrives. This suits responses too
+/// long to collect in a buffer, such as reading back a certificate with
+/// `AT%CMNG=2` or the command list from `AT+CLAC`. See
+/// `AtSocket::poll_response_to`.
+///
+/// Creates and destroys a new NRF_AF_LTE/NRF_PROTO_AT socket. Will block
+/// until we get 'OK' or some sort of error response from the modem.
+#[cfg(all(feature = "embedded-io", target_arch = "arm"))]
+pub fn execute_to<W>(command: &str, sink: &mut W) -> Result<(), Error>
+where
+	W: embedded_io::Write + ?Sized,
+{
+	let mut skt = AtSocket::new()?;
+	skt.send_command(command)?;
+	skt.poll_response_to(sink)
+}
+
+/// Get the URC subscriptions which have been sent to the modem, through this
+/// crate, since start-up.
+#[cfg(target_arch = "arm")]
+pub fn subscriptions() -> Subscriptions {
+	cortex_m::interrupt::free(|cs| *SUBSCRIPTIONS.borrow(cs).borrow())
+}
+
+/// Get the number of AT commands which have been sent but which have not yet
+/// had a final response.
+#[cfg(target_arch = "arm")]
+pub fn pending_commands() -> usize {
+	PENDING_COMMANDS.load(Ordering::Relaxed)
+}
+
 //******************************************************************************
 // Private Functions and Impl on Private Types
 //******************************************************************************
 
-// None
+/// If this command changes a URC subscription, remember the new level.
+#[cfg(target_arch = "arm")]
+fn record_subscription(command: &str) {
+	let command = command.trim();
+	let (name, value) = match command.find('=') {
+		Some(idx) => (&command[0..idx], &command[idx + 1..]),
+		None => return,
+	};
+	let level = match value.split(',').next().and_then(|v| v.trim().parse().ok()) {
+		Some(level) => level,
+		None => return,
+	};
+	cortex_m::interrupt::free(|cs| {
+		let mut subscriptions = SUBSCRIPTIONS.borrow(cs).borrow_mut();
+		match name {
+			"AT+CEREG" => subscriptions.cereg = level,
+			"AT+CSCON" => subscriptions.cscon = level,
+			"AT+CGEREP" => subscriptions.cgerep = level,
+			"AT%MDMEV" => subscriptions.mdmev = level,
+			"AT+CNEC" => subscriptions.cnec = level,
+			_ => {}
+		}
+	});
+}
+
+#[cfg(all(feature = "embedded-io", target_arch = "arm"))]
+impl<'w, W> ResponseStream<'w, W>
+where
+	W: embedded_io::Write + ?Sized,
+{
+	/// Pass on one read's worth of data. Returns the final response, once
+	/// we've seen it. A final response is recognised at the end of a line or
+	/// at the end of a read, as in `AtSocket::poll_response`.
+	fn feed(&mut self, mut data: &[u8]) -> Result<Option<Result<(), Error>>, Error> {
+		while !data.is_empty() {
+			let (segment, rest, ends_line) = match data.iter().position(|&b| b == b'\n') {
+				Some(idx) => {
+					let (segment, rest) = data.split_at(idx + 1);
+					(segment, rest, true)
+				}
+				None => (data, &[][..], false),
+			};
+			data = rest;
+			if self.streaming {
+				self.write(segment)?;
+			} else if self.held.extend_from_slice(segment).is_err() {
+				// Too long to be a final response
+				self.write_held()?;
+				self.write(segment)?;
+				self.streaming = true;
+			} else if ends_line {
+				if let Some(result) = self.final_response() {
+					return Ok(Some(result));
+				}
+				self.write_held()?;
+			}
+			if ends_line {
+				self.streaming = false;
+			}
+		}
+		Ok(self.final_response())
+	}
+
+	/// Is the line we're holding a final response? If so, it's added to the
+	/// AT transcript.
+	fn final_response(&self) -> Option<Result<(), Error>> {
+		let line = core::str::from_utf8(&self.held).ok()?.trim();
+		let result = parse_final_response(line)?;
+		crate::transcript::record(Direction::FromModem, line);
+		Some(result)
+	}
+
+	/// Pass on the line we're holding, which isn't a final response.
+	fn write_held(&mut self) -> Result<(), Error> {
+		self.sink
+			.write_all(&self.held)
+			.map_err(|_| Error::WriteError)?;
+		self.held.clear();
+		Ok(())
+	}
+
+	fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+		self.sink.write_all(data).map_err(|_| Error::WriteError)
+	}
+}
 
 //******************************************************************************
 // End of File
@@ -0,0 +1,511 @@
+//! # Library start-up configuration for nrfxlib
+//!
+//! `init()` starts libmodem with the settings from the NCS 1.5.1 release.
+//! If you need something different - modem traces, bigger buffers, other
+//! interrupt priorities, or callbacks registered before the modem starts -
+//! use an `NrfxlibBuilder` instead:
+//!
+//! ```ignore
+//! nrfxlib::NrfxlibBuilder::new()
+//!     .trace(0x2000, send_trace_to_uart)
+//!     .rx_size(0x4000)
+//!     .watchdog_feeder(feed_watchdog)
+//!     .init()?;
+//! ```
+//!
+//! Every setting is checked before anything is started. If any are wrong,
+//! `init` returns `Error::InvalidConfig` listing all of the problems, not just
+//! the first.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+use crate::delay::DelayProvider;
+#[cfg(target_arch = "arm")]
+use crate::destinations::DestinationPolicy;
+#[cfg(target_arch = "arm")]
+use crate::entropy::EntropySource;
+#[cfg(target_arch = "arm")]
+use crate::last_position::PositionStore;
+#[cfg(target_arch = "arm")]
+use crate::observer::SocketObserver;
+#[cfg(target_arch = "arm")]
+use crate::timeouts::Timeouts;
+#[cfg(target_arch = "arm")]
+use crate::traces::TracePolicy;
+#[cfg(target_arch = "arm")]
+use crate::{cpu, sys, Error};
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Collects the settings used to start libmodem. See the module
+/// documentation.
+#[cfg(target_arch = "arm")]
+#[derive(Clone)]
+pub struct NrfxlibBuilder {
+	shmem_base: u32,
+	tx_size: u32,
+	rx_size: u32,
+	trace_size: u32,
+	trace_policy: Option<TracePolicy>,
+	ipc_irq_priority: u8,
+	application_irq_priority: Option<u8>,
+	trace_irq_priority: Option<u8>,
+	watchdog_feeder: Option<fn()>,
+	socket_observer: Option<&'static dyn SocketObserver>,
+	entropy_source: Option<&'static dyn EntropySource>,
+	delay_provider: Option<&'static dyn DelayProvider>,
+	position_store: Option<&'static dyn PositionStore>,
+	transcript_sink: Option<crate::transcript::Sink>,
+	timeouts: Option<Timeouts>,
+	destination_policy: Option<DestinationPolicy>,
+	minimize_nvm_writes: bool,
+	dfu_mode: bool,
+}
+
+/// Something wrong with an `NrfxlibBuilder`'s settings.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConfigConflict {
+	/// The shared memory doesn't start on a four byte boundary
+	SharedMemoryUnaligned,
+	/// The shared memory isn't all within the lower 128 KiB of RAM, which
+	/// is the only RAM the modem can see
+	SharedMemoryOutOfRange,
+	/// The TX buffer is empty, or not a multiple of four bytes
+	BadTxSize,
+	/// The RX buffer is empty, or not a multiple of four bytes
+	BadRxSize,
+	/// The trace buffer is not a multiple of four bytes
+	BadTraceSize,
+	/// A trace buffer was given, but nothing to send the traces to
+	TraceWithoutHandler,
+	/// A trace handler was given, but no trace buffer
+	TraceHandlerWithoutBuffer,
+	/// An interrupt priority is above `MAX_IRQ_PRIORITY`
+	BadIrqPriority,
+}
+
+/// Every `ConfigConflict` found in an `NrfxlibBuilder`'s settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigConflicts(heapless::Vec<ConfigConflict, MAX_CONFLICTS>);
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The numerically largest (i.e. least urgent) interrupt priority. The
+/// nRF9160 has three priority bits.
+pub const MAX_IRQ_PRIORITY: u8 = 7;
+
+/// How far up the priority register the priority bits are.
+#[cfg(target_arch = "arm")]
+const IRQ_PRIORITY_SHIFT: u8 = 8 - 3;
+
+/// Where the shared memory starts, unless told otherwise (see memory.x).
+#[cfg(target_arch = "arm")]
+const DEFAULT_SHMEM_BASE: u32 = 0x2001_0000;
+
+/// The TX and RX buffer sizes specified in the NCS 1.5.1 release.
+#[cfg(target_arch = "arm")]
+const DEFAULT_BUFFER_SIZE: u32 = 0x0000_2000;
+
+/// The start of RAM.
+#[cfg(target_arch = "arm")]
+const RAM_START: u32 = 0x2000_0000;
+
+/// The modem can only see the lower 128 KiB of RAM.
+#[cfg(target_arch = "arm")]
+const SHARED_RAM_END: u32 = RAM_START + 0x0002_0000;
+
+/// There is one of each `ConfigConflict`, at most.
+const MAX_CONFLICTS: usize = 8;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+impl NrfxlibBuilder {
+	/// Start with the same settings `init()` uses: no traces, 8 KiB TX and
+	/// RX buffers, the IPC interrupt at priority 0, and no callbacks.
+	pub fn new() -> NrfxlibBuilder {
+		NrfxlibBuilder {
+			shmem_base: DEFAULT_SHMEM_BASE,
+			tx_size: DEFAULT_BUFFER_SIZE,
+			rx_size: DEFAULT_BUFFER_SIZE,
+			trace_size: 0,
+			trace_policy: None,
+			ipc_irq_priority: 0,
+			application_irq_priority: None,
+			trace_irq_priority: None,
+			watchdog_feeder: None,
+			socket_observer: None,
+			entropy_source: None,
+			delay_provider: None,
+			position_store: None,
+			transcript_sink: None,
+			timeouts: None,
+			destination_policy: None,
+			minimize_nvm_writes: false,
+			dfu_mode: false,
+		}
+	}
+
+	/// Set where the memory shared with the modem starts. It must match the
+	/// region your memory.x keeps free. The control block, TX, RX and trace
+	/// buffers follow each other from here.
+	pub fn shared_memory_base(mut self, base: u32) -> NrfxlibBuilder {
+		self.shmem_base = base;
+		self
+	}
+
+	/// Set the size of the buffer used to send data to the modem.
+	pub fn tx_size(mut self, size: u32) -> NrfxlibBuilder {
+		self.tx_size = size;
+		self
+	}
+
+	/// Set the size of the buffer used to receive data from the modem.
+	pub fn rx_size(mut self, size: u32) -> NrfxlibBuilder {
+		self.rx_size = size;
+		self
+	}
+
+	/// Turn modem traces on, with a `size` byte trace buffer. `handler` is
+	/// given the trace data, from `trace_irq_handler`, and should pass it on
+	/// quickly (e.g. to a UART DMA buffer) - the modem waits for it. You
+	/// still need to ask the modem for traces with `AT%XMODEMTRACE`.
+	pub fn trace(self, size: u32, handler: fn(&[u8])) -> NrfxlibBuilder {
+		self.trace_with_policy(size, TracePolicy::Backpressure(handler))
+	}
+
+	/// Turn modem traces on, as `trace` does, but with a choice of what
+	/// happens when the handler can't keep up. See `traces`.
+	pub fn trace_with_policy(mut self, size: u32, policy: TracePolicy) -> NrfxlibBuilder {
+		self.trace_size = size;
+		self.trace_policy = Some(policy);
+		self
+	}
+
+	/// Set the priority of the IPC interrupt, from 0 (the most urgent) to
+	/// `MAX_IRQ_PRIORITY`. libmodem sets this itself when it starts.
+	pub fn ipc_irq_priority(mut self, priority: u8) -> NrfxlibBuilder {
+		self.ipc_irq_priority = priority;
+		self
+	}
+
+	/// Set the priority of the EGU1 interrupt, which calls
+	/// `application_irq_handler`. Nordic use 6. By default we leave it
+	/// alone.
+	pub fn application_irq_priority(mut self, priority: u8) -> NrfxlibBuilder {
+		self.application_irq_priority = Some(priority);
+		self
+	}
+
+	/// Set the priority of the EGU2 interrupt, which calls
+	/// `trace_irq_handler`. By default we leave it alone.
+	pub fn trace_irq_priority(mut self, priority: u8) -> NrfxlibBuilder {
+		self.trace_irq_priority = Some(priority);
+		self
+	}
+
+	/// Register a watchdog feeder before the modem starts. See
+	/// `watchdog::set_feeder`.
+	pub fn watchdog_feeder(mut self, feeder: fn()) -> NrfxlibBuilder {
+		self.watchdog_feeder = Some(feeder);
+		self
+	}
+
+	/// Register a socket observer before the modem starts. See
+	/// `observer::set_observer`.
+	pub fn socket_observer(mut self, observer: &'static dyn SocketObserver) -> NrfxlibBuilder {
+		self.socket_observer = Some(observer);
+		self
+	}
+
+	/// Register the source of random numbers before the modem starts. See
+	/// `entropy::set_source`.
+	pub fn entropy_source(mut self, source: &'static dyn EntropySource) -> NrfxlibBuilder {
+		self.entropy_source = Some(source);
+		self
+	}
+
+	/// Register what the crate waits on, before the modem starts. See
+	/// `delay::set_provider`.
+	pub fn delay_provider(mut self, provider: &'static dyn DelayProvider) -> NrfxlibBuilder {
+		self.delay_provider = Some(provider);
+		self
+	}
+
+	/// Register where the last known position is kept over a reset. See
+	/// `last_position::set_store`.
+	pub fn position_store(mut self, store: &'static dyn PositionStore) -> NrfxlibBuilder {
+		self.position_store = Some(store);
+		self
+	}
+
+	/// Register an AT transcript sink before the modem starts. See
+	/// `transcript::set_sink`.
+	pub fn transcript_sink(mut self, sink: crate::transcript::Sink) -> NrfxlibBuilder {
+		self.transcript_sink = Some(sink);
+		self
+	}
+
+	/// Set the timeouts, including the receive and send timeouts every new
+	/// socket starts with. See `timeouts::set`.
+	pub fn timeouts(mut self, timeouts: Timeouts) -> NrfxlibBuilder {
+		self.timeouts = Some(timeouts);
+		self
+	}
+
+	/// Restrict where sockets may connect to. See
+	/// `destinations::set_policy`.
+	pub fn destination_policy(mut self, policy: DestinationPolicy) -> NrfxlibBuilder {
+		self.destination_policy = Some(policy);
+		self
+	}
+
+	/// Turn "minimize writes" mode on or off. See `nvm::set_minimize_writes`.
+	pub fn minimize_nvm_writes(mut self, minimize: bool) -> NrfxlibBuilder {
+		self.minimize_nvm_writes = minimize;
+		self
+	}
+
+	/// Start libmodem in full DFU mode, for a modem firmware update, rather
+	/// than normal mode. The modem can't do anything else in DFU mode.
+	pub fn dfu_mode(mut self, dfu_mode: bool) -> NrfxlibBuilder {
+		self.dfu_mode = dfu_mode;
+		self
+	}
+
+	/// Check the settings without starting anything.
+	pub fn validate(&self) -> Result<(), Error> {
+		let mut conflicts = ConfigConflicts(heapless::Vec::new());
+		let mut check = |ok: bool, conflict: ConfigConflict| {
+			if !ok {
+				// Can't fail - there are only `MAX_CONFLICTS` kinds
+				let _ = conflicts.0.push(conflict);
+			}
+		};
+		let end = u64::from(self.shmem_base)
+			+ u64::from(sys::NRF_MODEM_SHMEM_CTRL_SIZE)
+			+ u64::from(self.tx_size)
+			+ u64::from(self.rx_size)
+			+ u64::from(self.trace_size);
+		check(
+			is_word_aligned(self.shmem_base),
+			ConfigConflict::SharedMemoryUnaligned,
+		);
+		check(
+			self.shmem_base >= RAM_START && end <= u64::from(SHARED_RAM_END),
+			ConfigConflict::SharedMemoryOutOfRange,
+		);
+		check(
+			self.tx_size != 0 && is_word_aligned(self.tx_size),
+			ConfigConflict::BadTxSize,
+		);
+		check(
+			self.rx_size != 0 && is_word_aligned(self.rx_size),
+			ConfigConflict::BadRxSize,
+		);
+		check(
+			is_word_aligned(self.trace_size),
+			ConfigConflict::BadTraceSize,
+		);
+		check(
+			self.trace_size == 0 || self.trace_policy.is_some(),
+			ConfigConflict::TraceWithoutHandler,
+		);
+		check(
+			self.trace_size != 0 || self.trace_policy.is_none(),
+			ConfigConflict::TraceHandlerWithoutBuffer,
+		);
+		let priorities = [
+			Some(self.ipc_irq_priority),
+			self.application_irq_priority,
+			self.trace_irq_priority,
+		];
+		check(
+			priorities
+				.iter()
+				.flatten()
+				.all(|&priority| priority <= MAX_IRQ_PRIORITY),
+			ConfigConflict::BadIrqPriority,
+		);
+		if conflicts.0.is_empty() {
+			Ok(())
+		} else {
+			Err(Error::InvalidConfig(conflicts))
+		}
+	}
+
+	/// Check the settings, register the callbacks, and start libmodem.
+	/// Returns `Error::AlreadyInitialised`, before registering anything, if
+	/// the library is already running (or starting).
+	pub fn init(self) -> Result<(), Error> {
+		self.validate()?;
+		crate::claim_start()?;
+
+		if let Some(feeder) = self.watchdog_feeder {
+			crate::watchdog::set_feeder(Some(feeder));
+		}
+		if let Some(observer) = self.socket_observer {
+			crate::observer::set_observer(Some(observer));
+		}
+		if let Some(source) = self.entropy_source {
+			crate::entropy::set_source(Some(source));
+		}
+		if let Some(provider) = self.delay_provider {
+			crate::delay::set_provider(Some(provider));
+		}
+		if let Some(store) = self.position_store {
+			crate::last_position::set_store(Some(store));
+		}
+		if let Some(sink) = self.transcript_sink {
+			crate::transcript::set_sink(Some(sink));
+		}
+		if let Some(timeouts) = self.timeouts {
+			crate::timeouts::set(timeouts);
+		}
+		if let Some(policy) = self.destination_policy {
+			crate::destinations::set_policy(Some(policy));
+		}
+		crate::nvm::set_minimize_writes(self.minimize_nvm_writes);
+		crate::traces::set_policy(self.trace_policy);
+		if let Some(priority) = self.application_irq_priority {
+			set_irq_priority(cpu::Interrupt::EGU1, priority);
+		}
+		if let Some(priority) = self.trace_irq_priority {
+			set_irq_priority(cpu::Interrupt::EGU2, priority);
+		}
+
+		let ctrl_base = self.shmem_base;
+		let ctrl_size = sys::NRF_MODEM_SHMEM_CTRL_SIZE;
+		let tx_base = ctrl_base + ctrl_size;
+		let rx_base = tx_base + self.tx_size;
+		let trace_base = if self.trace_size == 0 {
+			0
+		} else {
+			rx_base + self.rx_size
+		};
+		let params = sys::nrf_modem_init_params_t {
+			shmem: sys::nrf_modem_shmem_cfg {
+				ctrl: sys::nrf_modem_shmem_cfg__bindgen_ty_1 {
+					base: ctrl_base,
+					size: ctrl_size,
+				},
+				tx: sys::nrf_modem_shmem_cfg__bindgen_ty_2 {
+					base: tx_base,
+					size: self.tx_size,
+				},
+				rx: sys::nrf_modem_shmem_cfg__bindgen_ty_3 {
+					base: rx_base,
+					size: self.rx_size,
+				},
+				trace: sys::nrf_modem_shmem_cfg__bindgen_ty_4 {
+					base: trace_base,
+					size: self.trace_size,
+				},
+			},
+			// `nrfx_ipc_init` writes this straight into the priority register
+			ipc_irq_prio: u32::from(self.ipc_irq_priority << IRQ_PRIORITY_SHIFT),
+		};
+		let mode = if self.dfu_mode {
+			sys::nrf_modem_mode_t_FULL_DFU_MODE
+		} else {
+			sys::nrf_modem_mode_t_NORMAL_MODE
+		};
+		crate::start(&params, mode)
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl Default for NrfxlibBuilder {
+	fn default() -> NrfxlibBuilder {
+		NrfxlibBuilder::new()
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl core::fmt::Debug for NrfxlibBuilder {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		f.debug_struct("NrfxlibBuilder")
+			.field("shmem_base", &self.shmem_base)
+			.field("tx_size", &self.tx_size)
+			.field("rx_size", &self.rx_size)
+			.field("trace_size", &self.trace_size)
+			.field("trace_policy", &self.trace_policy)
+			.field("ipc_irq_priority", &self.ipc_irq_priority)
+			.field("application_irq_priority", &self.application_irq_priority)
+			.field("trace_irq_priority", &self.trace_irq_priority)
+			.field("timeouts", &self.timeouts)
+			.field("minimize_nvm_writes", &self.minimize_nvm_writes)
+			.field("dfu_mode", &self.dfu_mode)
+			.finish()
+	}
+}
+
+impl ConfigConflicts {
+	/// Go through the conflicts found.
+	pub fn iter(&self) -> impl Iterator<Item = &ConfigConflict> {
+		self.0.iter()
+	}
+
+	/// Was this conflict found?
+	pub fn contains(&self, conflict: ConfigConflict) -> bool {
+		self.0.contains(&conflict)
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Is this a multiple of four?
+#[cfg(target_arch = "arm")]
+fn is_word_aligned(value: u32) -> bool {
+	value & 0b11 == 0
+}
+
+/// Set the priority of one of our interrupts, from 0 to `MAX_IRQ_PRIORITY`.
+#[cfg(target_arch = "arm")]
+fn set_irq_priority(irq: cpu::Interrupt, priority: u8) {
+	use cortex_m::interrupt::InterruptNumber;
+	let irq_num = usize::from(irq.number());
+	unsafe {
+		if let Some(ipr) = (*cortex_m::peripheral::NVIC::PTR).ipr.get(irq_num) {
+			ipr.write(priority << IRQ_PRIORITY_SHIFT);
+		}
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
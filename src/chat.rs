@@ -0,0 +1,242 @@
+//! # AT chat scripts for nrfxlib
+//!
+//! A chat script is a list of steps - send this command, wait for that
+//! response - with branches on what the modem says. Carrier onboarding
+//! sequences can then be kept as data, one script per carrier, rather than
+//! as code. For example:
+//!
+//! ```ignore
+//! use nrfxlib::chat::{Branch, Line, Next, Step};
+//!
+//! static ONBOARD: [Line; 4] = [
+//!     // 0: Does the SIM need a PIN?
+//!     Line {
+//!         step: Step::Send("AT+CPIN?"),
+//!         branches: &[Branch { pattern: "READY", next: Next::Goto(2) }],
+//!     },
+//!     // 1: Unlock it
+//!     Line { step: Step::Send("AT+CPIN=\"1234\""), branches: &[] },
+//!     // 2: Set the APN
+//!     Line { step: Step::Send("AT+CGDCONT=0,\"IP\",\"iot.carrier\""), branches: &[] },
+//!     // 3: Wait to register
+//!     Line {
+//!         step: Step::Expect { pattern: "+CEREG: 1", timeout_ms: 60_000, on_timeout: Next::Fail },
+//!         branches: &[],
+//!     },
+//! ];
+//!
+//! chat::run(&ONBOARD, now_ms)?;
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::{at::AtSocket, raw::wait_readable, AtError, Error};
+use core::fmt::Write;
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// One step of a chat script, and where to go next.
+#[derive(Debug, Copy, Clone)]
+pub struct Line<'a> {
+	/// What to do
+	pub step: Step<'a>,
+	/// Checked in order against what the modem said. The first branch whose
+	/// pattern is found decides what happens next. If none match, a
+	/// successful step carries on with the next line.
+	pub branches: &'a [Branch<'a>],
+}
+
+/// What a chat script step does.
+#[derive(Debug, Copy, Clone)]
+pub enum Step<'a> {
+	/// Send an AT command and wait for its final response. The branches are
+	/// checked against each line of the response, and then the final result
+	/// (e.g. `OK`, `ERROR` or `+CME ERROR: 10`). If no branch matches, an
+	/// error from the modem stops the script.
+	Send(&'a str),
+	/// Wait for the modem to send a line containing `pattern` (e.g. a URC).
+	/// Other lines are ignored. The branches are checked against the line.
+	Expect {
+		/// The text to wait for
+		pattern: &'a str,
+		/// The longest to wait for the pattern, however much else the modem
+		/// says meanwhile. Zero means wait forever.
+		timeout_ms: u32,
+		/// What to do if we time out
+		on_timeout: Next,
+	},
+}
+
+/// A branch taken if the modem says something.
+#[derive(Debug, Copy, Clone)]
+pub struct Branch<'a> {
+	/// Text to look for in what the modem said
+	pub pattern: &'a str,
+	/// Where to go if it is found
+	pub next: Next,
+}
+
+/// Where a chat script goes after a step.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Next {
+	/// On to the next line (finishing, if this was the last)
+	Continue,
+	/// To the line with this index
+	Goto(usize),
+	/// Stop, successfully
+	Done,
+	/// Stop, with `Error::ChatFailed`
+	Fail,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The most steps a script can run, so a script which loops back on itself
+/// can't run forever.
+pub const MAX_STEPS: usize = 256;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Run a chat script on a new AT socket. `now_ms` is a millisecond
+/// counter, which may wrap, for timing `Step::Expect`.
+///
+/// Fails with `Error::ChatFailed(n)` if line `n` went to `Next::Fail`, to a
+/// line which doesn't exist, or if `MAX_STEPS` steps have been run. Errors
+/// from the modem that no branch catches are returned as they are.
+pub fn run(script: &[Line], now_ms: fn() -> u32) -> Result<(), Error> {
+	let mut socket = AtSocket::new()?;
+	let mut index = 0;
+	for _ in 0..MAX_STEPS {
+		let line = match script.get(index) {
+			Some(line) => line,
+			None => return Ok(()),
+		};
+		let next = match line.step {
+			Step::Send(command) => send(&mut socket, command, line.branches)?,
+			Step::Expect {
+				pattern,
+				timeout_ms,
+				on_timeout,
+			} => expect(&socket, pattern, timeout_ms, line.branches, now_ms)?.unwrap_or(on_timeout),
+		};
+		debug!("chat step {} => {:?}", index, next);
+		index = match next {
+			Next::Continue => index + 1,
+			Next::Goto(target) if target < script.len() => target,
+			Next::Done => return Ok(()),
+			Next::Goto(_) | Next::Fail => return Err(Error::ChatFailed(index)),
+		};
+	}
+	Err(Error::ChatFailed(index))
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Find the first branch whose pattern is in `text`.
+fn find_branch(branches: &[Branch], text: &str) -> Option<Next> {
+	branches
+		.iter()
+		.find(|branch| text.contains(branch.pattern))
+		.map(|branch| branch.next)
+}
+
+/// Run a `Step::Send`.
+fn send(socket: &mut AtSocket, command: &str, branches: &[Branch]) -> Result<Next, Error> {
+	let mut matched = None;
+	socket.send_command(command)?;
+	let result = socket.poll_response(|line| {
+		if matched.is_none() {
+			matched = find_branch(branches, line);
+		}
+	});
+	if let Some(next) = matched {
+		return Ok(next);
+	}
+	let mut final_result: heapless::String<24> = heapless::String::new();
+	match &result {
+		Ok(()) => write!(final_result, "OK")?,
+		Err(Error::AtError(AtError::Error)) => write!(final_result, "ERROR")?,
+		Err(Error::AtError(AtError::CmeError(n))) => write!(final_result, "+CME ERROR: {}", n)?,
+		Err(Error::AtError(AtError::CmsError(n))) => write!(final_result, "+CMS ERROR: {}", n)?,
+		Err(_) => {}
+	}
+	match find_branch(branches, &final_result) {
+		Some(next) => Ok(next),
+		None => result.map(|_| Next::Continue),
+	}
+}
+
+/// Run a `Step::Expect`. Returns `None` if we timed out.
+fn expect(
+	socket: &AtSocket,
+	pattern: &str,
+	timeout_ms: u32,
+	branches: &[Branch],
+	now_ms: fn() -> u32,
+) -> Result<Option<Next>, Error> {
+	let mut buf = crate::pool::Buffer::take()?;
+	let started_ms = now_ms();
+	loop {
+		let length = match socket.recv(&mut buf)? {
+			Some(n) => n,
+			None => {
+				// Only wait for what is left of the timeout
+				let elapsed_ms = now_ms().wrapping_sub(started_ms);
+				let remaining_ms = match timeout_ms {
+					0 => 0,
+					_ if elapsed_ms >= timeout_ms => return Ok(None),
+					_ => timeout_ms - elapsed_ms,
+				};
+				match wait_readable(socket, remaining_ms) {
+					Ok(()) => continue,
+					Err(Error::TimedOut) => return Ok(None),
+					Err(e) => return Err(e),
+				}
+			}
+		};
+		let data = buf.get(0..length).unwrap_or(&[]);
+		let data = data.strip_suffix(&[0]).unwrap_or(data);
+		let s = core::str::from_utf8(data).map_err(|_| Error::BadDataFormat)?;
+		if let Some(line) = s.lines().map(str::trim).find(|line| line.contains(pattern)) {
+			return Ok(Some(find_branch(branches, line).unwrap_or(Next::Continue)));
+		}
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,183 @@
+//! # Write coalescing for nrfxlib
+//!
+//! Chatty text protocols tend to write a line (or a word) at a time, and each
+//! write is a separate `nrf_send` - an IPC round trip to the modem, and
+//! possibly a separate packet on the air. A `Coalescer` gathers up small
+//! writes made within a few milliseconds of each other and sends them with a
+//! single `nrf_send`, rather like Nagle's algorithm.
+//!
+//! The crate has no timers of its own, so you give the `Coalescer` a
+//! millisecond counter and call `poll` now and again (or `flush` when you
+//! know you have finished writing) to send anything left over:
+//!
+//! ```ignore
+//! let mut out = Coalescer::<_, 256>::new(&socket, 5, board::uptime_ms);
+//! write!(out, "HELO {}\r\n", name)?;
+//! write!(out, "MAIL FROM:<{}>\r\n", from)?;
+//! out.flush()?;
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::{raw::Socket, Error};
+use log::{debug, warn};
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Gathers small writes to a socket into buffers of up to `SIZE` bytes.
+///
+/// Anything still buffered is sent when the `Coalescer` is dropped, but use
+/// `flush` if you want to see any errors.
+#[derive(Debug)]
+pub struct Coalescer<'s, S, const SIZE: usize>
+where
+	S: core::ops::Deref<Target = Socket>,
+{
+	socket: &'s S,
+	buffer: heapless::Vec<u8, SIZE>,
+	delay_ms: u32,
+	now_ms: fn() -> u32,
+	/// When the first byte currently in `buffer` was written
+	first_write_ms: u32,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl<'s, S, const SIZE: usize> Coalescer<'s, S, SIZE>
+where
+	S: core::ops::Deref<Target = Socket>,
+{
+	/// Coalesce writes to the given socket (e.g. a `TcpSocket` or
+	/// `TlsSocket`). Buffered data is held for at most `delay_ms`, as measured
+	/// by `now_ms`.
+	pub fn new(socket: &'s S, delay_ms: u32, now_ms: fn() -> u32) -> Self {
+		Coalescer {
+			socket,
+			buffer: heapless::Vec::new(),
+			delay_ms,
+			now_ms,
+			first_write_ms: 0,
+		}
+	}
+
+	/// Write some data. It is buffered, unless the buffer is full or the
+	/// delay has passed, in which case it is sent straight away. Writes
+	/// bigger than the buffer are sent on their own.
+	pub fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+		if (SIZE - self.buffer.len()) < data.len() {
+			self.flush()?;
+		}
+		if data.len() > SIZE {
+			self.socket.write_all(data)?;
+			return Ok(data.len());
+		}
+		if self.buffer.is_empty() {
+			self.first_write_ms = (self.now_ms)();
+		}
+		// We made room above
+		let _ = self.buffer.extend_from_slice(data);
+		self.poll()?;
+		Ok(data.len())
+	}
+
+	/// Send the buffered data if it has been held for `delay_ms`.
+	///
+	/// Returns how many milliseconds are left before it needs sending, or
+	/// `None` if nothing is buffered.
+	pub fn poll(&mut self) -> Result<Option<u32>, Error> {
+		if self.buffer.is_empty() {
+			return Ok(None);
+		}
+		let held_ms = (self.now_ms)().wrapping_sub(self.first_write_ms);
+		if held_ms >= self.delay_ms {
+			self.flush()?;
+			Ok(None)
+		} else {
+			Ok(Some(self.delay_ms - held_ms))
+		}
+	}
+
+	/// Send the buffered data now.
+	pub fn flush(&mut self) -> Result<(), Error> {
+		if !self.buffer.is_empty() {
+			debug!("Flushing {} coalesced bytes", self.buffer.len());
+			let result = self.socket.write_all(&self.buffer);
+			self.buffer.clear();
+			result?;
+		}
+		Ok(())
+	}
+
+	/// How many bytes are waiting to be sent.
+	pub fn pending(&self) -> usize {
+		self.buffer.len()
+	}
+}
+
+impl<'s, S, const SIZE: usize> core::fmt::Write for Coalescer<'s, S, SIZE>
+where
+	S: core::ops::Deref<Target = Socket>,
+{
+	fn write_str(&mut self, s: &str) -> core::fmt::Result {
+		match self.write(s.as_bytes()) {
+			Ok(_n) => Ok(()),
+			Err(_e) => Err(core::fmt::Error),
+		}
+	}
+}
+
+impl<'s, S, const SIZE: usize> Drop for Coalescer<'s, S, SIZE>
+where
+	S: core::ops::Deref<Target = Socket>,
+{
+	fn drop(&mut self) {
+		if let Err(e) = self.flush() {
+			warn!("Failed to flush coalesced writes: {:?}", e);
+		}
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,845 @@
+//! # CoAP Observe for nrfxlib
+//!
+//! A device asleep on NB-IoT can't be polled, but a server can still push to
+//! it: the device registers interest in a resource with a CoAP Observe
+//! request (RFC 7641), and the server sends a notification whenever the
+//! resource changes. Those arrive the next time the device is reachable -
+//! which, with PSM or eDRX, is when it next wakes up. A `CoapObserver` makes
+//! the registrations over DTLS and calls a handler for each notification:
+//!
+//! ```ignore
+//! fn connect() -> Result<DtlsSocket, Error> {
+//!     let socket = DtlsSocket::new(PeerVerification::Enabled, &[TAG], Version::Dtls1v2)?;
+//!     socket.connect("coap.example.com", 5684)?;
+//!     Ok(socket)
+//! }
+//!
+//! fn config_changed(notification: &Notification) {
+//!     info!("{} => {:?}", notification.path, notification.payload);
+//! }
+//!
+//! let mut observer: CoapObserver<2> = CoapObserver::new(connect, board::uptime_ms)?;
+//! observer.observe("config/interval", config_changed)?;
+//! loop {
+//!     observer.wait(60_000)?;
+//! }
+//! ```
+//!
+//! DTLS sessions don't survive everything - the modem restarting, the link
+//! going down, the server forgetting the session - and the registrations go
+//! with them. When `poll` finds the socket has failed, or the modem has
+//! restarted, it calls the connect function again and re-registers every
+//! observation (see `set_auto_reregister`). Handlers get a `Notification`
+//! with no `sequence` when the server stops notifying, e.g. on an error
+//! response, and `reregister` tries again on demand.
+//!
+//! Registrations are confirmable, and are retransmitted as RFC 7252 section
+//! 4.2 says until the server answers: first after `ACK_TIMEOUT` (2 s) times
+//! a random factor of 1 to 1.5, then twice as long each time, up to
+//! `MAX_RETRANSMIT` (4) times. Those timeouts are for LTE-M in good
+//! coverage - on NB-IoT, where a round trip can take several seconds, and
+//! at higher coverage enhancement levels, they are scaled up as the socket
+//! timeouts are (see `timeouts::scaled`). If the server still hasn't
+//! answered, or resets the registration, the handler gets a `Notification`
+//! with `Code::EMPTY` and no `sequence`. `poll` and `wait` send the
+//! retransmissions, so call one of them at least every couple of seconds
+//! while a registration is outstanding. Deregistrations are
+//! non-confirmable, and sent once - a notification arriving afterwards is
+//! reset, which cancels the observation too.
+//!
+//! Only what Observe needs is implemented: GET requests with `Uri-Path` and
+//! `Observe` options, and parsing the responses. Confirmable notifications
+//! are acknowledged, and notifications for tokens we don't know are reset,
+//! so the server stops sending them. Notifications are only put in order by
+//! their sequence numbers, not by the 128 second rule of RFC 7641 section
+//! 3.4.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+use crate::dtls::DtlsSocket;
+#[cfg(target_arch = "arm")]
+use crate::Error;
+#[cfg(target_arch = "arm")]
+use crate::Pollable;
+use core::convert::TryFrom;
+#[cfg(target_arch = "arm")]
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// The four kinds of CoAP message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MessageType {
+	/// Must be acknowledged (CON)
+	Confirmable = 0,
+	/// Needn't be acknowledged (NON)
+	NonConfirmable = 1,
+	/// Acknowledges a confirmable message (ACK)
+	Acknowledgement = 2,
+	/// Rejects a message (RST)
+	Reset = 3,
+}
+
+/// A CoAP method or response code, e.g. `2.05` (Content) is `Code(0x45)`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Code(pub u8);
+
+/// A CoAP message, as much of it as Observe needs. Other options are
+/// skipped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Message<'a> {
+	/// CON, NON, ACK or RST
+	pub message_type: MessageType,
+	/// The method or response code
+	pub code: Code,
+	/// Matches an ACK or RST to the message it answers
+	pub message_id: u16,
+	/// Matches a response to its request
+	pub token: &'a [u8],
+	/// The `Observe` option, if present
+	pub observe: Option<u32>,
+	/// The `Content-Format` option, if present
+	pub content_format: Option<u16>,
+	/// Anything after the payload marker
+	pub payload: &'a [u8],
+}
+
+/// A notification from the server, given to a `NotificationHandler`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Notification<'a> {
+	/// The path given to `CoapObserver::observe`
+	pub path: &'static str,
+	/// The response code - `Code::CONTENT`, usually, or `Code::EMPTY` if
+	/// the registration went unanswered or was reset
+	pub code: Code,
+	/// The `Observe` sequence number. `None` means the server has stopped
+	/// sending notifications for this path.
+	pub sequence: Option<u32>,
+	/// The `Content-Format` of the payload, if the server said
+	pub content_format: Option<u16>,
+	/// The resource's new representation
+	pub payload: &'a [u8],
+}
+
+/// Called with each notification for the path it was registered with.
+pub type NotificationHandler = fn(&Notification);
+
+/// Opens a new DTLS socket to the server, and connects it.
+#[cfg(target_arch = "arm")]
+pub type Connector = fn() -> Result<DtlsSocket, Error>;
+
+/// Registers CoAP observations over DTLS, and calls the handlers given for
+/// each notification. Holds up to `OBSERVATIONS` of them. See the module
+/// documentation.
+#[cfg(target_arch = "arm")]
+#[derive(Debug)]
+pub struct CoapObserver<const OBSERVATIONS: usize> {
+	socket: DtlsSocket,
+	connect: Connector,
+	observations: heapless::Vec<Observation, OBSERVATIONS>,
+	next_message_id: u16,
+	/// What `crate::starts` was when we last (re-)connected
+	starts: u32,
+	auto_reregister: bool,
+	now_ms: fn() -> u32,
+}
+
+/// One registration.
+#[cfg(target_arch = "arm")]
+#[derive(Debug, Copy, Clone)]
+struct Observation {
+	path: &'static str,
+	token: [u8; TOKEN_LEN],
+	handler: NotificationHandler,
+	/// The last sequence number seen, so older notifications can be dropped
+	last_sequence: Option<u32>,
+	/// The registration, if the server hasn't answered it yet
+	pending: Option<Pending>,
+}
+
+/// A registration waiting for an answer.
+#[cfg(target_arch = "arm")]
+#[derive(Debug, Copy, Clone)]
+struct Pending {
+	message_id: u16,
+	/// When it was last sent
+	sent_ms: u32,
+	/// How long to wait for an answer before sending it again
+	timeout_ms: u32,
+	/// How many times it has been sent again
+	retransmissions: u8,
+}
+
+/// Builds a message into a buffer.
+#[cfg(target_arch = "arm")]
+struct Writer<'b> {
+	buf: &'b mut [u8],
+	len: usize,
+	/// The last option number written, as options are delta-encoded
+	last_option: u16,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The largest message we send or receive. RFC 7252 suggests 1152 bytes;
+/// Observe payloads are usually much smaller, and this comes off the stack.
+pub const MAX_MESSAGE_LEN: usize = 512;
+
+/// The CoAP version we speak.
+const VERSION: u8 = 1;
+
+/// How long our tokens are.
+#[cfg(target_arch = "arm")]
+const TOKEN_LEN: usize = 4;
+
+/// The longest token allowed.
+const MAX_TOKEN_LEN: usize = 8;
+
+/// Separates the options from the payload.
+const PAYLOAD_MARKER: u8 = 0xFF;
+
+/// The option numbers we use.
+const OPTION_OBSERVE: u16 = 6;
+#[cfg(target_arch = "arm")]
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_CONTENT_FORMAT: u16 = 12;
+
+/// The `Observe` values in a request.
+#[cfg(target_arch = "arm")]
+const OBSERVE_REGISTER: u32 = 0;
+#[cfg(target_arch = "arm")]
+const OBSERVE_DEREGISTER: u32 = 1;
+
+/// How long to wait for a confirmable message to be acknowledged, before
+/// the random factor and any NB-IoT scaling (RFC 7252 section 4.8).
+#[cfg(target_arch = "arm")]
+const ACK_TIMEOUT_MS: u32 = 2_000;
+
+/// How many times a confirmable message is sent again (RFC 7252 section
+/// 4.8).
+#[cfg(target_arch = "arm")]
+const MAX_RETRANSMIT: u8 = 4;
+
+/// Sequence numbers wrap at 2^24, so one more than 2^23 behind is newer.
+#[cfg(target_arch = "arm")]
+const SEQUENCE_HALF_RANGE: u32 = 1 << 23;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl Code {
+	/// Empty message (e.g. an ACK with nothing piggybacked)
+	pub const EMPTY: Code = Code(0x00);
+	/// The GET method
+	pub const GET: Code = Code(0x01);
+	/// 2.05 Content
+	pub const CONTENT: Code = Code(0x45);
+
+	/// The class: 0 for a request, 2 for success, 4 for a client error or 5
+	/// for a server error.
+	pub fn class(self) -> u8 {
+		self.0 >> 5
+	}
+
+	/// The detail, e.g. 5 for 2.05.
+	pub fn detail(self) -> u8 {
+		self.0 & 0x1F
+	}
+
+	/// Is this a success response (2.xx)?
+	pub fn is_success(self) -> bool {
+		self.class() == 2
+	}
+}
+
+impl core::fmt::Display for Code {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(f, "{}.{:02}", self.class(), self.detail())
+	}
+}
+
+impl MessageType {
+	/// Convert the two-bit type field.
+	fn from_bits(bits: u8) -> MessageType {
+		match bits & 0b11 {
+			0 => MessageType::Confirmable,
+			1 => MessageType::NonConfirmable,
+			2 => MessageType::Acknowledgement,
+			_ => MessageType::Reset,
+		}
+	}
+}
+
+impl<'a> Message<'a> {
+	/// Parse a CoAP message. Returns `None` if it isn't one.
+	pub fn parse(data: &'a [u8]) -> Option<Message<'a>> {
+		let (&first, rest) = data.split_first()?;
+		if first >> 6 != VERSION {
+			return None;
+		}
+		let token_len = usize::from(first & 0x0F);
+		if token_len > MAX_TOKEN_LEN {
+			return None;
+		}
+		let code = Code(*rest.first()?);
+		let message_id = u16::from_be_bytes([*rest.get(1)?, *rest.get(2)?]);
+		let token = rest.get(3..3 + token_len)?;
+		let mut options = rest.get(3 + token_len..)?;
+		let mut message = Message {
+			message_type: MessageType::from_bits(first >> 4),
+			code,
+			message_id,
+			token,
+			observe: None,
+			content_format: None,
+			payload: &[],
+		};
+		let mut number = 0u16;
+		while let Some((&byte, tail)) = options.split_first() {
+			if byte == PAYLOAD_MARKER {
+				// A marker with nothing after it is a format error
+				if tail.is_empty() {
+					return None;
+				}
+				message.payload = tail;
+				break;
+			}
+			let (delta, tail) = option_field(byte >> 4, tail)?;
+			let (length, tail) = option_field(byte & 0x0F, tail)?;
+			number = number.checked_add(delta)?;
+			let value = tail.get(0..usize::from(length))?;
+			options = tail.get(usize::from(length)..)?;
+			match number {
+				OPTION_OBSERVE => message.observe = Some(decode_uint(value)?),
+				OPTION_CONTENT_FORMAT => {
+					message.content_format = Some(u16::try_from(decode_uint(value)?).ok()?)
+				}
+				_ => {}
+			}
+		}
+		Some(message)
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl<const OBSERVATIONS: usize> CoapObserver<OBSERVATIONS> {
+	/// Connect to the server with `connect`, with no observations yet. The
+	/// same function is used to reconnect. `now_ms` is a millisecond clock,
+	/// for timing retransmissions.
+	pub fn new(connect: Connector, now_ms: fn() -> u32) -> Result<Self, Error> {
+		let socket = connect()?;
+		// Start the message IDs somewhere unpredictable, if we can
+		let next_message_id = crate::entropy::random_u32().map_or(0, |n| n as u16);
+		Ok(CoapObserver {
+			socket,
+			connect,
+			observations: heapless::Vec::new(),
+			next_message_id,
+			starts: crate::starts(),
+			auto_reregister: true,
+			now_ms,
+		})
+	}
+
+	/// Choose whether `poll` reconnects and re-registers by itself when the
+	/// socket fails or the modem has restarted. It does by default.
+	pub fn set_auto_reregister(&mut self, enabled: bool) {
+		self.auto_reregister = enabled;
+	}
+
+	/// Ask the server to notify us of changes to the resource at `path`
+	/// (e.g. `"config/interval"`), and call `handler` with each
+	/// notification - starting with the current representation, which the
+	/// server sends straight back. Returns `Error::BufferFull` if there are
+	/// already `OBSERVATIONS` of them.
+	pub fn observe(
+		&mut self,
+		path: &'static str,
+		handler: NotificationHandler,
+	) -> Result<(), Error> {
+		let token = self.new_token();
+		let observation = Observation {
+			path,
+			token,
+			handler,
+			last_sequence: None,
+			pending: None,
+		};
+		self.observations
+			.push(observation)
+			.map_err(|_| Error::BufferFull)?;
+		self.register(self.observations.len() - 1)
+	}
+
+	/// Stop observing `path`, and tell the server so. Does nothing if we
+	/// weren't observing it.
+	pub fn cancel(&mut self, path: &str) -> Result<(), Error> {
+		let index = match self.observations.iter().position(|o| o.path == path) {
+			Some(index) => index,
+			None => return Ok(()),
+		};
+		let observation = self.observations.swap_remove(index);
+		let message_id = self.new_message_id();
+		self.send_request(
+			MessageType::NonConfirmable,
+			message_id,
+			&observation.token,
+			observation.path,
+			OBSERVE_DEREGISTER,
+		)
+	}
+
+	/// Connect again (with the function given to `new`), and send every
+	/// registration again. Sequence numbers start afresh, as the server may
+	/// have forgotten them.
+	pub fn reregister(&mut self) -> Result<(), Error> {
+		debug!(
+			"Re-registering {} CoAP observation(s)",
+			self.observations.len()
+		);
+		self.socket = (self.connect)()?;
+		self.starts = crate::starts();
+		for index in 0..self.observations.len() {
+			if let Some(observation) = self.observations.get_mut(index) {
+				observation.last_sequence = None;
+			}
+			self.register(index)?;
+		}
+		Ok(())
+	}
+
+	/// Read whatever has arrived, without waiting, and call the handlers for
+	/// each notification. Then send again any registration the server hasn't
+	/// answered in time. Returns how many notifications were handled - a
+	/// registration given up on counts as one.
+	///
+	/// If the modem has restarted, or the socket fails, this reconnects and
+	/// re-registers first (see `set_auto_reregister`). If that fails, so
+	/// does this, with the socket's error if there was one.
+	pub fn poll(&mut self) -> Result<usize, Error> {
+		if self.auto_reregister && self.starts != crate::starts() {
+			self.reregister()?;
+		}
+		let mut buf = [0u8; MAX_MESSAGE_LEN];
+		let mut handled = 0;
+		loop {
+			let length = match self.socket.recv(&mut buf) {
+				Ok(Some(length)) => length,
+				Ok(None) => break,
+				Err(e) if self.auto_reregister => {
+					debug!("CoAP socket failed: {:?}", e);
+					self.reregister().map_err(|_| e)?;
+					break;
+				}
+				Err(e) => return Err(e),
+			};
+			match buf.get(0..length).and_then(Message::parse) {
+				Some(message) => {
+					if self.handle(&message)? {
+						handled += 1;
+					}
+				}
+				None => debug!("Dropped {} bytes which aren't CoAP", length),
+			}
+		}
+		Ok(handled + self.retransmit()?)
+	}
+
+	/// Wait up to `timeout_ms` for something to arrive, then handle it as
+	/// `poll` does. Returns `Ok(0)` if nothing arrived in time. Returns
+	/// sooner if a registration is due to be sent again before then.
+	pub fn wait(&mut self, timeout_ms: u32) -> Result<usize, Error> {
+		let timeout_ms = match self.next_retransmission_in() {
+			Some(due_ms) if timeout_ms == 0 || due_ms < timeout_ms => due_ms.max(1),
+			_ => timeout_ms,
+		};
+		match crate::raw::wait_readable(&self.socket, timeout_ms) {
+			Ok(()) | Err(Error::TimedOut) => self.poll(),
+			Err(e) => Err(e),
+		}
+	}
+
+	/// The paths being observed.
+	pub fn paths(&self) -> impl Iterator<Item = &'static str> + '_ {
+		self.observations.iter().map(|o| o.path)
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl<const OBSERVATIONS: usize> Pollable for CoapObserver<OBSERVATIONS> {
+	/// Get the underlying socket ID for this observer's DTLS socket.
+	fn get_fd(&self) -> i32 {
+		self.socket.get_fd()
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+impl<const OBSERVATIONS: usize> CoapObserver<OBSERVATIONS> {
+	/// Deal with one message from the server. Returns whether a handler was
+	/// called.
+	fn handle(&mut self, message: &Message) -> Result<bool, Error> {
+		// An ACK or RST answers one of our registrations, by message ID
+		if let MessageType::Acknowledgement | MessageType::Reset = message.message_type {
+			let answered = self.observations.iter().position(|o| {
+				o.pending
+					.is_some_and(|pending| pending.message_id == message.message_id)
+			});
+			if let Some(index) = answered {
+				if let Some(observation) = self.observations.get_mut(index) {
+					observation.pending = None;
+				}
+				if message.message_type == MessageType::Reset {
+					debug!("CoAP registration reset by the server");
+					self.give_up(index);
+					return Ok(true);
+				}
+			}
+		}
+		// An empty ACK just says our registration arrived
+		if message.code == Code::EMPTY {
+			return Ok(false);
+		}
+		let observation = self
+			.observations
+			.iter_mut()
+			.find(|o| o.token[..] == *message.token);
+		let observation = match observation {
+			Some(observation) => observation,
+			None => {
+				debug!("CoAP message for unknown token {:02x?}", message.token);
+				if message.message_type != MessageType::Acknowledgement {
+					self.send_empty(MessageType::Reset, message.message_id)?;
+				}
+				return Ok(false);
+			}
+		};
+		let sequence = if message.code.is_success() {
+			message.observe
+		} else {
+			None
+		};
+		let fresh = match (observation.last_sequence, sequence) {
+			(Some(last), Some(sequence)) => is_newer(last, sequence),
+			_ => true,
+		};
+		let handler = observation.handler;
+		let notification = Notification {
+			path: observation.path,
+			code: message.code,
+			sequence,
+			content_format: message.content_format,
+			payload: message.payload,
+		};
+		// A response with our token answers the registration too
+		observation.pending = None;
+		if fresh {
+			observation.last_sequence = sequence;
+		}
+		if message.message_type == MessageType::Confirmable {
+			self.send_empty(MessageType::Acknowledgement, message.message_id)?;
+		}
+		if !fresh {
+			debug!("Dropped stale CoAP notification {:?}", sequence);
+			return Ok(false);
+		}
+		handler(&notification);
+		Ok(true)
+	}
+
+	/// Send the registration for the observation at `index`, and start
+	/// waiting for the server to answer it.
+	fn register(&mut self, index: usize) -> Result<(), Error> {
+		let observation = *self.observations.get(index).ok_or(Error::BadDataFormat)?;
+		let message_id = self.new_message_id();
+		// RFC 7252 picks the first timeout at random, between ACK_TIMEOUT and
+		// ACK_TIMEOUT * ACK_RANDOM_FACTOR (1.5)
+		let spread_ms = crate::entropy::random_u32().map_or(0, |n| n % (ACK_TIMEOUT_MS / 2));
+		let pending = Pending {
+			message_id,
+			sent_ms: (self.now_ms)(),
+			timeout_ms: crate::timeouts::scaled(ACK_TIMEOUT_MS + spread_ms),
+			retransmissions: 0,
+		};
+		if let Some(observation) = self.observations.get_mut(index) {
+			observation.pending = Some(pending);
+		}
+		self.send_request(
+			MessageType::Confirmable,
+			message_id,
+			&observation.token,
+			observation.path,
+			OBSERVE_REGISTER,
+		)
+	}
+
+	/// Send again every registration which hasn't been answered in time,
+	/// doubling its timeout, and give up on those sent `MAX_RETRANSMIT`
+	/// times already. Returns how many were given up on.
+	fn retransmit(&mut self) -> Result<usize, Error> {
+		let now_ms = (self.now_ms)();
+		let mut given_up = 0;
+		for index in 0..self.observations.len() {
+			let observation = match self.observations.get_mut(index) {
+				Some(observation) => observation,
+				None => break,
+			};
+			let pending = match observation.pending.as_mut() {
+				Some(pending) if now_ms.wrapping_sub(pending.sent_ms) >= pending.timeout_ms => {
+					pending
+				}
+				_ => continue,
+			};
+			if pending.retransmissions >= MAX_RETRANSMIT {
+				debug!("No answer to CoAP registration for {}", observation.path);
+				observation.pending = None;
+				self.give_up(index);
+				given_up += 1;
+				continue;
+			}
+			pending.retransmissions += 1;
+			pending.sent_ms = now_ms;
+			pending.timeout_ms = pending.timeout_ms.saturating_mul(2);
+			let message_id = pending.message_id;
+			let (token, path) = (observation.token, observation.path);
+			self.send_request(
+				MessageType::Confirmable,
+				message_id,
+				&token,
+				path,
+				OBSERVE_REGISTER,
+			)?;
+		}
+		Ok(given_up)
+	}
+
+	/// How long until the next registration is due to be sent again, if
+	/// any is waiting for an answer.
+	fn next_retransmission_in(&self) -> Option<u32> {
+		let now_ms = (self.now_ms)();
+		self.observations
+			.iter()
+			.filter_map(|o| o.pending)
+			.map(|pending| {
+				pending
+					.timeout_ms
+					.saturating_sub(now_ms.wrapping_sub(pending.sent_ms))
+			})
+			.min()
+	}
+
+	/// Tell the handler for the observation at `index` that it isn't
+	/// registered, because the server reset the registration or never
+	/// answered it.
+	fn give_up(&self, index: usize) {
+		if let Some(observation) = self.observations.get(index) {
+			(observation.handler)(&Notification {
+				path: observation.path,
+				code: Code::EMPTY,
+				sequence: None,
+				content_format: None,
+				payload: &[],
+			});
+		}
+	}
+
+	/// Send a GET for `path` with this `Observe` value.
+	fn send_request(
+		&mut self,
+		message_type: MessageType,
+		message_id: u16,
+		token: &[u8],
+		path: &str,
+		observe: u32,
+	) -> Result<(), Error> {
+		let mut buf = [0u8; MAX_MESSAGE_LEN];
+		let mut writer = Writer::new(&mut buf);
+		writer.header(message_type, Code::GET, message_id, token)?;
+		writer.option(OPTION_OBSERVE, &encode_uint(observe))?;
+		for segment in path.split('/').filter(|s| !s.is_empty()) {
+			writer.option(OPTION_URI_PATH, segment.as_bytes())?;
+		}
+		let length = writer.len;
+		debug!("CoAP GET {} (observe {})", path, observe);
+		self.socket.write(buf.get(0..length).unwrap_or(&[]))?;
+		Ok(())
+	}
+
+	/// Send an empty ACK or RST for this message ID.
+	fn send_empty(&mut self, message_type: MessageType, message_id: u16) -> Result<(), Error> {
+		let mut buf = [0u8; 4];
+		let mut writer = Writer::new(&mut buf);
+		writer.header(message_type, Code::EMPTY, message_id, &[])?;
+		self.socket.write(&buf)?;
+		Ok(())
+	}
+
+	fn new_message_id(&mut self) -> u16 {
+		let message_id = self.next_message_id;
+		self.next_message_id = self.next_message_id.wrapping_add(1);
+		message_id
+	}
+
+	/// A token unique among our observations: random if we have an entropy
+	/// source, otherwise from the message IDs.
+	fn new_token(&mut self) -> [u8; TOKEN_LEN] {
+		loop {
+			let value = match crate::entropy::random_u32() {
+				Ok(value) => value,
+				Err(_) => u32::from(self.new_message_id()) << 16 | self.observations.len() as u32,
+			};
+			let token = value.to_be_bytes();
+			if !self.observations.iter().any(|o| o.token == token) {
+				return token;
+			}
+		}
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl<'b> Writer<'b> {
+	fn new(buf: &'b mut [u8]) -> Writer<'b> {
+		Writer {
+			buf,
+			len: 0,
+			last_option: 0,
+		}
+	}
+
+	fn push(&mut self, bytes: &[u8]) -> Result<(), Error> {
+		let end = self.len + bytes.len();
+		self.buf
+			.get_mut(self.len..end)
+			.ok_or(Error::BufferFull)?
+			.copy_from_slice(bytes);
+		self.len = end;
+		Ok(())
+	}
+
+	fn header(
+		&mut self,
+		message_type: MessageType,
+		code: Code,
+		message_id: u16,
+		token: &[u8],
+	) -> Result<(), Error> {
+		let first = VERSION << 6 | (message_type as u8) << 4 | token.len() as u8;
+		let id = message_id.to_be_bytes();
+		self.push(&[first, code.0, id[0], id[1]])?;
+		self.push(token)
+	}
+
+	/// Write an option. They must be written in order of their numbers.
+	fn option(&mut self, number: u16, value: &[u8]) -> Result<(), Error> {
+		let delta = number
+			.checked_sub(self.last_option)
+			.ok_or(Error::UnrecognisedValue)?;
+		let length = u16::try_from(value.len()).map_err(|_| Error::BufferFull)?;
+		self.last_option = number;
+		let (delta_nibble, delta_extended) = option_nibble(delta);
+		let (length_nibble, length_extended) = option_nibble(length);
+		self.push(&[delta_nibble << 4 | length_nibble])?;
+		self.push(&delta_extended)?;
+		self.push(&length_extended)?;
+		self.push(value)
+	}
+}
+
+/// Read an option delta or length, given its nibble and what follows.
+fn option_field(nibble: u8, data: &[u8]) -> Option<(u16, &[u8])> {
+	match nibble {
+		0..=12 => Some((u16::from(nibble), data)),
+		13 => {
+			let (&byte, rest) = data.split_first()?;
+			Some((u16::from(byte) + 13, rest))
+		}
+		14 => {
+			let value = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+			Some((value.checked_add(269)?, data.get(2..)?))
+		}
+		// 15 is reserved for the payload marker
+		_ => None,
+	}
+}
+
+/// Encode an option delta or length as its nibble and extended bytes.
+#[cfg(target_arch = "arm")]
+fn option_nibble(value: u16) -> (u8, heapless::Vec<u8, 2>) {
+	let mut extended = heapless::Vec::new();
+	let nibble = match value {
+		0..=12 => value as u8,
+		13..=268 => {
+			let _ = extended.push((value - 13) as u8);
+			13
+		}
+		_ => {
+			let _ = extended.extend_from_slice(&(value - 269).to_be_bytes());
+			14
+		}
+	};
+	(nibble, extended)
+}
+
+/// Read an unsigned integer option value: big-endian, up to four bytes.
+fn decode_uint(value: &[u8]) -> Option<u32> {
+	if value.len() > 4 {
+		return None;
+	}
+	Some(value.iter().fold(0, |n, &byte| n << 8 | u32::from(byte)))
+}
+
+/// Encode an unsigned integer option value, in as few bytes as it takes
+/// (none, for zero).
+#[cfg(target_arch = "arm")]
+fn encode_uint(value: u32) -> heapless::Vec<u8, 4> {
+	let bytes = value.to_be_bytes();
+	let skip = bytes.iter().take_while(|&&byte| byte == 0).count();
+	let mut encoded = heapless::Vec::new();
+	let _ = encoded.extend_from_slice(bytes.get(skip..).unwrap_or(&[]));
+	encoded
+}
+
+/// Is sequence number `new` newer than `last` (RFC 7641 section 3.4)?
+#[cfg(target_arch = "arm")]
+fn is_newer(last: u32, new: u32) -> bool {
+	(last < new && new - last < SEQUENCE_HALF_RANGE)
+		|| (last > new && last - new > SEQUENCE_HALF_RANGE)
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
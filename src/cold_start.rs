@@ -0,0 +1,355 @@
+//! # Supervised GNSS cold starts for nrfxlib
+//!
+//! With no assistance data, a cold start has to find the satellites on its
+//! own and then download their ephemerides from the sky, which takes at
+//! least a few minutes in the open - and, somewhere the sky is mostly
+//! blocked, may never finish while the GNSS module keeps burning power. A
+//! `ColdStart` keeps an eye on how a start is going, from the PVT frames:
+//! how many satellites are being tracked, and whether the time is known
+//! yet. If neither has improved for `progress_timeout_ms`, it stops the
+//! GNSS module, waits `retry_delay_ms`, and tries again with the next
+//! elevation mask on the list - and after `max_attempts`, or
+//! `total_timeout_ms`, it gives up:
+//!
+//! ```ignore
+//! let config = ColdStartConfig {
+//!     elevation_masks: &[5, 0, 10],
+//!     ..ColdStartConfig::new(now_ms)
+//! };
+//! let mut start = ColdStart::new(&gnss, config);
+//! start.begin(DeleteMask::cold_start())?;
+//! loop {
+//!     match start.poll()? {
+//!         Some(ColdStartEvent::Fix(fix)) => break Some(fix),
+//!         Some(ColdStartEvent::GaveUp) => break None,
+//!         Some(ColdStartEvent::Retrying { attempt, .. }) => info!("GNSS retry {}", attempt),
+//!         None => sleep_ms(1000),
+//!     }
+//! }
+//! ```
+//!
+//! Only the first attempt deletes anything (as `begin` is told); retries keep
+//! whatever the earlier attempts found. `progress` says how the current
+//! attempt is doing, for an application which wants to show it.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::gnss::{DeleteMask, GnssData, GnssSocket, PvtFrame};
+use crate::Error;
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// How a `ColdStart` goes about it.
+#[derive(Debug, Copy, Clone)]
+pub struct ColdStartConfig {
+	/// How long an attempt may go without tracking more satellites, or
+	/// learning the time, before it is abandoned
+	pub progress_timeout_ms: u32,
+	/// How long to leave the GNSS module stopped between attempts
+	pub retry_delay_ms: u32,
+	/// How many attempts to make in all
+	pub max_attempts: u8,
+	/// How long all the attempts may take together
+	pub total_timeout_ms: u32,
+	/// The elevation mask (in degrees) for each attempt. Attempts past the
+	/// end of the list use the last one, and an empty list leaves the mask
+	/// alone.
+	pub elevation_masks: &'static [u8],
+	/// A millisecond counter, which may wrap
+	pub now_ms: fn() -> u32,
+}
+
+/// What `ColdStart::poll` has to report.
+// It is only ever returned, not stored, so the size of `Fix` doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+pub enum ColdStartEvent {
+	/// We have a fix. The GNSS module is left running.
+	Fix(PvtFrame),
+	/// The last attempt got nowhere, so the GNSS module has been stopped,
+	/// and will be started again once `retry_delay_ms` is up
+	Retrying {
+		/// The attempt about to be made, counting from 1
+		attempt: u8,
+		/// The elevation mask it will use, if any
+		elevation_mask: Option<u8>,
+	},
+	/// Every attempt, or all the time allowed, has been used up. The GNSS
+	/// module has been stopped.
+	GaveUp,
+}
+
+/// How the current attempt is going.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ColdStartProgress {
+	/// Which attempt this is, counting from 1 (0 before `begin`)
+	pub attempt: u8,
+	/// The most satellites tracked at once in this attempt
+	pub satellites: usize,
+	/// Whether the GNSS module knows the time yet
+	pub time_known: bool,
+	/// How long since the first attempt began, in milliseconds
+	pub elapsed_ms: u32,
+	/// How long since the satellites or time last improved, in milliseconds
+	pub since_progress_ms: u32,
+}
+
+/// Supervises a cold start. See the module documentation.
+#[derive(Debug)]
+pub struct ColdStart<'g> {
+	gnss: &'g GnssSocket,
+	config: ColdStartConfig,
+	state: State,
+	attempt: u8,
+	started_ms: u32,
+	/// When the current attempt began, or the retry delay did
+	phase_ms: u32,
+	progress_ms: u32,
+	satellites: usize,
+	time_known: bool,
+}
+
+/// Where a `ColdStart` is up to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+	Idle,
+	Running,
+	Waiting,
+	Done,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl ColdStartConfig {
+	/// Three attempts, each abandoned after two minutes without progress,
+	/// 30 seconds apart and 15 minutes in all, leaving the elevation mask
+	/// alone.
+	pub const fn new(now_ms: fn() -> u32) -> ColdStartConfig {
+		ColdStartConfig {
+			progress_timeout_ms: 120_000,
+			retry_delay_ms: 30_000,
+			max_attempts: 3,
+			total_timeout_ms: 900_000,
+			elevation_masks: &[],
+			now_ms,
+		}
+	}
+}
+
+impl<'g> ColdStart<'g> {
+	/// Supervise a cold start of `gnss`, which should have its fix interval
+	/// and use case set already. Nothing happens until `begin`.
+	pub fn new(gnss: &'g GnssSocket, config: ColdStartConfig) -> ColdStart<'g> {
+		ColdStart {
+			gnss,
+			config,
+			state: State::Idle,
+			attempt: 0,
+			started_ms: 0,
+			phase_ms: 0,
+			progress_ms: 0,
+			satellites: 0,
+			time_known: false,
+		}
+	}
+
+	/// Start the first attempt, deleting what `delete_mask` says (usually
+	/// `DeleteMask::cold_start()`).
+	pub fn begin(&mut self, delete_mask: DeleteMask) -> Result<(), Error> {
+		self.started_ms = (self.config.now_ms)();
+		self.attempt = 1;
+		self.start_attempt(delete_mask)
+	}
+
+	/// Read whatever the GNSS module has sent, and move things along: a fix
+	/// ends the start, and an attempt which has stopped making progress is
+	/// abandoned. Call this at least every second or so while the start is
+	/// running. Returns `None` when there is nothing to report.
+	pub fn poll(&mut self) -> Result<Option<ColdStartEvent>, Error> {
+		let now_ms = (self.config.now_ms)();
+		match self.state {
+			State::Idle | State::Done => return Ok(None),
+			State::Waiting => {
+				if self.out_of_time(now_ms) {
+					return self.give_up();
+				}
+				if now_ms.wrapping_sub(self.phase_ms) >= self.config.retry_delay_ms {
+					self.start_attempt(DeleteMask::new())?;
+				}
+				return Ok(None);
+			}
+			State::Running => {}
+		}
+		while let Some(data) = self.gnss.get_fix()? {
+			let pvt = match data {
+				GnssData::Position(ref frame) => PvtFrame::from(frame),
+				_ => continue,
+			};
+			if pvt.has_fix() {
+				debug!(
+					"Cold start fix on attempt {} after {} ms",
+					self.attempt,
+					now_ms.wrapping_sub(self.started_ms)
+				);
+				self.state = State::Done;
+				return Ok(Some(ColdStartEvent::Fix(pvt)));
+			}
+			self.note_progress(&pvt, now_ms);
+		}
+		if self.out_of_time(now_ms) {
+			return self.give_up();
+		}
+		if now_ms.wrapping_sub(self.progress_ms) < self.config.progress_timeout_ms {
+			return Ok(None);
+		}
+		debug!(
+			"Cold start attempt {} stalled at {} satellites (time known: {})",
+			self.attempt, self.satellites, self.time_known
+		);
+		if self.attempt >= self.config.max_attempts {
+			return self.give_up();
+		}
+		self.gnss.stop()?;
+		self.attempt += 1;
+		self.state = State::Waiting;
+		self.phase_ms = now_ms;
+		Ok(Some(ColdStartEvent::Retrying {
+			attempt: self.attempt,
+			elevation_mask: self.elevation_mask(),
+		}))
+	}
+
+	/// Stop the GNSS module and the supervision, e.g. because the
+	/// application no longer needs a fix.
+	pub fn abort(&mut self) -> Result<(), Error> {
+		let running = self.state == State::Running;
+		self.state = State::Done;
+		if running {
+			self.gnss.stop()?;
+		}
+		Ok(())
+	}
+
+	/// How the current attempt is going.
+	pub fn progress(&self) -> ColdStartProgress {
+		let now_ms = (self.config.now_ms)();
+		let since = |then_ms: u32| {
+			if self.attempt == 0 {
+				0
+			} else {
+				now_ms.wrapping_sub(then_ms)
+			}
+		};
+		ColdStartProgress {
+			attempt: self.attempt,
+			satellites: self.satellites,
+			time_known: self.time_known,
+			elapsed_ms: since(self.started_ms),
+			since_progress_ms: since(self.progress_ms),
+		}
+	}
+
+	/// Has the start finished, with a fix or not?
+	pub fn is_done(&self) -> bool {
+		self.state == State::Done
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl<'g> ColdStart<'g> {
+	/// Set the elevation mask for this attempt, and start the GNSS module.
+	fn start_attempt(&mut self, delete_mask: DeleteMask) -> Result<(), Error> {
+		if let Some(degrees) = self.elevation_mask() {
+			self.gnss.set_elevation_mask(degrees)?;
+		}
+		debug!(
+			"Cold start attempt {} (elevation mask {:?})",
+			self.attempt,
+			self.elevation_mask()
+		);
+		self.gnss.start(delete_mask)?;
+		let now_ms = (self.config.now_ms)();
+		self.state = State::Running;
+		self.phase_ms = now_ms;
+		self.progress_ms = now_ms;
+		self.satellites = 0;
+		self.time_known = false;
+		Ok(())
+	}
+
+	/// The elevation mask for the current attempt, if there is one.
+	fn elevation_mask(&self) -> Option<u8> {
+		let masks = self.config.elevation_masks;
+		let index = usize::from(self.attempt.saturating_sub(1));
+		masks.get(index).or_else(|| masks.last()).copied()
+	}
+
+	/// Note any improvement this frame shows.
+	fn note_progress(&mut self, pvt: &PvtFrame, now_ms: u32) {
+		let time_known = pvt.datetime.year != 0;
+		if pvt.satellites.len() > self.satellites || (time_known && !self.time_known) {
+			self.progress_ms = now_ms;
+		}
+		self.satellites = core::cmp::max(self.satellites, pvt.satellites.len());
+		self.time_known |= time_known;
+	}
+
+	/// Have all the attempts together run out of time?
+	fn out_of_time(&self, now_ms: u32) -> bool {
+		now_ms.wrapping_sub(self.started_ms) >= self.config.total_timeout_ms
+	}
+
+	/// Stop for good, stopping the GNSS module if it is running.
+	fn give_up(&mut self) -> Result<Option<ColdStartEvent>, Error> {
+		debug!("Cold start gave up after {} attempts", self.attempt);
+		let running = self.state == State::Running;
+		self.state = State::Done;
+		if running {
+			self.gnss.stop()?;
+		}
+		Ok(Some(ColdStartEvent::GaveUp))
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,326 @@
+//! # Compressed uploads for nrfxlib
+//!
+//! Over NB-IoT every byte sent costs money and energy, and logs or waveform
+//! data usually compress well. `CompressedWriter` sits in front of any
+//! connected socket and compresses what is written to it on the way out:
+//!
+//! ```ignore
+//! use nrfxlib::compress::CompressedWriter;
+//! let mut upload = CompressedWriter::new(&socket);
+//! for block in log.blocks() {
+//!     upload.write(block)?;
+//! }
+//! let sent = upload.finish()?;
+//! ```
+//!
+//! The format is heatshrink (LZSS), with an 8 bit window and a 4 bit
+//! lookahead - decompress it on the server with `heatshrink -d -w 8 -l 4`,
+//! or any heatshrink library set up the same way. It was picked over
+//! DEFLATE as the encoder needs under 300 bytes of RAM (and the
+//! `CompressedWriter` another `CHUNK_SIZE`), and no tables. Finding matches
+//! is a plain search of the window, so compressing costs a few thousand
+//! comparisons per byte - fine at the speed NB-IoT sends, but not free.
+//!
+//! `Encoder` is the compressor on its own, for output which goes somewhere
+//! other than a socket.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::raw::Socket;
+use crate::Error;
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A streaming heatshrink compressor. See the module documentation.
+#[derive(Debug, Clone)]
+pub struct Encoder {
+	/// The last `WINDOW_SIZE` bytes consumed, as a ring
+	history: [u8; WINDOW_SIZE],
+	/// Where the next byte goes in `history`
+	history_pos: usize,
+	/// How much of `history` is filled
+	history_len: usize,
+	/// Bytes waiting to be encoded
+	lookahead: [u8; LOOKAHEAD_SIZE],
+	lookahead_len: usize,
+	/// Output bits not yet making a whole byte, in the bottom bits
+	bits: u32,
+	bit_count: u8,
+}
+
+/// Compresses everything written to it, and sends it on a socket. See the
+/// module documentation.
+#[derive(Debug)]
+pub struct CompressedWriter<'s> {
+	socket: &'s Socket,
+	encoder: Encoder,
+	chunk: heapless::Vec<u8, CHUNK_SIZE>,
+	written: usize,
+	sent: usize,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// How many bytes of compressed data `CompressedWriter` collects before
+/// sending them.
+pub const CHUNK_SIZE: usize = 128;
+
+/// The heatshrink window size, as a power of two (`-w`).
+pub const WINDOW_BITS: u8 = 8;
+
+/// The heatshrink lookahead size, as a power of two (`-l`).
+pub const LOOKAHEAD_BITS: u8 = 4;
+
+const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
+
+const LOOKAHEAD_SIZE: usize = 1 << LOOKAHEAD_BITS;
+
+/// A back-reference takes 13 bits, and a literal 9, so only matches of two
+/// bytes or more are worth it.
+const MIN_MATCH: usize = 2;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl Encoder {
+	/// Create a compressor with an empty window.
+	pub const fn new() -> Encoder {
+		Encoder {
+			history: [0u8; WINDOW_SIZE],
+			history_pos: 0,
+			history_len: 0,
+			lookahead: [0u8; LOOKAHEAD_SIZE],
+			lookahead_len: 0,
+			bits: 0,
+			bit_count: 0,
+		}
+	}
+
+	/// Compress `input`, passing each byte of output to `emit` as it is
+	/// finished. Up to `LOOKAHEAD_SIZE` bytes of input are held back, for
+	/// the next call (or `finish`) to match against. Stops at the first
+	/// error `emit` returns.
+	#[allow(clippy::indexing_slicing)] // `step` leaves room in the lookahead
+	pub fn feed<F>(&mut self, input: &[u8], mut emit: F) -> Result<(), Error>
+	where
+		F: FnMut(u8) -> Result<(), Error>,
+	{
+		for &byte in input {
+			if self.lookahead_len == LOOKAHEAD_SIZE {
+				self.step(&mut emit)?;
+			}
+			self.lookahead[self.lookahead_len] = byte;
+			self.lookahead_len += 1;
+		}
+		Ok(())
+	}
+
+	/// Compress whatever input is held back, and pad the output to a whole
+	/// byte. The encoder is then ready to start a new stream.
+	pub fn finish<F>(&mut self, mut emit: F) -> Result<(), Error>
+	where
+		F: FnMut(u8) -> Result<(), Error>,
+	{
+		while self.lookahead_len > 0 {
+			self.step(&mut emit)?;
+		}
+		if self.bit_count > 0 {
+			let padding = 8 - self.bit_count;
+			self.push_bits(0, padding, &mut emit)?;
+		}
+		*self = Encoder::new();
+		Ok(())
+	}
+}
+
+impl Default for Encoder {
+	fn default() -> Encoder {
+		Encoder::new()
+	}
+}
+
+impl<'s> CompressedWriter<'s> {
+	/// Compress everything written into a new stream on `socket`, which
+	/// should already be connected.
+	pub fn new(socket: &'s Socket) -> CompressedWriter<'s> {
+		CompressedWriter {
+			socket,
+			encoder: Encoder::new(),
+			chunk: heapless::Vec::new(),
+			written: 0,
+			sent: 0,
+		}
+	}
+
+	/// Compress `data`, sending the output on the socket whenever
+	/// `CHUNK_SIZE` bytes of it are ready. Blocks as `Socket::write` does.
+	pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+		let CompressedWriter {
+			socket,
+			encoder,
+			chunk,
+			sent,
+			..
+		} = self;
+		encoder.feed(data, |byte| collect(socket, chunk, sent, byte))?;
+		self.written += data.len();
+		Ok(())
+	}
+
+	/// End the stream, sending whatever compressed data is left. Returns
+	/// how many bytes were sent in all.
+	pub fn finish(mut self) -> Result<usize, Error> {
+		let CompressedWriter {
+			socket,
+			encoder,
+			chunk,
+			sent,
+			..
+		} = &mut self;
+		encoder.finish(|byte| collect(socket, chunk, sent, byte))?;
+		socket.write_all(chunk)?;
+		*sent += chunk.len();
+		debug!("Compressed {} bytes to {}", self.written, self.sent);
+		Ok(self.sent)
+	}
+
+	/// How many bytes have been written so far, before compression.
+	pub fn written(&self) -> usize {
+		self.written
+	}
+
+	/// How many compressed bytes have been sent so far.
+	pub fn sent(&self) -> usize {
+		self.sent
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl Encoder {
+	/// Encode the start of the lookahead, as a back-reference if it matches
+	/// something in the window, or a literal if not.
+	#[allow(clippy::indexing_slicing)] // positions are kept within the buffers
+	fn step<F>(&mut self, emit: &mut F) -> Result<(), Error>
+	where
+		F: FnMut(u8) -> Result<(), Error>,
+	{
+		let (offset, length) = self.longest_match();
+		let consumed = if length >= MIN_MATCH {
+			// Tag 0, then the offset and length less one
+			self.push_bits(0, 1, emit)?;
+			self.push_bits((offset - 1) as u32, WINDOW_BITS, emit)?;
+			self.push_bits((length - 1) as u32, LOOKAHEAD_BITS, emit)?;
+			length
+		} else {
+			// Tag 1, then the byte
+			self.push_bits(0x100 | u32::from(self.lookahead[0]), 9, emit)?;
+			1
+		};
+		for idx in 0..consumed {
+			self.history[self.history_pos] = self.lookahead[idx];
+			self.history_pos = (self.history_pos + 1) % WINDOW_SIZE;
+		}
+		self.history_len = core::cmp::min(self.history_len + consumed, WINDOW_SIZE);
+		self.lookahead.copy_within(consumed..self.lookahead_len, 0);
+		self.lookahead_len -= consumed;
+		Ok(())
+	}
+
+	/// Find the longest match for the start of the lookahead, as how far
+	/// back it starts and how long it is. A match may run on into the
+	/// lookahead itself, as the decoder copies one byte at a time.
+	#[allow(clippy::indexing_slicing)] // positions are kept within the buffers
+	fn longest_match(&self) -> (usize, usize) {
+		let mut best = (0, 0);
+		for offset in 1..=self.history_len {
+			let start = (self.history_pos + WINDOW_SIZE - offset) % WINDOW_SIZE;
+			let length = (0..self.lookahead_len)
+				.take_while(|&idx| {
+					let earlier = if idx < offset {
+						self.history[(start + idx) % WINDOW_SIZE]
+					} else {
+						self.lookahead[idx - offset]
+					};
+					earlier == self.lookahead[idx]
+				})
+				.count();
+			if length > best.1 {
+				best = (offset, length);
+				if length == self.lookahead_len {
+					break;
+				}
+			}
+		}
+		best
+	}
+
+	/// Add the bottom `count` bits of `value` to the output, most
+	/// significant first, emitting each byte as it fills.
+	fn push_bits<F>(&mut self, value: u32, count: u8, emit: &mut F) -> Result<(), Error>
+	where
+		F: FnMut(u8) -> Result<(), Error>,
+	{
+		self.bits = (self.bits << count) | (value & ((1 << count) - 1));
+		self.bit_count += count;
+		while self.bit_count >= 8 {
+			self.bit_count -= 8;
+			emit((self.bits >> self.bit_count) as u8)?;
+		}
+		self.bits &= (1 << self.bit_count) - 1;
+		Ok(())
+	}
+}
+
+/// Add a byte of output to `chunk`, sending the chunk first if it is full.
+fn collect(
+	socket: &Socket,
+	chunk: &mut heapless::Vec<u8, CHUNK_SIZE>,
+	sent: &mut usize,
+	byte: u8,
+) -> Result<(), Error> {
+	if chunk.is_full() {
+		socket.write_all(chunk)?;
+		*sent += chunk.len();
+		chunk.clear();
+	}
+	// Can't fail, as there is room now
+	chunk.push(byte).map_err(|_| Error::BufferFull)
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,334 @@
+//! # Staged credential changes for nrfxlib
+//!
+//! The modem only changes its TLS credentials while LTE is off, so a
+//! certificate rotation pushed from the cloud - which arrives while we are
+//! very much online - would otherwise fail with
+//! `StoreError::NotAllowedWhileActive`. A `CredentialQueue` holds the
+//! changes instead, and makes them at the next safe moment:
+//!
+//! ```ignore
+//! fn rotated(change: &StagedChange, result: Result<(), Error>) {
+//!     info!("{:?}: {:?}", change, result);
+//! }
+//!
+//! let mut queue: CredentialQueue<4> = CredentialQueue::new(ApplyWindow::FlightModeCycle);
+//! queue.store_certificate(TAG, CredentialType::ClientCertificate, &new_cert, Some(rotated))?;
+//! queue.store_certificate(TAG, CredentialType::ClientPrivateKey, &new_key, Some(rotated))?;
+//! // Later, from the main loop
+//! queue.poll()?;
+//! ```
+//!
+//! If LTE is already off, a change is made straight away. Otherwise it is
+//! held until `poll` finds a safe window - which, with
+//! `ApplyWindow::FlightModeCycle`, is right then: `poll` enters flight mode,
+//! makes every staged change, and puts the modem back (re-applying what
+//! `modem::on` sets up, and waiting for it to register again). With `ApplyWindow::NextOffPeriod`, `poll` waits until
+//! the application takes LTE down itself - for a planned power-off, or with
+//! `modem::suspend` - so call it then. Either way, the completion handler
+//! is called with the result of each change as it is made.
+//!
+//! The queue only borrows the contents, so they must stay put until the
+//! change has been made.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::modem::{FlightModeGuard, FunctionalMode};
+use crate::tls_credentials::{CredentialType, StoreError};
+use crate::Error;
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// When `CredentialQueue::poll` may make the staged changes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ApplyWindow {
+	/// Straight away, dropping into flight mode for as long as it takes
+	FlightModeCycle,
+	/// Only once LTE is off anyway
+	NextOffPeriod,
+}
+
+/// A change to the modem's credential storage.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StagedChange<'a> {
+	/// Store `content`, as `tls_credentials::store_certificate` does
+	Store {
+		/// The security tag
+		tag: u32,
+		/// What kind of credential it is
+		kind: CredentialType,
+		/// The credential itself
+		content: &'a str,
+	},
+	/// Delete a credential, as `tls_credentials::delete` does
+	Delete {
+		/// The security tag
+		tag: u32,
+		/// What kind of credential it is
+		kind: CredentialType,
+	},
+}
+
+/// Called with each staged change once it has been made (or has failed).
+pub type CompletionHandler = fn(&StagedChange, Result<(), Error>);
+
+/// Holds credential changes until LTE is off. See the module documentation.
+#[derive(Debug)]
+pub struct CredentialQueue<'a, const N: usize> {
+	window: ApplyWindow,
+	pending: heapless::Vec<Staged<'a>, N>,
+}
+
+/// A change waiting to be made, and who to tell.
+#[derive(Debug)]
+struct Staged<'a> {
+	change: StagedChange<'a>,
+	on_complete: Option<CompletionHandler>,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl<'a, const N: usize> CredentialQueue<'a, N> {
+	/// Create an empty queue, holding up to `N` changes, which `poll` makes
+	/// in the given window.
+	pub const fn new(window: ApplyWindow) -> CredentialQueue<'a, N> {
+		CredentialQueue {
+			window,
+			pending: heapless::Vec::new(),
+		}
+	}
+
+	/// Store a certificate or key (in PEM format) under the given security
+	/// tag, now if LTE is off or once `poll` finds a window if not. Content
+	/// with a `"` in gives `Error::BadDataFormat`, and a full queue
+	/// `Error::BufferFull`, without staging anything.
+	pub fn store_certificate(
+		&mut self,
+		tag: u32,
+		kind: CredentialType,
+		content: &'a str,
+		on_complete: Option<CompletionHandler>,
+	) -> Result<(), Error> {
+		if content.contains('"') {
+			return Err(Error::BadDataFormat);
+		}
+		self.stage(&[StagedChange::Store { tag, kind, content }], on_complete)
+	}
+
+	/// Store a pre-shared key (in hex) and its identity under the given
+	/// security tag, as `store_certificate` does. This takes two places in
+	/// the queue, and `on_complete` is called for each.
+	pub fn store_psk(
+		&mut self,
+		tag: u32,
+		psk_hex: &'a str,
+		identity: &'a str,
+		on_complete: Option<CompletionHandler>,
+	) -> Result<(), Error> {
+		if psk_hex.is_empty()
+			|| !psk_hex.bytes().all(|b| b.is_ascii_hexdigit())
+			|| identity.contains('"')
+		{
+			return Err(Error::BadDataFormat);
+		}
+		let changes = [
+			StagedChange::Store {
+				tag,
+				kind: CredentialType::Psk,
+				content: psk_hex,
+			},
+			StagedChange::Store {
+				tag,
+				kind: CredentialType::PskIdentity,
+				content: identity,
+			},
+		];
+		self.stage(&changes, on_complete)
+	}
+
+	/// Delete a stored credential, as `store_certificate` stores one.
+	pub fn delete(
+		&mut self,
+		tag: u32,
+		kind: CredentialType,
+		on_complete: Option<CompletionHandler>,
+	) -> Result<(), Error> {
+		self.stage(&[StagedChange::Delete { tag, kind }], on_complete)
+	}
+
+	/// Make the staged changes, if this is a safe window (see
+	/// `ApplyWindow`). Returns how many were made, successfully or not.
+	///
+	/// If the modem turns LTE back on part way through, the changes not yet
+	/// made stay queued for next time.
+	pub fn poll(&mut self) -> Result<usize, Error> {
+		if self.pending.is_empty() {
+			return Ok(0);
+		}
+		if current_mode()?.is_lte_off() {
+			return Ok(self.apply_all());
+		}
+		match self.window {
+			ApplyWindow::FlightModeCycle => {
+				debug!(
+					"Entering flight mode for {} credential changes",
+					self.pending.len()
+				);
+				let guard = FlightModeGuard::new()?;
+				let applied = self.apply_all();
+				guard.restore()?;
+				Ok(applied)
+			}
+			ApplyWindow::NextOffPeriod => Ok(0),
+		}
+	}
+
+	/// How many changes are waiting.
+	pub fn pending(&self) -> usize {
+		self.pending.len()
+	}
+
+	/// When `poll` makes the changes.
+	pub fn window(&self) -> ApplyWindow {
+		self.window
+	}
+
+	/// Change when `poll` makes the changes.
+	pub fn set_window(&mut self, window: ApplyWindow) {
+		self.window = window;
+	}
+}
+
+impl<'a> StagedChange<'a> {
+	/// The security tag the change is to.
+	pub fn tag(&self) -> u32 {
+		match *self {
+			StagedChange::Store { tag, .. } | StagedChange::Delete { tag, .. } => tag,
+		}
+	}
+
+	/// The kind of credential the change is to.
+	pub fn kind(&self) -> CredentialType {
+		match *self {
+			StagedChange::Store { kind, .. } | StagedChange::Delete { kind, .. } => kind,
+		}
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl<'a, const N: usize> CredentialQueue<'a, N> {
+	/// Queue `changes`, all or none of them. If nothing is ahead of them and
+	/// the modem is known to have LTE off, they are made straight away.
+	fn stage(
+		&mut self,
+		changes: &[StagedChange<'a>],
+		on_complete: Option<CompletionHandler>,
+	) -> Result<(), Error> {
+		if self.pending.capacity() - self.pending.len() < changes.len() {
+			return Err(Error::BufferFull);
+		}
+		for &change in changes {
+			// Can't fail, as we checked there was room
+			self.pending
+				.push(Staged {
+					change,
+					on_complete,
+				})
+				.map_err(|_| Error::BufferFull)?;
+		}
+		let lte_off = matches!(
+			crate::modem::last_functional_mode(),
+			Some(mode) if mode.is_lte_off()
+		);
+		if lte_off && self.pending.len() == changes.len() {
+			self.apply_all();
+		}
+		Ok(())
+	}
+
+	/// Make the staged changes in order, telling each handler how it went,
+	/// until they are all done or the modem says LTE is back on. Returns
+	/// how many were made.
+	fn apply_all(&mut self) -> usize {
+		let mut applied = 0;
+		while let Some(staged) = self.pending.first() {
+			let result = apply(&staged.change);
+			if matches!(
+				result,
+				Err(Error::CredentialStore(StoreError::NotAllowedWhileActive))
+			) {
+				debug!(
+					"LTE is back on, {} credential changes left",
+					self.pending.len()
+				);
+				break;
+			}
+			let staged = self.pending.remove(0);
+			if let Some(on_complete) = staged.on_complete {
+				on_complete(&staged.change, result);
+			}
+			applied += 1;
+		}
+		applied
+	}
+}
+
+/// Make one change.
+fn apply(change: &StagedChange) -> Result<(), Error> {
+	match *change {
+		StagedChange::Store { tag, kind, content } => {
+			crate::tls_credentials::write_credential(tag, kind, content)
+		}
+		StagedChange::Delete { tag, kind } => crate::tls_credentials::delete(tag, kind),
+	}
+}
+
+/// The functional mode, as last seen if we know it, or asked for if not.
+fn current_mode() -> Result<FunctionalMode, Error> {
+	match crate::modem::last_functional_mode() {
+		Some(mode) => Ok(mode),
+		None => crate::modem::get_functional_mode(),
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
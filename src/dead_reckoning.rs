@@ -0,0 +1,224 @@
+//! # GNSS dead reckoning for nrfxlib
+//!
+//! Estimates the position between GNSS fixes by carrying the last fix
+//! forward along its speed and heading. The estimate's accuracy gets worse
+//! the longer it has been since the fix, so a tracker reporting more often
+//! than it gets fixes has something principled to send - and can stop
+//! sending when the estimate is too old to be useful.
+//!
+//! Only built with the `dead-reckoning` feature. For example:
+//!
+//! ```ignore
+//! let mut dr = dead_reckoning::DeadReckoning::new(2.0, 120_000);
+//! if let Some(fix) = gnss.get_fix()? {
+//!     dr.update(&fix, board::uptime_ms());
+//! }
+//! if let Some(estimate) = dr.estimate(board::uptime_ms()) {
+//!     report(estimate.latitude, estimate.longitude, estimate.accuracy);
+//! }
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::gnss::GnssData;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Carries the last valid GNSS fix forward in time.
+#[derive(Debug, Clone)]
+pub struct DeadReckoning {
+	last_fix: Option<Fix>,
+	growth_m_per_s: f32,
+	max_age_ms: u32,
+}
+
+/// An estimated position.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Estimate {
+	/// Latitude, in degrees
+	pub latitude: f64,
+	/// Longitude, in degrees
+	pub longitude: f64,
+	/// Altitude, in metres - just the altitude of the last fix
+	pub altitude: f32,
+	/// Accuracy (2D 1-sigma), in metres
+	pub accuracy: f32,
+	/// How long it has been since the fix this is based on, in milliseconds.
+	/// Zero means this is the fix itself.
+	pub age_ms: u32,
+}
+
+/// The parts of a fix we need.
+#[derive(Debug, Copy, Clone)]
+struct Fix {
+	latitude: f64,
+	longitude: f64,
+	altitude: f32,
+	accuracy: f32,
+	speed: f32,
+	heading: f32,
+	time_ms: u32,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// Below this speed (in metres per second) the heading is mostly noise, so we
+/// assume we're standing still.
+const MIN_SPEED_M_PER_S: f32 = 0.5;
+
+/// The mean radius of the Earth, in metres.
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Stop before the poles, where longitude stops meaning much.
+const MAX_LATITUDE: f64 = 89.9;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl DeadReckoning {
+	/// Create a new estimator, with no fix yet.
+	///
+	/// * `growth_m_per_s` - how much worse (in metres) the accuracy gets for
+	///   each second since the fix. Something like the largest change in
+	///   speed you expect over a second.
+	/// * `max_age_ms` - how long after a fix to keep giving estimates.
+	pub fn new(growth_m_per_s: f32, max_age_ms: u32) -> DeadReckoning {
+		DeadReckoning {
+			last_fix: None,
+			growth_m_per_s,
+			max_age_ms,
+		}
+	}
+
+	/// Give the estimator some GNSS data, received at `now_ms`. Returns true
+	/// if it was a valid fix, which replaces the one we had.
+	pub fn update(&mut self, data: &GnssData, now_ms: u32) -> bool {
+		match data {
+			GnssData::Position(pvt) if data.is_valid() => {
+				self.last_fix = Some(Fix {
+					latitude: pvt.latitude,
+					longitude: pvt.longitude,
+					altitude: pvt.altitude,
+					accuracy: pvt.accuracy,
+					speed: pvt.speed,
+					heading: pvt.heading,
+					time_ms: now_ms,
+				});
+				true
+			}
+			_ => false,
+		}
+	}
+
+	/// Estimate where we are at `now_ms`. Returns `None` if we haven't had a
+	/// fix, or the last one is more than `max_age_ms` old.
+	pub fn estimate(&self, now_ms: u32) -> Option<Estimate> {
+		let fix = self.last_fix?;
+		let age_ms = now_ms.wrapping_sub(fix.time_ms);
+		if age_ms > self.max_age_ms {
+			return None;
+		}
+		let age_s = age_ms as f32 / 1000.0;
+		let (latitude, longitude) = if fix.speed < MIN_SPEED_M_PER_S {
+			(fix.latitude, fix.longitude)
+		} else {
+			let distance_m = f64::from(fix.speed * age_s);
+			let (sin_heading, cos_heading) = sin_cos(f64::from(fix.heading).to_radians());
+			let north_m = distance_m * cos_heading;
+			let east_m = distance_m * sin_heading;
+			let latitude = fix.latitude + (north_m / EARTH_RADIUS_M).to_degrees();
+			let latitude = latitude.clamp(-MAX_LATITUDE, MAX_LATITUDE);
+			let (_, cos_latitude) = sin_cos(latitude.to_radians());
+			let longitude = wrap_longitude(
+				fix.longitude + (east_m / (EARTH_RADIUS_M * cos_latitude)).to_degrees(),
+			);
+			(latitude, longitude)
+		};
+		Some(Estimate {
+			latitude,
+			longitude,
+			altitude: fix.altitude,
+			accuracy: fix.accuracy + (self.growth_m_per_s * age_s),
+			age_ms,
+		})
+	}
+
+	/// Forget the last fix.
+	pub fn reset(&mut self) {
+		self.last_fix = None;
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Keep a longitude within -180..=180 degrees.
+fn wrap_longitude(longitude: f64) -> f64 {
+	if longitude > 180.0 {
+		longitude - 360.0
+	} else if longitude < -180.0 {
+		longitude + 360.0
+	} else {
+		longitude
+	}
+}
+
+/// The sine and cosine of an angle in radians. `core` doesn't have them, and
+/// a few terms of the Taylor series are plenty once the angle has been
+/// reduced to within 45 degrees of zero.
+fn sin_cos(angle: f64) -> (f64, f64) {
+	let quarter_turns = angle / core::f64::consts::FRAC_PI_2;
+	// Round to the nearest quarter turn
+	let quadrant = if quarter_turns >= 0.0 {
+		(quarter_turns + 0.5) as i64
+	} else {
+		(quarter_turns - 0.5) as i64
+	};
+	let x = angle - (quadrant as f64 * core::f64::consts::FRAC_PI_2);
+	let x2 = x * x;
+	let sin = x * (1.0 - x2 / 6.0 * (1.0 - x2 / 20.0 * (1.0 - x2 / 42.0 * (1.0 - x2 / 72.0))));
+	let x4 = x2 * x2;
+	let cos =
+		1.0 - x2 / 2.0 + x4 / 24.0 * (1.0 - x2 / 30.0 * (1.0 - x2 / 56.0 * (1.0 - x2 / 90.0)));
+	match quadrant.rem_euclid(4) {
+		0 => (sin, cos),
+		1 => (cos, -sin),
+		2 => (-sin, -cos),
+		_ => (-cos, sin),
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
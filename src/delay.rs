@@ -0,0 +1,140 @@
+//! # Delays for nrfxlib
+//!
+//! The crate has to wait in a few places where the modem gives it nothing to
+//! poll on: libmodem's own timed waits (`nrf_modem_os_timedwait`), and
+//! `modem::wait_for_lte` between checks of its AT socket. By default these
+//! spin (`cortex_m::asm::delay`, assuming 64 MHz) or sleep until the next
+//! event (`wfe`). Give `set_provider` (or `NrfxlibBuilder::delay_provider`)
+//! a `DelayProvider`, and they wait on that instead - a SysTick delay, an
+//! RTIC monotonic or an Embassy timer, say - so the rest of the system knows
+//! we're idle:
+//!
+//! ```ignore
+//! struct SysTickDelay;
+//!
+//! impl DelayProvider for SysTickDelay {
+//!     fn delay_ms(&self, ms: u32) {
+//!         let start = now_ms();
+//!         while now_ms().wrapping_sub(start) < ms {
+//!             cortex_m::asm::wfi();
+//!         }
+//!     }
+//! }
+//!
+//! static DELAY: SysTickDelay = SysTickDelay;
+//! nrfxlib::delay::set_provider(Some(&DELAY));
+//! ```
+//!
+//! Waits are still broken into chunks of at most
+//! `watchdog::FEED_INTERVAL_MS` while a watchdog feeder is set.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Something which can block for a while.
+pub trait DelayProvider: Sync {
+	/// Return after at least `ms` milliseconds. This is called from inside
+	/// libmodem calls, so it must not call back into this crate.
+	fn delay_ms(&self, ms: u32);
+}
+
+/// Somewhere to keep the provider, which we can share with libmodem's waits.
+type WrappedProvider = Mutex<RefCell<Option<&'static dyn DelayProvider>>>;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// How long `idle` waits with a provider, before its caller checks again.
+pub const IDLE_INTERVAL_MS: u32 = 10;
+
+/// The nRF9160 runs at 64 MHz, which is what the spinning delay assumes.
+const CYCLES_PER_MS: u32 = 64_000;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The provider given to `set_provider`.
+static PROVIDER: WrappedProvider = Mutex::new(RefCell::new(None));
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Set what the crate waits on, or `None` to go back to spinning.
+pub fn set_provider(provider: Option<&'static dyn DelayProvider>) {
+	cortex_m::interrupt::free(|cs| {
+		*PROVIDER.borrow(cs).borrow_mut() = provider;
+	});
+}
+
+/// Has a provider been set?
+pub fn has_provider() -> bool {
+	get_provider().is_some()
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Wait for `ms` milliseconds, feeding the watchdog (if there is one) at
+/// least every `watchdog::FEED_INTERVAL_MS`.
+pub(crate) fn delay_ms(ms: u32) {
+	let provider = get_provider();
+	let mut remaining_ms = ms;
+	while remaining_ms > 0 {
+		let chunk_ms = core::cmp::min(remaining_ms, u32::from(crate::watchdog::FEED_INTERVAL_MS));
+		match provider {
+			Some(provider) => provider.delay_ms(chunk_ms),
+			None => cortex_m::asm::delay(chunk_ms * CYCLES_PER_MS),
+		}
+		crate::watchdog::feed();
+		remaining_ms -= chunk_ms;
+	}
+}
+
+/// Wait a little while for something to happen. Without a provider this is
+/// `wfe`, which returns at the next event; with one, it is a delay of
+/// `IDLE_INTERVAL_MS`.
+pub(crate) fn idle() {
+	match get_provider() {
+		Some(provider) => provider.delay_ms(IDLE_INTERVAL_MS),
+		None => cortex_m::asm::wfe(),
+	}
+}
+
+/// Get the provider, outside the critical section, so waiting on it doesn't
+/// hold up interrupts.
+fn get_provider() -> Option<&'static dyn DelayProvider> {
+	cortex_m::interrupt::free(|cs| *PROVIDER.borrow(cs).borrow())
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,338 @@
+//! # Outbound destination policy for nrfxlib
+//!
+//! A product which must only ever talk to known endpoints can give
+//! `set_policy` (or `NrfxlibBuilder::destination_policy`) a
+//! `DestinationPolicy`, and every TCP, UDP, TLS and DTLS connect is then
+//! checked against it. A connect the policy doesn't allow fails with
+//! `Error::DestinationDenied`, before anything is sent:
+//!
+//! ```ignore
+//! static RULES: [Rule; 3] = [
+//!     Rule::deny(Destination::Network(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8), PortRange::ANY),
+//!     Rule::allow(Destination::Host("*.example.com"), PortRange::single(443)),
+//!     Rule::allow(Destination::Host("ntp.example.org"), PortRange::single(123)),
+//! ];
+//! nrfxlib::destinations::set_policy(Some(DestinationPolicy {
+//!     rules: &RULES,
+//!     default: Action::Deny,
+//! }));
+//! ```
+//!
+//! The rules are tried in order, and the first which matches the
+//! destination decides; if none match, `default` does. A connect to a
+//! hostname is decided by the hostname (`Destination::Host` and
+//! `Destination::Any` rules). Each address it then resolves to is checked
+//! again, against the address rules alone: the first of those matching it
+//! decides, and if none do the address is allowed, as its hostname was.
+//! Resolved addresses a rule denies are skipped, so a DNS answer pointing
+//! somewhere it shouldn't (into the local network, say) can't be used to
+//! get around the policy. A hostname which is an IP address literal is
+//! decided as that address.
+//!
+//! With no policy set, every destination is allowed.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::net::IpAddr;
+use crate::Error;
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// What to do with a destination.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+	/// Let the connect go ahead
+	Allow,
+	/// Fail the connect with `Error::DestinationDenied`
+	Deny,
+}
+
+/// Where a rule applies.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Destination {
+	/// Anywhere
+	Any,
+	/// A hostname, compared without regard to case. A leading `*.` matches
+	/// any name ending in the rest - `*.example.com` matches
+	/// `api.example.com` but not `example.com` itself.
+	Host(&'static str),
+	/// One IP address
+	Address(IpAddr),
+	/// Every IP address whose first `prefix_len` bits match
+	Network(IpAddr, u8),
+}
+
+/// An inclusive range of port numbers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PortRange {
+	/// The lowest port in the range
+	pub first: u16,
+	/// The highest port in the range
+	pub last: u16,
+}
+
+/// One entry in a `DestinationPolicy`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rule {
+	/// What to do with a destination this rule matches
+	pub action: Action,
+	/// Where the rule applies
+	pub destination: Destination,
+	/// Which ports it applies to
+	pub ports: PortRange,
+}
+
+/// The rules every outbound connect is checked against. See the module
+/// documentation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DestinationPolicy {
+	/// The rules, tried in order
+	pub rules: &'static [Rule],
+	/// What to do when no rule matches
+	pub default: Action,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The policy given to `set_policy`.
+static POLICY: Mutex<RefCell<Option<DestinationPolicy>>> = Mutex::new(RefCell::new(None));
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Set the policy outbound connects are checked against, or `None` to allow
+/// every destination.
+pub fn set_policy(policy: Option<DestinationPolicy>) {
+	cortex_m::interrupt::free(|cs| {
+		*POLICY.borrow(cs).borrow_mut() = policy;
+	});
+}
+
+/// Get the policy given to `set_policy`, if any.
+pub fn get_policy() -> Option<DestinationPolicy> {
+	cortex_m::interrupt::free(|cs| *POLICY.borrow(cs).borrow())
+}
+
+impl PortRange {
+	/// Every port.
+	pub const ANY: PortRange = PortRange {
+		first: 0,
+		last: u16::MAX,
+	};
+
+	/// Just the one port.
+	pub const fn single(port: u16) -> PortRange {
+		PortRange {
+			first: port,
+			last: port,
+		}
+	}
+
+	/// Is `port` in the range?
+	pub fn contains(&self, port: u16) -> bool {
+		port >= self.first && port <= self.last
+	}
+}
+
+impl Rule {
+	/// A rule allowing `destination` on `ports`.
+	pub const fn allow(destination: Destination, ports: PortRange) -> Rule {
+		Rule {
+			action: Action::Allow,
+			destination,
+			ports,
+		}
+	}
+
+	/// A rule denying `destination` on `ports`.
+	pub const fn deny(destination: Destination, ports: PortRange) -> Rule {
+		Rule {
+			action: Action::Deny,
+			destination,
+			ports,
+		}
+	}
+}
+
+impl DestinationPolicy {
+	/// Would a connect to `hostname` on `port` be allowed? This is the check
+	/// made before the hostname is looked up.
+	pub fn allows_host(&self, hostname: &str, port: u16) -> bool {
+		if let Ok(address) = hostname.parse::<IpAddr>() {
+			return self.allows_address(address, port);
+		}
+		let hostname = hostname.trim_end_matches('.');
+		self.decide(port, |destination| match destination {
+			Destination::Any => true,
+			Destination::Host(pattern) => host_matches(pattern, hostname),
+			Destination::Address(_) | Destination::Network(_, _) => false,
+		})
+		.unwrap_or(self.default)
+			== Action::Allow
+	}
+
+	/// Would a connect straight to `address` on `port` be allowed?
+	pub fn allows_address(&self, address: IpAddr, port: u16) -> bool {
+		self.decide(port, |destination| match destination {
+			Destination::Any => true,
+			Destination::Host(_) => false,
+			_ => address_matches(destination, address),
+		})
+		.unwrap_or(self.default)
+			== Action::Allow
+	}
+
+	/// May an address a hostname resolved to be used, on `port`? Only the
+	/// address rules count, and if none match it is allowed.
+	pub fn allows_resolved(&self, address: IpAddr, port: u16) -> bool {
+		self.decide(port, |destination| address_matches(destination, address))
+			.unwrap_or(Action::Allow)
+			== Action::Allow
+	}
+
+	/// The action of the first rule covering `port` whose destination
+	/// `matches` accepts.
+	fn decide<F>(&self, port: u16, matches: F) -> Option<Action>
+	where
+		F: Fn(&Destination) -> bool,
+	{
+		self.rules
+			.iter()
+			.find(|rule| rule.ports.contains(port) && matches(&rule.destination))
+			.map(|rule| rule.action)
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Fail with `Error::DestinationDenied` if the policy doesn't allow a
+/// connect to `hostname` on `port`.
+pub(crate) fn check_host(hostname: &str, port: u16) -> Result<(), Error> {
+	match get_policy() {
+		Some(policy) if !policy.allows_host(hostname, port) => {
+			debug!("Destination {}:{} denied by policy", hostname, port);
+			Err(Error::DestinationDenied)
+		}
+		_ => Ok(()),
+	}
+}
+
+/// Fail with `Error::DestinationDenied` if the policy doesn't allow a
+/// connect straight to `address` on `port`.
+#[cfg(feature = "embedded-nal-async")]
+pub(crate) fn check_address(address: IpAddr, port: u16) -> Result<(), Error> {
+	match get_policy() {
+		Some(policy) if !policy.allows_address(address, port) => {
+			debug!("Destination {}:{} denied by policy", address, port);
+			Err(Error::DestinationDenied)
+		}
+		_ => Ok(()),
+	}
+}
+
+/// May we use `address`, which a hostname resolved to, on `port`?
+pub(crate) fn resolved_allowed(address: IpAddr, port: u16) -> bool {
+	match get_policy() {
+		Some(policy) if !policy.allows_resolved(address, port) => {
+			debug!("Resolved address {} denied by policy", address);
+			false
+		}
+		_ => true,
+	}
+}
+
+/// Does `pattern` (see `Destination::Host`) match `hostname`?
+fn host_matches(pattern: &str, hostname: &str) -> bool {
+	let pattern = pattern.trim_end_matches('.');
+	match pattern.strip_prefix("*.") {
+		Some(suffix) => {
+			// Compare bytes, as the split needn't be on a character boundary
+			let hostname = hostname.as_bytes();
+			let (labels, tail) = hostname.split_at(hostname.len().saturating_sub(suffix.len()));
+			labels.len() > 1
+				&& labels.ends_with(b".")
+				&& tail.eq_ignore_ascii_case(suffix.as_bytes())
+		}
+		None => hostname.eq_ignore_ascii_case(pattern),
+	}
+}
+
+/// Is `address` the `Destination::Address`, or in the
+/// `Destination::Network`? IPv4-mapped IPv6 addresses match as IPv4.
+fn address_matches(destination: &Destination, address: IpAddr) -> bool {
+	let (rule_address, prefix_len) = match *destination {
+		Destination::Address(rule_address) => (rule_address, u8::MAX),
+		Destination::Network(rule_address, prefix_len) => (rule_address, prefix_len),
+		Destination::Any | Destination::Host(_) => return false,
+	};
+	match (unmap(rule_address), unmap(address)) {
+		(IpAddr::V4(a), IpAddr::V4(b)) => prefix_matches(&a.octets(), &b.octets(), prefix_len),
+		(IpAddr::V6(a), IpAddr::V6(b)) => prefix_matches(&a.octets(), &b.octets(), prefix_len),
+		_ => false,
+	}
+}
+
+/// Turn an IPv4-mapped IPv6 address into the IPv4 address.
+fn unmap(address: IpAddr) -> IpAddr {
+	match address {
+		IpAddr::V6(ipv6) => ipv6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+		IpAddr::V4(_) => address,
+	}
+}
+
+/// Do the first `prefix_len` bits of `a` and `b` match? A `prefix_len`
+/// longer than the address compares all of it.
+fn prefix_matches(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+	let bits = core::cmp::min(usize::from(prefix_len), a.len() * 8);
+	let whole = bits / 8;
+	if a.get(0..whole) != b.get(0..whole) {
+		return false;
+	}
+	match (bits % 8, a.get(whole), b.get(whole)) {
+		(0, _, _) => true,
+		(partial, Some(a), Some(b)) => {
+			let mask = 0xFFu8 << (8 - partial);
+			(a & mask) == (b & mask)
+		}
+		_ => false,
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
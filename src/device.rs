@@ -0,0 +1,180 @@
+//! # Device information for nrfxlib
+//!
+//! Traits for the device metadata that cloud integrations report -
+//! manufacturer, model, serial number, firmware version and battery state,
+//! as in the LwM2M Device object (object 3). An application implements them
+//! once and hands the same provider to whichever integration it uses.
+//!
+//! `ModemInfo` implements both traits using the modem's own AT commands,
+//! which is a reasonable default for a board without a fuel gauge.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::firmware::FirmwareVersion;
+use crate::Error;
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A piece of device information, such as a serial number.
+pub type InfoString = heapless::String<INFO_STRING_LEN>;
+
+/// Provides the identity of the device.
+pub trait DeviceInfoProvider {
+	/// The manufacturer (LwM2M resource 3/0/0)
+	fn manufacturer(&self) -> Result<InfoString, Error>;
+	/// The model number (LwM2M resource 3/0/1)
+	fn model_number(&self) -> Result<InfoString, Error>;
+	/// The serial number (LwM2M resource 3/0/2)
+	fn serial_number(&self) -> Result<InfoString, Error>;
+	/// The firmware version (LwM2M resource 3/0/3)
+	fn firmware_version(&self) -> Result<InfoString, Error>;
+	/// The hardware version (LwM2M resource 3/0/18), if known
+	fn hardware_version(&self) -> Result<Option<InfoString>, Error> {
+		Ok(None)
+	}
+}
+
+/// Provides the state of the device's power source.
+pub trait BatteryInfoProvider {
+	/// The battery level, as a percentage (LwM2M resource 3/0/9), if known
+	fn battery_level(&self) -> Result<Option<u8>, Error>;
+	/// The power source voltage, in millivolts (LwM2M resource 3/0/7), if
+	/// known
+	fn voltage_mv(&self) -> Result<Option<u32>, Error>;
+	/// The battery status (LwM2M resource 3/0/20)
+	fn battery_status(&self) -> Result<BatteryStatus, Error> {
+		Ok(BatteryStatus::Unknown)
+	}
+}
+
+/// The battery status, with the values LwM2M uses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BatteryStatus {
+	/// The battery is working normally and isn't charging
+	Normal = 0,
+	/// The battery is charging
+	Charging = 1,
+	/// The battery is fully charged and still on the charger
+	ChargeComplete = 2,
+	/// The battery has a problem
+	Damaged = 3,
+	/// The battery is low
+	Low = 4,
+	/// There is no battery
+	NotInstalled = 5,
+	/// We don't know
+	Unknown = 6,
+}
+
+/// Device information read from the modem: `AT+CGMI`, `AT+CGMM`, `AT+CGSN`
+/// (the IMEI), `AT+CGMR`, and `AT%XVBAT` for the supply voltage. The battery
+/// level isn't known.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ModemInfo;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The longest piece of device information we can hold.
+pub const INFO_STRING_LEN: usize = 64;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl DeviceInfoProvider for ModemInfo {
+	fn manufacturer(&self) -> Result<InfoString, Error> {
+		read_info("AT+CGMI")
+	}
+
+	fn model_number(&self) -> Result<InfoString, Error> {
+		read_info("AT+CGMM")
+	}
+
+	fn serial_number(&self) -> Result<InfoString, Error> {
+		read_info("AT+CGSN")
+	}
+
+	/// Also records the version for `firmware::current`.
+	fn firmware_version(&self) -> Result<InfoString, Error> {
+		let info = read_info("AT+CGMR")?;
+		if let Some(version) = FirmwareVersion::parse(&info) {
+			crate::firmware::record(version);
+		}
+		Ok(info)
+	}
+}
+
+impl BatteryInfoProvider for ModemInfo {
+	fn battery_level(&self) -> Result<Option<u8>, Error> {
+		Ok(None)
+	}
+
+	fn voltage_mv(&self) -> Result<Option<u32>, Error> {
+		let mut result = Err(Error::UnrecognisedValue);
+		crate::at::send_at_command("AT%XVBAT", |res| {
+			if let Some(value) = res.strip_prefix("%XVBAT:") {
+				if let Ok(mv) = value.trim().parse() {
+					result = Ok(Some(mv));
+				}
+			}
+			debug!("{:?} => {:?}", res, result);
+		})?;
+		result
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Send an AT command which responds with a single line of text, and return
+/// that text.
+fn read_info(command: &str) -> Result<InfoString, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command(command, |res| {
+		let res = res.trim().trim_matches('"');
+		if matches!(result, Err(Error::UnrecognisedValue)) && !res.is_empty() {
+			let mut info = InfoString::new();
+			result = info
+				.push_str(res)
+				.map(|_| info)
+				.map_err(|_| Error::BufferFull);
+		}
+		debug!("{:?} => {:?}", command, result);
+	})?;
+	result
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
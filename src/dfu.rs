@@ -0,0 +1,417 @@
+//! # Modem delta firmware updates for nrfxlib
+//!
+//! The modem can update its own firmware from a delta image - the
+//! difference between the firmware it has and the one it should have -
+//! which it keeps in a scratch area of its flash. The image is streamed to
+//! the modem through a `DfuTarget` while it runs normally, fetched however
+//! the application likes (e.g. over HTTPS, a chunk at a time):
+//!
+//! ```ignore
+//! let target = dfu::DfuTarget::new()?;
+//! if target.scratch_area()? == dfu::ScratchArea::Dirty {
+//!     target.erase()?;
+//!     // ... wait for `scratch_area()` to report `Empty`
+//! }
+//! // Carry on from where an earlier, interrupted download got to
+//! let mut offset = target.resume()?;
+//! while let Some(chunk) = download(offset)? {
+//!     target.write(chunk)?;
+//!     offset = target.offset();
+//! }
+//! target.apply()?;
+//! // ... reset, and call `update_result()` once libmodem has started again
+//! ```
+//!
+//! `apply` only schedules the update. libmodem performs it while starting up
+//! after the next reset, and `update_result` reports how it went. The modem
+//! then needs resetting once more to run the new firmware.
+//!
+//! Full (non-delta) updates need libmodem started in DFU mode instead - see
+//! `NrfxlibBuilder::dfu_mode`.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+use crate::get_last_error;
+#[cfg(target_arch = "arm")]
+use crate::raw::*;
+#[cfg(target_arch = "arm")]
+use crate::Error;
+#[cfg(target_arch = "arm")]
+use core::cell::Cell;
+#[cfg(target_arch = "arm")]
+use core::sync::atomic::{AtomicU32, Ordering};
+#[cfg(target_arch = "arm")]
+use log::debug;
+#[cfg(target_arch = "arm")]
+use nrfxlib_sys as sys;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A connection to the modem's DFU service, for streaming a delta firmware
+/// image to it.
+#[cfg(target_arch = "arm")]
+#[derive(Debug)]
+pub struct DfuTarget {
+	socket: Socket,
+	/// How much of the image the modem has
+	offset: Cell<u32>,
+}
+
+/// What is in the modem's DFU scratch area.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScratchArea {
+	/// Nothing - it is ready for a new image
+	Empty,
+	/// Something which isn't a new image (e.g. the backup of the previous
+	/// firmware). It must be erased before a new image can be written.
+	Dirty,
+	/// This many bytes of a new image
+	Received(u32),
+}
+
+/// The errors the modem's DFU service reports.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DfuError {
+	/// The modem ran out of memory receiving the image
+	ReceiverOutOfMemory,
+	/// A block of the image was too large
+	BlockTooLarge,
+	/// The image header is invalid
+	InvalidHeaderData,
+	/// The image data is invalid
+	InvalidData,
+	/// The image isn't for the firmware the modem has
+	InvalidUuid,
+	/// The image would be written to an invalid address
+	InvalidAddress,
+	/// The scratch area needs erasing first
+	AreaNotBlank,
+	/// Writing to the modem's flash failed
+	WriteError,
+	/// Erasing the modem's flash failed
+	EraseError,
+	/// The offset given to resume from is invalid
+	InvalidFileOffset,
+	/// The modem's record of the download so far is invalid
+	ProgressLogInvalid,
+	/// The download can't be resumed
+	InvalidResumeAttempt,
+	/// The scratch area is still being erased
+	ErasePending,
+	/// The operation isn't allowed now
+	OperationNotAllowed,
+	/// The image is incomplete, so can't be applied
+	IncompleteData,
+	/// A write was interrupted
+	InterruptedWrite,
+	/// Some other (internal) error code
+	Other(i32),
+}
+
+/// How the firmware update scheduled with `DfuTarget::apply` went, as
+/// reported by libmodem when it started after the reset.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UpdateResult {
+	/// The update worked. The modem runs the new firmware after the next
+	/// reset.
+	Ok,
+	/// The modem had an internal error during the update
+	InternalError,
+	/// The modem had a hardware error during the update
+	HardwareError,
+	/// The image failed authentication. The modem keeps the old firmware.
+	AuthError,
+	/// The image isn't for the firmware the modem has. The modem keeps the
+	/// old firmware.
+	UuidError,
+	/// A result code we don't know
+	Other(u32),
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The offset the modem reports when the scratch area is dirty: 2.5 MiB.
+#[cfg(target_arch = "arm")]
+const DIRTY_OFFSET: u32 = 2_621_440;
+
+/// The length of the firmware UUID.
+pub const UUID_LEN: usize = 36;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// What `nrf_modem_init` returned, if it performed an update. Zero if not.
+#[cfg(target_arch = "arm")]
+static UPDATE_RESULT: AtomicU32 = AtomicU32::new(0);
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Get the result of the firmware update libmodem performed when it
+/// started, or `None` if it didn't perform one.
+#[cfg(target_arch = "arm")]
+pub fn update_result() -> Option<UpdateResult> {
+	match UPDATE_RESULT.load(Ordering::Relaxed) {
+		0 => None,
+		code => Some(UpdateResult::from_u32(code)),
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl DfuTarget {
+	/// Open a connection to the modem's DFU service.
+	pub fn new() -> Result<DfuTarget, Error> {
+		let socket = Socket::new(SocketDomain::Local, SocketType::Stream, SocketProtocol::Dfu)?;
+		Ok(DfuTarget {
+			socket,
+			offset: Cell::new(0),
+		})
+	}
+
+	/// Get the UUID of the firmware the modem is running.
+	pub fn firmware_version(&self) -> Result<heapless::String<UUID_LEN>, Error> {
+		let version = self.get_option(
+			"dfu_fw_version",
+			sys::NRF_SO_DFU_FW_VERSION,
+			[0u8; UUID_LEN],
+		)?;
+		// The UUID is NUL-terminated, unless it fills the buffer
+		let text = version.split(|&b| b == 0).next().unwrap_or(&[]);
+		let text = core::str::from_utf8(text).map_err(|_| Error::BadDataFormat)?;
+		let mut uuid = heapless::String::new();
+		uuid.push_str(text).map_err(|_| Error::BufferFull)?;
+		Ok(uuid)
+	}
+
+	/// Get the size of the largest image the scratch area can take, in
+	/// bytes.
+	pub fn max_image_size(&self) -> Result<u32, Error> {
+		self.get_option("dfu_resources", sys::NRF_SO_DFU_RESOURCES, 0u32)
+	}
+
+	/// Find out what is in the scratch area.
+	pub fn scratch_area(&self) -> Result<ScratchArea, Error> {
+		let offset = self.get_option("dfu_offset", sys::NRF_SO_DFU_OFFSET, 0u32)?;
+		Ok(match offset {
+			0 => ScratchArea::Empty,
+			DIRTY_OFFSET => ScratchArea::Dirty,
+			received => ScratchArea::Received(received),
+		})
+	}
+
+	/// Start erasing the scratch area, which also deletes the backup of the
+	/// previous firmware (so `revert` is no longer possible). The modem
+	/// erases in the background - `scratch_area` reports `Dirty` until it
+	/// has finished, and writes fail with `DfuError::ErasePending`.
+	pub fn erase(&self) -> Result<(), Error> {
+		debug!("Erasing DFU scratch area");
+		self.socket
+			.set_option(SocketOption::DfuBackupDelete)
+			.map_err(|e| self.dfu_error(e))?;
+		self.offset.set(0);
+		Ok(())
+	}
+
+	/// Get ready to carry on with an image the modem already has part of,
+	/// after a reset or a lost connection. Returns how many bytes of the
+	/// image the modem has (zero if none), which is where the next `write`
+	/// should start from. Fails with `DfuError::AreaNotBlank` if the scratch
+	/// area needs erasing first.
+	pub fn resume(&self) -> Result<u32, Error> {
+		let offset = match self.scratch_area()? {
+			ScratchArea::Empty => 0,
+			ScratchArea::Dirty => return Err(Error::Dfu(DfuError::AreaNotBlank)),
+			ScratchArea::Received(offset) => {
+				self.socket
+					.set_option(SocketOption::DfuOffset(offset))
+					.map_err(|e| self.dfu_error(e))?;
+				offset
+			}
+		};
+		debug!("Resuming DFU from offset {}", offset);
+		self.offset.set(offset);
+		Ok(offset)
+	}
+
+	/// Send the next piece of the image to the modem, waiting until it has
+	/// taken all of it. Pieces can be any size.
+	pub fn write(&self, mut data: &[u8]) -> Result<(), Error> {
+		while !data.is_empty() {
+			let sent = self.socket.write(data).map_err(|e| self.dfu_error(e))?;
+			if sent == 0 {
+				return Err(self.dfu_error(Error::WriteError));
+			}
+			self.offset.set(self.offset.get().wrapping_add(sent as u32));
+			data = data.get(sent..).unwrap_or(&[]);
+		}
+		Ok(())
+	}
+
+	/// Get how much of the image the modem has - what has been written,
+	/// plus the offset given by `resume`.
+	pub fn offset(&self) -> u32 {
+		self.offset.get()
+	}
+
+	/// Schedule the image to be applied at the next reset. Fails with
+	/// `DfuError::IncompleteData` if the image isn't all there.
+	pub fn apply(&self) -> Result<(), Error> {
+		debug!("Applying modem firmware update ({} bytes)", self.offset());
+		self.socket
+			.set_option(SocketOption::DfuApply)
+			.map_err(|e| self.dfu_error(e))
+	}
+
+	/// Schedule a rollback to the previous firmware at the next reset, if
+	/// the scratch area still has it.
+	pub fn revert(&self) -> Result<(), Error> {
+		debug!("Reverting modem firmware update");
+		self.socket
+			.set_option(SocketOption::DfuRevert)
+			.map_err(|e| self.dfu_error(e))
+	}
+
+	/// Get the last error the DFU service reported, if any.
+	pub fn last_error(&self) -> Result<Option<DfuError>, Error> {
+		let code = self.get_option("dfu_error", sys::NRF_SO_DFU_ERROR, 0i32)?;
+		Ok(DfuError::from_i32(code))
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl Pollable for DfuTarget {
+	/// Get the underlying socket ID for this socket.
+	fn get_fd(&self) -> i32 {
+		self.socket.fd
+	}
+}
+
+impl DfuError {
+	/// Convert the error code read with `NRF_SO_DFU_ERROR`. Zero (no error)
+	/// gives `None`.
+	pub fn from_i32(code: i32) -> Option<DfuError> {
+		Some(match code {
+			0 => return None,
+			-1 => DfuError::ReceiverOutOfMemory,
+			-2 => DfuError::BlockTooLarge,
+			-3 => DfuError::InvalidHeaderData,
+			-5 => DfuError::InvalidData,
+			-9 => DfuError::InvalidUuid,
+			-10 => DfuError::InvalidAddress,
+			-11 => DfuError::AreaNotBlank,
+			-12 => DfuError::WriteError,
+			-13 => DfuError::EraseError,
+			-14 => DfuError::InvalidFileOffset,
+			-15 => DfuError::ProgressLogInvalid,
+			-16 => DfuError::InvalidResumeAttempt,
+			-17 => DfuError::ErasePending,
+			-18 => DfuError::OperationNotAllowed,
+			-19 => DfuError::IncompleteData,
+			-20 => DfuError::InterruptedWrite,
+			other => DfuError::Other(other),
+		})
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl UpdateResult {
+	/// Convert a `MODEM_DFU_RESULT_*` code returned by `nrf_modem_init`.
+	pub fn from_u32(code: u32) -> UpdateResult {
+		match code {
+			sys::MODEM_DFU_RESULT_OK => UpdateResult::Ok,
+			sys::MODEM_DFU_RESULT_INTERNAL_ERROR => UpdateResult::InternalError,
+			sys::MODEM_DFU_RESULT_HARDWARE_ERROR => UpdateResult::HardwareError,
+			sys::MODEM_DFU_RESULT_AUTH_ERROR => UpdateResult::AuthError,
+			sys::MODEM_DFU_RESULT_UUID_ERROR => UpdateResult::UuidError,
+			other => UpdateResult::Other(other),
+		}
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Remember what `nrf_modem_init` returned, if it performed an update.
+#[cfg(target_arch = "arm")]
+pub(crate) fn record_init_result(result: i32) {
+	if result > 0 {
+		let result = UpdateResult::from_u32(result as u32);
+		debug!("Modem firmware update result: {:?}", result);
+		UPDATE_RESULT.store(result_code(result), Ordering::Relaxed);
+	}
+}
+
+/// Get the code back from an `UpdateResult`.
+#[cfg(target_arch = "arm")]
+fn result_code(result: UpdateResult) -> u32 {
+	match result {
+		UpdateResult::Ok => sys::MODEM_DFU_RESULT_OK,
+		UpdateResult::InternalError => sys::MODEM_DFU_RESULT_INTERNAL_ERROR,
+		UpdateResult::HardwareError => sys::MODEM_DFU_RESULT_HARDWARE_ERROR,
+		UpdateResult::AuthError => sys::MODEM_DFU_RESULT_AUTH_ERROR,
+		UpdateResult::UuidError => sys::MODEM_DFU_RESULT_UUID_ERROR,
+		UpdateResult::Other(code) => code,
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl DfuTarget {
+	/// Read a DFU socket option, starting from `value`.
+	fn get_option<T>(&self, what: &'static str, name: u32, mut value: T) -> Result<T, Error> {
+		let mut length = core::mem::size_of::<T>() as u32;
+		let result = unsafe {
+			sys::nrf_getsockopt(
+				self.socket.fd,
+				sys::NRF_SOL_DFU as i32,
+				name as i32,
+				&mut value as *mut T as *mut sys::ctypes::c_void,
+				&mut length as *mut u32,
+			)
+		};
+		if result < 0 {
+			Err(Error::Nordic(what, result, get_last_error()))
+		} else {
+			Ok(value)
+		}
+	}
+
+	/// If the DFU service has an error to report, report that instead of
+	/// this (less specific) one.
+	fn dfu_error(&self, error: Error) -> Error {
+		match self.last_error() {
+			Ok(Some(dfu_error)) => Error::Dfu(dfu_error),
+			_ => error,
+		}
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,264 @@
+//! # DNS lookups for nrfxlib
+//!
+//! The socket types look hostnames up themselves when asked to connect to
+//! one. This module is for when you want the addresses - to cache them, to
+//! pick one, or to look a name up on a PDN connection other than the
+//! default:
+//!
+//! ```ignore
+//! let addresses = dns::resolve("example.com", dns::Family::Any)?;
+//! let server = dns::resolve_socket_addr("example.com", 443, dns::Family::Ipv4)?;
+//! let private = dns::resolve_on_pdn("server.private", dns::Family::Ipv4, "private.apn")?;
+//! ```
+//!
+//! The lookup blocks until the modem's resolver answers, or for at most
+//! `timeouts::Timeouts::dns_ms`, after which it fails with
+//! `Error::DnsTimeout` (see `timeouts` for how). libmodem's results are
+//! copied out and freed before these functions return.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use crate::raw::{AddrInfoList, SocketDomain, SocketType};
+use crate::Error;
+use core::convert::TryFrom;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Which kinds of address to look up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Family {
+	/// IPv4 addresses (`A` records) only
+	Ipv4,
+	/// IPv6 addresses (`AAAA` records) only
+	Ipv6,
+	/// Both - IPv4 addresses first. Only fails if neither lookup finds
+	/// anything.
+	Any,
+}
+
+/// The addresses a lookup found.
+pub type Addresses = heapless::Vec<IpAddr, MAX_ADDRESSES>;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The most addresses a lookup returns. Any more are dropped.
+pub const MAX_ADDRESSES: usize = 4;
+
+/// How long to wait at a time, while a lookup with a timeout is waiting for
+/// ever, so we can count the time.
+const LOOKUP_POLL_MS: u32 = 10;
+
+/// The states of `LOOKUP_STATE`. A lookup with a timeout goes from `IDLE`
+/// to `STARTING` while it sets itself up, then to `ARMED`. Its first wait
+/// goes to `RECORDING` while it notes its context, then to `WAITING`.
+const IDLE: u8 = 0;
+const STARTING: u8 = 1;
+const ARMED: u8 = 2;
+const RECORDING: u8 = 3;
+const WAITING: u8 = 4;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// Where the lookup with a timeout, if any, has got to.
+static LOOKUP_STATE: AtomicU8 = AtomicU8::new(IDLE);
+
+/// The `nrf_modem_os_timedwait` context of the lookup's first wait. Only
+/// waits with this context count against its time.
+static LOOKUP_CONTEXT: AtomicU32 = AtomicU32::new(0);
+
+/// How many milliseconds the lookup in progress has left.
+static LOOKUP_REMAINING_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Did the lookup in progress run out of time?
+static LOOKUP_TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Look up a hostname's addresses, using the DNS servers of the default PDN
+/// connection. Fails with `Error::Nordic` if the lookup fails, or
+/// `Error::BadDataFormat` if it found no addresses of the family asked for.
+pub fn resolve(hostname: &str, family: Family) -> Result<Addresses, Error> {
+	lookup(hostname, family, None)
+}
+
+/// Look up a hostname's addresses as `resolve` does, using the DNS servers of
+/// the PDN connection to this APN (see `pdn::create_context`).
+pub fn resolve_on_pdn(hostname: &str, family: Family, apn: &str) -> Result<Addresses, Error> {
+	lookup(hostname, family, Some(apn))
+}
+
+/// Look up a hostname, and return the first address found with the given
+/// port.
+pub fn resolve_socket_addr(hostname: &str, port: u16, family: Family) -> Result<SocketAddr, Error> {
+	let addresses = resolve(hostname, family)?;
+	let ip = addresses.first().ok_or(Error::BadDataFormat)?;
+	Ok(SocketAddr::new(*ip, port))
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Run a lookup (`nrf_getaddrinfo`) with the DNS timeout. If it fails after
+/// running out of time, that's `Error::DnsTimeout`. Only one lookup at a
+/// time can have the timeout - if another already has it (one running in
+/// another thread, say), this one goes without.
+pub(crate) fn with_timeout<T, F>(lookup: F) -> Result<T, Error>
+where
+	F: FnOnce() -> Result<T, Error>,
+{
+	let timeout_ms = crate::timeouts::current().dns_ms;
+	if timeout_ms == 0 {
+		return lookup();
+	}
+	if LOOKUP_STATE
+		.compare_exchange(IDLE, STARTING, Ordering::SeqCst, Ordering::SeqCst)
+		.is_err()
+	{
+		debug!("DNS lookup already in progress, so no timeout for this one");
+		return lookup();
+	}
+	LOOKUP_REMAINING_MS.store(timeout_ms, Ordering::SeqCst);
+	LOOKUP_TIMED_OUT.store(false, Ordering::SeqCst);
+	LOOKUP_STATE.store(ARMED, Ordering::SeqCst);
+	let result = lookup();
+	LOOKUP_STATE.store(IDLE, Ordering::SeqCst);
+	match result {
+		Err(_) if LOOKUP_TIMED_OUT.load(Ordering::SeqCst) => {
+			debug!("DNS lookup timed out after {} ms", timeout_ms);
+			Err(Error::DnsTimeout)
+		}
+		result => result,
+	}
+}
+
+/// Wait on behalf of `nrf_modem_os_timedwait`, if this wait (`context`) is
+/// the lookup with a timeout waiting, counting the time against it. The
+/// lookup's first wait is taken to be its own, and later waits with other
+/// contexts - other sockets, other threads - don't count. Returns what the
+/// wait should return, or `None` if it isn't the lookup's.
+pub(crate) fn lookup_wait(context: u32, timeout_ms: i32) -> Option<i32> {
+	match LOOKUP_STATE.load(Ordering::SeqCst) {
+		ARMED => {
+			if LOOKUP_STATE
+				.compare_exchange(ARMED, RECORDING, Ordering::SeqCst, Ordering::SeqCst)
+				.is_err()
+			{
+				return None;
+			}
+			LOOKUP_CONTEXT.store(context, Ordering::SeqCst);
+			LOOKUP_STATE.store(WAITING, Ordering::SeqCst);
+		}
+		WAITING if LOOKUP_CONTEXT.load(Ordering::SeqCst) == context => {}
+		_ => return None,
+	}
+	let remaining_ms = LOOKUP_REMAINING_MS.load(Ordering::SeqCst);
+	let wait_ms = match u32::try_from(timeout_ms) {
+		Ok(timeout_ms) => core::cmp::min(timeout_ms, remaining_ms),
+		// Waiting for ever, so go round again shortly
+		Err(_) => core::cmp::min(LOOKUP_POLL_MS, remaining_ms),
+	};
+	crate::delay::delay_ms(wait_ms);
+	LOOKUP_REMAINING_MS.store(remaining_ms - wait_ms, Ordering::SeqCst);
+	if remaining_ms == wait_ms {
+		LOOKUP_TIMED_OUT.store(true, Ordering::SeqCst);
+		Some(nrfxlib_sys::NRF_ETIMEDOUT as i32)
+	} else if timeout_ms >= 0 && wait_ms == timeout_ms as u32 {
+		// libmodem's own timeout came first
+		Some(nrfxlib_sys::NRF_ETIMEDOUT as i32)
+	} else {
+		Some(0)
+	}
+}
+
+/// Look up a hostname in one or both families.
+fn lookup(hostname: &str, family: Family, apn: Option<&str>) -> Result<Addresses, Error> {
+	let mut addresses = Addresses::new();
+	match family {
+		Family::Ipv4 => lookup_ipv4(hostname, apn, &mut addresses)?,
+		Family::Ipv6 => lookup_ipv6(hostname, apn, &mut addresses)?,
+		Family::Any => {
+			// A name with only one kind of record fails the other lookup
+			let ipv4 = lookup_ipv4(hostname, apn, &mut addresses);
+			let ipv6 = lookup_ipv6(hostname, apn, &mut addresses);
+			if addresses.is_empty() {
+				ipv4.and(ipv6)?;
+			}
+		}
+	}
+	debug!("{:?} => {:?}", hostname, addresses);
+	if addresses.is_empty() {
+		Err(Error::BadDataFormat)
+	} else {
+		Ok(addresses)
+	}
+}
+
+/// Add a hostname's IPv4 addresses to `addresses`, while there is room.
+fn lookup_ipv4(hostname: &str, apn: Option<&str>, addresses: &mut Addresses) -> Result<(), Error> {
+	let list =
+		AddrInfoList::lookup_on(SocketDomain::Inet, hostname, SocketType::Stream, apn, "dns")?;
+	for addr in list.addresses(0) {
+		// `s_addr` holds the octets in network order
+		let [a, b, c, d] = addr.sin_addr.s_addr.to_ne_bytes();
+		if addresses
+			.push(IpAddr::V4(Ipv4Addr::new(a, b, c, d)))
+			.is_err()
+		{
+			break;
+		}
+	}
+	Ok(())
+}
+
+/// Add a hostname's IPv6 addresses to `addresses`, while there is room.
+fn lookup_ipv6(hostname: &str, apn: Option<&str>, addresses: &mut Addresses) -> Result<(), Error> {
+	let list = AddrInfoList::lookup_on(
+		SocketDomain::Inet6,
+		hostname,
+		SocketType::Stream,
+		apn,
+		"dns6",
+	)?;
+	for addr in list.ipv6_addresses(0) {
+		let ip = Ipv6Addr::from_octets(addr.sin6_addr.s6_addr);
+		if addresses.push(IpAddr::V6(ip)).is_err() {
+			break;
+		}
+	}
+	Ok(())
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
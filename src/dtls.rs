@@ -21,6 +21,8 @@ pub use crate::tls::provision_certificates;
 
 use super::{get_last_error, Error};
 use crate::raw::*;
+use crate::tls::{SESSION_CACHE_DISABLED, SESSION_CACHE_ENABLED};
+use core::cell::Cell;
 use log::debug;
 use nrfxlib_sys as sys;
 
@@ -32,6 +34,11 @@ use nrfxlib_sys as sys;
 #[derive(Debug)]
 pub struct DtlsSocket {
 	socket: Socket,
+	/// How far `connect_start` has got
+	connecting: Cell<Connecting>,
+	/// Whether `set_tls_hostname` has chosen the hostname, so connecting
+	/// doesn't set it
+	custom_hostname: Cell<bool>,
 }
 
 /// Specify which version of the DTLS standard to use
@@ -90,12 +97,14 @@ impl DtlsSocket {
 
 		// Now configure this socket
 
+		// Limit how long blocking receives and sends wait (see `timeouts`)
+		socket.apply_default_timeouts()?;
+
 		// Set whether we verify the peer
 		socket.set_option(SocketOption::TlsPeerVerify(peer_verify.as_integer()))?;
 
-		// Always enable session caching to speed up connecting. 0 = enabled, 1
-		// = disabled (the default).
-		socket.set_option(SocketOption::TlsSessionCache(0))?;
+		// Always enable session caching to speed up connecting
+		socket.set_option(SocketOption::TlsSessionCache(SESSION_CACHE_ENABLED))?;
 
 		// We don't set the cipher list, and assume the defaults are sensible.
 
@@ -105,92 +114,125 @@ impl DtlsSocket {
 			socket.set_option(SocketOption::TlsTagList(security_tags))?;
 		}
 
-		Ok(DtlsSocket { socket })
+		Ok(DtlsSocket {
+			socket,
+			connecting: Cell::new(Connecting::Idle),
+			custom_hostname: Cell::new(false),
+		})
 	}
 
 	/// Look up the hostname and for each result returned, try to connect to
 	/// it.
 	pub fn connect(&self, hostname: &str, port: u16) -> Result<(), Error> {
-		use core::fmt::Write;
-
 		debug!("Connecting via DTLS to {}:{}", hostname, port);
+		crate::destinations::check_host(hostname, port)?;
 
-		// First we set the hostname
+		// Limit how long connecting can take (see `timeouts`)
 		self.socket
-			.set_option(SocketOption::TlsHostName(hostname))?;
-
-		let mut result;
-		// Now, make a null-terminated hostname
-		let mut hostname_smallstring: heapless::String<64> = heapless::String::new();
-		write!(hostname_smallstring, "{}\0", hostname).map_err(|_| Error::HostnameTooLong)?;
-		// Now call getaddrinfo with some hints
-		let hints = sys::nrf_addrinfo {
-			ai_flags: 0,
-			ai_family: sys::NRF_AF_INET as i32,
-			ai_socktype: sys::NRF_SOCK_DGRAM as i32,
-			ai_protocol: 0,
-			ai_addrlen: 0,
-			ai_addr: core::ptr::null_mut(),
-			ai_canonname: core::ptr::null_mut(),
-			ai_next: core::ptr::null_mut(),
-		};
-		let mut output_ptr: *mut sys::nrf_addrinfo = core::ptr::null_mut();
-		result = unsafe {
-			sys::nrf_getaddrinfo(
-				// hostname
-				hostname_smallstring.as_ptr(),
-				// service
-				core::ptr::null(),
-				// hints
-				&hints,
-				// output pointer
-				&mut output_ptr,
-			)
-		};
+			.start_connect_timeout(crate::timeouts::current().tls_handshake_ms)?;
 
-		if (result != 0) && output_ptr.is_null() {
-			return Err(Error::Nordic("dtls_dns", result, get_last_error()));
-		} else {
-			let mut record: &sys::nrf_addrinfo = unsafe { &*output_ptr };
-			loop {
-				let dns_addr: &sys::nrf_sockaddr_in =
-					unsafe { &*(record.ai_addr as *const sys::nrf_sockaddr_in) };
-				// Create a new sockaddr_in with the right port
-				let connect_addr = sys::nrf_sockaddr_in {
-					sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
-					sin_family: sys::NRF_AF_INET as i32,
-					sin_port: htons(port),
-					sin_addr: dns_addr.sin_addr,
-				};
-
-				debug!("Trying IP address {}", &crate::NrfSockAddrIn(connect_addr));
-
-				// try and connect to this result
-				result = unsafe {
-					sys::nrf_connect(
-						self.socket.fd,
-						&connect_addr as *const sys::nrf_sockaddr_in as *const _,
-						connect_addr.sin_len as u32,
-					)
-				};
-				if result == 0 {
-					break;
-				}
-				if !record.ai_next.is_null() {
-					record = unsafe { &*record.ai_next };
-				} else {
-					break;
-				}
-			}
-			unsafe {
-				sys::nrf_freeaddrinfo(output_ptr);
-			}
+		// First we set the hostname, unless `set_tls_hostname` chose one
+		if !self.custom_hostname.get() {
+			self.socket
+				.set_option(SocketOption::TlsHostName(hostname))?;
 		}
-		if result != 0 {
-			Err(Error::Nordic("dtls_connect", result, get_last_error()))
-		} else {
-			Ok(())
+
+		let result = AddrInfoList::lookup(hostname, SocketType::Datagram, "dtls_dns")
+			.and_then(|addresses| self.socket.connect_any(&addresses, port));
+		// Sends go back to their own timeout, once connected. Read the error
+		// first, in case this changes it.
+		let error = get_last_error();
+		self.socket.end_connect_timeout()?;
+		match result? {
+			0 => Ok(()),
+			result => Err(Error::Nordic("dtls_connect", result, error)),
+		}
+	}
+
+	/// Start connecting to the hostname, without waiting for the DTLS
+	/// handshake to finish. Call `connect_poll` until it returns
+	/// `ConnectStatus::Connected`.
+	///
+	/// Over NB-IoT a handshake can take tens of seconds, so this lets the
+	/// application get on with other things in the meantime. The DNS lookup
+	/// still blocks, and only the first address found is tried. The
+	/// `timeouts` don't apply - give up (by dropping the socket) whenever you
+	/// like.
+	pub fn connect_start(&self, hostname: &str, port: u16) -> Result<ConnectStatus, Error> {
+		debug!("Starting DTLS connect to {}:{}", hostname, port);
+		crate::destinations::check_host(hostname, port)?;
+
+		// First we set the hostname, unless `set_tls_hostname` chose one
+		if !self.custom_hostname.get() {
+			self.socket
+				.set_option(SocketOption::TlsHostName(hostname))?;
+		}
+
+		let addr = lookup_ipv4(hostname, SocketType::Datagram, port)?;
+		debug!("Trying IP address {}", &crate::NrfSockAddrIn(addr));
+		let status = self.socket.connect_start(&addr)?;
+		if status == ConnectStatus::InProgress {
+			self.connecting.set(Connecting::InProgress(addr));
 		}
+		Ok(status)
+	}
+
+	/// See whether a connect started with `connect_start` has finished. This
+	/// doesn't block.
+	pub fn connect_poll(&self) -> Result<ConnectStatus, Error> {
+		self.socket.poll_connecting(&self.connecting)
+	}
+
+	/// Change whether we verify the peer, e.g. before connecting again.
+	pub fn set_peer_verification(&self, peer_verify: PeerVerification) -> Result<(), Error> {
+		self.socket
+			.set_option(SocketOption::TlsPeerVerify(peer_verify.as_integer()))
+	}
+
+	/// Get whether we verify the peer, as the modem has it.
+	pub fn peer_verification(&self) -> Result<PeerVerification, Error> {
+		let value = self.socket.get_option(
+			"peer_verification",
+			sys::NRF_SOL_SECURE,
+			sys::NRF_SO_SEC_PEER_VERIFY,
+			0 as sys::nrf_sec_peer_verify_t,
+		)?;
+		PeerVerification::from_integer(value).ok_or(Error::UnrecognisedValue)
+	}
+
+	/// Turn session caching on or off. It is on for a new socket, so that
+	/// the modem can resume the session when reconnecting to the same
+	/// server, rather than do a full handshake. Set this before connecting.
+	pub fn set_session_cache(&self, enabled: bool) -> Result<(), Error> {
+		let value = if enabled {
+			SESSION_CACHE_ENABLED
+		} else {
+			SESSION_CACHE_DISABLED
+		};
+		self.socket.set_option(SocketOption::TlsSessionCache(value))
+	}
+
+	/// Get whether session caching is on, as the modem has it.
+	pub fn session_cache(&self) -> Result<bool, Error> {
+		let value = self.socket.get_option(
+			"session_cache",
+			sys::NRF_SOL_SECURE,
+			sys::NRF_SO_SEC_SESSION_CACHE,
+			SESSION_CACHE_DISABLED,
+		)?;
+		Ok(value != SESSION_CACHE_DISABLED)
+	}
+
+	/// Choose the hostname sent to the server (as SNI) and checked against
+	/// its certificate, instead of the one given to `connect` - e.g. when
+	/// connecting by IP address, or through a name which isn't on the
+	/// certificate. `None` sends no hostname, and doesn't check it. This
+	/// lasts as long as the socket.
+	pub fn set_tls_hostname(&self, hostname: Option<&str>) -> Result<(), Error> {
+		self.socket
+			.set_option(SocketOption::TlsHostName(hostname.unwrap_or("")))?;
+		self.custom_hostname.set(true);
+		Ok(())
 	}
 }
 
@@ -224,6 +266,16 @@ impl PeerVerification {
 			PeerVerification::Disabled => 0,
 		}
 	}
+
+	/// The reverse of `as_integer`.
+	fn from_integer(value: u32) -> Option<PeerVerification> {
+		match value {
+			2 => Some(PeerVerification::Enabled),
+			1 => Some(PeerVerification::Optional),
+			0 => Some(PeerVerification::Disabled),
+			_ => None,
+		}
+	}
 }
 
 //******************************************************************************
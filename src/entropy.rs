@@ -0,0 +1,115 @@
+//! # Random numbers for nrfxlib
+//!
+//! Protocol layers need unpredictable numbers - local port numbers, DNS
+//! transaction IDs, MQTT client IDs, CoAP tokens. The application core of
+//! the nRF9160 gets its random numbers from the CryptoCell (or the RNG, in
+//! the secure world), which this crate can't reach, so the application hands
+//! us an `EntropySource` with `set_source`.
+//!
+//! The crate uses it for `TcpSocket::bind_random` and
+//! `UdpSocket::bind_random`. Anything built on the crate can use it too,
+//! through `fill_bytes` and `random_u32`.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::Error;
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Something which can produce random bytes.
+pub trait EntropySource: Sync {
+	/// Fill `buf` with random bytes. This may be called from any context,
+	/// and must not call back into this crate.
+	fn fill_bytes(&self, buf: &mut [u8]);
+
+	/// Get a random `u32`.
+	fn next_u32(&self) -> u32 {
+		let mut bytes = [0u8; 4];
+		self.fill_bytes(&mut bytes);
+		u32::from_le_bytes(bytes)
+	}
+}
+
+/// Somewhere to keep the source, which any protocol layer can reach.
+type WrappedSource = Mutex<RefCell<Option<&'static dyn EntropySource>>>;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The source given to `set_source`.
+static SOURCE: WrappedSource = Mutex::new(RefCell::new(None));
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Set the source of random numbers, or `None` to remove it.
+pub fn set_source(source: Option<&'static dyn EntropySource>) {
+	cortex_m::interrupt::free(|cs| {
+		*SOURCE.borrow(cs).borrow_mut() = source;
+	});
+}
+
+/// Has a source been set?
+pub fn has_source() -> bool {
+	get_source().is_some()
+}
+
+/// Fill `buf` with random bytes from the source. Fails with
+/// `Error::NoEntropySource` if there isn't one.
+pub fn fill_bytes(buf: &mut [u8]) -> Result<(), Error> {
+	let source = get_source().ok_or(Error::NoEntropySource)?;
+	source.fill_bytes(buf);
+	Ok(())
+}
+
+/// Get a random `u32` from the source. Fails with `Error::NoEntropySource`
+/// if there isn't one.
+pub fn random_u32() -> Result<u32, Error> {
+	let source = get_source().ok_or(Error::NoEntropySource)?;
+	Ok(source.next_u32())
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Get the source, outside the critical section, so it doesn't hold up
+/// interrupts while it works.
+fn get_source() -> Option<&'static dyn EntropySource> {
+	cortex_m::interrupt::free(|cs| *SOURCE.borrow(cs).borrow())
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
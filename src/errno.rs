@@ -0,0 +1,235 @@
+//! # libmodem error numbers for nrfxlib
+//!
+//! The socket calls report failures as an error number (see
+//! `nrf_errno.h`), which we turn into an `Error` - picking out the ones
+//! which mean the connection or the network link has gone, and the ones
+//! which mean there is nothing to read. The mappings don't touch libmodem,
+//! so they are tested on the host, with the numbers given here; built for
+//! the device, those are checked against `nrfxlib-sys`.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::Error;
+use core::convert::TryFrom;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// Resource temporarily unavailable
+const NRF_EAGAIN: u32 = 35;
+/// Network is down
+const NRF_ENETDOWN: u32 = 50;
+/// Network is unreachable
+const NRF_ENETUNREACH: u32 = 51;
+/// Connection aborted by network
+const NRF_ENETRESET: u32 = 52;
+/// Software caused connection abort
+const NRF_ECONNABORTED: u32 = 53;
+/// Connection reset by peer
+const NRF_ECONNRESET: u32 = 54;
+/// Transport endpoint is not connected
+const NRF_ENOTCONN: u32 = 57;
+/// Connection timed out
+const NRF_ETIMEDOUT: u32 = 60;
+/// Host is down
+const NRF_EHOSTDOWN: u32 = 112;
+
+#[cfg(target_arch = "arm")]
+const _: () = {
+	use nrfxlib_sys as sys;
+	assert!(NRF_EAGAIN == sys::NRF_EAGAIN);
+	assert!(NRF_ENETDOWN == sys::NRF_ENETDOWN);
+	assert!(NRF_ENETUNREACH == sys::NRF_ENETUNREACH);
+	assert!(NRF_ENETRESET == sys::NRF_ENETRESET);
+	assert!(NRF_ECONNABORTED == sys::NRF_ECONNABORTED);
+	assert!(NRF_ECONNRESET == sys::NRF_ECONNRESET);
+	assert!(NRF_ENOTCONN == sys::NRF_ENOTCONN);
+	assert!(NRF_ETIMEDOUT == sys::NRF_ETIMEDOUT);
+	assert!(NRF_EHOSTDOWN == sys::NRF_EHOSTDOWN);
+};
+
+/// The errors which mean the connection has gone.
+const DISCONNECTED: [u32; 4] = [
+	NRF_ECONNRESET,
+	NRF_ECONNABORTED,
+	NRF_ENETRESET,
+	NRF_ENOTCONN,
+];
+
+/// The errors which mean the network link has gone.
+const LINK_DOWN: [u32; 2] = [NRF_ENETDOWN, NRF_ENETUNREACH];
+
+/// The `connect` errors which suggest the address has gone stale.
+const STALE: [u32; 6] = [
+	NRF_ETIMEDOUT,
+	NRF_EHOSTDOWN,
+	NRF_ENETUNREACH,
+	NRF_ECONNRESET,
+	NRF_ECONNABORTED,
+	NRF_ENETRESET,
+];
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Turn a failed socket call into an `Error`, picking out the errors which
+/// mean the connection or the link has gone.
+pub(crate) fn socket_error(name: &'static str, result: i32, errno: i32) -> Error {
+	if is_one_of(&DISCONNECTED, errno) {
+		Error::Disconnected
+	} else if is_one_of(&LINK_DOWN, errno) {
+		Error::LinkDown
+	} else {
+		Error::Nordic(name, result, errno)
+	}
+}
+
+/// Does this `connect` error suggest the address we tried has gone stale,
+/// so it's worth looking the hostname up again?
+pub(crate) fn is_stale_address_error(errno: i32) -> bool {
+	is_one_of(&STALE, errno)
+}
+
+/// Turn what a non-blocking `nrf_recv` returned, and the error number
+/// straight afterwards, into our `recv` model: `Ok(None)` if there was
+/// nothing to read, and `Error::Disconnected` if a stream (`stream`) has
+/// been closed by the peer, which it shows by returning zero bytes.
+pub(crate) fn recv_status(
+	name: &'static str,
+	stream: bool,
+	result: i32,
+	errno: i32,
+) -> Result<Option<usize>, Error> {
+	match result {
+		-1 if errno == NRF_EAGAIN as i32 => Ok(None),
+		0 if stream => Err(Error::Disconnected),
+		n => match usize::try_from(n) {
+			Ok(length) => Ok(Some(length)),
+			Err(_) => Err(socket_error(name, n, errno)),
+		},
+	}
+}
+
+/// Is `errno` one of `errors`?
+fn is_one_of(errors: &[u32], errno: i32) -> bool {
+	errors.iter().any(|&e| e as i32 == errno)
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn recv_with_nothing_to_read_is_none() {
+		assert!(matches!(
+			recv_status("recv", true, -1, NRF_EAGAIN as i32),
+			Ok(None)
+		));
+		assert!(matches!(
+			recv_status("recv", false, -1, NRF_EAGAIN as i32),
+			Ok(None)
+		));
+	}
+
+	#[test]
+	fn recv_of_data_is_its_length() {
+		assert!(matches!(recv_status("recv", true, 12, 0), Ok(Some(12))));
+		// An empty datagram is still a datagram
+		assert!(matches!(recv_status("recv", false, 0, 0), Ok(Some(0))));
+	}
+
+	#[test]
+	fn recv_of_nothing_from_a_stream_is_peer_closed() {
+		assert!(matches!(
+			recv_status("recv", true, 0, 0),
+			Err(Error::Disconnected)
+		));
+	}
+
+	#[test]
+	fn recv_after_reset_is_peer_closed() {
+		for &errno in DISCONNECTED.iter() {
+			assert!(matches!(
+				recv_status("recv", true, -1, errno as i32),
+				Err(Error::Disconnected)
+			));
+		}
+	}
+
+	#[test]
+	fn recv_with_the_network_gone_is_link_down() {
+		for &errno in LINK_DOWN.iter() {
+			assert!(matches!(
+				recv_status("recv", true, -1, errno as i32),
+				Err(Error::LinkDown)
+			));
+		}
+	}
+
+	#[test]
+	fn other_errors_are_passed_on() {
+		assert!(matches!(
+			socket_error("recv", -1, NRF_ETIMEDOUT as i32),
+			Error::Nordic("recv", -1, errno) if errno == NRF_ETIMEDOUT as i32
+		));
+		assert!(matches!(
+			recv_status("recv_wait", true, -1, NRF_EHOSTDOWN as i32),
+			Err(Error::Nordic("recv_wait", -1, errno)) if errno == NRF_EHOSTDOWN as i32
+		));
+	}
+
+	#[test]
+	fn stale_address_errors() {
+		assert!(is_stale_address_error(NRF_ETIMEDOUT as i32));
+		assert!(is_stale_address_error(NRF_ECONNRESET as i32));
+		assert!(!is_stale_address_error(NRF_EAGAIN as i32));
+		assert!(!is_stale_address_error(NRF_ENETDOWN as i32));
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -113,28 +113,39 @@ pub fn get_last_error() -> i32 {
 
 /// Function required by BSD library
 #[no_mangle]
-pub extern "C" fn nrf_modem_os_timedwait(_context: u32, p_timeout_ms: *const i32) -> i32 {
+pub extern "C" fn nrf_modem_os_timedwait(context: u32, p_timeout_ms: *const i32) -> i32 {
 	let timeout_ms = unsafe { *p_timeout_ms };
+	// libmodem waits in here while it blocks, so keep the watchdog fed
+	crate::watchdog::feed();
+	if let Some(result) = crate::dns::lookup_wait(context, timeout_ms) {
+		return result;
+	}
 	if timeout_ms < 0 {
 		// With Zephyr, negative timeouts pend on a semaphore with K_FOREVER.
 		// We can't do that here.
 		0i32
 	} else {
-		// NRF9160 runs at 64 MHz, so this is close enough
-		cortex_m::asm::delay((timeout_ms as u32) * 64_000);
+		// Spins, unless a `delay::DelayProvider` has been set
+		crate::delay::delay_ms(timeout_ms as u32);
 		nrfxlib_sys::NRF_ETIMEDOUT as i32
 	}
 }
 
-/// Function required by BSD library
+/// Function required by BSD library. Passes modem traces on according to
+/// the `traces::TracePolicy` given to `NrfxlibBuilder`, if any.
 #[no_mangle]
-pub extern "C" fn nrf_modem_os_trace_put(_data: *const u8, _len: u32) -> i32 {
-	// Do nothing
+pub extern "C" fn nrf_modem_os_trace_put(data: *const u8, len: u32) -> i32 {
+	if !data.is_null() {
+		crate::traces::put(unsafe { core::slice::from_raw_parts(data, len as usize) });
+	}
 	0
 }
 
-/// Function required by BSD library
+/// Function required by BSD library. libmodem calls this when it has failed
+/// and can't carry on, and it mustn't return - so we panic, and leave the
+/// application's panic handler to reset the device.
 #[no_mangle]
+#[allow(clippy::panic)]
 pub extern "C" fn nrf_modem_irrecoverable_error_handler(err: u32) -> ! {
 	panic!("bsd_irrecoverable_error_handler({})", err);
 }
@@ -193,12 +204,16 @@ pub extern "C" fn nrfx_ipc_config_load(p_config: *const NrfxIpcConfig) {
 
 		let ipc = &(*nrf9160_pac::IPC_NS::ptr());
 
-		for (i, value) in config.send_task_config.iter().enumerate() {
-			ipc.send_cnf[i as usize].write(|w| w.bits(*value));
+		for (register, value) in ipc.send_cnf.iter().zip(config.send_task_config.iter()) {
+			register.write(|w| w.bits(*value));
 		}
 
-		for (i, value) in config.receive_event_config.iter().enumerate() {
-			ipc.receive_cnf[i as usize].write(|w| w.bits(*value));
+		for (register, value) in ipc
+			.receive_cnf
+			.iter()
+			.zip(config.receive_event_config.iter())
+		{
+			register.write(|w| w.bits(*value));
 		}
 
 		ipc.intenset
@@ -221,23 +236,39 @@ pub extern "C" fn nrfx_ipc_init(
 	handler: NrfxIpcHandler,
 	p_context: usize,
 ) -> NrfxErr {
+	use core::sync::atomic::Ordering;
 	use cortex_m::interrupt::InterruptNumber;
 	let irq = nrf9160_pac::Interrupt::IPC;
 	let irq_num = usize::from(irq.number());
+	// The context must be in place before the handler can be seen
+	let claimed = cortex_m::interrupt::free(|_cs| {
+		if IPC_HANDLER.load(Ordering::SeqCst) != 0 {
+			return false;
+		}
+		IPC_CONTEXT.store(p_context, Ordering::SeqCst);
+		IPC_HANDLER.store(handler as usize, Ordering::SeqCst);
+		true
+	});
+	if !claimed {
+		return NrfxErr::ErrorInvalidState;
+	}
 	unsafe {
+		if let Some(ipr) = (*cortex_m::peripheral::NVIC::PTR).ipr.get(irq_num) {
+			ipr.write(irq_priority);
+		}
 		cortex_m::peripheral::NVIC::unmask(irq);
-		(*cortex_m::peripheral::NVIC::PTR).ipr[irq_num].write(irq_priority);
 	}
-	IPC_CONTEXT.store(p_context, core::sync::atomic::Ordering::SeqCst);
-	IPC_HANDLER.store(handler as usize, core::sync::atomic::Ordering::SeqCst);
 	// Report success
 	NrfxErr::Success
 }
 
-/// Function for uninitializing the IPC module.
+/// Function for uninitializing the IPC module. libmodem calls this from
+/// `nrf_modem_shutdown`.
 #[no_mangle]
 pub extern "C" fn nrfx_ipc_uninit() {
-	unimplemented!();
+	cortex_m::peripheral::NVIC::mask(nrf9160_pac::Interrupt::IPC);
+	IPC_HANDLER.store(0, core::sync::atomic::Ordering::SeqCst);
+	IPC_CONTEXT.store(0, core::sync::atomic::Ordering::SeqCst);
 }
 
 /// Allocate some memory from the given heap.
@@ -275,7 +306,8 @@ unsafe fn generic_alloc(num_bytes_requested: usize, heap: &crate::WrappedHeap) -
 ///
 /// First we must wind the pointer back four bytes to recover the `usize` we
 /// stashed during the allocation. We use this to recreate the `Layout` required
-/// for the `deallocate` function.
+/// for the `deallocate` function. With the `zeroize` feature, the block is
+/// wiped first, as it may have held a credential on its way to the modem.
 ///
 /// This function is safe to call from an ISR.
 unsafe fn generic_free(ptr: *mut u8, heap: &crate::WrappedHeap) {
@@ -288,6 +320,10 @@ unsafe fn generic_free(ptr: *mut u8, heap: &crate::WrappedHeap) {
 			num_bytes_allocated,
 			sizeof_usize as usize,
 		);
+		crate::wipe(core::slice::from_raw_parts_mut(
+			ptr,
+			num_bytes_allocated - sizeof_usize as usize,
+		));
 		if let Some(ref mut inner_alloc) = *heap.borrow(cs).borrow_mut() {
 			inner_alloc.deallocate(core::ptr::NonNull::new_unchecked(real_ptr), layout);
 		}
@@ -305,11 +341,20 @@ pub unsafe fn ipc_irq_handler() {
 	while bitmask != 0 {
 		let event_idx = bitmask.trailing_zeros();
 		bitmask ^= 1 << event_idx;
-		(*nrf9160_pac::IPC_NS::ptr()).events_receive[event_idx as usize].write(|w| w.bits(0));
+		if let Some(event) = (*nrf9160_pac::IPC_NS::ptr())
+			.events_receive
+			.get(event_idx as usize)
+		{
+			event.write(|w| w.bits(0));
+		}
 	}
 
 	// Execute interrupt handler to provide information about events to app
 	let handler_addr = IPC_HANDLER.load(core::sync::atomic::Ordering::SeqCst);
+	if handler_addr == 0 {
+		// Not initialised, or shut down since
+		return;
+	}
 	let handler = core::mem::transmute::<usize, NrfxIpcHandler>(handler_addr);
 	let context = IPC_CONTEXT.load(core::sync::atomic::Ordering::SeqCst);
 	(handler)(events_map, context as *mut u8);
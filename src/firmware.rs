@@ -0,0 +1,258 @@
+//! # Modem firmware versions for nrfxlib
+//!
+//! Some AT commands changed between modem firmware releases - a parameter
+//! became mandatory, or a command appeared for the first time. Rather than
+//! each helper guessing, they look up the command to send in a table of
+//! `SyntaxRule`s, according to the firmware the modem is running. Call
+//! `detect` once the library is up (it reads `AT+CGMR`), and helpers such as
+//! `UrcDispatcher::subscribe` and `modem::evaluate_connection` then use the
+//! right syntax for it:
+//!
+//! ```ignore
+//! nrfxlib::init()?;
+//! let version = firmware::detect()?;
+//! info!("Modem firmware {}", version);
+//! ```
+//!
+//! Until the version is known, every helper sends the syntax it always has.
+//! The table covers firmware 1.2 and 1.3, which this version of libmodem
+//! supports. If your firmware needs something different, give your own
+//! table to `set_rules`.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+use crate::Error;
+#[cfg(target_arch = "arm")]
+use core::cell::RefCell;
+#[cfg(target_arch = "arm")]
+use cortex_m::interrupt::Mutex;
+#[cfg(target_arch = "arm")]
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A modem firmware version, e.g. 1.3.1 for `mfw_nrf9160_1.3.1`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FirmwareVersion {
+	/// The major version
+	pub major: u8,
+	/// The minor version
+	pub minor: u8,
+	/// The patch version
+	pub patch: u8,
+}
+
+/// The commands whose syntax depends on the firmware version.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AtSyntax {
+	/// Subscribing to `%XMODEMSLEEP` reports (`UrcKind::ModemSleep`)
+	ModemSleepReports,
+	/// Evaluating the connection (`modem::evaluate_connection`)
+	ConnectionEvaluation,
+	/// Setting the Release Assistance Indication
+	/// (`modem::set_release_assistance`)
+	ReleaseAssistance,
+}
+
+/// One row of the syntax table: the command to send for `syntax` on
+/// firmware `since` and later, up to the next row for the same `syntax`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SyntaxRule {
+	/// Which command this row is for
+	pub syntax: AtSyntax,
+	/// The first firmware version this row applies to
+	pub since: FirmwareVersion,
+	/// The command to send, or `None` if these versions don't support it
+	pub command: Option<&'static str>,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The table used unless `set_rules` is given another.
+pub const DEFAULT_RULES: &[SyntaxRule] = &[
+	// From 1.3.0, `<warning_time>` and `<threshold>` are given. We ask for
+	// no warning, and only sleeps of at least ten seconds.
+	SyntaxRule {
+		syntax: AtSyntax::ModemSleepReports,
+		since: FirmwareVersion::new(1, 3, 0),
+		command: Some("AT%XMODEMSLEEP=1,0,10240"),
+	},
+	// `%CONEVAL` arrived in 1.2.0
+	SyntaxRule {
+		syntax: AtSyntax::ConnectionEvaluation,
+		since: FirmwareVersion::new(0, 0, 0),
+		command: None,
+	},
+	SyntaxRule {
+		syntax: AtSyntax::ConnectionEvaluation,
+		since: FirmwareVersion::new(1, 2, 0),
+		command: Some("AT%CONEVAL"),
+	},
+];
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The firmware version found by `detect`, if it has been called.
+#[cfg(target_arch = "arm")]
+static VERSION: Mutex<RefCell<Option<FirmwareVersion>>> = Mutex::new(RefCell::new(None));
+
+/// The table given to `set_rules`.
+#[cfg(target_arch = "arm")]
+static RULES: Mutex<RefCell<&'static [SyntaxRule]>> = Mutex::new(RefCell::new(DEFAULT_RULES));
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Find out which firmware the modem is running, using `AT+CGMR`, and use
+/// the syntax for it from now on.
+#[cfg(target_arch = "arm")]
+pub fn detect() -> Result<FirmwareVersion, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT+CGMR", |res| {
+		if let Some(version) = FirmwareVersion::parse(res) {
+			result = Ok(version);
+		}
+	})?;
+	debug!("AT+CGMR => {:?}", result);
+	let version = result?;
+	record(version);
+	Ok(version)
+}
+
+/// Get the firmware version found by `detect` (or by reading `AT+CGMR`
+/// through `device::ModemInfo`), if it has been.
+#[cfg(target_arch = "arm")]
+pub fn current() -> Option<FirmwareVersion> {
+	cortex_m::interrupt::free(|cs| *VERSION.borrow(cs).borrow())
+}
+
+/// Use a different syntax table, e.g. with rows for firmware this crate
+/// doesn't know about. It replaces `DEFAULT_RULES` completely, so start
+/// from a copy of those.
+#[cfg(target_arch = "arm")]
+pub fn set_rules(rules: &'static [SyntaxRule]) {
+	cortex_m::interrupt::free(|cs| {
+		*RULES.borrow(cs).borrow_mut() = rules;
+	});
+}
+
+/// Get the syntax table in use.
+#[cfg(target_arch = "arm")]
+pub fn rules() -> &'static [SyntaxRule] {
+	cortex_m::interrupt::free(|cs| *RULES.borrow(cs).borrow())
+}
+
+/// Get the command to send for `syntax` on the firmware the modem is
+/// running. If the version isn't known, or no row in the table covers it,
+/// this is `AtSyntax::default_command`. Returns
+/// `Error::UnsupportedFirmware` if the firmware doesn't have the command -
+/// by the table, or by `modem::supported_commands`, if that has been read.
+#[cfg(target_arch = "arm")]
+pub fn command(syntax: AtSyntax) -> Result<&'static str, Error> {
+	let command = match current() {
+		Some(version) => {
+			let rule = rules()
+				.iter()
+				.filter(|rule| rule.syntax == syntax && rule.since <= version)
+				.max_by_key(|rule| rule.since);
+			match rule {
+				Some(rule) => rule.command.ok_or(Error::UnsupportedFirmware)?,
+				None => syntax.default_command(),
+			}
+		}
+		None => syntax.default_command(),
+	};
+	match crate::modem::listed(command) {
+		Some(false) => Err(Error::UnsupportedFirmware),
+		_ => Ok(command),
+	}
+}
+
+impl FirmwareVersion {
+	/// Make a version number.
+	pub const fn new(major: u8, minor: u8, patch: u8) -> FirmwareVersion {
+		FirmwareVersion {
+			major,
+			minor,
+			patch,
+		}
+	}
+
+	/// Read the version from the response to `AT+CGMR`, e.g.
+	/// `mfw_nrf9160_1.3.1`. Anything after the patch number (e.g. `-FOTA`)
+	/// is ignored. Returns `None` if there's no version there.
+	pub fn parse(line: &str) -> Option<FirmwareVersion> {
+		let line = line.trim();
+		let version = &line[line.rfind('_').map_or(0, |idx| idx + 1)..];
+		let mut numbers = version.splitn(3, '.');
+		let major = numbers.next()?.parse().ok()?;
+		let minor = numbers.next()?.parse().ok()?;
+		let patch = numbers.next()?;
+		let digits = patch
+			.find(|c: char| !c.is_ascii_digit())
+			.unwrap_or(patch.len());
+		let patch = patch[0..digits].parse().ok()?;
+		Some(FirmwareVersion::new(major, minor, patch))
+	}
+}
+
+impl core::fmt::Display for FirmwareVersion {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+	}
+}
+
+impl AtSyntax {
+	/// The command sent when the firmware version isn't known - the one
+	/// this crate has always sent.
+	pub fn default_command(self) -> &'static str {
+		match self {
+			AtSyntax::ModemSleepReports => "AT%XMODEMSLEEP=1",
+			AtSyntax::ConnectionEvaluation => "AT%CONEVAL",
+			AtSyntax::ReleaseAssistance => "AT%XRAI",
+		}
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Record the firmware version, once we know it.
+#[cfg(target_arch = "arm")]
+pub(crate) fn record(version: FirmwareVersion) {
+	cortex_m::interrupt::free(|cs| {
+		*VERSION.borrow(cs).borrow_mut() = Some(version);
+	});
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,187 @@
+//! # Receive flow control for nrfxlib
+//!
+//! An application which can't keep up with what a socket receives - a FOTA
+//! download written to slow flash, say - has to stop reading rather than
+//! queue without limit. `Socket::pause_receive` stops us reading, so the
+//! data waits in the modem and, on a TCP socket, the receive window closes
+//! and the server stops sending. `RxFlowControl` decides when, with a high
+//! and a low watermark on the application's own queue, so it doesn't flap
+//! between paused and not on every block:
+//!
+//! ```ignore
+//! let flow = RxFlowControl::new(12 * 1024, 4 * 1024);
+//! loop {
+//!     flow.update(&socket, queue.len());
+//!     if let Some(n) = socket.recv(&mut buffer)? {
+//!         queue.extend(&buffer[0..n]);
+//!     }
+//!     // ... meanwhile, another task drains `queue` into flash
+//! }
+//! ```
+//!
+//! While paused, `recv` returns `Ok(None)` and `recv_wait` fails with
+//! `Error::ReceivePaused`.
+//!
+//! The other way round, an application which writes what it receives to
+//! flash does best to write a page at a time, not a packet at a time. An
+//! `RxWatermark` peeks (see `Socket::peek`) at how much is waiting, and
+//! calls a handler once there is at least a page of it:
+//!
+//! ```ignore
+//! fn page_ready(socket: &Socket, waiting: usize) {
+//!     // read a page with `socket.recv` and write it to flash
+//! }
+//!
+//! let watermark = RxWatermark::new(4096, page_ready);
+//! loop {
+//!     watermark.poll(&socket, &mut scratch)?;
+//!     // ... sleep until the next poll
+//! }
+//! ```
+//!
+//! The handler is called from every `poll` which finds the threshold
+//! reached, so it should read at least some of what is waiting. Whatever is
+//! left when the transfer finishes, short of the threshold, can be read
+//! with `recv` as usual.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::raw::Socket;
+use crate::Error;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Pauses a socket when the application's queue gets too full, and resumes
+/// it once the queue has drained. See the module documentation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RxFlowControl {
+	high_water: usize,
+	low_water: usize,
+}
+
+/// Called by `RxWatermark::poll` with the socket and how many bytes are
+/// waiting on it (at least the threshold), when the threshold is reached.
+pub type WatermarkHandler = fn(&Socket, usize);
+
+/// Calls a handler when enough data is waiting on a socket. See the module
+/// documentation.
+#[derive(Debug, Copy, Clone)]
+pub struct RxWatermark {
+	threshold: usize,
+	handler: WatermarkHandler,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl RxFlowControl {
+	/// Pause once the queue holds `high_water` bytes (or more), and resume
+	/// once it is down to `low_water` (or less). A `low_water` above
+	/// `high_water` is taken as `high_water`.
+	pub fn new(high_water: usize, low_water: usize) -> RxFlowControl {
+		RxFlowControl {
+			high_water,
+			low_water: core::cmp::min(low_water, high_water),
+		}
+	}
+
+	/// Pause or resume `socket`, given that the queue now holds `queued`
+	/// bytes. In between the watermarks, it's left as it is. Returns whether
+	/// the socket is paused.
+	pub fn update(&self, socket: &Socket, queued: usize) -> bool {
+		if queued >= self.high_water {
+			socket.pause_receive();
+		} else if queued <= self.low_water {
+			socket.resume_receive();
+		}
+		socket.is_receive_paused()
+	}
+
+	/// The high watermark
+	pub fn high_water(&self) -> usize {
+		self.high_water
+	}
+
+	/// The low watermark
+	pub fn low_water(&self) -> usize {
+		self.low_water
+	}
+}
+
+impl RxWatermark {
+	/// Call `handler` whenever `threshold` bytes (at least one) are waiting.
+	pub fn new(threshold: usize, handler: WatermarkHandler) -> RxWatermark {
+		RxWatermark {
+			threshold: core::cmp::max(threshold, 1),
+			handler,
+		}
+	}
+
+	/// See how much is waiting on `socket`, up to the threshold, and call
+	/// the handler if the threshold is reached. `scratch` is peeked into, so
+	/// it must hold at least `threshold` bytes, or this fails with
+	/// `Error::BufferFull`. Returns how many bytes are waiting, counting no
+	/// further than the threshold.
+	///
+	/// On a datagram socket only the next datagram can be seen, so that is
+	/// what counts.
+	pub fn poll(&self, socket: &Socket, scratch: &mut [u8]) -> Result<usize, Error> {
+		let scratch = scratch
+			.get_mut(0..self.threshold)
+			.ok_or(Error::BufferFull)?;
+		let waiting = socket.peek(scratch)?.unwrap_or(0);
+		if waiting >= self.threshold {
+			(self.handler)(socket, waiting);
+		}
+		Ok(waiting)
+	}
+
+	/// How many bytes must be waiting for the handler to be called.
+	pub fn threshold(&self) -> usize {
+		self.threshold
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// End of File
+//******************************************************************************
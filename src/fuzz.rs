@@ -0,0 +1,260 @@
+//! # Fuzzing entry points for nrfxlib
+//!
+//! Everything the modem sends us - AT responses, URCs, SMS PDUs - goes
+//! through a parser before we act on it, and a parser which panics on
+//! malformed input takes the whole device down. These functions feed
+//! arbitrary bytes through each family of parsers, so they can be run under
+//! a fuzzer on the host (with the `std` feature). The targets in `fuzz/` use
+//! them with `cargo fuzz`:
+//!
+//! ```text
+//! cargo +nightly fuzz run at_response
+//! ```
+//!
+//! Each function throws away what it parses. All that matters is that it
+//! returns.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::net::{IpAddr, SocketAddr};
+use core::str::FromStr;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Treat `data` as one read from an AT socket, and frame it into lines and
+/// final responses as `AtSocket::poll_response` does.
+pub fn at_response(data: &[u8]) {
+	if let Ok(text) = crate::at::response_text(data) {
+		for line in text.lines() {
+			let _ = crate::at::parse_final_response(line.trim());
+		}
+	}
+}
+
+/// Treat `data` as one read from an AT socket, and give each line to every
+/// URC and response parser.
+pub fn urc(data: &[u8]) {
+	if let Ok(text) = crate::at::response_text(data) {
+		for line in text.lines() {
+			let _ = crate::modem::parse_cereg(line, true);
+			let _ = crate::modem::parse_cereg(line, false);
+			let _ = crate::modem::parse_connstat(line);
+			let _ = crate::modem::parse_error_cause(line);
+			let _ = crate::modem::PsmGranted::parse(line);
+			let _ = crate::modem::EdrxGranted::parse(line);
+			let _ = crate::modem::SignalQuality::parse(line);
+			let _ = crate::modem::ConnectionEvaluation::parse(line);
+			let _ = crate::modem::Operator::parse(line);
+			let _ = crate::pdn::PdnEvent::parse(line);
+			let _ = crate::pdn::Addresses::parse(line);
+			let _ = crate::sms::ServiceSupport::parse(line);
+			let _ = crate::firmware::FirmwareVersion::parse(line);
+			let _ = crate::urc::Urc::parse(line);
+			let _ = crate::tls_credentials::CredentialInfo::parse(line);
+			#[cfg(feature = "std")]
+			let _ = crate::transcript::parse_line(line);
+		}
+	}
+}
+
+/// Treat `data` as the line after a `+CMT` URC: an SMS-DELIVER PDU in hex.
+/// The raw bytes are also tried as a User Data Header.
+pub fn sms_pdu(data: &[u8]) {
+	if let Ok(text) = core::str::from_utf8(data) {
+		if let Ok(message) = crate::sms::Deliver::parse(text) {
+			let _ = message.text();
+		}
+	}
+	let _ = crate::sms::ConcatInfo::parse_udh(data);
+}
+
+/// Treat `data` as text from the network: an address someone gave us, or
+/// the start of an HTTP response. It's also tried as a CoAP message.
+pub fn network_text(data: &[u8]) {
+	let _ = crate::net::parse_status_line(data);
+	let _ = crate::coap::Message::parse(data);
+	if let Ok(text) = core::str::from_utf8(data) {
+		let _ = IpAddr::from_str(text);
+		let _ = SocketAddr::from_str(text);
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Lines the modem really sends, for `at_response` and `urc`.
+	const AT_LINES: &[&str] = &[
+		"OK\r\n",
+		"ERROR\r\n",
+		"+CME ERROR: 516\r\n",
+		"+CMS ERROR: 305\r\n",
+		"+CEREG: 5,\"1A2B\",\"011B0780\",7,,,\"11100000\",\"11100000\"\r\n",
+		"+CEREG: 2,0,\"1A2B\",\"011B0780\",7,0,13\r\n",
+		"+CESQ: 99,99,255,255,31,62\r\n",
+		"%CONEVAL: 0,1,5,8,2,14,\"011B0780\",\"26295\",7,1575,3,1,1,23,16,32,130\r\n",
+		"+COPS: 0,2,\"26201\",7\r\n",
+		"+CGPADDR: 0,\"10.0.0.1\",\"2001:DB8:0:0:0:0:0:1\"\r\n",
+		"+CGEV: ME PDN ACT 0\r\n",
+		"%XCONNSTAT: 0,0,0,0,0,0\r\n",
+		"+CEER: \"EMM\",15\r\n",
+		"%XMONITOR: 1,\"\",\"\",\"26201\",\"1A2B\",7,20,\"011B0780\"\r\n",
+		"%CMNG: 42,0,\"2C43952EE9E000FF2ACC4E2ED0897C0A72AD5FA72C3D934E81741CBD54F05BD1\"\r\n",
+		"mfw_nrf9160_1.3.2\r\n",
+	];
+
+	/// A real SMS-DELIVER ("How are you?"), in hex as `+CMT` gives it.
+	const SMS_PDU: &str =
+		"07911326040000F0040B911346610089F60000208062917314080CC8F71D14969741F977FD07";
+
+	/// Text from the network, and a CoAP GET with a token and a path.
+	const NETWORK: &[&[u8]] = &[
+		b"HTTP/1.1 200 OK\r\n",
+		b"2001:db8::ffff:10.0.0.1",
+		b"[2001:db8::1]:5683",
+		b"10.0.0.1:80",
+		&[
+			0x44, 0x01, 0x12, 0x34, 1, 2, 3, 4, 0xB4, b't', b'e', b's', b't', 0xFF, b'!',
+		],
+	];
+
+	/// Every family of parsers, given the same bytes.
+	fn all_parsers(data: &[u8]) {
+		at_response(data);
+		urc(data);
+		sms_pdu(data);
+		network_text(data);
+	}
+
+	/// Each sample, as bytes.
+	fn samples() -> impl Iterator<Item = &'static [u8]> {
+		AT_LINES
+			.iter()
+			.map(|line| line.as_bytes())
+			.chain(core::iter::once(SMS_PDU.as_bytes()))
+			.chain(NETWORK.iter().copied())
+	}
+
+	#[test]
+	fn parsers_take_empty_input() {
+		all_parsers(&[]);
+		all_parsers(b"\r\n");
+		all_parsers(&[0]);
+	}
+
+	#[test]
+	fn parsers_take_truncated_input() {
+		for sample in samples() {
+			for length in 0..=sample.len() {
+				all_parsers(sample.get(0..length).unwrap_or(&[]));
+			}
+		}
+	}
+
+	#[test]
+	fn parsers_take_non_utf8_input() {
+		all_parsers(&[0xFF, 0xFE]);
+		all_parsers(&[0xC3]);
+		all_parsers(&[0xE2, 0x82]);
+		let mut buffer = [0u8; 128];
+		for sample in samples() {
+			let sample = sample.get(0..buffer.len()).unwrap_or(sample);
+			for position in 0..sample.len() {
+				// An unfinished two-byte sequence, then a stray continuation byte
+				for &bad in [0xC3, 0x80].iter() {
+					let data = buffer.get_mut(0..sample.len()).unwrap_or_default();
+					data.copy_from_slice(sample);
+					if let Some(byte) = data.get_mut(position) {
+						*byte = bad;
+					}
+					all_parsers(data);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn non_utf8_response_is_rejected() {
+		assert!(matches!(
+			crate::at::response_text(b"OK\xFF\r\n"),
+			Err(crate::Error::BadDataFormat)
+		));
+		assert!(crate::net::parse_status_line(b"HTTP/1.1 \xC3 OK\r\n").is_none());
+	}
+
+	#[test]
+	fn truncated_sms_is_rejected() {
+		assert!(crate::sms::Deliver::parse(SMS_PDU).is_ok());
+		for length in 0..SMS_PDU.len() {
+			let truncated = SMS_PDU.get(0..length).unwrap_or("");
+			assert!(
+				crate::sms::Deliver::parse(truncated).is_err(),
+				"{} hex digits",
+				length
+			);
+		}
+	}
+
+	#[test]
+	fn truncated_udh_is_rejected() {
+		let udh = [5, 0, 3, 0x42, 2, 1];
+		assert!(crate::sms::ConcatInfo::parse_udh(&udh).is_some());
+		for length in 0..udh.len() {
+			let truncated = udh.get(0..length).unwrap_or(&[]);
+			assert!(crate::sms::ConcatInfo::parse_udh(truncated).is_none());
+		}
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -5,6 +5,13 @@
 //! Currently only GPS is supported by the nRF9160 modem firmware, but they
 //! could add GLONASS, Galileo and Beidou in the future.
 //!
+//! Position frames can be decoded into a `PvtFrame` with `GnssData::pvt`.
+//! Without assistance data a cold start can take minutes, so assistance
+//! data fetched some other way (e.g. from a cloud service, over LTE) can be
+//! given to the GNSS module with `GnssSocket::inject_agps`, as and when it
+//! asks with a `GnssData::Agps` frame. Valid fixes also update the
+//! `last_position` module.
+//!
 //! Copyright (c) 42 Technology Ltd 2019
 //!
 //! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
@@ -21,6 +28,8 @@
 //******************************************************************************
 
 use super::{get_last_error, Error};
+use crate::agps::AgpsKind;
+pub use crate::last_position::DateTime;
 use crate::raw::*;
 use nrfxlib_sys as sys;
 
@@ -91,15 +100,134 @@ pub enum DeleteField {
 	WeekNumber = 1 << 5,
 	/// Bit 6 denotes leap second (UTC parameters) data.
 	LeapSecond = 1 << 6,
-	/// Bit 7 denotes local clock (TCXO) frequency offset data.
+	/// Bit 7 denotes local clock (TCXO) frequency offset data. Deleting this
+	/// makes the next fix take longer, while the modem re-calibrates.
 	LocalClockFrequencyOffset = 1 << 7,
 }
 
+/// A position, velocity and time (PVT) fix, decoded from a
+/// `GnssData::Position` frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PvtFrame {
+	/// Latitude, in degrees
+	pub latitude: f64,
+	/// Longitude, in degrees
+	pub longitude: f64,
+	/// Altitude above the WGS-84 ellipsoid, in metres
+	pub altitude: f32,
+	/// Accuracy (2D 1-sigma), in metres
+	pub accuracy: f32,
+	/// Horizontal speed, in metres per second
+	pub speed: f32,
+	/// Heading of movement, in degrees
+	pub heading: f32,
+	/// The UTC date and time
+	pub datetime: DateTime,
+	/// Position dilution of precision
+	pub pdop: f32,
+	/// Horizontal dilution of precision
+	pub hdop: f32,
+	/// Vertical dilution of precision
+	pub vdop: f32,
+	/// Time dilution of precision
+	pub tdop: f32,
+	/// Whether the fix is valid, and other status
+	pub flags: PvtFlags,
+	/// The satellites being tracked
+	pub satellites: heapless::Vec<Satellite, MAX_SATELLITES>,
+}
+
+/// The flags in a PVT frame.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PvtFlags(u8);
+
+/// A satellite the GNSS module is tracking.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Satellite {
+	/// The satellite number (1 to 32 for GPS)
+	pub sv: u16,
+	/// The signal type (1 is GPS L1C/A)
+	pub signal: u8,
+	/// The carrier-to-noise density ratio, in 0.1 dB-Hz
+	pub cn0: u16,
+	/// Elevation, in degrees
+	pub elevation: i16,
+	/// Azimuth, in degrees
+	pub azimuth: i16,
+	/// This satellite was used to compute the fix
+	pub used_in_fix: bool,
+	/// This satellite is unhealthy
+	pub unhealthy: bool,
+}
+
+/// Which start performance the GNSS module aims for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StartMode {
+	/// The best time to the first fix from a cold start (the default)
+	SingleColdStart,
+	/// The best times for repeated hot starts, e.g. when taking a fix every
+	/// few minutes
+	MultipleHotStart,
+}
+
+/// Which accuracy of fix the GNSS module may report.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Accuracy {
+	/// Normal accuracy fixes (the default)
+	Normal,
+	/// Less accurate fixes are allowed, which can come sooner and with fewer
+	/// satellites
+	Low,
+}
+
+/// Whether the GNSS module may duty-cycle its tracking to save power. It
+/// produces fixes at the same rate either way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PowerSaveMode {
+	/// Track continuously (the default)
+	Disabled,
+	/// Duty-cycle when it costs little performance
+	DutyCyclingPerformance,
+	/// Duty-cycle whenever the performance is acceptable
+	DutyCyclingPower,
+}
+
+/// Assistance data to give the GNSS module with `GnssSocket::inject_agps`,
+/// in the formats libmodem defines (see `nrf_socket.h` for the scale of each
+/// field).
+#[derive(Debug, Copy, Clone)]
+pub enum AgpsData {
+	/// GPS UTC parameters
+	UtcParameters(sys::nrf_gnss_agps_data_utc_t),
+	/// The ephemeris of one satellite
+	Ephemeris(sys::nrf_gnss_agps_data_ephemeris_t),
+	/// The almanac of one satellite
+	Almanac(sys::nrf_gnss_agps_data_almanac_t),
+	/// Klobuchar model ionospheric corrections
+	KlobucharIonosphere(sys::nrf_gnss_agps_data_klobuchar_t),
+	/// NeQuick model ionospheric corrections
+	NequickIonosphere(sys::nrf_gnss_agps_data_nequick_t),
+	/// GPS system time and satellite time of week
+	SystemTime(sys::nrf_gnss_agps_data_system_time_and_sv_tow_t),
+	/// An approximate location
+	Location(sys::nrf_gnss_agps_data_location_t),
+	/// Which satellites are unhealthy
+	Integrity(sys::nrf_gnss_agps_data_integrity_t),
+}
+
 //******************************************************************************
 // Constants
 //******************************************************************************
 
-// None
+/// The most satellites a PVT frame describes.
+pub const MAX_SATELLITES: usize = sys::NRF_GNSS_MAX_SATELLITES as usize;
+
+// We receive GNSS frames straight into a buffer pool block, which is
+// aligned to 8 bytes, so check one fits.
+const _: () = assert!(
+	core::mem::size_of::<sys::nrf_gnss_data_frame_t>() <= crate::pool::BLOCK_SIZE
+		&& core::mem::align_of::<sys::nrf_gnss_data_frame_t>() <= 8
+);
 
 //******************************************************************************
 // Global Variables
@@ -117,6 +245,47 @@ pub enum DeleteField {
 // Public Functions and Impl on Public Types
 //******************************************************************************
 
+/// Activate GNSS in the modem and start a GNSS session.
+///
+/// This uses `AT+CFUN=31`, so LTE is left as it is and the modem does not
+/// need to be powered off. The system mode (`AT%XSYSTEMMODE`) must already
+/// include GNSS - set it once at start-up (e.g. with
+/// `modem::set_startup_commands`) and then call `start` and `stop` as often
+/// as you like to duty-cycle the receiver.
+///
+/// If the session can't be started, the modem is put back in the functional
+/// mode it was in before.
+pub fn start(delete_mask: DeleteMask) -> Result<GnssSocket, Error> {
+	let previous = crate::modem::get_functional_mode()?;
+	crate::modem::gnss_on()?;
+	let result = GnssSocket::new().and_then(|gnss| {
+		gnss.start(delete_mask)?;
+		Ok(gnss)
+	});
+	if result.is_err() {
+		if let Err(e) = crate::modem::set_functional_mode(previous) {
+			log::warn!(
+				"Failed to restore {:?} after GNSS start failed: {:?}",
+				previous,
+				e
+			);
+		}
+	}
+	result
+}
+
+/// Stop a GNSS session started with `start`, and deactivate GNSS in the
+/// modem (with `AT+CFUN=30`) so it stops drawing power. LTE is left as it is.
+///
+/// GNSS is deactivated even if the session can't be stopped, in which case
+/// that error is returned.
+pub fn stop(gnss: GnssSocket) -> Result<(), Error> {
+	let stopped = gnss.stop();
+	drop(gnss);
+	let off = crate::modem::gnss_off();
+	stopped.and(off)
+}
+
 impl GnssSocket {
 	/// Create a new GNSS socket.
 	pub fn new() -> Result<GnssSocket, Error> {
@@ -240,22 +409,104 @@ impl GnssSocket {
 		}
 	}
 
+	/// Set the elevation mask. Satellites lower than this (in degrees, from 0
+	/// to 30) are not tracked, as their signals are less accurate.
+	pub fn set_elevation_mask(&self, degrees: u8) -> Result<(), Error> {
+		self.0
+			.set_option(SocketOption::GnssElevationMask(degrees))?;
+		Ok(())
+	}
+
+	/// Set the start performance and accuracy to aim for.
+	///
+	/// For a fix every few minutes, `StartMode::MultipleHotStart` with
+	/// `Accuracy::Low` gets fixes soonest for the least power.
+	pub fn set_use_case(&self, start: StartMode, accuracy: Accuracy) -> Result<(), Error> {
+		let start = match start {
+			StartMode::SingleColdStart => sys::NRF_GNSS_USE_CASE_SINGLE_COLD_START,
+			StartMode::MultipleHotStart => sys::NRF_GNSS_USE_CASE_MULTIPLE_HOT_START,
+		};
+		let accuracy = match accuracy {
+			Accuracy::Normal => sys::NRF_GNSS_USE_CASE_NORMAL_ACCURACY,
+			Accuracy::Low => sys::NRF_GNSS_USE_CASE_LOW_ACCURACY,
+		};
+		self.0
+			.set_option(SocketOption::GnssUseCase((start | accuracy) as u8))?;
+		Ok(())
+	}
+
+	/// Set whether the GNSS module may duty-cycle its tracking.
+	pub fn set_power_save_mode(&self, mode: PowerSaveMode) -> Result<(), Error> {
+		let mode = match mode {
+			PowerSaveMode::Disabled => sys::NRF_GNSS_PSM_DISABLED,
+			PowerSaveMode::DutyCyclingPerformance => sys::NRF_GNSS_PSM_DUTY_CYCLING_PERFORMANCE,
+			PowerSaveMode::DutyCyclingPower => sys::NRF_GNSS_PSM_DUTY_CYCLING_POWER,
+		};
+		self.0
+			.set_option(SocketOption::GnssPowerSaveMode(mode as u8))?;
+		Ok(())
+	}
+
+	/// Give the GNSS module some assistance data. Ephemerides and almanacs
+	/// are injected one satellite at a time.
+	///
+	/// If you use an `AgpsTracker`, tell it with `AgpsTracker::injected`.
+	pub fn inject_agps(&self, data: &AgpsData) -> Result<(), Error> {
+		let data_type = data.kind().as_type();
+		let (value, length) = data.as_raw();
+		let result = unsafe {
+			sys::nrf_sendto(
+				self.0.fd,
+				value,
+				length,
+				0,
+				&data_type as *const sys::nrf_gnss_agps_data_type_t as *const sys::ctypes::c_void,
+				core::mem::size_of_val(&data_type) as u32,
+			)
+		};
+		if result < 0 {
+			Err(Error::Nordic("inject_agps", result, get_last_error()))
+		} else {
+			crate::observer::notify(|o| o.sent(self.0.fd, length as usize));
+			Ok(())
+		}
+	}
+
+	/// Give the GNSS module the last known position (see `last_position`)
+	/// as its reference location, if there is one. `uncertainty_m` is how
+	/// far the device may have moved since. Returns whether there was a
+	/// position to give.
+	pub fn inject_last_known_position(&self, uncertainty_m: f32) -> Result<bool, Error> {
+		match crate::last_position::get() {
+			Some(position) => {
+				let location = position.to_agps_location(uncertainty_m);
+				self.inject_agps(&AgpsData::Location(location))?;
+				Ok(true)
+			}
+			None => Ok(false),
+		}
+	}
+
 	/// Get a fix from the GNSS system.
 	///
 	/// Performs a read on the GNSS socket. The Nordic library determines which
 	/// frame type you get on each read. You will get `None` if there is no fix
 	/// to be read.
 	pub fn get_fix(&self) -> Result<Option<GnssData>, Error> {
-		let mut frame = core::mem::MaybeUninit::<sys::nrf_gnss_data_frame_t>::uninit();
+		let mut buffer = crate::pool::Buffer::take()?;
 		let buffer_size = core::mem::size_of::<sys::nrf_gnss_data_frame_t>();
 		let result = unsafe {
 			sys::nrf_recv(
 				self.0.fd,
-				frame.as_mut_ptr() as *mut sys::ctypes::c_void,
+				buffer.as_mut_ptr() as *mut sys::ctypes::c_void,
 				buffer_size as u32,
 				sys::NRF_MSG_DONTWAIT as i32,
 			)
 		};
+		// NOTE(unsafe) - pool blocks are big enough, and aligned enough, to
+		// hold a frame (this is checked at compile time), and are always
+		// initialised.
+		let frame = unsafe { &*(buffer.as_ptr() as *const sys::nrf_gnss_data_frame_t) };
 		self.process_fix(result, frame)
 	}
 
@@ -266,19 +517,49 @@ impl GnssSocket {
 	/// `GnssData::Position`. The Nordic library determines which you get on
 	/// each read. You will get `None` if there is no fix to be read.
 	pub fn get_fix_blocking(&self) -> Result<Option<GnssData>, Error> {
-		let mut frame = core::mem::MaybeUninit::<sys::nrf_gnss_data_frame_t>::uninit();
+		let mut buffer = crate::pool::Buffer::take()?;
 		let buffer_size = core::mem::size_of::<sys::nrf_gnss_data_frame_t>();
 		let result = unsafe {
 			sys::nrf_recv(
 				self.0.fd,
-				frame.as_mut_ptr() as *mut sys::ctypes::c_void,
+				buffer.as_mut_ptr() as *mut sys::ctypes::c_void,
 				buffer_size as u32,
 				0,
 			)
 		};
+		// NOTE(unsafe) - pool blocks are big enough, and aligned enough, to
+		// hold a frame (this is checked at compile time), and are always
+		// initialised.
+		let frame = unsafe { &*(buffer.as_ptr() as *const sys::nrf_gnss_data_frame_t) };
 		self.process_fix(result, frame)
 	}
 
+	/// Get a fix from the GNSS system as `get_fix` does, and say when it
+	/// arrived: the UTC time in a PVT frame once the GNSS module knows it,
+	/// or otherwise the `now_ms` tick when it was read. There is never a
+	/// source address.
+	pub fn get_fix_with_meta(
+		&self,
+		now_ms: fn() -> u32,
+	) -> Result<Option<(GnssData, RxMeta)>, Error> {
+		let tick_ms = now_ms();
+		let data = match self.get_fix()? {
+			Some(data) => data,
+			None => return Ok(None),
+		};
+		let timestamp = match &data {
+			GnssData::Position(pvt) if pvt.datetime.year != 0 => {
+				RxTimestamp::Modem(PvtFrame::from(pvt).datetime)
+			}
+			_ => RxTimestamp::TickMs(tick_ms),
+		};
+		let meta = RxMeta {
+			timestamp,
+			source: None,
+		};
+		Ok(Some((data, meta)))
+	}
+
 	/// Parse the data returned from a GNSS socket read.
 	///
 	/// We get either an NMEA frame, a Position frame, or an AGPS frame. We
@@ -286,7 +567,7 @@ impl GnssSocket {
 	fn process_fix(
 		&self,
 		result: i32,
-		frame: core::mem::MaybeUninit<sys::nrf_gnss_data_frame_t>,
+		frame: &sys::nrf_gnss_data_frame_t,
 	) -> Result<Option<GnssData>, Error> {
 		match result {
 			0 => {
@@ -303,14 +584,17 @@ impl GnssSocket {
 					Err(Error::Nordic("get_fix", n as i32, err))
 				}
 			}
-			_ => {
+			n => {
+				crate::observer::notify(|o| o.received(self.0.fd, n as usize));
 				// Got some valid data - but what?
-				let frame = unsafe { frame.assume_init() };
 				// Unpack the C union and return a nice Rust structure...
 				if frame.data_id as u32 == sys::NRF_GNSS_PVT_DATA_ID {
 					// We have frame.pvt
 					// NOTE(unsafe) - we have to trust that the Nordic library has given us enough bytes for the frame.
 					let pvt = unsafe { frame.__bindgen_anon_1.pvt };
+					if (pvt.flags & sys::NRF_GNSS_PVT_FLAG_FIX_VALID_BIT as u8) != 0 {
+						crate::last_position::record_fix(&PvtFrame::from(&pvt));
+					}
 					Ok(Some(GnssData::Position(pvt)))
 				} else if frame.data_id as u32 == sys::NRF_GNSS_NMEA_DATA_ID {
 					// We have frame.nmea
@@ -323,7 +607,8 @@ impl GnssSocket {
 						.find(|x| x.1 == b'\0' || x.1 == b'\r' || x.1 == b'\n')
 						.map(|x| x.0)
 						.unwrap_or(0);
-					if core::str::from_utf8(&nmea[0..string_length]).is_ok() {
+					let text = nmea.get(0..string_length).unwrap_or(&[]);
+					if core::str::from_utf8(text).is_ok() {
 						// Valid UTF-8
 						Ok(Some(GnssData::Nmea {
 							buffer: unsafe { frame.__bindgen_anon_1.nmea },
@@ -384,12 +669,29 @@ impl GnssData {
 	}
 }
 
+impl GnssData {
+	/// Decode a position frame. Returns `None` for other frames.
+	pub fn pvt(&self) -> Option<PvtFrame> {
+		match self {
+			GnssData::Position(p) => Some(PvtFrame::from(p)),
+			_ => None,
+		}
+	}
+
+	/// Get the sentence from an NMEA frame. Returns `None` for other frames.
+	pub fn nmea(&self) -> Option<&str> {
+		match self {
+			GnssData::Nmea { buffer, length } => core::str::from_utf8(buffer.get(0..*length)?).ok(),
+			_ => None,
+		}
+	}
+}
+
 impl core::fmt::Debug for GnssData {
 	fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
 		match self {
-			GnssData::Nmea { buffer, length } => {
-				// NOTE(unsafe) - we checked this when we created the GnssData on line 890
-				let nmea_str = unsafe { core::str::from_utf8_unchecked(&buffer[0..*length]) };
+			GnssData::Nmea { .. } => {
+				let nmea_str = self.nmea().unwrap_or("");
 				fmt.debug_struct("GnssData")
 					.field("nmea", &nmea_str)
 					.finish()
@@ -445,6 +747,28 @@ impl DeleteMask {
 		DeleteMask(self.0 & !field.value())
 	}
 
+	/// Select everything for deletion except the TCXO frequency offset, for a
+	/// cold start which still benefits from the crystal calibration.
+	///
+	/// The modem measures the offset itself and keeps it in NVM, so it
+	/// survives resets and battery changes as long as it isn't deleted. The
+	/// libmodem version we use has no way to read it out or inject it.
+	pub fn cold_start() -> Self {
+		DeleteMask::new()
+			.set(DeleteField::Ephemerides)
+			.set(DeleteField::Almanac)
+			.set(DeleteField::IonosphericCorrection)
+			.set(DeleteField::LastGoodFix)
+			.set(DeleteField::TimeOfWeek)
+			.set(DeleteField::WeekNumber)
+			.set(DeleteField::LeapSecond)
+	}
+
+	/// Is a particular field marked for deletion?
+	pub fn is_set(self, field: DeleteField) -> bool {
+		(self.0 & field.value()) != 0
+	}
+
 	/// Convert to an integer, for the socket to consume.
 	pub fn as_u32(self) -> u32 {
 		self.0
@@ -458,11 +782,140 @@ impl DeleteField {
 	}
 }
 
+impl PvtFrame {
+	/// Is this a valid fix?
+	pub fn has_fix(&self) -> bool {
+		self.flags.fix_valid()
+	}
+}
+
+impl From<&sys::nrf_gnss_pvt_data_frame_t> for PvtFrame {
+	fn from(p: &sys::nrf_gnss_pvt_data_frame_t) -> PvtFrame {
+		let mut satellites = heapless::Vec::new();
+		// Unused slots have satellite number zero. There are only as many
+		// slots as `satellites` has room for, so the push can't fail.
+		for sv in p.sv.iter().filter(|sv| sv.sv != 0) {
+			let _ = satellites.push(Satellite::from(sv));
+		}
+		PvtFrame {
+			latitude: p.latitude,
+			longitude: p.longitude,
+			altitude: p.altitude,
+			accuracy: p.accuracy,
+			speed: p.speed,
+			heading: p.heading,
+			datetime: DateTime {
+				year: p.datetime.year,
+				month: p.datetime.month,
+				day: p.datetime.day,
+				hour: p.datetime.hour,
+				minute: p.datetime.minute,
+				second: p.datetime.seconds,
+				millisecond: p.datetime.ms,
+			},
+			pdop: p.pdop,
+			hdop: p.hdop,
+			vdop: p.vdop,
+			tdop: p.tdop,
+			flags: PvtFlags(p.flags),
+			satellites,
+		}
+	}
+}
+
+impl PvtFlags {
+	/// The fix is valid.
+	pub fn fix_valid(self) -> bool {
+		self.is_set(sys::NRF_GNSS_PVT_FLAG_FIX_VALID_BIT)
+	}
+
+	/// The leap second count is known, so the time is UTC rather than GPS
+	/// time.
+	pub fn leap_second_valid(self) -> bool {
+		self.is_set(sys::NRF_GNSS_PVT_FLAG_LEAP_SECOND_VALID)
+	}
+
+	/// The GNSS module was duty-cycling, and slept between the last fix and
+	/// this one.
+	pub fn sleep_between_pvt(self) -> bool {
+		self.is_set(sys::NRF_GNSS_PVT_FLAG_SLEEP_BETWEEN_PVT)
+	}
+
+	/// The fix interval was missed, because LTE was using the radio.
+	pub fn deadline_missed(self) -> bool {
+		self.is_set(sys::NRF_GNSS_PVT_FLAG_DEADLINE_MISSED)
+	}
+
+	/// LTE didn't leave the GNSS module enough time to get a fix.
+	pub fn not_enough_window_time(self) -> bool {
+		self.is_set(sys::NRF_GNSS_PVT_FLAG_NOT_ENOUGH_WINDOW_TIME)
+	}
+
+	/// Get the raw flags.
+	pub fn as_u8(self) -> u8 {
+		self.0
+	}
+
+	fn is_set(self, flag: u32) -> bool {
+		(self.0 & flag as u8) != 0
+	}
+}
+
+impl From<&sys::nrf_gnss_sv_t> for Satellite {
+	fn from(sv: &sys::nrf_gnss_sv_t) -> Satellite {
+		Satellite {
+			sv: sv.sv,
+			signal: sv.signal,
+			cn0: sv.cn0,
+			elevation: sv.elevation,
+			azimuth: sv.azimuth,
+			used_in_fix: (sv.flags & sys::NRF_GNSS_SV_FLAG_USED_IN_FIX as u8) != 0,
+			unhealthy: (sv.flags & sys::NRF_GNSS_SV_FLAG_UNHEALTHY as u8) != 0,
+		}
+	}
+}
+
+impl AgpsData {
+	/// Which kind of assistance data this is.
+	pub fn kind(&self) -> AgpsKind {
+		match self {
+			AgpsData::UtcParameters(_) => AgpsKind::UtcParameters,
+			AgpsData::Ephemeris(_) => AgpsKind::Ephemerides,
+			AgpsData::Almanac(_) => AgpsKind::Almanac,
+			AgpsData::KlobucharIonosphere(_) => AgpsKind::KlobucharIonosphere,
+			AgpsData::NequickIonosphere(_) => AgpsKind::NequickIonosphere,
+			AgpsData::SystemTime(_) => AgpsKind::SystemTime,
+			AgpsData::Location(_) => AgpsKind::Location,
+			AgpsData::Integrity(_) => AgpsKind::Integrity,
+		}
+	}
+}
+
 //******************************************************************************
 // Private Functions and Impl on Private Types
 //******************************************************************************
 
-// None
+impl AgpsData {
+	/// Get a pointer to, and the length of, the libmodem structure inside.
+	fn as_raw(&self) -> (*const sys::ctypes::c_void, u32) {
+		fn raw<T>(value: &T) -> (*const sys::ctypes::c_void, u32) {
+			(
+				value as *const T as *const sys::ctypes::c_void,
+				core::mem::size_of::<T>() as u32,
+			)
+		}
+		match self {
+			AgpsData::UtcParameters(x) => raw(x),
+			AgpsData::Ephemeris(x) => raw(x),
+			AgpsData::Almanac(x) => raw(x),
+			AgpsData::KlobucharIonosphere(x) => raw(x),
+			AgpsData::NequickIonosphere(x) => raw(x),
+			AgpsData::SystemTime(x) => raw(x),
+			AgpsData::Location(x) => raw(x),
+			AgpsData::Integrity(x) => raw(x),
+		}
+	}
+}
 
 //******************************************************************************
 // End of File
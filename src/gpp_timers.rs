@@ -0,0 +1,314 @@
+//! # 3GPP timer encodings for nrfxlib
+//!
+//! The PSM and eDRX AT commands take their timers as strings of bits, in
+//! the encodings 3GPP TS 24.008 defines: a GPRS timer 3 for the periodic
+//! TAU (T3412 extended), a GPRS timer 2 for the active time (T3324), and
+//! four-bit table lookups for the eDRX cycle and paging time window. Most
+//! durations can't be expressed exactly, so each `encode_` function says
+//! what you will actually get:
+//!
+//! ```ignore
+//! let tau = gpp_timers::encode_t3412(Duration::from_secs(33 * 3600), Rounding::Up);
+//! // Too many 1 hour units, so it becomes 4 x 10 hours
+//! assert_eq!(tau.duration, Duration::from_secs(40 * 3600));
+//! write!(command, "AT+CPSMS=1,,,\"{}\",\"{}\"", tau.bit_string(), active.bit_string())?;
+//! ```
+//!
+//! `modem::set_psm` and `modem::set_edrx` round up, so the modem is never
+//! asked to sleep less than you wanted. The `decode_` functions turn what
+//! the modem reports back into durations.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::modem::EdrxAccessTechnology;
+use core::fmt::Write;
+use core::time::Duration;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Which way to go when a duration falls between two values an encoding
+/// can express.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rounding {
+	/// To the shortest value at least as long, or the longest there is
+	Up,
+	/// To the closest value, going up when it is exactly half way
+	Nearest,
+}
+
+/// A duration encoded as a 3GPP timer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Encoded {
+	/// The encoded value, in the bottom `width` bits
+	pub bits: u8,
+	/// The duration `bits` stands for
+	pub duration: Duration,
+	/// The duration asked for
+	pub requested: Duration,
+	width: u8,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The T3412 extended value (GPRS timer 3) meaning "deactivated".
+pub const T3412_DEACTIVATED: u8 = 0b1110_0000;
+
+/// The T3324 value (GPRS timer 2) meaning "deactivated".
+pub const T3324_DEACTIVATED: u8 = 0b1110_0000;
+
+/// The units of a GPRS timer 3 value (T3412 extended), in seconds, indexed
+/// by its top three bits. Zero marks the deactivated value.
+const TIMER_3_UNITS_S: [u64; 8] = [600, 3_600, 36_000, 2, 30, 60, 1_152_000, 0];
+
+/// The units of a GPRS timer 2 value (T3324), in seconds, indexed by its top
+/// three bits. Zero marks a deactivated (or reserved) value.
+const TIMER_2_UNITS_S: [u64; 8] = [2, 60, 360, 0, 0, 0, 0, 0];
+
+/// The largest value of a GPRS timer 2 or 3, in its unit.
+const TIMER_MAX_VALUE: u64 = 31;
+
+/// The eDRX cycles (24.008 table 10.5.5.32), in units of 10 ms, indexed by
+/// their four-bit value. For NB-IoT, values 0, 1, 4, 6, 7 and 8 are not
+/// defined (see `EDRX_NB_IOT_CYCLES`).
+const EDRX_CYCLES_10MS: [u32; 16] = [
+	512, 1_024, 2_048, 4_096, 6_144, 8_192, 10_240, 12_288, 14_336, 16_384, 32_768, 65_536,
+	131_072, 262_144, 524_288, 1_048_576,
+];
+
+/// Which eDRX cycles NB-IoT allows.
+const EDRX_NB_IOT_CYCLES: [bool; 16] = [
+	false, false, true, true, false, true, false, false, false, true, true, true, true, true, true,
+	true,
+];
+
+/// The paging time window unit, in units of 10 ms, for LTE-M and NB-IoT.
+const PTW_UNIT_10MS: [u32; 2] = [128, 256];
+
+/// The most units a paging time window can be.
+const PTW_MAX_UNITS: u64 = 16;
+
+const NANOS_PER_10MS: u128 = 10_000_000;
+
+const NANOS_PER_S: u128 = 1_000_000_000;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Encode a periodic TAU (T3412 extended, a GPRS timer 3), which can be up
+/// to 31 x 320 hours.
+pub fn encode_t3412(requested: Duration, rounding: Rounding) -> Encoded {
+	encode_timer(requested, &TIMER_3_UNITS_S, rounding)
+}
+
+/// Decode a periodic TAU. Returns `None` if it is deactivated.
+pub fn decode_t3412(bits: u8) -> Option<Duration> {
+	decode_timer(bits, &TIMER_3_UNITS_S)
+}
+
+/// Encode an active time (T3324, a GPRS timer 2), which can be up to 31 x 6
+/// minutes.
+pub fn encode_t3324(requested: Duration, rounding: Rounding) -> Encoded {
+	encode_timer(requested, &TIMER_2_UNITS_S, rounding)
+}
+
+/// Decode an active time. Returns `None` if it is deactivated.
+pub fn decode_t3324(bits: u8) -> Option<Duration> {
+	decode_timer(bits, &TIMER_2_UNITS_S)
+}
+
+/// Encode an eDRX cycle, from 5.12 seconds (20.48 for NB-IoT) to 10485.76
+/// seconds, as one of the values defined for the access technology.
+pub fn encode_edrx(act: EdrxAccessTechnology, requested: Duration, rounding: Rounding) -> Encoded {
+	let candidates = EDRX_CYCLES_10MS
+		.iter()
+		.enumerate()
+		.filter(|&(value, _)| edrx_allowed(act, value))
+		.map(|(value, &cycle_10ms)| (value as u8, u128::from(cycle_10ms) * NANOS_PER_10MS));
+	pick(requested, candidates, rounding, 4)
+}
+
+/// Decode a four-bit eDRX value. Returns `None` if it isn't defined for
+/// this access technology.
+pub fn decode_edrx(act: EdrxAccessTechnology, bits: u8) -> Option<Duration> {
+	let value = usize::from(bits & 0x0F);
+	if edrx_allowed(act, value) {
+		EDRX_CYCLES_10MS
+			.get(value)
+			.map(|&cycle_10ms| Duration::from_millis(u64::from(cycle_10ms) * 10))
+	} else {
+		None
+	}
+}
+
+/// Encode a paging time window: 1 to 16 units of 1.28 seconds (2.56 for
+/// NB-IoT).
+pub fn encode_ptw(act: EdrxAccessTechnology, requested: Duration, rounding: Rounding) -> Encoded {
+	let unit_nanos = u128::from(ptw_unit_10ms(act)) * NANOS_PER_10MS;
+	let candidates =
+		(1..=PTW_MAX_UNITS).map(|units| ((units - 1) as u8, u128::from(units) * unit_nanos));
+	pick(requested, candidates, rounding, 4)
+}
+
+/// Decode a four-bit paging time window value.
+pub fn decode_ptw(act: EdrxAccessTechnology, bits: u8) -> Duration {
+	let units = u64::from(bits & 0x0F) + 1;
+	Duration::from_millis(units * u64::from(ptw_unit_10ms(act)) * 10)
+}
+
+/// Parse a string of bits as the modem reports them, quoted or not, like
+/// `"00100001"`.
+pub fn parse_bits(field: &str) -> Option<u8> {
+	u8::from_str_radix(field.trim().trim_matches('"'), 2).ok()
+}
+
+impl Encoded {
+	/// The value as a string of bits, as the AT commands take it - eight
+	/// for the GPRS timers, four for the eDRX values.
+	pub fn bit_string(&self) -> heapless::String<8> {
+		let mut text = heapless::String::new();
+		// Can't fail, as it is at most eight characters
+		let _ = write!(
+			text,
+			"{:0width$b}",
+			self.bits,
+			width = usize::from(self.width)
+		);
+		text
+	}
+
+	/// Is `duration` exactly what was asked for?
+	pub fn is_exact(&self) -> bool {
+		self.duration == self.requested
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Encode a GPRS timer 2 or 3 value (24.008 10.5.7.4a and 10.5.7.3). When
+/// several encodings give the same duration, the finest unit is used.
+fn encode_timer(requested: Duration, units_s: &[u64; 8], rounding: Rounding) -> Encoded {
+	let requested_nanos = requested.as_nanos();
+	// Finest unit first, so it wins a tie
+	let mut units: heapless::Vec<(usize, u64), 8> = units_s
+		.iter()
+		.copied()
+		.enumerate()
+		.filter(|&(_, unit_s)| unit_s != 0)
+		.collect();
+	units.sort_unstable_by_key(|&(_, unit_s)| unit_s);
+	let candidates = units.into_iter().map(|(index, unit_s)| {
+		let unit_nanos = u128::from(unit_s) * NANOS_PER_S;
+		// The best value in this unit: the multiple just above, or the
+		// nearest (both clamped to the largest there is)
+		let count = match rounding {
+			Rounding::Up => requested_nanos.div_ceil(unit_nanos),
+			Rounding::Nearest => (requested_nanos + unit_nanos / 2) / unit_nanos,
+		};
+		let count = core::cmp::min(count, u128::from(TIMER_MAX_VALUE));
+		(((index as u8) << 5) | count as u8, count * unit_nanos)
+	});
+	pick(requested, candidates, rounding, 8)
+}
+
+/// Decode a GPRS timer 2 or 3 value. Returns `None` if it is deactivated.
+fn decode_timer(bits: u8, units_s: &[u64; 8]) -> Option<Duration> {
+	match units_s.get(usize::from(bits >> 5)) {
+		Some(0) | None => None,
+		Some(unit_s) => Some(Duration::from_secs(unit_s * u64::from(bits & 0x1F))),
+	}
+}
+
+/// Choose from the `(bits, nanoseconds)` candidates, which must not be
+/// empty. Earlier candidates win ties.
+fn pick<I>(requested: Duration, candidates: I, rounding: Rounding, width: u8) -> Encoded
+where
+	I: Iterator<Item = (u8, u128)>,
+{
+	let wanted = requested.as_nanos();
+	let mut best: Option<(u8, u128)> = None;
+	for (bits, nanos) in candidates {
+		let better = match best {
+			None => true,
+			Some((_, best_nanos)) => match rounding {
+				// The shortest which is long enough, or failing that the
+				// longest
+				Rounding::Up => {
+					if best_nanos >= wanted {
+						nanos >= wanted && nanos < best_nanos
+					} else {
+						nanos > best_nanos
+					}
+				}
+				// The closest, or the longer of two as close
+				Rounding::Nearest => {
+					let distance = nanos.abs_diff(wanted);
+					let best_distance = best_nanos.abs_diff(wanted);
+					distance < best_distance || (distance == best_distance && nanos > best_nanos)
+				}
+			},
+		};
+		if better {
+			best = Some((bits, nanos));
+		}
+	}
+	let (bits, nanos) = best.unwrap_or((0, 0));
+	Encoded {
+		bits,
+		duration: from_nanos(nanos),
+		requested,
+		width,
+	}
+}
+
+fn from_nanos(nanos: u128) -> Duration {
+	Duration::new((nanos / NANOS_PER_S) as u64, (nanos % NANOS_PER_S) as u32)
+}
+
+/// Can this four-bit eDRX value be used with this access technology?
+fn edrx_allowed(act: EdrxAccessTechnology, value: usize) -> bool {
+	act == EdrxAccessTechnology::LteM || EDRX_NB_IOT_CYCLES.get(value) == Some(&true)
+}
+
+/// The paging time window unit for this access technology, in units of 10 ms.
+fn ptw_unit_10ms(act: EdrxAccessTechnology) -> u32 {
+	match act {
+		EdrxAccessTechnology::LteM => PTW_UNIT_10MS[0],
+		EdrxAccessTechnology::NbIot => PTW_UNIT_10MS[1],
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
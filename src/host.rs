@@ -0,0 +1,204 @@
+//! # Host-side sockets for nrfxlib
+//!
+//! Application code written against the `embedded-nal-async` and
+//! `embedded-io-async` traits, as implemented by `asynch::TcpConnector` and
+//! `AsyncSocket`, can be run on a development machine with a `StdTcpConnector`
+//! in their place. It makes real TCP connections with `std::net`, and
+//! reports errors as the crate's own `Error`, so the code under test sees
+//! the same types it would on the device:
+//!
+//! ```ignore
+//! async fn fetch<T>(connector: &T, server: SocketAddr) -> Result<usize, nrfxlib::Error>
+//! where
+//!     T: TcpConnect<Error = nrfxlib::Error>,
+//! {
+//!     let mut connection = connector.connect(server).await?;
+//!     connection.write_all(b"GET / HTTP/1.0\r\n\r\n").await?;
+//!     connection.read(&mut buffer).await
+//! }
+//!
+//! // On the device
+//! fetch(&TcpConnector, server).await?;
+//! // In an integration test on the host
+//! futures::executor::block_on(fetch(&StdTcpConnector::new(), server))?;
+//! ```
+//!
+//! The `std::net` calls block, so a connection holds up the executor while
+//! it waits. That is fine for a test driving one task with `block_on`, but
+//! not for a real async application. Set a timeout with
+//! `StdTcpConnector::with_timeout`, so a server which never answers fails
+//! the test instead of hanging it.
+//!
+//! Only available with both the `std` and `embedded-nal-async` features.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::Error;
+use core::time::Duration;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Makes TCP connections with `std::net`, for `embedded-nal-async`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct StdTcpConnector {
+	timeout: Option<Duration>,
+}
+
+/// A TCP connection made by a `StdTcpConnector`.
+#[derive(Debug)]
+pub struct StdTcpConnection {
+	stream: TcpStream,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl StdTcpConnector {
+	/// Create a connector whose connections wait as long as the OS lets
+	/// them.
+	pub fn new() -> StdTcpConnector {
+		StdTcpConnector { timeout: None }
+	}
+
+	/// Create a connector whose connections give up with `Error::TimedOut`
+	/// if connecting, or any one read or write, takes longer than `timeout`.
+	pub fn with_timeout(timeout: Duration) -> StdTcpConnector {
+		StdTcpConnector {
+			timeout: Some(timeout),
+		}
+	}
+}
+
+impl StdTcpConnection {
+	/// Get at the underlying stream, e.g. to shut it down part way.
+	pub fn get_ref(&self) -> &TcpStream {
+		&self.stream
+	}
+
+	/// Get the underlying stream back.
+	pub fn into_inner(self) -> TcpStream {
+		self.stream
+	}
+}
+
+impl embedded_nal_async::TcpConnect for StdTcpConnector {
+	type Error = Error;
+	type Connection<'a> = StdTcpConnection;
+
+	/// Connect to an IPv4 or IPv6 address.
+	async fn connect(
+		&self,
+		remote: embedded_nal_async::SocketAddr,
+	) -> Result<StdTcpConnection, Error> {
+		let remote = to_std_addr(remote);
+		let stream = match self.timeout {
+			Some(timeout) => TcpStream::connect_timeout(&remote, timeout),
+			None => TcpStream::connect(remote),
+		}
+		.map_err(|e| from_io_error("connect", &e))?;
+		stream
+			.set_read_timeout(self.timeout)
+			.and_then(|()| stream.set_write_timeout(self.timeout))
+			.map_err(|e| from_io_error("setsockopt", &e))?;
+		Ok(StdTcpConnection { stream })
+	}
+}
+
+impl embedded_io_async::ErrorType for StdTcpConnection {
+	type Error = Error;
+}
+
+impl embedded_io_async::Read for StdTcpConnection {
+	/// Receive some data. The peer closing the connection is reported as
+	/// end-of-file (`Ok(0)`), as it is for an `AsyncSocket`.
+	async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		self.stream.read(buf).map_err(|e| from_io_error("recv", &e))
+	}
+}
+
+impl embedded_io_async::Write for StdTcpConnection {
+	async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+		self.stream
+			.write(buf)
+			.map_err(|e| from_io_error("send", &e))
+	}
+
+	async fn flush(&mut self) -> Result<(), Error> {
+		self.stream.flush().map_err(|e| from_io_error("send", &e))
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Convert an `embedded-nal-async` address to the `std::net` one.
+fn to_std_addr(remote: embedded_nal_async::SocketAddr) -> std::net::SocketAddr {
+	use embedded_nal_async::SocketAddr;
+	match remote {
+		SocketAddr::V4(addr) => std::net::SocketAddr::from((addr.ip().octets(), addr.port())),
+		SocketAddr::V6(addr) => std::net::SocketAddr::V6(std::net::SocketAddrV6::new(
+			addr.ip().octets().into(),
+			addr.port(),
+			addr.flowinfo(),
+			addr.scope_id(),
+		)),
+	}
+}
+
+/// Report an error from `std::net` as the nRF sockets would. Anything
+/// without an equivalent comes out as `Error::Nordic`, with the OS error
+/// number (which, for the common ones, libmodem shares).
+fn from_io_error(function: &'static str, error: &std::io::Error) -> Error {
+	match error.kind() {
+		ErrorKind::ConnectionRefused
+		| ErrorKind::ConnectionReset
+		| ErrorKind::ConnectionAborted
+		| ErrorKind::BrokenPipe
+		| ErrorKind::UnexpectedEof => Error::Disconnected,
+		ErrorKind::NotConnected | ErrorKind::AddrNotAvailable => Error::LinkDown,
+		ErrorKind::TimedOut | ErrorKind::WouldBlock => Error::TimedOut,
+		ErrorKind::InvalidInput | ErrorKind::InvalidData => Error::BadDataFormat,
+		_ => Error::Nordic(function, -1, error.raw_os_error().unwrap_or(0)),
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,312 @@
+//! # Hardware test runner for nrfxlib
+//!
+//! Runs a scripted sequence of tests against a real network - attach, DNS,
+//! UDP echo, TLS connect and a GNSS fix - and reports the results as JUnit
+//! XML, so the crate can be regression tested on real hardware. The report
+//! is written to anything that implements `core::fmt::Write`, such as an RTT
+//! channel or a UART.
+//!
+//! Only built with the `hw-test` feature. For example:
+//!
+//! ```ignore
+//! let config = hw_test::Config {
+//!     dns_hostname: "example.com",
+//!     udp_echo: Some(("udp-echo.example.com", 7)),
+//!     tls: Some(("example.com", 443, &[42])),
+//!     gnss_timeout_ms: Some(120_000),
+//!     now_ms: board::uptime_ms,
+//! };
+//! let results = hw_test::run(&config, &mut rtt_channel)?;
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::{
+	gnss, modem,
+	tls::{PeerVerification, TlsSocket, Version},
+	udp::UdpSocket,
+	Error, PollEntry, PollFlags,
+};
+use core::fmt::{self, Write};
+use log::info;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// What to test, and where.
+#[derive(Debug, Clone)]
+pub struct Config<'a> {
+	/// A hostname to look up, to test DNS.
+	pub dns_hostname: &'a str,
+	/// The hostname and port of a UDP echo server. The test is skipped if this
+	/// is `None`.
+	pub udp_echo: Option<(&'a str, u16)>,
+	/// The hostname, port and security tags for a TLS server. The test is
+	/// skipped if this is `None`.
+	pub tls: Option<(&'a str, u16, &'a [u32])>,
+	/// How long to wait for a GNSS fix. The test is skipped if this is `None`.
+	pub gnss_timeout_ms: Option<u32>,
+	/// Returns a millisecond counter, used for timeouts and for the test
+	/// durations in the report.
+	pub now_ms: fn() -> u32,
+}
+
+/// How a test went.
+#[derive(Debug, Clone)]
+pub enum Outcome {
+	/// The test passed.
+	Passed,
+	/// The test wasn't run.
+	Skipped,
+	/// The test failed with an error.
+	Failed(Error),
+	/// The test didn't complete in time.
+	TimedOut,
+	/// The data we got back didn't match what we sent.
+	Mismatch,
+}
+
+/// The result of one test.
+#[derive(Debug, Clone)]
+pub struct TestResult {
+	/// The name of the test
+	pub name: &'static str,
+	/// How it went
+	pub outcome: Outcome,
+	/// How long it took, in milliseconds
+	pub duration_ms: u32,
+}
+
+/// Escapes text written through it for use in an XML attribute.
+struct XmlEscape<'w, W: Write>(&'w mut W);
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// How many tests `run` performs.
+pub const NUM_TESTS: usize = 5;
+
+/// What we send to the UDP echo server.
+const UDP_ECHO_PAYLOAD: &[u8] = b"nrfxlib hw-test";
+
+/// How long to wait for the UDP echo, in milliseconds.
+const UDP_ECHO_TIMEOUT_MS: u16 = 10_000;
+
+/// How long to wait for each GNSS poll, in milliseconds.
+const GNSS_POLL_MS: u16 = 1_000;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Run every test in turn, and write a JUnit XML report to `out`.
+///
+/// The attach test powers the modem on and waits (for as long as it takes)
+/// for it to register. If it fails, the network tests are skipped. Returns an
+/// error only if writing the report fails.
+pub fn run<W: Write>(
+	config: &Config,
+	out: &mut W,
+) -> Result<heapless::Vec<TestResult, NUM_TESTS>, fmt::Error> {
+	let mut results = heapless::Vec::new();
+
+	let attach = timed(config, "attach", || {
+		modem::on()?;
+		modem::wait_for_lte()?;
+		Ok(Outcome::Passed)
+	});
+	let attached = matches!(attach.outcome, Outcome::Passed);
+	let _ = results.push(attach);
+
+	let _ = results.push(if attached {
+		timed(config, "dns", || {
+			// Connecting a UDP socket looks up the hostname but sends nothing.
+			let socket = UdpSocket::new()?;
+			socket.connect(config.dns_hostname, 53)?;
+			Ok(Outcome::Passed)
+		})
+	} else {
+		skipped("dns")
+	});
+
+	let _ = results.push(match config.udp_echo {
+		Some((hostname, port)) if attached => {
+			timed(config, "udp_echo", || udp_echo(hostname, port))
+		}
+		_ => skipped("udp_echo"),
+	});
+
+	let _ = results.push(match config.tls {
+		Some((hostname, port, tags)) if attached => timed(config, "tls_connect", || {
+			let socket = TlsSocket::new(PeerVerification::Enabled, tags, Version::Tls1v2)?;
+			socket.connect(hostname, port)?;
+			Ok(Outcome::Passed)
+		}),
+		_ => skipped("tls_connect"),
+	});
+
+	let _ = results.push(match config.gnss_timeout_ms {
+		Some(timeout_ms) => timed(config, "gnss_fix", || gnss_fix(config, timeout_ms)),
+		None => skipped("gnss_fix"),
+	});
+
+	write_report(&results, out)?;
+	Ok(results)
+}
+
+/// Write a JUnit XML report of the given results to `out`.
+pub fn write_report<W: Write>(results: &[TestResult], out: &mut W) -> fmt::Result {
+	let failures = results
+		.iter()
+		.filter(|r| !matches!(r.outcome, Outcome::Passed | Outcome::Skipped))
+		.count();
+	let skipped = results
+		.iter()
+		.filter(|r| matches!(r.outcome, Outcome::Skipped))
+		.count();
+	let total_ms: u32 = results.iter().map(|r| r.duration_ms).sum();
+	writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+	writeln!(
+		out,
+		"<testsuite name=\"nrfxlib\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{}.{:03}\">",
+		results.len(),
+		failures,
+		skipped,
+		total_ms / 1000,
+		total_ms % 1000
+	)?;
+	for result in results {
+		write!(
+			out,
+			"  <testcase classname=\"nrfxlib.hw_test\" name=\"{}\" time=\"{}.{:03}\"",
+			result.name,
+			result.duration_ms / 1000,
+			result.duration_ms % 1000
+		)?;
+		match &result.outcome {
+			Outcome::Passed => writeln!(out, "/>")?,
+			Outcome::Skipped => writeln!(out, "><skipped/></testcase>")?,
+			Outcome::Failed(error) => {
+				write!(out, "><failure message=\"")?;
+				write!(XmlEscape(out), "{:?}", error)?;
+				writeln!(out, "\"/></testcase>")?;
+			}
+			Outcome::TimedOut => writeln!(out, "><failure message=\"timed out\"/></testcase>")?,
+			Outcome::Mismatch => writeln!(out, "><failure message=\"data mismatch\"/></testcase>")?,
+		}
+	}
+	writeln!(out, "</testsuite>")
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Run a test, timing it.
+fn timed<F>(config: &Config, name: &'static str, test: F) -> TestResult
+where
+	F: FnOnce() -> Result<Outcome, Error>,
+{
+	info!("hw-test: running {}", name);
+	let start = (config.now_ms)();
+	let outcome = test().unwrap_or_else(Outcome::Failed);
+	let duration_ms = (config.now_ms)().wrapping_sub(start);
+	info!("hw-test: {} => {:?}", name, outcome);
+	TestResult {
+		name,
+		outcome,
+		duration_ms,
+	}
+}
+
+/// The result for a test we didn't run.
+fn skipped(name: &'static str) -> TestResult {
+	TestResult {
+		name,
+		outcome: Outcome::Skipped,
+		duration_ms: 0,
+	}
+}
+
+/// Send a datagram to an echo server and check we get the same thing back.
+fn udp_echo(hostname: &str, port: u16) -> Result<Outcome, Error> {
+	let socket = UdpSocket::new()?;
+	socket.connect(hostname, port)?;
+	socket.write(UDP_ECHO_PAYLOAD)?;
+	let mut poll_list = [PollEntry::new(&socket, PollFlags::Read)];
+	if crate::poll(&mut poll_list, UDP_ECHO_TIMEOUT_MS)? == 0 {
+		return Ok(Outcome::TimedOut);
+	}
+	let mut buffer = [0u8; 64];
+	match socket.recv(&mut buffer)? {
+		Some(length) if buffer.get(0..length) == Some(UDP_ECHO_PAYLOAD) => Ok(Outcome::Passed),
+		_ => Ok(Outcome::Mismatch),
+	}
+}
+
+/// Start GNSS and wait for a valid fix.
+fn gnss_fix(config: &Config, timeout_ms: u32) -> Result<Outcome, Error> {
+	let gnss = gnss::start(gnss::DeleteMask::new())?;
+	let start = (config.now_ms)();
+	let mut outcome = Outcome::TimedOut;
+	while (config.now_ms)().wrapping_sub(start) < timeout_ms {
+		let mut poll_list = [PollEntry::new(&gnss, PollFlags::Read)];
+		if crate::poll(&mut poll_list, GNSS_POLL_MS)? == 0 {
+			continue;
+		}
+		if let Some(fix) = gnss.get_fix()? {
+			if fix.is_valid() {
+				outcome = Outcome::Passed;
+				break;
+			}
+		}
+	}
+	gnss::stop(gnss)?;
+	Ok(outcome)
+}
+
+impl<'w, W: Write> Write for XmlEscape<'w, W> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		for c in s.chars() {
+			match c {
+				'&' => self.0.write_str("&amp;")?,
+				'<' => self.0.write_str("&lt;")?,
+				'>' => self.0.write_str("&gt;")?,
+				'"' => self.0.write_str("&quot;")?,
+				c => self.0.write_char(c)?,
+			}
+		}
+		Ok(())
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
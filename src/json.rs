@@ -0,0 +1,1021 @@
+//! # JSON encoding and decoding for nrfxlib
+//!
+//! A small JSON writer and pull parser, for the payloads nRF Cloud and most
+//! REST backends expect, without pulling a `serde_json`-alike into a build
+//! with 64 KiB of RAM. Neither needs a heap, or more stack than the structs
+//! themselves take.
+//!
+//! `JsonWriter` writes straight into a caller-supplied buffer, one call per
+//! token, and puts the commas and colons in for you:
+//!
+//! ```ignore
+//! use nrfxlib::json::JsonWriter;
+//! let mut buf = [0u8; 128];
+//! let mut json = JsonWriter::new(&mut buf);
+//! json.begin_object()?;
+//! json.key("appId")?;
+//! json.string("TEMP")?;
+//! json.key("messageType")?;
+//! json.string("DATA")?;
+//! json.key("data")?;
+//! json.number(21.5)?;
+//! json.end_object()?;
+//! let len = json.finish()?;
+//! socket.write(&buf[0..len])?;
+//! ```
+//!
+//! `JsonReader` is handed a received message and returns one `Event` at a
+//! time. Strings are not copied - a `JsonStr` borrows from the message, and
+//! its escapes are only decoded when you ask:
+//!
+//! ```ignore
+//! use nrfxlib::json::{Event, JsonReader};
+//! let mut reader = JsonReader::new(&message);
+//! while let Some(event) = reader.next()? {
+//!     match event {
+//!         Event::Key(key) if key.matches("interval") => {
+//!             if let Some(Event::Number(n)) = reader.next()? {
+//!                 interval = n.as_u32()?;
+//!             }
+//!         }
+//!         Event::Key(_) => reader.skip()?,
+//!         _ => {}
+//!     }
+//! }
+//! ```
+//!
+//! Both give `Error::BadDataFormat` for anything which isn't JSON (or, for
+//! the writer, wouldn't be), and nest at most `MAX_DEPTH` deep.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::Error;
+use core::fmt::Write;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Writes JSON into a buffer. See the module documentation.
+#[derive(Debug)]
+pub struct JsonWriter<'b> {
+	buffer: &'b mut [u8],
+	position: usize,
+	nesting: Nesting,
+	/// Bit `n` is set once the container at depth `n + 1` has an item
+	has_items: u32,
+	/// A key has been written, and its value hasn't
+	after_key: bool,
+	/// The top-level value has been written
+	done: bool,
+}
+
+/// Reads JSON one token at a time. See the module documentation.
+#[derive(Debug, Clone)]
+pub struct JsonReader<'a> {
+	input: &'a [u8],
+	position: usize,
+	nesting: Nesting,
+	expect: Expect,
+}
+
+/// One token read by a `JsonReader`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event<'a> {
+	/// `{`
+	BeginObject,
+	/// `}`
+	EndObject,
+	/// `[`
+	BeginArray,
+	/// `]`
+	EndArray,
+	/// The key of an object member. Its value comes next.
+	Key(JsonStr<'a>),
+	/// A string value
+	String(JsonStr<'a>),
+	/// A number value
+	Number(JsonNumber<'a>),
+	/// `true` or `false`
+	Bool(bool),
+	/// `null`
+	Null,
+}
+
+/// A string in the JSON being read, between its quotes, with any escapes
+/// still in it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JsonStr<'a>(&'a str);
+
+/// A number in the JSON being read, as it was written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JsonNumber<'a>(&'a str);
+
+/// The characters of a `JsonStr`, with its escapes decoded.
+#[derive(Debug, Clone)]
+pub struct Chars<'a> {
+	rest: core::str::Chars<'a>,
+}
+
+/// Which containers we are inside, innermost last.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Nesting {
+	depth: u8,
+	/// Bit `n` is set if the container at depth `n + 1` is an object
+	objects: u32,
+}
+
+/// What a `JsonReader` may read next.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Expect {
+	/// Any value
+	Value,
+	/// A value, or the end of an empty array
+	ValueOrEnd,
+	/// A key
+	Key,
+	/// A key, or the end of an empty object
+	KeyOrEnd,
+	/// A comma, or the end of the container
+	CommaOrEnd,
+	/// Nothing more - the top-level value has been read
+	Done,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// How deeply objects and arrays may nest.
+pub const MAX_DEPTH: u8 = 32;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl<'b> JsonWriter<'b> {
+	/// Start writing JSON at the start of `buffer`.
+	pub fn new(buffer: &'b mut [u8]) -> JsonWriter<'b> {
+		JsonWriter {
+			buffer,
+			position: 0,
+			nesting: Nesting::new(),
+			has_items: 0,
+			after_key: false,
+			done: false,
+		}
+	}
+
+	/// Write `{`.
+	pub fn begin_object(&mut self) -> Result<(), Error> {
+		self.begin(b'{', true)
+	}
+
+	/// Write `}`, to close the innermost object.
+	pub fn end_object(&mut self) -> Result<(), Error> {
+		self.end(b'}', true)
+	}
+
+	/// Write `[`.
+	pub fn begin_array(&mut self) -> Result<(), Error> {
+		self.begin(b'[', false)
+	}
+
+	/// Write `]`, to close the innermost array.
+	pub fn end_array(&mut self) -> Result<(), Error> {
+		self.end(b']', false)
+	}
+
+	/// Write the key of the next member of the innermost object. Its value
+	/// must be written next.
+	pub fn key(&mut self, key: &str) -> Result<(), Error> {
+		if !self.nesting.in_object() || self.after_key {
+			return Err(Error::BadDataFormat);
+		}
+		self.separator()?;
+		self.quoted(key)?;
+		self.bytes(b":")?;
+		self.after_key = true;
+		Ok(())
+	}
+
+	/// Write a string value, escaping it as needed.
+	pub fn string(&mut self, value: &str) -> Result<(), Error> {
+		self.value_start()?;
+		self.quoted(value)
+	}
+
+	/// Write an integer value.
+	pub fn integer(&mut self, value: i64) -> Result<(), Error> {
+		self.value_start()?;
+		write!(self, "{}", value).map_err(|_| Error::BufferFull)
+	}
+
+	/// Write a number value. JSON can't hold infinities or NaN, so those
+	/// give `Error::BadDataFormat`.
+	pub fn number(&mut self, value: f64) -> Result<(), Error> {
+		if !value.is_finite() {
+			return Err(Error::BadDataFormat);
+		}
+		self.value_start()?;
+		write!(self, "{}", value).map_err(|_| Error::BufferFull)
+	}
+
+	/// Write `true` or `false`.
+	pub fn bool(&mut self, value: bool) -> Result<(), Error> {
+		self.value_start()?;
+		self.bytes(if value { b"true" } else { b"false" })
+	}
+
+	/// Write `null`.
+	pub fn null(&mut self) -> Result<(), Error> {
+		self.value_start()?;
+		self.bytes(b"null")
+	}
+
+	/// Write some JSON which is already encoded, as a value - a payload from
+	/// elsewhere, say. It is not checked.
+	pub fn raw(&mut self, json: &str) -> Result<(), Error> {
+		self.value_start()?;
+		self.bytes(json.as_bytes())
+	}
+
+	/// Check every object and array has been closed, and return the number
+	/// of bytes written.
+	pub fn finish(self) -> Result<usize, Error> {
+		if self.done {
+			Ok(self.position)
+		} else {
+			Err(Error::BadDataFormat)
+		}
+	}
+}
+
+impl<'b> Write for JsonWriter<'b> {
+	fn write_str(&mut self, s: &str) -> core::fmt::Result {
+		self.bytes(s.as_bytes()).map_err(|_| core::fmt::Error)
+	}
+}
+
+impl<'a> JsonReader<'a> {
+	/// Start reading the JSON in `input`, which should hold exactly one
+	/// value (and perhaps some whitespace).
+	pub fn new(input: &'a [u8]) -> JsonReader<'a> {
+		JsonReader {
+			input,
+			position: 0,
+			nesting: Nesting::new(),
+			expect: Expect::Value,
+		}
+	}
+
+	/// Read the next token. Returns `Ok(None)` once the top-level value has
+	/// been read, and `Error::BadDataFormat` if the input isn't valid JSON.
+	#[allow(clippy::should_implement_trait)]
+	pub fn next(&mut self) -> Result<Option<Event<'a>>, Error> {
+		loop {
+			self.skip_whitespace();
+			let byte = self.peek();
+			match (self.expect, byte) {
+				(Expect::Done, None) => return Ok(None),
+				(Expect::Done, Some(_)) | (_, None) => return Err(Error::BadDataFormat),
+				(Expect::CommaOrEnd, Some(b',')) => {
+					self.position += 1;
+					self.expect = if self.nesting.in_object() {
+						Expect::Key
+					} else {
+						Expect::Value
+					};
+				}
+				(Expect::CommaOrEnd, Some(b'}')) | (Expect::KeyOrEnd, Some(b'}')) => {
+					return self.close(true).map(|()| Some(Event::EndObject));
+				}
+				(Expect::CommaOrEnd, Some(b']')) | (Expect::ValueOrEnd, Some(b']')) => {
+					return self.close(false).map(|()| Some(Event::EndArray));
+				}
+				(Expect::Key, Some(b'"')) | (Expect::KeyOrEnd, Some(b'"')) => {
+					let key = self.string()?;
+					self.skip_whitespace();
+					if self.peek() != Some(b':') {
+						return Err(Error::BadDataFormat);
+					}
+					self.position += 1;
+					self.expect = Expect::Value;
+					return Ok(Some(Event::Key(key)));
+				}
+				(Expect::Value, Some(byte)) | (Expect::ValueOrEnd, Some(byte)) => {
+					return self.value(byte).map(Some);
+				}
+				_ => return Err(Error::BadDataFormat),
+			}
+		}
+	}
+
+	/// Skip the next value, and everything in it if it is an object or an
+	/// array. Call this after an `Event::Key` whose value you don't want.
+	pub fn skip(&mut self) -> Result<(), Error> {
+		let depth = self.nesting.depth;
+		match self.next()? {
+			Some(Event::BeginObject) | Some(Event::BeginArray) => {
+				while self.nesting.depth > depth {
+					if self.next()?.is_none() {
+						return Err(Error::BadDataFormat);
+					}
+				}
+				Ok(())
+			}
+			Some(Event::Key(_)) | Some(Event::EndObject) | Some(Event::EndArray) | None => {
+				Err(Error::BadDataFormat)
+			}
+			Some(_) => Ok(()),
+		}
+	}
+
+	/// How many objects and arrays we are inside.
+	pub fn depth(&self) -> u8 {
+		self.nesting.depth
+	}
+}
+
+impl<'a> JsonStr<'a> {
+	/// The string as it was in the JSON, with any escapes still in it.
+	pub fn raw(&self) -> &'a str {
+		self.0
+	}
+
+	/// The characters of the string, with its escapes decoded.
+	pub fn chars(&self) -> Chars<'a> {
+		Chars {
+			rest: self.0.chars(),
+		}
+	}
+
+	/// Is the string, once decoded, `other`?
+	pub fn matches(&self, other: &str) -> bool {
+		if self.0.contains('\\') {
+			self.chars().eq(other.chars())
+		} else {
+			self.0 == other
+		}
+	}
+
+	/// Decode the string into `buffer`, and return it. Returns
+	/// `Error::BufferFull` if it doesn't fit.
+	pub fn unescape_into<'b>(&self, buffer: &'b mut [u8]) -> Result<&'b str, Error> {
+		let mut length = 0;
+		for ch in self.chars() {
+			let end = length + ch.len_utf8();
+			ch.encode_utf8(buffer.get_mut(length..end).ok_or(Error::BufferFull)?);
+			length = end;
+		}
+		// We only wrote whole characters, so this can't fail
+		let written = buffer.get(0..length).ok_or(Error::BufferFull)?;
+		core::str::from_utf8(written).map_err(|_| Error::BadDataFormat)
+	}
+}
+
+impl<'a> JsonNumber<'a> {
+	/// The number as it was in the JSON.
+	pub fn raw(&self) -> &'a str {
+		self.0
+	}
+
+	/// The number as an `f64`, rounded if need be.
+	pub fn as_f64(&self) -> f64 {
+		// `JsonReader` only makes valid numbers, so this can't fail
+		self.0.parse().unwrap_or(f64::NAN)
+	}
+
+	/// The number as an `i64`. Returns `Error::BadDataFormat` if it has a
+	/// fraction or exponent, or `Error::UnrecognisedValue` if it is out of
+	/// range.
+	pub fn as_i64(&self) -> Result<i64, Error> {
+		if self.0.bytes().any(|b| matches!(b, b'.' | b'e' | b'E')) {
+			return Err(Error::BadDataFormat);
+		}
+		self.0.parse().map_err(|_| Error::UnrecognisedValue)
+	}
+
+	/// The number as a `u32`, as for `as_i64`.
+	pub fn as_u32(&self) -> Result<u32, Error> {
+		let value = self.as_i64()?;
+		core::convert::TryFrom::try_from(value).map_err(|_| Error::UnrecognisedValue)
+	}
+}
+
+impl<'a> Iterator for Chars<'a> {
+	type Item = char;
+
+	/// The next character.
+	fn next(&mut self) -> Option<char> {
+		let ch = self.rest.next()?;
+		if ch != '\\' {
+			return Some(ch);
+		}
+		// `JsonReader` has already checked the escapes, and that surrogates
+		// come in pairs
+		let decoded = match self.rest.next()? {
+			'b' => '\u{8}',
+			'f' => '\u{c}',
+			'n' => '\n',
+			'r' => '\r',
+			't' => '\t',
+			'u' => {
+				let high = self.hex4();
+				if (0xD800..0xDC00).contains(&high) && self.rest.as_str().starts_with("\\u") {
+					let mut lookahead = self.rest.clone();
+					lookahead.nth(1);
+					let mut low_chars = Chars { rest: lookahead };
+					let low = low_chars.hex4();
+					if (0xDC00..0xE000).contains(&low) {
+						self.rest = low_chars.rest;
+						let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+						return Some(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+					}
+				}
+				char::from_u32(high).unwrap_or(char::REPLACEMENT_CHARACTER)
+			}
+			// `"`, `\` and `/` stand for themselves
+			other => other,
+		};
+		Some(decoded)
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl<'b> JsonWriter<'b> {
+	/// Write some bytes, or fail with `Error::BufferFull`
+	fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+		let end = self.position + data.len();
+		self.buffer
+			.get_mut(self.position..end)
+			.ok_or(Error::BufferFull)?
+			.copy_from_slice(data);
+		self.position = end;
+		Ok(())
+	}
+
+	/// Write the comma before an item, if it isn't the first in its
+	/// container.
+	fn separator(&mut self) -> Result<(), Error> {
+		if self.nesting.depth > 0 {
+			let bit = 1 << (self.nesting.depth - 1);
+			if self.has_items & bit != 0 {
+				self.bytes(b",")?;
+			}
+			self.has_items |= bit;
+		}
+		Ok(())
+	}
+
+	/// Check a value may go here, and write the comma before it if need be.
+	fn value_start(&mut self) -> Result<(), Error> {
+		if self.done || (self.nesting.in_object() && !self.after_key) {
+			return Err(Error::BadDataFormat);
+		}
+		if self.after_key {
+			self.after_key = false;
+		} else {
+			self.separator()?;
+		}
+		if self.nesting.depth == 0 {
+			self.done = true;
+		}
+		Ok(())
+	}
+
+	fn begin(&mut self, open: u8, object: bool) -> Result<(), Error> {
+		self.value_start()?;
+		self.done = false;
+		self.nesting.push(object)?;
+		self.has_items &= !(1 << (self.nesting.depth - 1));
+		self.bytes(&[open])
+	}
+
+	fn end(&mut self, close: u8, object: bool) -> Result<(), Error> {
+		if self.after_key {
+			return Err(Error::BadDataFormat);
+		}
+		self.nesting.pop(object)?;
+		self.done = self.nesting.depth == 0;
+		self.bytes(&[close])
+	}
+
+	/// Write a string in quotes, escaping what JSON says must be.
+	fn quoted(&mut self, value: &str) -> Result<(), Error> {
+		self.bytes(b"\"")?;
+		let mut start = 0;
+		for (idx, byte) in value.bytes().enumerate() {
+			// `None` for the control characters with no short escape
+			let escape: Option<&[u8]> = match byte {
+				b'"' => Some(b"\\\""),
+				b'\\' => Some(b"\\\\"),
+				b'\n' => Some(b"\\n"),
+				b'\r' => Some(b"\\r"),
+				b'\t' => Some(b"\\t"),
+				0x00..=0x1F => None,
+				_ => continue,
+			};
+			self.bytes(value.as_bytes().get(start..idx).unwrap_or(&[]))?;
+			match escape {
+				Some(escape) => self.bytes(escape)?,
+				None => write!(self, "\\u{:04x}", byte).map_err(|_| Error::BufferFull)?,
+			}
+			start = idx + 1;
+		}
+		self.bytes(value.as_bytes().get(start..).unwrap_or(&[]))?;
+		self.bytes(b"\"")
+	}
+}
+
+impl<'a> JsonReader<'a> {
+	fn peek(&self) -> Option<u8> {
+		self.input.get(self.position).copied()
+	}
+
+	fn skip_whitespace(&mut self) {
+		while matches!(
+			self.peek(),
+			Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n')
+		) {
+			self.position += 1;
+		}
+	}
+
+	/// What comes after a value: a comma or the end of its container, or
+	/// nothing at all at the top level.
+	fn after_value(&self) -> Expect {
+		if self.nesting.depth == 0 {
+			Expect::Done
+		} else {
+			Expect::CommaOrEnd
+		}
+	}
+
+	/// Read the value starting with `byte`.
+	fn value(&mut self, byte: u8) -> Result<Event<'a>, Error> {
+		let event = match byte {
+			b'{' => {
+				self.position += 1;
+				self.nesting.push(true)?;
+				self.expect = Expect::KeyOrEnd;
+				return Ok(Event::BeginObject);
+			}
+			b'[' => {
+				self.position += 1;
+				self.nesting.push(false)?;
+				self.expect = Expect::ValueOrEnd;
+				return Ok(Event::BeginArray);
+			}
+			b'"' => Event::String(self.string()?),
+			b't' => self.literal("true", Event::Bool(true))?,
+			b'f' => self.literal("false", Event::Bool(false))?,
+			b'n' => self.literal("null", Event::Null)?,
+			b'-' | b'0'..=b'9' => Event::Number(self.number()?),
+			_ => return Err(Error::BadDataFormat),
+		};
+		self.expect = self.after_value();
+		Ok(event)
+	}
+
+	/// Read the end of the innermost container.
+	fn close(&mut self, object: bool) -> Result<(), Error> {
+		self.position += 1;
+		self.nesting.pop(object)?;
+		self.expect = self.after_value();
+		Ok(())
+	}
+
+	fn literal(&mut self, text: &str, event: Event<'a>) -> Result<Event<'a>, Error> {
+		let rest = self.input.get(self.position..).unwrap_or(&[]);
+		if rest.starts_with(text.as_bytes()) {
+			self.position += text.len();
+			Ok(event)
+		} else {
+			Err(Error::BadDataFormat)
+		}
+	}
+
+	/// Read a string, from its opening quote to its closing one, checking
+	/// its escapes.
+	fn string(&mut self) -> Result<JsonStr<'a>, Error> {
+		let start = self.position + 1;
+		let mut idx = start;
+		loop {
+			match self.input.get(idx) {
+				Some(b'"') => break,
+				Some(b'\\') => {
+					idx += match self.input.get(idx + 1) {
+						Some(b'"') | Some(b'\\') | Some(b'/') | Some(b'b') | Some(b'f')
+						| Some(b'n') | Some(b'r') | Some(b't') => 2,
+						Some(b'u') => match self.hex_escape(idx) {
+							// Half a surrogate pair must have the other half
+							// straight after it
+							Some(0xD800..=0xDBFF) => match self.hex_escape(idx + 6) {
+								Some(0xDC00..=0xDFFF) => 12,
+								_ => return Err(Error::BadDataFormat),
+							},
+							Some(0xDC00..=0xDFFF) | None => return Err(Error::BadDataFormat),
+							Some(_) => 6,
+						},
+						_ => return Err(Error::BadDataFormat),
+					};
+				}
+				Some(0x00..=0x1F) | None => return Err(Error::BadDataFormat),
+				Some(_) => idx += 1,
+			}
+		}
+		let text = self.input.get(start..idx).ok_or(Error::BadDataFormat)?;
+		let text = core::str::from_utf8(text).map_err(|_| Error::BadDataFormat)?;
+		self.position = idx + 1;
+		Ok(JsonStr(text))
+	}
+
+	/// Read the `\uXXXX` escape at `idx`, if there is one there.
+	fn hex_escape(&self, idx: usize) -> Option<u32> {
+		let hex = self.input.get(idx..idx + 6)?.strip_prefix(b"\\u")?;
+		if !hex.iter().all(u8::is_ascii_hexdigit) {
+			return None;
+		}
+		u32::from_str_radix(core::str::from_utf8(hex).ok()?, 16).ok()
+	}
+
+	/// Read a number, checking it against JSON's grammar.
+	fn number(&mut self) -> Result<JsonNumber<'a>, Error> {
+		let start = self.position;
+		let mut idx = start;
+		let digits = |idx: &mut usize| {
+			let first = *idx;
+			while matches!(self.input.get(*idx), Some(b'0'..=b'9')) {
+				*idx += 1;
+			}
+			*idx > first
+		};
+		if self.input.get(idx) == Some(&b'-') {
+			idx += 1;
+		}
+		if self.input.get(idx) == Some(&b'0') {
+			idx += 1;
+		} else if !digits(&mut idx) {
+			return Err(Error::BadDataFormat);
+		}
+		if self.input.get(idx) == Some(&b'.') {
+			idx += 1;
+			if !digits(&mut idx) {
+				return Err(Error::BadDataFormat);
+			}
+		}
+		if matches!(self.input.get(idx), Some(b'e') | Some(b'E')) {
+			idx += 1;
+			if matches!(self.input.get(idx), Some(b'+') | Some(b'-')) {
+				idx += 1;
+			}
+			if !digits(&mut idx) {
+				return Err(Error::BadDataFormat);
+			}
+		}
+		// It is all ASCII, so this can't fail
+		let text = self.input.get(start..idx).ok_or(Error::BadDataFormat)?;
+		let text = core::str::from_utf8(text).map_err(|_| Error::BadDataFormat)?;
+		self.position = idx;
+		Ok(JsonNumber(text))
+	}
+}
+
+impl<'a> Chars<'a> {
+	/// Read four hex digits, already checked by `JsonReader`.
+	fn hex4(&mut self) -> u32 {
+		(0..4).fold(0, |acc, _| {
+			let digit = self.rest.next().and_then(|ch| ch.to_digit(16)).unwrap_or(0);
+			(acc << 4) | digit
+		})
+	}
+}
+
+impl Nesting {
+	const fn new() -> Nesting {
+		Nesting {
+			depth: 0,
+			objects: 0,
+		}
+	}
+
+	/// Is the innermost container an object?
+	fn in_object(&self) -> bool {
+		self.depth > 0 && self.objects & (1 << (self.depth - 1)) != 0
+	}
+
+	fn push(&mut self, object: bool) -> Result<(), Error> {
+		if self.depth >= MAX_DEPTH {
+			return Err(Error::BadDataFormat);
+		}
+		let bit = 1 << self.depth;
+		if object {
+			self.objects |= bit;
+		} else {
+			self.objects &= !bit;
+		}
+		self.depth += 1;
+		Ok(())
+	}
+
+	/// Leave the innermost container, which must be an object if `object`
+	/// (or an array if not).
+	fn pop(&mut self, object: bool) -> Result<(), Error> {
+		if self.depth == 0 || self.in_object() != object {
+			return Err(Error::BadDataFormat);
+		}
+		self.depth -= 1;
+		Ok(())
+	}
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Read every event in `input`, returning how many there were.
+	fn read_all(input: &[u8]) -> Result<usize, Error> {
+		let mut reader = JsonReader::new(input);
+		let mut count = 0;
+		while reader.next()?.is_some() {
+			count += 1;
+		}
+		Ok(count)
+	}
+
+	#[test]
+	fn round_trip() {
+		let mut buffer = [0u8; 256];
+		let mut json = JsonWriter::new(&mut buffer);
+		assert!(json.begin_object().is_ok());
+		assert!(json.key("name").is_ok());
+		assert!(json.string("say \"hi\"\\\n\t\u{1}é😀").is_ok());
+		assert!(json.key("values").is_ok());
+		assert!(json.begin_array().is_ok());
+		assert!(json.integer(i64::MIN).is_ok());
+		assert!(json.number(-0.015625).is_ok());
+		assert!(json.bool(true).is_ok());
+		assert!(json.null().is_ok());
+		assert!(json.begin_object().is_ok());
+		assert!(json.end_object().is_ok());
+		assert!(json.end_array().is_ok());
+		assert!(json.end_object().is_ok());
+		let length = json.finish().unwrap_or(0);
+		let written = buffer.get(0..length).unwrap_or(&[]);
+		assert_eq!(
+			written,
+			r#"{"name":"say \"hi\"\\\n\t\u0001é😀","values":[-9223372036854775808,-0.015625,true,null,{}]}"#.as_bytes()
+		);
+
+		let mut reader = JsonReader::new(written);
+		assert_eq!(reader.next().ok(), Some(Some(Event::BeginObject)));
+		assert!(matches!(reader.next(), Ok(Some(Event::Key(k))) if k.matches("name")));
+		let value = match reader.next() {
+			Ok(Some(Event::String(value))) => Some(value),
+			_ => None,
+		};
+		assert!(value.is_some_and(|value| value.matches("say \"hi\"\\\n\t\u{1}é😀")));
+		let mut decoded = [0u8; 32];
+		assert_eq!(
+			value.and_then(|value| value.unescape_into(&mut decoded).ok()),
+			Some("say \"hi\"\\\n\t\u{1}é😀")
+		);
+		// Too small a buffer
+		assert_eq!(
+			value.map(|value| matches!(value.unescape_into(&mut [0u8; 4]), Err(Error::BufferFull))),
+			Some(true)
+		);
+		assert!(matches!(reader.next(), Ok(Some(Event::Key(k))) if k.matches("values")));
+		assert_eq!(reader.next().ok(), Some(Some(Event::BeginArray)));
+		assert!(
+			matches!(reader.next(), Ok(Some(Event::Number(n))) if n.as_i64().ok() == Some(i64::MIN))
+		);
+		assert!(
+			matches!(reader.next(), Ok(Some(Event::Number(n))) if n.as_f64() == -0.015625 && n.as_i64().is_err())
+		);
+		assert_eq!(reader.next().ok(), Some(Some(Event::Bool(true))));
+		assert_eq!(reader.next().ok(), Some(Some(Event::Null)));
+		assert_eq!(reader.next().ok(), Some(Some(Event::BeginObject)));
+		assert_eq!(reader.next().ok(), Some(Some(Event::EndObject)));
+		assert_eq!(reader.next().ok(), Some(Some(Event::EndArray)));
+		assert_eq!(reader.next().ok(), Some(Some(Event::EndObject)));
+		assert_eq!(reader.next().ok(), Some(None));
+	}
+
+	#[test]
+	fn read_escapes() {
+		let mut reader = JsonReader::new(br#""\u00e9\/\ud83d\ude00\u0041""#);
+		let mut decoded = [0u8; 16];
+		assert!(matches!(
+			reader.next(),
+			Ok(Some(Event::String(s))) if s.unescape_into(&mut decoded).ok() == Some("é/😀A")
+		));
+	}
+
+	#[test]
+	fn skip_nested() {
+		let mut reader = JsonReader::new(br#"{"skip":{"a":[1,{"b":2}]},"keep":3}"#);
+		assert_eq!(reader.next().ok(), Some(Some(Event::BeginObject)));
+		assert!(matches!(reader.next(), Ok(Some(Event::Key(k))) if k.matches("skip")));
+		assert!(reader.skip().is_ok());
+		assert_eq!(reader.depth(), 1);
+		assert!(matches!(reader.next(), Ok(Some(Event::Key(k))) if k.matches("keep")));
+		assert!(matches!(reader.next(), Ok(Some(Event::Number(n))) if n.as_u32().ok() == Some(3)));
+	}
+
+	#[test]
+	fn accepts_valid() {
+		for (input, events) in &[
+			(&b" 0 "[..], 1),
+			(b"-0.5e+10", 1),
+			(b"1E-2", 1),
+			(b"\"\"", 1),
+			(b"[]", 2),
+			(b" { \"a\" : [ 1 , 2 ] } ", 7),
+		] {
+			assert_eq!(read_all(input).ok(), Some(*events), "{:?}", input);
+		}
+	}
+
+	#[test]
+	fn rejects_trailing_commas() {
+		for input in &[
+			&b"[1,]"[..],
+			b"{\"a\":1,}",
+			b"[,]",
+			b"{,}",
+			b"[1,,2]",
+			b"1,",
+		] {
+			assert!(
+				matches!(read_all(input), Err(Error::BadDataFormat)),
+				"{:?}",
+				input
+			);
+		}
+	}
+
+	#[test]
+	fn rejects_leading_zeros() {
+		for input in &[
+			&b"01"[..],
+			b"-01",
+			b"[00]",
+			b"{\"a\":007}",
+			b"0.1.2",
+			b".5",
+			b"5.",
+			b"1e",
+			b"+1",
+			b"-",
+		] {
+			assert!(
+				matches!(read_all(input), Err(Error::BadDataFormat)),
+				"{:?}",
+				input
+			);
+		}
+	}
+
+	#[test]
+	fn rejects_control_characters() {
+		for input in &[&b"\"a\nb\""[..], b"\"\x00\"", b"\"\x1f\"", b"\"\t\""] {
+			assert!(
+				matches!(read_all(input), Err(Error::BadDataFormat)),
+				"{:?}",
+				input
+			);
+		}
+		// Escaped, they are fine
+		assert_eq!(read_all(br#""a\nb\u0000\u001f\t""#).ok(), Some(1));
+	}
+
+	#[test]
+	fn rejects_lone_surrogates() {
+		for input in &[
+			&br#""\ud800""#[..],
+			br#""\udc00""#,
+			br#""\ud800x""#,
+			br#""\ud800\u0041""#,
+			br#""\ud800\ud800""#,
+			br#""\ude00\ud83d""#,
+		] {
+			assert!(
+				matches!(read_all(input), Err(Error::BadDataFormat)),
+				"{:?}",
+				input
+			);
+		}
+	}
+
+	#[test]
+	fn rejects_other_errors() {
+		for input in &[
+			&b""[..],
+			b"[1 2]",
+			b"{\"a\" 1}",
+			b"{1:2}",
+			b"[1}",
+			b"{\"a\":1]",
+			b"tru",
+			b"nul",
+			b"\"\\x\"",
+			b"\"\\u12\"",
+			b"\"abc",
+			b"\"\xff\"",
+			b"1 2",
+		] {
+			assert!(
+				matches!(read_all(input), Err(Error::BadDataFormat)),
+				"{:?}",
+				input
+			);
+		}
+	}
+
+	#[test]
+	fn depth_limit() {
+		let mut input = [0u8; 2 * (MAX_DEPTH as usize + 1)];
+		let (open, close) = input.split_at_mut(MAX_DEPTH as usize + 1);
+		open.fill(b'[');
+		close.fill(b']');
+		// `MAX_DEPTH` deep is fine...
+		let deepest = input.get(1..input.len() - 1).unwrap_or(&[]);
+		assert_eq!(read_all(deepest).ok(), Some(2 * usize::from(MAX_DEPTH)));
+		// ... but one more isn't
+		assert!(matches!(read_all(&input), Err(Error::BadDataFormat)));
+
+		let mut buffer = [0u8; 128];
+		let mut json = JsonWriter::new(&mut buffer);
+		for _ in 0..MAX_DEPTH {
+			assert!(json.begin_array().is_ok());
+		}
+		assert!(matches!(json.begin_array(), Err(Error::BadDataFormat)));
+	}
+
+	#[test]
+	fn writer_rejects_misuse() {
+		let mut buffer = [0u8; 64];
+		let mut json = JsonWriter::new(&mut buffer);
+		// A key outside an object
+		assert!(matches!(json.key("a"), Err(Error::BadDataFormat)));
+		assert!(json.begin_object().is_ok());
+		// A value without a key
+		assert!(matches!(json.integer(1), Err(Error::BadDataFormat)));
+		assert!(json.key("a").is_ok());
+		assert!(matches!(json.key("b"), Err(Error::BadDataFormat)));
+		assert!(matches!(json.end_object(), Err(Error::BadDataFormat)));
+		assert!(matches!(json.number(f64::NAN), Err(Error::BadDataFormat)));
+		assert!(json.integer(1).is_ok());
+		assert!(matches!(json.end_array(), Err(Error::BadDataFormat)));
+		assert!(json.end_object().is_ok());
+		// Only one top-level value
+		assert!(matches!(json.null(), Err(Error::BadDataFormat)));
+		assert!(matches!(json.finish(), Ok(7)));
+
+		let mut json = JsonWriter::new(&mut buffer);
+		assert!(json.begin_array().is_ok());
+		assert!(matches!(json.finish(), Err(Error::BadDataFormat)));
+
+		let mut small = [0u8; 4];
+		let mut json = JsonWriter::new(&mut small);
+		assert!(matches!(json.string("hello"), Err(Error::BufferFull)));
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
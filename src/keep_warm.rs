@@ -0,0 +1,279 @@
+//! # Keep-warm scheduling for nrfxlib
+//!
+//! A socket nobody sends on goes cold: the carrier's NAT forgets the mapping
+//! after a while, and the server can no longer reach the device. The fix is
+//! to send something small now and then - but every send wakes the radio.
+//! With eDRX the modem is awake anyway during each paging time window, so a
+//! `KeepWarm` schedules each socket's keep-warm traffic for just before the
+//! last window closes ahead of that socket's deadline, and no more often
+//! than the deadline needs.
+//!
+//! ```ignore
+//! let mut keep_warm: KeepWarm<4> = KeepWarm::new();
+//! keep_warm.add(&socket, 120_000, now_ms())?;
+//! keep_warm.set_paging(modem::get_edrx()?.as_ref(), rrc_idle_at_ms);
+//! loop {
+//!     let next = keep_warm.poll(now_ms(), |fd| socket.send(b"\0").map(|_| ()));
+//!     // ... sleep until `next`, or until there is other work
+//! }
+//! ```
+//!
+//! The modem doesn't say when its paging windows are, so you give an
+//! estimate of when one started. The best one is when the RRC connection was
+//! released (`+CSCON: 0`), as the first eDRX cycle starts then. Without one
+//! (or without eDRX) sends are scheduled for the deadline itself.
+//!
+//! Tell the scheduler about other traffic on a socket with `activity`, so it
+//! doesn't send when it doesn't need to. Times are in milliseconds from the
+//! same `now_ms` counter, and may wrap.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::modem::EdrxGranted;
+use crate::raw::Pollable;
+use crate::Error;
+use core::convert::TryFrom;
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Schedules keep-warm traffic for up to `N` sockets.
+#[derive(Debug, Clone)]
+pub struct KeepWarm<const N: usize> {
+	sockets: [Option<Entry>; N],
+	paging: Option<Paging>,
+	lead_ms: u32,
+}
+
+/// Which socket is next due some keep-warm traffic, and when.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeepWarmDue {
+	/// The socket ID
+	pub fd: i32,
+	/// How many milliseconds until it is due. Zero means it is due now.
+	pub in_ms: u32,
+}
+
+/// What we know about one socket.
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+	fd: i32,
+	/// The longest the socket may go without traffic
+	max_idle_ms: u32,
+	/// When there was last traffic on it
+	last_activity_ms: u32,
+}
+
+/// When the modem's eDRX paging time windows are.
+#[derive(Debug, Copy, Clone)]
+struct Paging {
+	cycle_ms: u32,
+	window_ms: u32,
+	/// When a window started
+	window_start_ms: u32,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// How long before a paging time window closes we send, by default - enough
+/// for the modem to get the data out while it is still awake.
+pub const DEFAULT_LEAD_MS: u32 = 1_000;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl<const N: usize> KeepWarm<N> {
+	/// Create a scheduler with no sockets and no eDRX timing.
+	pub fn new() -> KeepWarm<N> {
+		KeepWarm {
+			sockets: [None; N],
+			paging: None,
+			lead_ms: DEFAULT_LEAD_MS,
+		}
+	}
+
+	/// Keep a socket warm, making sure it never goes more than `max_idle_ms`
+	/// without traffic (e.g. a little less than the carrier's NAT timeout).
+	/// Its idle time is counted from `now_ms`. Adding a socket again changes
+	/// its `max_idle_ms`. Returns `Error::TooManySockets` if there are
+	/// already `N`.
+	pub fn add<P>(&mut self, socket: &P, max_idle_ms: u32, now_ms: u32) -> Result<(), Error>
+	where
+		P: Pollable,
+	{
+		let fd = socket.get_fd();
+		let entry = Entry {
+			fd,
+			max_idle_ms,
+			last_activity_ms: now_ms,
+		};
+		if let Some(slot) = self.sockets.iter_mut().flatten().find(|e| e.fd == fd) {
+			*slot = entry;
+			return Ok(());
+		}
+		let slot = self
+			.sockets
+			.iter_mut()
+			.find(|slot| slot.is_none())
+			.ok_or(Error::TooManySockets)?;
+		*slot = Some(entry);
+		Ok(())
+	}
+
+	/// Stop keeping a socket warm. Do this before closing it, as libmodem
+	/// re-uses socket IDs.
+	pub fn remove<P>(&mut self, socket: &P)
+	where
+		P: Pollable,
+	{
+		let fd = socket.get_fd();
+		for slot in self.sockets.iter_mut() {
+			if matches!(slot, Some(entry) if entry.fd == fd) {
+				*slot = None;
+			}
+		}
+	}
+
+	/// Record traffic on a socket at `now_ms`, which keeps it warm as well as
+	/// a keep-warm send would.
+	pub fn activity<P>(&mut self, socket: &P, now_ms: u32)
+	where
+		P: Pollable,
+	{
+		self.activity_fd(socket.get_fd(), now_ms);
+	}
+
+	/// Set the eDRX timing the network granted (see `modem::get_edrx`), and
+	/// when a paging time window started (see the module documentation), or
+	/// `None` if eDRX isn't in use.
+	pub fn set_paging(&mut self, granted: Option<&EdrxGranted>, window_start_ms: u32) {
+		self.paging = granted.and_then(|granted| {
+			let cycle_ms = u32::try_from(granted.cycle.as_millis()).ok()?;
+			let window_ms = u32::try_from(granted.paging_time_window.as_millis()).ok()?;
+			if cycle_ms == 0 {
+				return None;
+			}
+			Some(Paging {
+				cycle_ms,
+				window_ms,
+				window_start_ms,
+			})
+		});
+	}
+
+	/// Change how long before a paging time window closes we send.
+	pub fn set_lead_ms(&mut self, lead_ms: u32) {
+		self.lead_ms = lead_ms;
+	}
+
+	/// Find the socket which is due some keep-warm traffic soonest. Returns
+	/// `None` if there are no sockets.
+	pub fn next_due(&self, now_ms: u32) -> Option<KeepWarmDue> {
+		self.sockets
+			.iter()
+			.flatten()
+			.map(|entry| KeepWarmDue {
+				fd: entry.fd,
+				in_ms: self.due_in(entry, now_ms),
+			})
+			.min_by_key(|due| due.in_ms)
+	}
+
+	/// Call `send` with the ID of every socket which is due some keep-warm
+	/// traffic at `now_ms`. Those it sends on successfully are counted as
+	/// active now; those it fails on are tried again next time. Returns the
+	/// next one due, as `next_due` does.
+	pub fn poll<F>(&mut self, now_ms: u32, mut send: F) -> Option<KeepWarmDue>
+	where
+		F: FnMut(i32) -> Result<(), Error>,
+	{
+		for index in 0..N {
+			let due = match self.sockets.get(index).copied().flatten() {
+				Some(entry) if self.due_in(&entry, now_ms) == 0 => entry,
+				_ => continue,
+			};
+			match send(due.fd) {
+				Ok(()) => self.activity_fd(due.fd, now_ms),
+				Err(e) => debug!("Keep-warm send on {} failed: {:?}", due.fd, e),
+			}
+		}
+		self.next_due(now_ms)
+	}
+}
+
+impl<const N: usize> Default for KeepWarm<N> {
+	fn default() -> KeepWarm<N> {
+		KeepWarm::new()
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl<const N: usize> KeepWarm<N> {
+	/// Record traffic on a socket, by ID.
+	fn activity_fd(&mut self, fd: i32, now_ms: u32) {
+		for entry in self.sockets.iter_mut().flatten() {
+			if entry.fd == fd {
+				entry.last_activity_ms = now_ms;
+			}
+		}
+	}
+
+	/// How many milliseconds from `now_ms` until this socket is due: at the
+	/// last send point (just before a paging window closes) before its
+	/// deadline, or at the deadline if there isn't one.
+	fn due_in(&self, entry: &Entry, now_ms: u32) -> u32 {
+		let idle_ms = now_ms.wrapping_sub(entry.last_activity_ms);
+		let deadline_in_ms = entry.max_idle_ms.saturating_sub(idle_ms);
+		let paging = match self.paging {
+			Some(paging) => paging,
+			None => return deadline_in_ms,
+		};
+		// Where we are in the eDRX cycle, and where in it we want to send
+		let phase_ms = now_ms.wrapping_sub(paging.window_start_ms) % paging.cycle_ms;
+		let send_phase_ms = paging.window_ms.saturating_sub(self.lead_ms) % paging.cycle_ms;
+		let next_send_in_ms = (send_phase_ms + paging.cycle_ms - phase_ms) % paging.cycle_ms;
+		if next_send_in_ms > deadline_in_ms {
+			// No window before the deadline
+			return deadline_in_ms;
+		}
+		let cycles = (deadline_in_ms - next_send_in_ms) / paging.cycle_ms;
+		next_send_in_ms + cycles * paging.cycle_ms
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
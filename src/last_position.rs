@@ -0,0 +1,340 @@
+//! # Last known position for nrfxlib
+//!
+//! The GNSS module gets its first fix much sooner if it is told roughly
+//! where it is, and an application often wants a position to report before
+//! the first fix of the day. This module remembers the last good fix, and
+//! can keep it somewhere which survives a reset (flash, a retained RAM
+//! section, an external EEPROM) through a `PositionStore` you provide:
+//!
+//! ```ignore
+//! struct FlashStore;
+//!
+//! impl last_position::PositionStore for FlashStore {
+//!     fn load(&self) -> Option<[u8; last_position::ENCODED_LEN]> {
+//!         flash::read_record(POSITION_RECORD)
+//!     }
+//!     fn save(&self, encoded: &[u8; last_position::ENCODED_LEN]) {
+//!         flash::write_record(POSITION_RECORD, encoded);
+//!     }
+//! }
+//!
+//! static STORE: FlashStore = FlashStore;
+//! last_position::set_store(Some(&STORE));
+//! ```
+//!
+//! `GnssSocket::get_fix` and `get_fix_blocking` update it with every valid
+//! fix, and `get` reads it back - loading it from the store, the first time,
+//! after a reset. `GnssSocket::inject_last_known_position` gives it to the
+//! GNSS module as its A-GPS reference position.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+use crate::gnss::PvtFrame;
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+#[cfg(target_arch = "arm")]
+use nrfxlib_sys as sys;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A position from a GNSS fix.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LastKnownPosition {
+	/// Latitude, in degrees
+	pub latitude: f64,
+	/// Longitude, in degrees
+	pub longitude: f64,
+	/// Altitude above the WGS-84 ellipsoid, in metres
+	pub altitude: f32,
+	/// Accuracy (2D 1-sigma) of the fix, in metres
+	pub accuracy: f32,
+	/// The UTC date and time of the fix
+	pub datetime: DateTime,
+}
+
+/// A UTC date and time from the GNSS module.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DateTime {
+	/// The year, e.g. 2021
+	pub year: u16,
+	/// 1 to 12
+	pub month: u8,
+	/// 1 to 31
+	pub day: u8,
+	/// 0 to 23
+	pub hour: u8,
+	/// 0 to 59
+	pub minute: u8,
+	/// 0 to 59
+	pub second: u8,
+	/// 0 to 999
+	pub millisecond: u16,
+}
+
+/// Somewhere to keep the last known position over a reset.
+///
+/// `save` is called with every valid fix - once a second, with the default
+/// fix interval - from whichever context reads the GNSS socket. If writes
+/// are expensive (e.g. erasing flash), save only when the position has
+/// moved far enough, or often enough, to be worth it.
+pub trait PositionStore: Sync {
+	/// Get the position saved last, if there is one.
+	fn load(&self) -> Option<[u8; ENCODED_LEN]>;
+	/// Save a new position.
+	fn save(&self, encoded: &[u8; ENCODED_LEN]);
+}
+
+/// Somewhere to keep the store, which the GNSS code can reach.
+type WrappedStore = Mutex<RefCell<Option<&'static dyn PositionStore>>>;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// How many bytes a `LastKnownPosition` is stored in.
+pub const ENCODED_LEN: usize = 34;
+
+/// The first byte of an encoded position, so we can change the format later.
+const ENCODING_VERSION: u8 = 1;
+
+/// The confidence (in percent) we give the modem in our reference position.
+#[cfg(target_arch = "arm")]
+const LOCATION_CONFIDENCE: u8 = 68;
+
+/// The largest uncertainty code the modem accepts.
+#[cfg(target_arch = "arm")]
+const MAX_UNCERTAINTY_CODE: u8 = 127;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The store given to `set_store`.
+static STORE: WrappedStore = Mutex::new(RefCell::new(None));
+
+/// The last known position, once we have one.
+static LAST_POSITION: Mutex<RefCell<Option<LastKnownPosition>>> = Mutex::new(RefCell::new(None));
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Set where the last known position is kept over a reset, or `None` to
+/// keep it in RAM only.
+pub fn set_store(store: Option<&'static dyn PositionStore>) {
+	cortex_m::interrupt::free(|cs| {
+		*STORE.borrow(cs).borrow_mut() = store;
+	});
+}
+
+/// Get the last known position. If there hasn't been a fix since reset, this
+/// is loaded from the store (if there is one, and it has a position saved).
+pub fn get() -> Option<LastKnownPosition> {
+	if let Some(position) = cortex_m::interrupt::free(|cs| *LAST_POSITION.borrow(cs).borrow()) {
+		return Some(position);
+	}
+	let position = get_store()?
+		.load()
+		.and_then(|encoded| LastKnownPosition::decode(&encoded))?;
+	cortex_m::interrupt::free(|cs| {
+		LAST_POSITION
+			.borrow(cs)
+			.borrow_mut()
+			.get_or_insert(position);
+	});
+	Some(position)
+}
+
+/// Set the last known position, e.g. from a fix obtained some other way.
+/// It is saved in the store too.
+pub fn set(position: LastKnownPosition) {
+	cortex_m::interrupt::free(|cs| {
+		*LAST_POSITION.borrow(cs).borrow_mut() = Some(position);
+	});
+	if let Some(store) = get_store() {
+		store.save(&position.encode());
+	}
+}
+
+/// Forget the last known position. The store is left as it is, so `get`
+/// loads the saved one again.
+pub fn clear() {
+	cortex_m::interrupt::free(|cs| {
+		*LAST_POSITION.borrow(cs).borrow_mut() = None;
+	});
+}
+
+impl LastKnownPosition {
+	/// Encode this position for a `PositionStore`.
+	pub fn encode(&self) -> [u8; ENCODED_LEN] {
+		let mut encoded = [0u8; ENCODED_LEN];
+		encoded[0] = ENCODING_VERSION;
+		encoded[1..9].copy_from_slice(&self.latitude.to_le_bytes());
+		encoded[9..17].copy_from_slice(&self.longitude.to_le_bytes());
+		encoded[17..21].copy_from_slice(&self.altitude.to_le_bytes());
+		encoded[21..25].copy_from_slice(&self.accuracy.to_le_bytes());
+		encoded[25..27].copy_from_slice(&self.datetime.year.to_le_bytes());
+		encoded[27] = self.datetime.month;
+		encoded[28] = self.datetime.day;
+		encoded[29] = self.datetime.hour;
+		encoded[30] = self.datetime.minute;
+		encoded[31] = self.datetime.second;
+		encoded[32..34].copy_from_slice(&self.datetime.millisecond.to_le_bytes());
+		encoded
+	}
+
+	/// Decode a position from a `PositionStore`. Returns `None` if it isn't
+	/// one we understand (e.g. erased flash).
+	#[allow(clippy::indexing_slicing)] // the offsets are all within `ENCODED_LEN`
+	pub fn decode(encoded: &[u8; ENCODED_LEN]) -> Option<LastKnownPosition> {
+		if encoded[0] != ENCODING_VERSION {
+			return None;
+		}
+		let f64_at = |i: usize| {
+			let mut bytes = [0u8; 8];
+			bytes.copy_from_slice(&encoded[i..i + 8]);
+			f64::from_le_bytes(bytes)
+		};
+		let f32_at = |i: usize| {
+			let mut bytes = [0u8; 4];
+			bytes.copy_from_slice(&encoded[i..i + 4]);
+			f32::from_le_bytes(bytes)
+		};
+		let position = LastKnownPosition {
+			latitude: f64_at(1),
+			longitude: f64_at(9),
+			altitude: f32_at(17),
+			accuracy: f32_at(21),
+			datetime: DateTime {
+				year: u16::from_le_bytes([encoded[25], encoded[26]]),
+				month: encoded[27],
+				day: encoded[28],
+				hour: encoded[29],
+				minute: encoded[30],
+				second: encoded[31],
+				millisecond: u16::from_le_bytes([encoded[32], encoded[33]]),
+			},
+		};
+		let in_range = (-90.0..=90.0).contains(&position.latitude)
+			&& (-180.0..=180.0).contains(&position.longitude);
+		if in_range {
+			Some(position)
+		} else {
+			None
+		}
+	}
+
+	/// Encode this position as A-GPS reference location data, for
+	/// `GnssSocket::inject_agps`. The uncertainty is the larger of the fix's
+	/// accuracy and `uncertainty_m` - how far the device may have moved
+	/// since.
+	#[cfg(target_arch = "arm")]
+	pub fn to_agps_location(&self, uncertainty_m: f32) -> sys::nrf_gnss_agps_data_location_t {
+		let uncertainty_m = if uncertainty_m > self.accuracy {
+			uncertainty_m
+		} else {
+			self.accuracy
+		};
+		let unc_horizontal = uncertainty_code(uncertainty_m, 10.0, 1.1);
+		sys::nrf_gnss_agps_data_location_t {
+			// N <= (2^23/90) * X < N + 1
+			latitude: clamped_floor(self.latitude * (8_388_608.0 / 90.0), 8_388_607),
+			// N <= (2^24/360) * X < N + 1
+			longitude: clamped_floor(self.longitude * (16_777_216.0 / 360.0), 8_388_607),
+			altitude: clamped_floor(f64::from(self.altitude), i16::MAX as i32) as i16,
+			unc_semimajor: unc_horizontal,
+			unc_semiminor: unc_horizontal,
+			orientation_major: 0,
+			unc_altitude: uncertainty_code(uncertainty_m, 45.0, 1.025),
+			confidence: LOCATION_CONFIDENCE,
+		}
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl From<&PvtFrame> for LastKnownPosition {
+	fn from(frame: &PvtFrame) -> LastKnownPosition {
+		LastKnownPosition {
+			latitude: frame.latitude,
+			longitude: frame.longitude,
+			altitude: frame.altitude,
+			accuracy: frame.accuracy,
+			datetime: frame.datetime,
+		}
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Get the store, outside the critical section, so it doesn't hold up
+/// interrupts while it works.
+fn get_store() -> Option<&'static dyn PositionStore> {
+	cortex_m::interrupt::free(|cs| *STORE.borrow(cs).borrow())
+}
+
+/// Record a valid fix from the GNSS socket.
+#[cfg(target_arch = "arm")]
+pub(crate) fn record_fix(frame: &PvtFrame) {
+	set(LastKnownPosition::from(frame));
+}
+
+/// Round down to an integer no bigger (in magnitude) than `limit`.
+#[cfg(target_arch = "arm")]
+fn clamped_floor(value: f64, limit: i32) -> i32 {
+	let limit = f64::from(limit);
+	let value = if value > limit {
+		limit
+	} else if value < -limit {
+		-limit
+	} else {
+		value
+	};
+	let truncated = value as i32;
+	if f64::from(truncated) > value {
+		truncated - 1
+	} else {
+		truncated
+	}
+}
+
+/// Find the smallest code K whose uncertainty, `c * (base^K - 1)` metres,
+/// covers `uncertainty_m`. This is how the modem encodes uncertainties.
+#[cfg(target_arch = "arm")]
+fn uncertainty_code(uncertainty_m: f32, c: f32, base: f32) -> u8 {
+	let mut power = 1.0f32;
+	for code in 0..MAX_UNCERTAINTY_CODE {
+		if c * (power - 1.0) >= uncertainty_m {
+			return code;
+		}
+		power *= base;
+	}
+	MAX_UNCERTAINTY_CODE
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -5,7 +5,8 @@
 //!
 //! The `nrfxlib_sys` crate is the auto-generated wrapper for `nrf_modem_os.h`
 //! and `nrf_socket.h`. This crate contains Rustic wrappers for those
-//! auto-generated types.
+//! auto-generated types. With the `sys` feature, the raw bindings are
+//! public too, as `nrfxlib::sys` (see that module for the rules).
 //!
 //! To bring up the LTE stack you need to call `nrf_modem_init()`. Before that
 //! you need to enable the EGU1 and EGU2 interrupts, and arrange for the
@@ -21,6 +22,26 @@
 //! Once that is complete, you can create TCP or TLS sockets and send/receive
 //! data.
 //!
+//! Nothing in this crate panics on what the modem or the network sends it -
+//! a panic in the field takes the whole device down. Malformed input gives
+//! an `Error` (usually `Error::BadDataFormat`) instead, and the lints below
+//! keep it that way. The one exception is
+//! `nrf_modem_irrecoverable_error_handler`, which libmodem calls when it
+//! cannot carry on.
+//!
+//! With the `zeroize` feature, memory which may have held credentials (PSKs,
+//! private keys, PINs, passwords or JWTs) is wiped as soon as we're done
+//! with it: the pool buffers AT responses are read into, the buffers gone
+//! back to libmodem (including the shared memory AT commands are sent
+//! through), and the command buffers built by `pdn::set_authentication`.
+//! Anything you keep yourself - the `&str` you pass in, or what an AT
+//! transcript sink stores - is yours to wipe.
+//!
+//! libmodem is only linked on ARM targets. Built for anything else (a
+//! development machine, say), the crate has no modem to drive, and contains
+//! only its types and the parsers for what the modem and network send - so
+//! they can be tested and fuzzed on the host.
+//!
 //! Copyright (c) 42 Technology Ltd 2021
 //!
 //! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
@@ -28,51 +49,174 @@
 
 #![no_std]
 #![deny(missing_docs)]
+#![deny(
+	clippy::panic,
+	clippy::unwrap_used,
+	clippy::expect_used,
+	clippy::indexing_slicing
+)]
+
+#[cfg(feature = "std")]
+extern crate std;
 
 //******************************************************************************
 // Sub-Modules
 //******************************************************************************
 
+#[cfg(target_arch = "arm")]
+pub mod agps;
+#[cfg(target_arch = "arm")]
 pub mod api;
+#[cfg(target_arch = "arm")]
+pub mod asynch;
 pub mod at;
+pub mod builder;
+#[cfg(target_arch = "arm")]
+pub mod chat;
+#[cfg(target_arch = "arm")]
+pub mod coalesce;
+pub mod coap;
+#[cfg(target_arch = "arm")]
+pub mod cold_start;
+#[cfg(all(feature = "compress", target_arch = "arm"))]
+pub mod compress;
+#[cfg(target_arch = "arm")]
+pub mod credential_queue;
+#[cfg(all(feature = "dead-reckoning", target_arch = "arm"))]
+pub mod dead_reckoning;
+#[cfg(target_arch = "arm")]
+pub mod delay;
+#[cfg(target_arch = "arm")]
+pub mod destinations;
+#[cfg(target_arch = "arm")]
+pub mod device;
+pub mod dfu;
+#[cfg(target_arch = "arm")]
+pub mod dns;
+#[cfg(target_arch = "arm")]
 pub mod dtls;
+#[cfg(target_arch = "arm")]
+pub mod entropy;
+#[cfg(any(target_arch = "arm", test))]
+mod errno;
+#[cfg(target_arch = "arm")]
 mod ffi;
+pub mod firmware;
+#[cfg(target_arch = "arm")]
+pub mod flow;
+#[cfg(any(feature = "std", test))]
+pub mod fuzz;
+#[cfg(target_arch = "arm")]
 pub mod gnss;
+pub mod gpp_timers;
+#[cfg(all(feature = "std", feature = "embedded-nal-async"))]
+pub mod host;
+#[cfg(all(feature = "hw-test", target_arch = "arm"))]
+pub mod hw_test;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(target_arch = "arm")]
+pub mod keep_warm;
+#[cfg(any(target_arch = "arm", test))]
+pub mod last_position;
+#[cfg(target_arch = "arm")]
+pub mod lines;
+pub mod log_levels;
+pub mod message_ids;
 pub mod modem;
+pub mod net;
+#[cfg(target_arch = "arm")]
+pub mod nvm;
+#[cfg(target_arch = "arm")]
+pub mod observer;
+pub mod pdn;
+#[cfg(target_arch = "arm")]
+pub mod pool;
+#[cfg(target_arch = "arm")]
 mod raw;
+#[cfg(target_arch = "arm")]
+pub mod roaming;
+#[cfg(feature = "senml")]
+pub mod senml;
+#[cfg(target_arch = "arm")]
+pub mod sim;
+pub mod sms;
+#[cfg(target_arch = "arm")]
+pub mod sms_command;
+#[cfg(target_arch = "arm")]
+pub mod snapshot;
+#[cfg(all(feature = "sys", target_arch = "arm"))]
+pub mod sys;
+#[cfg(target_arch = "arm")]
 pub mod tcp;
+#[cfg(target_arch = "arm")]
+pub mod telemetry;
+#[cfg(target_arch = "arm")]
+pub mod timeouts;
+#[cfg(target_arch = "arm")]
 pub mod tls;
+pub mod tls_credentials;
+#[cfg(target_arch = "arm")]
+pub mod traces;
+#[cfg(any(target_arch = "arm", test))]
+pub mod tracker;
+pub mod transcript;
+#[cfg(target_arch = "arm")]
 pub mod udp;
+pub mod urc;
+#[cfg(target_arch = "arm")]
+pub mod watchdog;
 
 //******************************************************************************
 // Imports
 //******************************************************************************
 
+#[cfg(target_arch = "arm")]
 pub use api::*;
+#[cfg(target_arch = "arm")]
+pub use builder::NrfxlibBuilder;
+#[cfg(target_arch = "arm")]
 pub use ffi::{get_last_error, NrfxErr};
-pub use raw::{poll, PollEntry, PollFlags, PollResult, Pollable};
-
+#[cfg(target_arch = "arm")]
+pub use raw::{
+	open_sockets, poll, ConnectStatus, PollEntry, PollFlags, PollResult, Pollable, RxMeta,
+	RxTimestamp, SocketProtocol, SocketRecord,
+};
+#[cfg(target_arch = "arm")]
+pub use snapshot::{state_snapshot, StateSnapshot};
+
+#[cfg(target_arch = "arm")]
 use core::cell::RefCell;
+#[cfg(target_arch = "arm")]
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+#[cfg(target_arch = "arm")]
 use cortex_m::interrupt::Mutex;
+#[cfg(target_arch = "arm")]
 use linked_list_allocator::Heap;
+#[cfg(target_arch = "arm")]
 use log::{debug, trace};
+#[cfg(target_arch = "arm")]
 use nrf9160_pac as cpu;
-pub use nrfxlib_sys as sys;
+#[cfg(all(not(feature = "sys"), target_arch = "arm"))]
+use nrfxlib_sys as sys;
 
 //******************************************************************************
 // Types
 //******************************************************************************
 
 /// Create a camel-case type name for socket addresses.
+#[cfg(target_arch = "arm")]
 #[derive(Debug, Clone)]
 #[repr(transparent)]
 pub struct NrfSockAddrIn(sys::nrf_sockaddr_in);
 
 /// Create a camel-case type name for socket information.
+#[cfg(target_arch = "arm")]
 #[derive(Debug, Clone)]
 #[repr(transparent)]
 pub struct NrfAddrInfo(sys::nrf_addrinfo);
 
+#[cfg(target_arch = "arm")]
 impl core::ops::Deref for NrfSockAddrIn {
 	type Target = sys::nrf_sockaddr_in;
 
@@ -92,6 +236,20 @@ pub enum AtError {
 	CmsError(i32),
 }
 
+/// A failure cause reported by the network, as read with `AT+CEER`. See
+/// `modem::get_error_cause`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorCause {
+	/// An EPS Mobility Management cause (3GPP TS 24.301, section 9.9.3.9),
+	/// e.g. a rejected attach.
+	Emm(u16),
+	/// An EPS Session Management cause (3GPP TS 24.301, section 9.9.4.4),
+	/// e.g. a rejected PDN connection.
+	Esm(u16),
+	/// A cause we couldn't categorise.
+	Other(u16),
+}
+
 /// The set of error codes we can get from this API.
 #[derive(Debug, Clone)]
 pub enum Error {
@@ -100,6 +258,9 @@ pub enum Error {
 	Nordic(&'static str, i32, i32),
 	/// An AT error (`ERROR`, `+CMS ERROR` or `+CME ERROR`) was returned by the modem.
 	AtError(AtError),
+	/// An AT error was returned by the modem, and the network gave this
+	/// reason for it. See `modem::annotate_error`.
+	AtErrorWithCause(AtError, ErrorCause),
 	/// Data returned by the modem was not in a format we could understand.
 	BadDataFormat,
 	/// Given hostname was too long for internal buffers to hold
@@ -110,6 +271,47 @@ pub enum Error {
 	WriteError,
 	/// Too many sockets given
 	TooManySockets,
+	/// There was no room left in the given buffer or queue
+	BufferFull,
+	/// The remote end did not acknowledge what we sent
+	NotAcknowledged,
+	/// Every block in the buffer pool was in use
+	PoolExhausted,
+	/// The operation didn't complete in time. See `timeouts`.
+	TimedOut,
+	/// The peer closed or reset the connection
+	Disconnected,
+	/// The network link is down (e.g. the PDN connection was deactivated)
+	LinkDown,
+	/// The settings given to an `NrfxlibBuilder` don't work, for these
+	/// reasons
+	InvalidConfig(builder::ConfigConflicts),
+	/// A chat script failed at this line. See `chat::run`.
+	ChatFailed(usize),
+	/// Random numbers were needed, but no `entropy::EntropySource` has been
+	/// set
+	NoEntropySource,
+	/// The modem's credential storage refused a `%CMNG` operation. See
+	/// `tls_credentials`.
+	CredentialStore(tls_credentials::StoreError),
+	/// The modem's DFU service refused an operation. See `dfu`.
+	Dfu(dfu::DfuError),
+	/// The modem must be detached (LTE off) for this. See
+	/// `modem::FunctionalMode::is_lte_off`.
+	NotDetached,
+	/// The modem's firmware doesn't support this (see `firmware`).
+	UnsupportedFirmware,
+	/// The library has already been started (or is starting), and must be
+	/// shut down before it can be started again. See `init`.
+	AlreadyInitialised,
+	/// Receiving on this socket is paused, so a blocking receive would never
+	/// return. See `Socket::pause_receive`.
+	ReceivePaused,
+	/// The destination policy doesn't allow connecting there. See
+	/// `destinations::set_policy`.
+	DestinationDenied,
+	/// A DNS lookup didn't finish within `timeouts::Timeouts::dns_ms`
+	DnsTimeout,
 }
 
 /// We need to wrap our heap so it's creatable at run-time and accessible from an ISR.
@@ -122,8 +324,25 @@ pub enum Error {
 /// * The Option is because the `linked_list_allocator::empty()` function is not
 ///   `const` yet and cannot be called here
 ///
+#[cfg(target_arch = "arm")]
 type WrappedHeap = Mutex<RefCell<Option<Heap>>>;
 
+/// Where the library is in its life. Only one caller can move it out of
+/// each state, so two `init` (or `shutdown`) calls can't overlap.
+#[cfg(target_arch = "arm")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+enum LibraryState {
+	/// Not started, or shut down since
+	Stopped = 0,
+	/// `init` is setting things up
+	Starting = 1,
+	/// libmodem is running
+	Running = 2,
+	/// `shutdown` is stopping libmodem
+	Stopping = 3,
+}
+
 //******************************************************************************
 // Constants
 //******************************************************************************
@@ -137,14 +356,27 @@ type WrappedHeap = Mutex<RefCell<Option<Heap>>>;
 /// Our general heap.
 ///
 /// We initialise it later with a static variable as the backing store.
+#[cfg(target_arch = "arm")]
 static LIBRARY_ALLOCATOR: WrappedHeap = Mutex::new(RefCell::new(None));
 
 /// Our transmit heap.
 
 /// We initalise this later using a special region of shared memory that can be
 /// seen by the Cortex-M33 and the modem CPU.
+#[cfg(target_arch = "arm")]
 static TX_ALLOCATOR: WrappedHeap = Mutex::new(RefCell::new(None));
 
+/// How many times libmodem has been started. Anything set up in the modem
+/// before the last start (e.g. URC subscriptions) has been lost.
+#[cfg(target_arch = "arm")]
+static STARTS: AtomicU32 = AtomicU32::new(0);
+
+/// A `LibraryState`. The heaps above, the IRQ registration and libmodem
+/// itself are only touched by whoever moved it out of `Stopped` (or
+/// `Running`).
+#[cfg(target_arch = "arm")]
+static STATE: AtomicU8 = AtomicU8::new(LibraryState::Stopped as u8);
+
 //******************************************************************************
 // Macros
 //******************************************************************************
@@ -155,48 +387,167 @@ static TX_ALLOCATOR: WrappedHeap = Mutex::new(RefCell::new(None));
 // Public Functions and Impl on Public Types
 //******************************************************************************
 
-/// Start the NRF Modem library
+/// Start the NRF Modem library, with the default settings. Use an
+/// `NrfxlibBuilder` to change them.
+///
+/// Returns `Error::AlreadyInitialised` if the library is already running,
+/// or another caller is starting it, without changing anything.
+#[cfg(target_arch = "arm")]
 pub fn init() -> Result<(), Error> {
+	NrfxlibBuilder::new().init()
+}
+
+/// Stop the NRF Modem library. Does nothing if it isn't running (or
+/// another caller is already stopping it), so it's safe to call twice.
+#[cfg(target_arch = "arm")]
+pub fn shutdown() {
+	if !transition(LibraryState::Running, LibraryState::Stopping) {
+		debug!("nrfxlib not running");
+		return;
+	}
+	debug!("nrfxlib shutdown");
+	unsafe {
+		sys::nrf_modem_shutdown();
+	}
+	STATE.store(LibraryState::Stopped as u8, Ordering::SeqCst);
+	trace!("nrfxlib shutdown complete");
+}
+
+/// Is the library running - started by `init`, and not shut down since?
+#[cfg(target_arch = "arm")]
+pub fn is_initialised() -> bool {
+	STATE.load(Ordering::SeqCst) == LibraryState::Running as u8
+}
+
+impl From<core::fmt::Error> for Error {
+	fn from(_err: core::fmt::Error) -> Error {
+		Error::WriteError
+	}
+}
+
+#[cfg(feature = "embedded-nal-async")]
+impl embedded_io_async::Error for Error {
+	fn kind(&self) -> embedded_io_async::ErrorKind {
+		use embedded_io_async::ErrorKind;
+		match self {
+			Error::Disconnected => ErrorKind::ConnectionReset,
+			Error::LinkDown => ErrorKind::NotConnected,
+			Error::TimedOut => ErrorKind::TimedOut,
+			Error::BufferFull | Error::PoolExhausted => ErrorKind::OutOfMemory,
+			_ => ErrorKind::Other,
+		}
+	}
+}
+
+impl ErrorCause {
+	/// Get a short description of this cause, if we know it.
+	pub fn description(&self) -> Option<&'static str> {
+		match *self {
+			ErrorCause::Emm(code) => match code {
+				2 => Some("IMSI unknown in HSS"),
+				3 => Some("illegal UE"),
+				5 => Some("IMEI not accepted"),
+				6 => Some("illegal ME"),
+				7 => Some("EPS services not allowed"),
+				8 => Some("EPS services and non-EPS services not allowed"),
+				9 => Some("UE identity cannot be derived by the network"),
+				10 => Some("implicitly detached"),
+				11 => Some("PLMN not allowed"),
+				12 => Some("tracking area not allowed"),
+				13 => Some("roaming not allowed in this tracking area"),
+				14 => Some("EPS services not allowed in this PLMN"),
+				15 => Some("no suitable cells in tracking area"),
+				16 => Some("MSC temporarily not reachable"),
+				17 => Some("network failure"),
+				18 => Some("CS domain not available"),
+				19 => Some("ESM failure"),
+				20 => Some("MAC failure"),
+				21 => Some("synch failure"),
+				22 => Some("congestion"),
+				25 => Some("not authorized for this CSG"),
+				35 => Some("requested service option not authorized in this PLMN"),
+				39 => Some("CS service temporarily not available"),
+				40 => Some("no EPS bearer context activated"),
+				42 => Some("severe network failure"),
+				_ => None,
+			},
+			ErrorCause::Esm(code) => match code {
+				8 => Some("operator determined barring"),
+				26 => Some("insufficient resources"),
+				27 => Some("missing or unknown APN"),
+				28 => Some("unknown PDN type"),
+				29 => Some("user authentication failed"),
+				30 => Some("request rejected by Serving GW or PDN GW"),
+				31 => Some("request rejected, unspecified"),
+				32 => Some("service option not supported"),
+				33 => Some("requested service option not subscribed"),
+				34 => Some("service option temporarily out of order"),
+				38 => Some("network failure"),
+				50 => Some("PDN type IPv4 only allowed"),
+				51 => Some("PDN type IPv6 only allowed"),
+				54 => Some("PDN connection does not exist"),
+				55 => Some("multiple PDN connections for a given APN not allowed"),
+				_ => None,
+			},
+			ErrorCause::Other(_) => None,
+		}
+	}
+}
+
+impl core::fmt::Display for ErrorCause {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			ErrorCause::Emm(code) => write!(f, "EMM cause {}", code)?,
+			ErrorCause::Esm(code) => write!(f, "ESM cause {}", code)?,
+			ErrorCause::Other(code) => write!(f, "cause {}", code)?,
+		}
+		if let Some(description) = self.description() {
+			write!(f, ": {}", description)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl core::fmt::Display for NrfSockAddrIn {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		let octets = self.sin_addr.s_addr.to_be_bytes();
+		write!(
+			f,
+			"{}.{}.{}.{}:{}",
+			octets[3],
+			octets[2],
+			octets[1],
+			octets[0],
+			u16::from_be(self.sin_port)
+		)
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Set up our heaps and start libmodem. `NrfxlibBuilder::init` has checked
+/// `params`.
+#[cfg(target_arch = "arm")]
+pub(crate) fn start(
+	params: &sys::nrf_modem_init_params_t,
+	mode: sys::nrf_modem_mode_t,
+) -> Result<(), Error> {
+	// We hold the claim from `claim_start`, so nobody else is in here
 	unsafe {
 		/// Allocate some space in global data to use as a heap.
 		static mut HEAP_MEMORY: [u32; 1024] = [0u32; 1024];
 		let heap_start = HEAP_MEMORY.as_mut_ptr() as *mut _;
 		let heap_size = HEAP_MEMORY.len() * core::mem::size_of::<u32>();
 		cortex_m::interrupt::free(|cs| {
-			*LIBRARY_ALLOCATOR.borrow(cs).borrow_mut() =
-				Some(Heap::new(heap_start, heap_size))
+			*LIBRARY_ALLOCATOR.borrow(cs).borrow_mut() = Some(Heap::new(heap_start, heap_size))
 		});
 	}
 
-	// Tell nrf_modem what memory it can use.
-	let params = sys::nrf_modem_init_params_t {
-		shmem: sys::nrf_modem_shmem_cfg {
-			ctrl: sys::nrf_modem_shmem_cfg__bindgen_ty_1 {
-				// At start of shared memory (see memory.x)
-				base: 0x2001_0000,
-				// This is the amount specified in the NCS 1.5.1 release.
-				size: 0x0000_04e8,
-			},
-			tx: sys::nrf_modem_shmem_cfg__bindgen_ty_2 {
-				// Follows on from control buffer
-				base: 0x2001_04e8,
-				// This is the amount specified in the NCS 1.5.1 release.
-				size: 0x0000_2000,
-			},
-			rx: sys::nrf_modem_shmem_cfg__bindgen_ty_3 {
-				// Follows on from TX buffer
-				base: 0x2001_24e8,
-				// This is the amount specified in the NCS 1.5.1 release.
-				size: 0x0000_2000,
-			},
-			// No trace info
-			trace: sys::nrf_modem_shmem_cfg__bindgen_ty_4 { base: 0, size: 0 },
-		},
-		ipc_irq_prio: 0,
-	};
-
 	unsafe {
-		// Use the same TX memory region as above
+		// Use the TX memory region we're giving to nrf_modem
 		cortex_m::interrupt::free(|cs| {
 			*TX_ALLOCATOR.borrow(cs).borrow_mut() = Some(Heap::new(
 				params.shmem.tx.base as *mut _,
@@ -206,52 +557,62 @@ pub fn init() -> Result<(), Error> {
 	}
 
 	// OK, let's start the library
-	let result = unsafe { sys::nrf_modem_init(&params, sys::nrf_modem_mode_t_NORMAL_MODE) };
+	let result = unsafe { sys::nrf_modem_init(params, mode) };
 
 	// Was it happy?
 	if result < 0 {
-		Err(Error::Nordic("init", result, ffi::get_last_error()))
+		let error = ffi::get_last_error();
+		STATE.store(LibraryState::Stopped as u8, Ordering::SeqCst);
+		Err(Error::Nordic("init", result, error))
 	} else {
+		// A positive result says how a firmware update went
+		dfu::record_init_result(result);
+		STARTS.fetch_add(1, Ordering::Relaxed);
+		STATE.store(LibraryState::Running as u8, Ordering::SeqCst);
 		trace!("nrfxlib init complete");
 		Ok(())
 	}
 }
 
-/// Stop the NRF Modem library
-pub fn shutdown() {
-	debug!("nrfxlib shutdown");
-	unsafe {
-		sys::nrf_modem_shutdown();
+/// Claim the right to start the library, before anything is set up for it.
+/// Fails with `Error::AlreadyInitialised` if it is running, or someone else
+/// has already claimed it. `start` hands it back, however it goes.
+#[cfg(target_arch = "arm")]
+pub(crate) fn claim_start() -> Result<(), Error> {
+	if transition(LibraryState::Stopped, LibraryState::Starting) {
+		Ok(())
+	} else {
+		debug!("nrfxlib already initialised");
+		Err(Error::AlreadyInitialised)
 	}
-	trace!("nrfxlib shutdown complete");
 }
 
-impl From<core::fmt::Error> for Error {
-	fn from(_err: core::fmt::Error) -> Error {
-		Error::WriteError
-	}
+/// Move the library from one state to another, if nobody else has moved it
+/// first.
+#[cfg(target_arch = "arm")]
+fn transition(from: LibraryState, to: LibraryState) -> bool {
+	STATE
+		.compare_exchange(from as u8, to as u8, Ordering::SeqCst, Ordering::SeqCst)
+		.is_ok()
 }
 
-impl core::fmt::Display for NrfSockAddrIn {
-	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-		let octets = self.sin_addr.s_addr.to_be_bytes();
-		write!(
-			f,
-			"{}.{}.{}.{}:{}",
-			octets[3],
-			octets[2],
-			octets[1],
-			octets[0],
-			u16::from_be(self.sin_port)
-		)
-	}
+/// How many times libmodem has been started (see `STARTS`).
+#[cfg(target_arch = "arm")]
+pub(crate) fn starts() -> u32 {
+	STARTS.load(Ordering::Relaxed)
 }
 
-//******************************************************************************
-// Private Functions and Impl on Private Types
-//******************************************************************************
+/// Wipe a buffer which may have held credentials, if the `zeroize` feature
+/// is enabled.
+#[cfg(all(feature = "zeroize", target_arch = "arm"))]
+pub(crate) fn wipe(buf: &mut [u8]) {
+	zeroize::Zeroize::zeroize(buf);
+}
 
-// None
+/// Wipe a buffer which may have held credentials - which, without the
+/// `zeroize` feature, does nothing.
+#[cfg(all(not(feature = "zeroize"), target_arch = "arm"))]
+pub(crate) fn wipe(_buf: &mut [u8]) {}
 
 //******************************************************************************
 // End of File
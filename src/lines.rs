@@ -0,0 +1,193 @@
+//! # Line-based reading for nrfxlib
+//!
+//! Text protocols (SMTP-like command protocols, NMEA over TCP, debug
+//! consoles) work a line at a time, but a TCP socket hands over whatever
+//! bytes have arrived - half a line, or several lines at once. A
+//! `LineReader` buffers what is received and hands it back one complete line
+//! at a time:
+//!
+//! ```ignore
+//! let mut lines = LineReader::<_, 256>::new(&socket);
+//! loop {
+//!     let line = lines.read_line_wait()?;
+//!     handle_reply(line);
+//! }
+//! ```
+//!
+//! Lines end with CRLF. A bare LF is accepted too, as plenty of servers send
+//! one.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::{raw::Socket, Error};
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Reads CRLF-terminated lines from a socket, holding lines of up to `SIZE`
+/// bytes (including the line ending).
+#[derive(Debug)]
+pub struct LineReader<'s, S, const SIZE: usize>
+where
+	S: core::ops::Deref<Target = Socket>,
+{
+	socket: &'s S,
+	buffer: heapless::Vec<u8, SIZE>,
+	/// How much of `buffer` is the line we last returned, which is thrown
+	/// away on the next read
+	consumed: usize,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The byte which ends a line
+const LINE_FEED: u8 = b'\n';
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl<'s, S, const SIZE: usize> LineReader<'s, S, SIZE>
+where
+	S: core::ops::Deref<Target = Socket>,
+{
+	/// Read lines from the given socket (e.g. a `TcpSocket` or `TlsSocket`).
+	pub fn new(socket: &'s S) -> Self {
+		LineReader {
+			socket,
+			buffer: heapless::Vec::new(),
+			consumed: 0,
+		}
+	}
+
+	/// Get the next complete line, without its line ending, if there is one.
+	/// Doesn't wait - returns `Ok(None)` if the rest of the line hasn't
+	/// arrived yet.
+	///
+	/// Socket errors are passed on as from `recv`. A line which isn't UTF-8
+	/// gives `Error::BadDataFormat`, and is thrown away. A line which is too
+	/// long for the buffer gives `Error::BufferFull`, and what has arrived
+	/// of it is thrown away - so the next line returned is the end of it.
+	pub fn read_line(&mut self) -> Result<Option<&str>, Error> {
+		self.discard_consumed();
+		loop {
+			if let Some(end) = self.find_line_end() {
+				return self.take_line(end).map(Some);
+			}
+			match self.fill(false)? {
+				0 => return Ok(None),
+				_ => continue,
+			}
+		}
+	}
+
+	/// Get the next complete line, without its line ending, waiting for it
+	/// to arrive. Errors are as for `read_line`.
+	pub fn read_line_wait(&mut self) -> Result<&str, Error> {
+		self.discard_consumed();
+		loop {
+			if let Some(end) = self.find_line_end() {
+				return self.take_line(end);
+			}
+			self.fill(true)?;
+		}
+	}
+
+	/// The bytes received but not yet returned as part of a line. Useful when
+	/// a protocol switches from lines to binary data (e.g. an HTTP body).
+	pub fn buffered(&self) -> &[u8] {
+		self.buffer.get(self.consumed..).unwrap_or(&[])
+	}
+
+	/// Throw away anything buffered.
+	pub fn clear(&mut self) {
+		self.buffer.clear();
+		self.consumed = 0;
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl<'s, S, const SIZE: usize> LineReader<'s, S, SIZE>
+where
+	S: core::ops::Deref<Target = Socket>,
+{
+	/// Remove the line we last returned from the buffer.
+	fn discard_consumed(&mut self) {
+		if self.consumed > 0 {
+			let remaining = self.buffer.len() - self.consumed;
+			self.buffer.copy_within(self.consumed.., 0);
+			self.buffer.truncate(remaining);
+			self.consumed = 0;
+		}
+	}
+
+	/// Find the line feed at the end of the first line in the buffer.
+	fn find_line_end(&self) -> Option<usize> {
+		self.buffer.iter().position(|&b| b == LINE_FEED)
+	}
+
+	/// Return the line ending at the line feed at `end`, and mark it to be
+	/// thrown away on the next read.
+	fn take_line(&mut self, end: usize) -> Result<&str, Error> {
+		self.consumed = end + 1;
+		let line = self.buffer.get(0..end).unwrap_or(&[]);
+		let line = line.strip_suffix(b"\r").unwrap_or(line);
+		core::str::from_utf8(line).map_err(|_| Error::BadDataFormat)
+	}
+
+	/// Receive more data into the buffer, returning how much arrived. If the
+	/// buffer is already full, it can't hold the line, so it is emptied.
+	fn fill(&mut self, wait: bool) -> Result<usize, Error> {
+		if self.buffer.is_full() {
+			self.buffer.clear();
+			return Err(Error::BufferFull);
+		}
+		let length = self.buffer.len();
+		// Fills the spare capacity, so can't fail
+		let _ = self.buffer.resize(SIZE, 0);
+		let free = self.buffer.get_mut(length..).unwrap_or_default();
+		let result = if wait {
+			self.socket.recv_wait(free)
+		} else {
+			self.socket.recv(free).map(|n| n.unwrap_or(0))
+		};
+		let received = *result.as_ref().unwrap_or(&0);
+		self.buffer.truncate(length + received);
+		result
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
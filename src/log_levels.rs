@@ -0,0 +1,297 @@
+//! # Per-subsystem log levels for nrfxlib
+//!
+//! The AT traffic is the first thing you want to see when a device in the
+//! field misbehaves, and the last thing you want filling the log the rest
+//! of the time. This module keeps a log level for each part of the crate,
+//! which can be changed at run time - from a remote command, say - and
+//! `FilteredLogger` applies them in front of your own logger:
+//!
+//! ```ignore
+//! static LOGGER: FilteredLogger<RttLogger> = FilteredLogger::new(RttLogger);
+//! log::set_logger(&LOGGER)?;
+//! log::set_max_level(LevelFilter::Debug);
+//! log_levels::set_level(Subsystem::At, LevelFilter::Warn);
+//! // Later, when asked to
+//! log_levels::configure("at=debug,gnss=off")?;
+//! ```
+//!
+//! Messages from other crates, and from the parts of this one which belong
+//! to no `Subsystem`, pass straight through. A subsystem's level can only
+//! narrow what is logged: `log::max_level()` (and any `max_level_` feature
+//! of the `log` crate) still applies, so set that as high as you will ever
+//! want to raise a subsystem to.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::Error;
+use core::sync::atomic::{AtomicU8, Ordering};
+use log::{LevelFilter, Log, Metadata, Record};
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A part of the crate with its own log level.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Subsystem {
+	/// AT commands, responses and URCs
+	At = 0,
+	/// Sockets, DNS and the layers built on them
+	Sockets = 1,
+	/// GNSS, A-GPS and position tracking
+	Gnss = 2,
+	/// Bringing the modem up and down, its functional mode, fault handling
+	/// and the watchdog
+	Supervisor = 3,
+}
+
+/// A logger which applies the per-subsystem levels to messages from this
+/// crate, and passes what is left to another logger. See the module
+/// documentation.
+#[derive(Debug)]
+pub struct FilteredLogger<L> {
+	inner: L,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// Every subsystem, in the order of their values.
+pub const SUBSYSTEMS: [Subsystem; 4] = [
+	Subsystem::At,
+	Subsystem::Sockets,
+	Subsystem::Gnss,
+	Subsystem::Supervisor,
+];
+
+/// Which subsystem each of our modules belongs to, by its path below the
+/// crate root.
+const MODULES: [(&str, Subsystem); 33] = [
+	("at", Subsystem::At),
+	("chat", Subsystem::At),
+	("sms", Subsystem::At),
+	("sms_command", Subsystem::At),
+	("transcript", Subsystem::At),
+	("urc", Subsystem::At),
+	("asynch", Subsystem::Sockets),
+	("coalesce", Subsystem::Sockets),
+	("coap", Subsystem::Sockets),
+	("compress", Subsystem::Sockets),
+	("dns", Subsystem::Sockets),
+	("dtls", Subsystem::Sockets),
+	("flow", Subsystem::Sockets),
+	("keep_warm", Subsystem::Sockets),
+	("pool", Subsystem::Sockets),
+	("raw", Subsystem::Sockets),
+	("roaming", Subsystem::Sockets),
+	("tcp", Subsystem::Sockets),
+	("telemetry", Subsystem::Sockets),
+	("tls", Subsystem::Sockets),
+	("udp", Subsystem::Sockets),
+	("agps", Subsystem::Gnss),
+	("dead_reckoning", Subsystem::Gnss),
+	("gnss", Subsystem::Gnss),
+	("last_position", Subsystem::Gnss),
+	("tracker", Subsystem::Gnss),
+	("api", Subsystem::Supervisor),
+	("builder", Subsystem::Supervisor),
+	("ffi", Subsystem::Supervisor),
+	("modem", Subsystem::Supervisor),
+	("pdn", Subsystem::Supervisor),
+	("traces", Subsystem::Supervisor),
+	("watchdog", Subsystem::Supervisor),
+];
+
+/// The level filters, indexed by how we store them.
+const FILTERS: [LevelFilter; 6] = [
+	LevelFilter::Off,
+	LevelFilter::Error,
+	LevelFilter::Warn,
+	LevelFilter::Info,
+	LevelFilter::Debug,
+	LevelFilter::Trace,
+];
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The level of each subsystem, as an index into `FILTERS`. They start at
+/// `Trace`, so nothing is filtered until asked.
+static LEVELS: [AtomicU8; 4] = [
+	AtomicU8::new(5),
+	AtomicU8::new(5),
+	AtomicU8::new(5),
+	AtomicU8::new(5),
+];
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Set the most verbose level logged for a subsystem.
+#[allow(clippy::indexing_slicing)] // `LEVELS` has an entry for every `Subsystem`
+pub fn set_level(subsystem: Subsystem, level: LevelFilter) {
+	LEVELS[subsystem as usize].store(level as u8, Ordering::Relaxed);
+}
+
+/// Get the most verbose level logged for a subsystem.
+#[allow(clippy::indexing_slicing)] // `LEVELS` has an entry for every `Subsystem`
+pub fn level(subsystem: Subsystem) -> LevelFilter {
+	let index = usize::from(LEVELS[subsystem as usize].load(Ordering::Relaxed));
+	FILTERS.get(index).copied().unwrap_or(LevelFilter::Trace)
+}
+
+/// Set the levels from a comma separated list of `subsystem=level` pairs,
+/// like `"at=debug,sockets=warn"`. Subsystem names are as
+/// `Subsystem::name` gives, and levels as `LevelFilter` parses them (`off`,
+/// `error`, `warn`, `info`, `debug` or `trace`). A level on its own sets
+/// every subsystem. Fails with `Error::BadDataFormat`, changing nothing, if
+/// any part doesn't make sense.
+pub fn configure(spec: &str) -> Result<(), Error> {
+	// Check it all before changing anything
+	for part in parts(spec) {
+		parse_part(part)?;
+	}
+	for part in parts(spec) {
+		match parse_part(part)? {
+			(Some(subsystem), level) => set_level(subsystem, level),
+			(None, level) => {
+				for subsystem in SUBSYSTEMS.iter() {
+					set_level(*subsystem, level);
+				}
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Would a message at this level, with this target, get through the
+/// per-subsystem levels?
+pub fn enabled(metadata: &Metadata) -> bool {
+	match Subsystem::of(metadata.target()) {
+		Some(subsystem) => metadata.level() <= level(subsystem),
+		None => true,
+	}
+}
+
+impl Subsystem {
+	/// The subsystem a log target (a module path, unless it was given
+	/// another) from this crate belongs to, if any.
+	pub fn of(target: &str) -> Option<Subsystem> {
+		let path = target.strip_prefix(env!("CARGO_CRATE_NAME"))?;
+		if path.is_empty() {
+			// The crate root brings the library up and down
+			return Some(Subsystem::Supervisor);
+		}
+		let path = path.strip_prefix("::")?;
+		let module = path.split("::").next().unwrap_or(path);
+		MODULES
+			.iter()
+			.find(|(name, _)| *name == module)
+			.map(|(_, subsystem)| *subsystem)
+	}
+
+	/// The subsystem's name, as `configure` takes it.
+	pub fn name(self) -> &'static str {
+		match self {
+			Subsystem::At => "at",
+			Subsystem::Sockets => "sockets",
+			Subsystem::Gnss => "gnss",
+			Subsystem::Supervisor => "supervisor",
+		}
+	}
+
+	/// Find a subsystem by its name, without regard to case.
+	pub fn from_name(name: &str) -> Option<Subsystem> {
+		SUBSYSTEMS
+			.iter()
+			.copied()
+			.find(|subsystem| subsystem.name().eq_ignore_ascii_case(name))
+	}
+}
+
+impl<L> FilteredLogger<L>
+where
+	L: Log,
+{
+	/// Filter the messages going to `inner`.
+	pub const fn new(inner: L) -> FilteredLogger<L> {
+		FilteredLogger { inner }
+	}
+
+	/// The logger the messages go to.
+	pub fn inner(&self) -> &L {
+		&self.inner
+	}
+}
+
+impl<L> Log for FilteredLogger<L>
+where
+	L: Log,
+{
+	fn enabled(&self, metadata: &Metadata) -> bool {
+		enabled(metadata) && self.inner.enabled(metadata)
+	}
+
+	fn log(&self, record: &Record) {
+		if enabled(record.metadata()) {
+			self.inner.log(record);
+		}
+	}
+
+	fn flush(&self) {
+		self.inner.flush();
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// The non-empty parts of a `configure` spec.
+fn parts(spec: &str) -> impl Iterator<Item = &str> {
+	spec.split(',')
+		.map(str::trim)
+		.filter(|part| !part.is_empty())
+}
+
+/// Parse one `subsystem=level` pair, or a level on its own.
+fn parse_part(part: &str) -> Result<(Option<Subsystem>, LevelFilter), Error> {
+	let (subsystem, level) = match part.split_once('=') {
+		Some((name, level)) => {
+			let subsystem = Subsystem::from_name(name.trim()).ok_or(Error::BadDataFormat)?;
+			(Some(subsystem), level)
+		}
+		None => (None, part),
+	};
+	let level = level
+		.trim()
+		.parse::<LevelFilter>()
+		.map_err(|_| Error::BadDataFormat)?;
+	Ok((subsystem, level))
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,160 @@
+//! # Message IDs for at-most-once delivery for nrfxlib
+//!
+//! When a send times out, you can't tell whether the message got there and
+//! only the reply was lost, or it never left. Sending it again risks the
+//! server acting on it twice - fine for a temperature reading, not for
+//! "dispense one item". The usual cure is for every message to carry an ID
+//! which is never reused, and the server to ignore an ID it has already
+//! seen from this device. `MessageIds` hands out such IDs, and keeps them
+//! unique over resets with the help of an `IdStore` you provide:
+//!
+//! ```ignore
+//! struct RetainedStore;
+//!
+//! impl message_ids::IdStore for RetainedStore {
+//!     fn load(&mut self) -> Option<MessageId> {
+//!         flash::read_u32(MESSAGE_ID_RECORD)
+//!     }
+//!     fn save(&mut self, reserved: MessageId) -> Result<(), Error> {
+//!         flash::write_u32(MESSAGE_ID_RECORD, reserved).map_err(|_| Error::WriteError)
+//!     }
+//! }
+//!
+//! let mut ids = MessageIds::new(RetainedStore, 64);
+//! batch.push_with_id(&mut ids, |id, buffer| encode_command(id, &command, buffer))?;
+//! ```
+//!
+//! The ID goes in the payload itself, so when `Telemetry::send` or
+//! `send_confirmed` fails and keeps the batch, sending it again sends the
+//! same IDs. Give the server's de-duplication window at least as many IDs
+//! as you might retry.
+//!
+//! So that the store isn't written for every message, IDs are reserved in
+//! blocks, and only the end of each block is saved. After a reset, the
+//! unused rest of the last block is skipped, so no ID is ever handed out
+//! twice, as long as the store's writes survive.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::Error;
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// The ID of a message, unique to this device.
+pub type MessageId = u32;
+
+/// Somewhere to keep the message IDs used so far, over a reset.
+pub trait IdStore {
+	/// Get the value saved last, if there is one.
+	fn load(&mut self) -> Option<MessageId>;
+	/// Save the first ID not yet reserved. No ID from here on will be given
+	/// out until this returns `Ok`.
+	fn save(&mut self, reserved: MessageId) -> Result<(), Error>;
+}
+
+/// Hands out message IDs which are never reused. See the module
+/// documentation.
+#[derive(Debug)]
+pub struct MessageIds<S>
+where
+	S: IdStore,
+{
+	store: S,
+	block: u32,
+	/// The next ID to give out, once we know it
+	next: Option<MessageId>,
+	/// The first ID past the block reserved in the store
+	reserved: MessageId,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl<S> MessageIds<S>
+where
+	S: IdStore,
+{
+	/// Hand out IDs kept unique by `store`, reserving `block` of them (at
+	/// least one) each time it is written.
+	pub fn new(store: S, block: u32) -> MessageIds<S> {
+		MessageIds {
+			store,
+			block: core::cmp::max(block, 1),
+			next: None,
+			reserved: 0,
+		}
+	}
+
+	/// Get a new ID. This loads the store the first time, and saves to it
+	/// whenever a new block is needed - if that fails, so does this, and the
+	/// ID isn't used up.
+	pub fn next_id(&mut self) -> Result<MessageId, Error> {
+		let next = match self.next {
+			Some(next) => next,
+			None => {
+				// Anything up to the saved value may have been used
+				let loaded = self.store.load().unwrap_or(0);
+				debug!("Message IDs start at {}", loaded);
+				self.reserved = loaded;
+				loaded
+			}
+		};
+		if next == self.reserved {
+			let reserved = next.wrapping_add(self.block);
+			self.store.save(reserved)?;
+			self.reserved = reserved;
+		}
+		self.next = Some(next.wrapping_add(1));
+		Ok(next)
+	}
+
+	/// The store the IDs are kept in.
+	pub fn store(&self) -> &S {
+		&self.store
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -17,7 +17,31 @@
 // Imports
 //******************************************************************************
 
+#[cfg(target_arch = "arm")]
+use crate::at::Subscriptions;
+#[cfg(target_arch = "arm")]
+use crate::firmware::AtSyntax;
+#[cfg(target_arch = "arm")]
+use crate::gpp_timers::Rounding;
+use crate::gpp_timers::{self, parse_bits};
+use crate::urc::Registration;
+#[cfg(target_arch = "arm")]
+use crate::urc::{ModemEvent, NetworkError, Urc};
+#[cfg(target_arch = "arm")]
 use crate::Error;
+#[cfg(any(target_arch = "arm", feature = "std", test))]
+use crate::ErrorCause;
+#[cfg(target_arch = "arm")]
+use core::cell::RefCell;
+use core::convert::TryFrom;
+#[cfg(target_arch = "arm")]
+use core::fmt::Write;
+#[cfg(target_arch = "arm")]
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::time::Duration;
+#[cfg(target_arch = "arm")]
+use cortex_m::interrupt::Mutex;
+#[cfg(target_arch = "arm")]
 use log::debug;
 
 //******************************************************************************
@@ -25,7 +49,7 @@ use log::debug;
 //******************************************************************************
 
 /// Identifies which radios in the nRF9160 should be active
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SystemMode {
 	/// LTE-M only
 	LteM,
@@ -39,17 +63,457 @@ pub enum SystemMode {
 	NbIotAndGnss,
 }
 
+/// The functional mode of the modem, as reported by `AT+CFUN?`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FunctionalMode {
+	/// The modem is powered off.
+	PowerOff = 0,
+	/// Normal mode - LTE and GNSS (if enabled in the system mode) are active.
+	Normal = 1,
+	/// Receive-only mode.
+	ReceiveOnly = 2,
+	/// Flight mode - all the radios are off.
+	FlightMode = 4,
+	/// LTE has been deactivated, but GNSS has not.
+	LteDeactivated = 20,
+	/// GNSS has been deactivated, but LTE has not.
+	GnssDeactivated = 30,
+	/// The UICC (SIM) has been deactivated.
+	UiccDeactivated = 40,
+	/// Flight mode, but with the UICC (SIM) still powered.
+	FlightModeUiccOn = 44,
+}
+
+/// The UE mode of operation (3GPP TS 24.301), as set with `AT+CEMODE`.
+/// The nRF9160 supports `PsMode2` and `CsPsMode2`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UeMode {
+	/// Packet-switched only, data-centric
+	PsMode2 = 0,
+	/// Circuit- and packet-switched, voice-centric
+	CsPsMode1 = 1,
+	/// Circuit- and packet-switched, data-centric. This lets the modem use
+	/// SMS over SGs (e.g. "SMS only" attach).
+	CsPsMode2 = 2,
+	/// Packet-switched only, voice-centric
+	PsMode1 = 3,
+}
+
+/// The network registration status, as reported by `+CEREG`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegistrationStatus {
+	/// Not registered, and not searching for a network.
+	NotRegistered = 0,
+	/// Registered on the home network.
+	RegisteredHome = 1,
+	/// Not registered, but searching for a network.
+	Searching = 2,
+	/// Registration was denied.
+	Denied = 3,
+	/// Unknown (e.g. out of coverage).
+	Unknown = 4,
+	/// Registered on a roaming network.
+	RegisteredRoaming = 5,
+	/// Not registered, due to a UICC (SIM) failure.
+	UiccFailure = 90,
+}
+
+/// The radio access technology the modem is registered with, as reported in
+/// the `<AcT>` field of `+CEREG`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessTechnology {
+	/// LTE-M (E-UTRAN)
+	LteM = 7,
+	/// NB-IoT (E-UTRAN NB-S1)
+	NbIot = 9,
+}
+
+/// The connectivity statistics the modem has collected since
+/// `start_connectivity_stats`, as reported by `AT%XCONNSTAT?`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ConnectivityStats {
+	/// The number of SMS messages sent
+	pub sms_tx: u32,
+	/// The number of SMS messages received
+	pub sms_rx: u32,
+	/// The amount of data sent, in kilobytes
+	pub data_tx_kib: u32,
+	/// The amount of data received, in kilobytes
+	pub data_rx_kib: u32,
+	/// The largest packet sent or received, in bytes
+	pub packet_max: u32,
+	/// The average size of the packets sent and received, in bytes
+	pub packet_average: u32,
+}
+
+/// An antenna configuration, selected with `AT%XANTCFG`, for boards with a
+/// switchable antenna. The value is the configuration number the command
+/// takes; which numbers mean what depends on the hardware variant.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AntennaConfig(pub u8);
+
+/// Keeps the modem in flight mode while it exists.
+///
+/// Creating one puts the modem into flight mode (e.g. so credentials can be
+/// written with `AT%CMNG`). Dropping it puts the modem back in the functional
+/// mode it was in before and, if that mode has LTE active, waits for the
+/// modem to register again. Going back to a mode with LTE active applies
+/// everything `on()` does first, as `on()` would. Use `restore` instead of
+/// dropping it if you want to see any errors.
+#[cfg(target_arch = "arm")]
+#[must_use]
+#[derive(Debug)]
+pub struct FlightModeGuard {
+	previous: FunctionalMode,
+	restored: bool,
+}
+
+/// How `suspend` put the modem to sleep.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SuspendMode {
+	/// The network has granted PSM, so the modem stays registered, and keeps
+	/// its PDN connections, while it sleeps.
+	Psm,
+	/// The network hasn't granted PSM, so the modem was put into flight mode.
+	FlightMode,
+}
+
+/// What `suspend` did, which `resume` undoes.
+#[cfg(target_arch = "arm")]
+#[must_use]
+#[derive(Debug)]
+pub struct Suspended {
+	mode: SuspendMode,
+	previous: FunctionalMode,
+}
+
+/// What `resume` had to do.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResumeReport {
+	/// How the modem had been suspended
+	pub mode: SuspendMode,
+	/// Whether the modem had to attach again, rather than finding itself
+	/// still registered
+	pub reattached: bool,
+	/// How long resuming took, in milliseconds
+	pub duration_ms: u32,
+}
+
+/// The PSM timers to ask the network for, with `set_psm`. Each is rounded
+/// up to the next value the 3GPP timer encodings can express.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PsmConfig {
+	/// How often the modem wakes up to do a tracking area update (T3412
+	/// extended). Up to 31 x 320 hours.
+	pub periodic_tau: Duration,
+	/// How long the modem stays reachable after going idle, before it goes
+	/// into PSM (T3324). Up to 31 x 6 minutes.
+	pub active_time: Duration,
+}
+
+/// The PSM timers the network has granted, as reported by `AT+CEREG?` at
+/// level 4. `None` means that timer is deactivated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PsmGranted {
+	/// The active time (T3324). PSM is only in use if this is `Some`.
+	pub active_time: Option<Duration>,
+	/// The periodic tracking area update time (T3412 extended)
+	pub periodic_tau: Option<Duration>,
+}
+
+/// Which radio access technology an eDRX setting is for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EdrxAccessTechnology {
+	/// LTE-M (E-UTRAN WB-S1)
+	LteM = 4,
+	/// NB-IoT (E-UTRAN NB-S1)
+	NbIot = 5,
+}
+
+/// The eDRX settings to ask the network for, with `set_edrx`. The cycle is
+/// rounded up to the next one 3GPP TS 24.008 defines for the access
+/// technology.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EdrxConfig {
+	/// Which access technology this is for
+	pub access_technology: EdrxAccessTechnology,
+	/// How often the modem listens for paging. From 5.12 seconds (20.48 for
+	/// NB-IoT) to 10485.76 seconds.
+	pub cycle: Duration,
+	/// How long the modem listens each cycle (the paging time window, set
+	/// with `AT%XPTW`), or `None` to leave it as it is. Rounded up to a
+	/// multiple of 1.28 seconds (2.56 for NB-IoT), up to 16 of them.
+	pub paging_time_window: Option<Duration>,
+}
+
+/// The eDRX settings in use, as reported by `+CEDRXP` or `AT+CEDRXRDP`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EdrxGranted {
+	/// Which access technology these are for
+	pub access_technology: EdrxAccessTechnology,
+	/// The cycle we asked for, if given
+	pub requested_cycle: Option<Duration>,
+	/// The cycle the network gave
+	pub cycle: Duration,
+	/// The paging time window the network gave
+	pub paging_time_window: Duration,
+}
+
+/// The settings `apply_config_diff` brings the modem to. Each `None` leaves
+/// that setting as it is.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ModemConfig {
+	/// Which radios should be active, as `set_system_mode` takes it
+	pub system_mode: Option<SystemMode>,
+	/// The LTE bands to lock to (`AT%XBANDLOCK`, as `sim::SimProfile`
+	/// does). An empty list removes the band lock.
+	pub bands: Option<&'static [u8]>,
+	/// What to ask for in the way of PSM, as `set_psm` takes it
+	pub psm: Option<Option<PsmConfig>>,
+	/// What to ask for in the way of eDRX, as `set_edrx` takes it
+	pub edrx: Option<Option<EdrxConfig>>,
+}
+
+/// Which settings `apply_config_diff` sent to the modem. The rest were
+/// already right, or weren't asked for.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ConfigChanges {
+	/// `AT%XSYSTEMMODE` was sent
+	pub system_mode: bool,
+	/// `AT%XBANDLOCK` was sent
+	pub bands: bool,
+	/// `AT+CPSMS` was sent
+	pub psm: bool,
+	/// `AT+CEDRXS` (and maybe `AT%XPTW`) was sent
+	pub edrx: bool,
+}
+
+/// Every setting which matters to battery life, as `power_audit` read them.
+/// Each is `None` if the modem wouldn't say (an error, or a command this
+/// firmware doesn't have).
+#[cfg(target_arch = "arm")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowerAudit {
+	/// The functional mode (`AT+CFUN?`)
+	pub functional_mode: Option<FunctionalMode>,
+	/// Which radios should be active (`AT%XSYSTEMMODE?`)
+	pub system_mode: Option<SystemMode>,
+	/// The runtime LTE band lock (`AT%XBANDLOCK?`), as a string of bits
+	/// without leading zeros. Empty means no lock.
+	pub band_lock: Option<heapless::String<{ crate::sim::MAX_BAND as usize }>>,
+	/// The PSM timers last requested with `AT+CPSMS`, or `Some(None)` if PSM
+	/// isn't requested
+	pub psm_requested: Option<Option<PsmGranted>>,
+	/// The PSM timers the network granted, as `get_psm` gives them
+	pub psm_granted: Option<Option<PsmGranted>>,
+	/// The eDRX cycle last requested with `AT+CEDRXS` for each access
+	/// technology it is requested for
+	pub edrx_requested: Option<heapless::Vec<(EdrxAccessTechnology, Duration), 2>>,
+	/// The eDRX settings in use, as `get_edrx` gives them
+	pub edrx_granted: Option<Option<EdrxGranted>>,
+	/// The Release Assistance Indication setting (`AT%XRAI?`, see
+	/// `set_release_assistance`), on firmware which has it
+	pub rai: Option<ReleaseAssistance>,
+	/// The data profile (`AT%XDATAPRFL?`), from 0 (ultra-low power) to 4
+	/// (high performance)
+	pub data_profile: Option<u8>,
+}
+
+/// The signal quality, as reported by `AT+CESQ`.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct SignalQuality {
+	/// Reference Signal Received Power, in dBm (-140 to -44), if known
+	pub rsrp_dbm: Option<i16>,
+	/// Reference Signal Received Quality, in dB (-19.5 to -3), if known
+	pub rsrq_db: Option<f32>,
+}
+
+/// How much energy the modem expects a transmission to take, compared with
+/// good conditions, as reported by `AT%CONEVAL`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EnergyEstimate {
+	/// Bad conditions - a transmission takes a lot more energy
+	Bad = 5,
+	/// Poor conditions
+	Poor = 6,
+	/// Normal conditions
+	Normal = 7,
+	/// Good conditions
+	Good = 8,
+	/// Excellent conditions - a transmission takes less energy than normal
+	Excellent = 9,
+}
+
+/// A PLMN (network operator) ID: a Mobile Country Code and Mobile Network
+/// Code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Plmn {
+	/// The Mobile Country Code
+	pub mcc: u16,
+	/// The Mobile Network Code
+	pub mnc: u16,
+	/// Is the MNC three digits long (so `01` and `001` differ)?
+	pub three_digit_mnc: bool,
+}
+
+/// The modem's evaluation of the cell it is on, as reported by
+/// `AT%CONEVAL`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ConnectionEvaluation {
+	/// Is the RRC connection up (rather than idle)?
+	pub rrc_connected: bool,
+	/// How much energy a transmission would take
+	pub energy_estimate: EnergyEstimate,
+	/// Reference Signal Received Power, in dBm, if known
+	pub rsrp_dbm: Option<i16>,
+	/// Reference Signal Received Quality, in dB, if known
+	pub rsrq_db: Option<f32>,
+	/// Signal to noise ratio, in dB (-24 to 25), if known
+	pub snr_db: Option<i8>,
+	/// The E-UTRAN cell ID
+	pub cell_id: u32,
+	/// The operator of the cell
+	pub plmn: Plmn,
+	/// The physical cell ID
+	pub phys_cell_id: u16,
+	/// The E-UTRA absolute radio frequency channel number
+	pub earfcn: u32,
+	/// The LTE band
+	pub band: u8,
+	/// Would sending now trigger a tracking area update (which costs more
+	/// energy)?
+	pub tau_triggered: bool,
+	/// The coverage enhancement level (0 to 3)
+	pub ce_level: u8,
+	/// The transmit power, in dBm
+	pub tx_power_dbm: i8,
+	/// How many times each uplink transmission is repeated
+	pub tx_repetitions: u16,
+	/// How many times each downlink transmission is repeated
+	pub rx_repetitions: u16,
+	/// The downlink path loss, in dB
+	pub downlink_pathloss_db: u8,
+}
+
+/// The network operator the modem has selected, as reported by
+/// `AT+COPS?`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Operator {
+	/// Is the operator chosen automatically (rather than manually)?
+	pub automatic: bool,
+	/// The operator, if the modem has selected one
+	pub plmn: Option<Plmn>,
+	/// The access technology in use, if given
+	pub access_technology: Option<AccessTechnology>,
+}
+
+/// What the modem tells the network to expect after the data it sends, so
+/// the network can release the RRC connection early. See
+/// `set_release_assistance`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReleaseAssistance {
+	/// No indication - the network's inactivity timer releases the
+	/// connection, as usual
+	Disabled = 0,
+	/// One downlink packet (e.g. a reply) is expected, then nothing more
+	OneResponse = 3,
+	/// Nothing more is expected, in either direction
+	NoResponse = 4,
+}
+
+/// Everything `get_signal_info` finds out about the network and the signal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SignalInfo {
+	/// The signal quality (`AT+CESQ`)
+	pub quality: SignalQuality,
+	/// The modem's evaluation of the cell (`AT%CONEVAL`), if it could make
+	/// one
+	pub evaluation: Option<ConnectionEvaluation>,
+	/// The registration, including the tracking area code (`AT+CEREG?`)
+	pub registration: Option<Registration>,
+	/// The selected operator (`AT+COPS?`)
+	pub operator: Option<Operator>,
+}
+
+/// An IMEI: 15 decimal digits.
+pub type Imei = heapless::String<15>;
+
+/// The AT commands the modem supports, as listed by `AT+CLAC`: one per
+/// line, without the `AT` (e.g. `%CONEVAL`).
+pub type CommandList = heapless::String<MAX_COMMAND_LIST_LEN>;
+
 //******************************************************************************
 // Constants
 //******************************************************************************
 
-// None
+/// The `AT+CEREG` level which reports the tracking area code and cell ID.
+#[cfg(target_arch = "arm")]
+const CEREG_LOCATION_LEVEL: u8 = 2;
+
+/// The `AT+CEREG` level which reports the PSM timers the network granted.
+#[cfg(target_arch = "arm")]
+const CEREG_PSM_LEVEL: u8 = 4;
+
+/// Stored in our atomics when we don't know the value.
+#[cfg(target_arch = "arm")]
+const UNKNOWN: u8 = 0xFF;
+
+/// The longest `CommandList` we keep. Firmware 1.3 lists about 1.3 KiB.
+pub const MAX_COMMAND_LIST_LEN: usize = 2048;
 
 //******************************************************************************
 // Global Variables
 //******************************************************************************
 
-// None
+/// The last functional mode we set or read.
+#[cfg(target_arch = "arm")]
+static LAST_FUNCTIONAL_MODE: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+/// The last registration status we saw.
+#[cfg(target_arch = "arm")]
+static LAST_REGISTRATION_STATUS: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+/// The last access technology we saw in a `+CEREG`.
+#[cfg(target_arch = "arm")]
+static LAST_ACCESS_TECHNOLOGY: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+/// The last coverage enhancement level we saw in a `%MDMEV`.
+#[cfg(target_arch = "arm")]
+static LAST_CE_LEVEL: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+/// The URC subscriptions `wait_for_lte` makes.
+#[cfg(target_arch = "arm")]
+static ATTACH_SUBSCRIPTIONS: Mutex<RefCell<Subscriptions>> =
+	Mutex::new(RefCell::new(Subscriptions {
+		cereg: 2,
+		cscon: 0,
+		cgerep: 0,
+		mdmev: 0,
+		cnec: 0,
+	}));
+
+/// The last `+CNEC` error cause we saw.
+#[cfg(target_arch = "arm")]
+static LAST_NETWORK_ERROR: Mutex<RefCell<Option<NetworkError>>> = Mutex::new(RefCell::new(None));
+
+/// The antenna configuration `on()` selects before powering up the modem.
+#[cfg(target_arch = "arm")]
+static ANTENNA_CONFIG: Mutex<RefCell<Option<AntennaConfig>>> = Mutex::new(RefCell::new(None));
+
+/// The PSM timers last asked for with `set_psm`, which `suspend` asks for
+/// again. `None` if PSM hasn't been asked for, or was turned off.
+#[cfg(target_arch = "arm")]
+static PSM_CONFIG: Mutex<RefCell<Option<PsmConfig>>> = Mutex::new(RefCell::new(None));
+
+/// The AT commands `on()` sends before powering up the modem.
+#[cfg(target_arch = "arm")]
+static STARTUP_COMMANDS: Mutex<RefCell<&'static [&'static str]>> = Mutex::new(RefCell::new(&[]));
+
+/// The commands `AT+CLAC` listed, and `crate::starts` when it did - the
+/// firmware may have been updated since an earlier start.
+#[cfg(target_arch = "arm")]
+static SUPPORTED_COMMANDS: Mutex<RefCell<Option<(u32, CommandList)>>> =
+	Mutex::new(RefCell::new(None));
 
 //******************************************************************************
 // Macros
@@ -65,58 +529,246 @@ pub enum SystemMode {
 ///
 /// The list of acceptable CEREG response indications is taken from the Nordic
 /// `lte_link_control` driver.
+///
+/// The URC subscriptions set with `set_attach_subscriptions` are made first.
+/// `+CEREG` reports are needed to see the modem register, so if the `cereg`
+/// level is `0` they are enabled (at level 1) while we wait and turned off
+/// again afterwards.
+#[cfg(target_arch = "arm")]
 pub fn wait_for_lte() -> Result<(), Error> {
 	debug!("Waiting for LTE...");
-	let skt = crate::at::AtSocket::new()?;
-	// Subscribe
-	skt.write(b"AT+CEREG=2")?;
+	let subscriptions = attach_subscriptions();
+	for (command, level) in [
+		("AT+CSCON", subscriptions.cscon),
+		("AT+CGEREP", subscriptions.cgerep),
+		("AT%MDMEV", subscriptions.mdmev),
+		("AT+CNEC", subscriptions.cnec),
+	] {
+		if level != 0 {
+			send_subscription(command, level)?;
+		}
+	}
+
+	let mut skt = crate::at::AtSocket::new()?;
+	// Subscribe. The first report may come before the `OK`.
+	let mut cereg: heapless::String<16> = heapless::String::new();
+	let _ = write!(cereg, "AT+CEREG={}", subscriptions.cereg.max(1));
+	skt.send_command(&cereg)?;
+	let mut registered = false;
+	skt.poll_response(|line| registered |= attach_report(line))?;
 
-	let connected_indications = ["+CEREG: 1", "+CEREG:1", "+CEREG: 5", "+CEREG:5"];
-	'outer: loop {
-		let mut buf = [0u8; 128];
+	let mut buf = crate::pool::Buffer::take()?;
+	while !registered {
 		let maybe_length = skt.recv(&mut buf)?;
 		if let Some(length) = maybe_length {
-			let s = unsafe { core::str::from_utf8_unchecked(&buf[0..length - 1]) };
+			// Skip anything which isn't text, rather than give up waiting
+			let s = buf
+				.get(0..length)
+				.and_then(|data| crate::at::response_text(data).ok())
+				.unwrap_or("");
 			for line in s.lines() {
-				let line = line.trim();
-				debug!("RX {:?}", line);
-				for ind in &connected_indications {
-					if line.starts_with(ind) {
-						break 'outer;
-					}
-				}
+				registered |= attach_report(line);
 			}
+		} else if crate::watchdog::is_set() {
+			// Wake up now and again to feed the watchdog
+			let mut poll_list = [crate::PollEntry::new(&skt, crate::PollFlags::Read)];
+			crate::poll(&mut poll_list, crate::watchdog::FEED_INTERVAL_MS)?;
+			crate::watchdog::feed();
 		} else {
-			cortex_m::asm::wfe();
+			crate::delay::idle();
 		}
 	}
+	if subscriptions.cereg == 0 {
+		send_subscription("AT+CEREG", 0)?;
+	}
 	Ok(())
 }
 
+/// Set which URC subscriptions `wait_for_lte` makes. The default is
+/// `AT+CEREG=2` and nothing else.
+#[cfg(target_arch = "arm")]
+pub fn set_attach_subscriptions(subscriptions: Subscriptions) {
+	cortex_m::interrupt::free(|cs| {
+		*ATTACH_SUBSCRIPTIONS.borrow(cs).borrow_mut() = subscriptions;
+	});
+}
+
+/// Get the URC subscriptions which `wait_for_lte` makes.
+#[cfg(target_arch = "arm")]
+pub fn attach_subscriptions() -> Subscriptions {
+	cortex_m::interrupt::free(|cs| *ATTACH_SUBSCRIPTIONS.borrow(cs).borrow())
+}
+
 /// Powers the modem on and sets it to auto-register, but does not wait for it
 /// to connect to a network.
+///
+/// Any antenna configuration given to `set_antenna_config` is selected
+/// first, then any commands given to `set_startup_commands` are sent. Then, if
+/// any SIM profiles have been set (see `sim`), the one for the SIM fitted is
+/// applied. If any of this fails, the modem is not powered on.
+#[cfg(target_arch = "arm")]
 pub fn on() -> Result<(), Error> {
 	debug!("Turning modem ON");
+	prepare_lte()?;
 	crate::at::send_at_command("AT+CFUN=1", |_| {})?;
+	LAST_FUNCTIONAL_MODE.store(FunctionalMode::Normal as u8, Ordering::Relaxed);
 	Ok(())
 }
 
+/// Set the AT commands which `on()` sends every time, before it powers up the
+/// modem.
+///
+/// Use this for settings which the modem doesn't keep in NVM (or which it
+/// must be powered off to change, like `AT%XSYSTEMMODE`), so they are never
+/// lost when the modem is power-cycled to recover from a fault. For example:
+///
+/// ```ignore
+/// modem::set_startup_commands(&["AT%XSYSTEMMODE=1,0,1,0", "AT+CEREG=5", "AT%XDATAPRFL=0"]);
+/// ```
+#[cfg(target_arch = "arm")]
+pub fn set_startup_commands(commands: &'static [&'static str]) {
+	cortex_m::interrupt::free(|cs| {
+		*STARTUP_COMMANDS.borrow(cs).borrow_mut() = commands;
+	});
+}
+
+/// Get the AT commands which `on()` sends before powering up the modem.
+#[cfg(target_arch = "arm")]
+pub fn startup_commands() -> &'static [&'static str] {
+	cortex_m::interrupt::free(|cs| *STARTUP_COMMANDS.borrow(cs).borrow())
+}
+
+/// Set the antenna configuration which `on()` selects every time, before it
+/// powers up the modem, or `None` to leave it alone.
+#[cfg(target_arch = "arm")]
+pub fn set_antenna_config(config: Option<AntennaConfig>) {
+	cortex_m::interrupt::free(|cs| {
+		*ANTENNA_CONFIG.borrow(cs).borrow_mut() = config;
+	});
+}
+
+/// Get the antenna configuration which `on()` selects.
+#[cfg(target_arch = "arm")]
+pub fn antenna_config() -> Option<AntennaConfig> {
+	cortex_m::interrupt::free(|cs| *ANTENNA_CONFIG.borrow(cs).borrow())
+}
+
+/// Select an antenna configuration now, using `AT%XANTCFG`. The modem
+/// should be powered off, or in flight mode.
+#[cfg(target_arch = "arm")]
+pub fn select_antenna(config: AntennaConfig) -> Result<(), Error> {
+	let mut command: heapless::String<16> = heapless::String::new();
+	write!(command, "AT%XANTCFG={}", config.0)?;
+	crate::at::send_at_command(&command, |res| {
+		debug!("{:?} => {:?}", command, res);
+	})
+}
+
 /// Puts the modem into flight mode.
+#[cfg(target_arch = "arm")]
 pub fn flight_mode() -> Result<(), Error> {
 	debug!("Turning mode to FLIGHT MODE");
 	crate::at::send_at_command("AT+CFUN=4", |_| {})?;
+	LAST_FUNCTIONAL_MODE.store(FunctionalMode::FlightMode as u8, Ordering::Relaxed);
 	Ok(())
 }
 
 /// Powers the modem off.
+///
+/// In "minimize writes" mode (see `nvm`) nothing is sent if the modem is
+/// already off, as `AT+CFUN=0` writes to NVM.
+#[cfg(target_arch = "arm")]
 pub fn off() -> Result<(), Error> {
+	let already_off = || get_functional_mode().ok() == Some(FunctionalMode::PowerOff);
+	if crate::nvm::minimize_writes() && already_off() {
+		debug!("Modem already OFF");
+		crate::nvm::record_skipped();
+		return Ok(());
+	}
 	debug!("Turning modem OFF");
 	crate::at::send_at_command("AT+CFUN=0", |_| {})?;
+	LAST_FUNCTIONAL_MODE.store(FunctionalMode::PowerOff as u8, Ordering::Relaxed);
+	Ok(())
+}
+
+/// Activates LTE, without changing the state of GNSS.
+#[cfg(target_arch = "arm")]
+pub fn lte_on() -> Result<(), Error> {
+	debug!("Turning LTE ON");
+	crate::at::send_at_command("AT+CFUN=21", |_| {})?;
+	// We can't be sure what the modem's overall mode is now
+	LAST_FUNCTIONAL_MODE.store(UNKNOWN, Ordering::Relaxed);
+	Ok(())
+}
+
+/// Deactivates LTE, without changing the state of GNSS.
+#[cfg(target_arch = "arm")]
+pub fn lte_off() -> Result<(), Error> {
+	debug!("Turning LTE OFF");
+	crate::at::send_at_command("AT+CFUN=20", |_| {})?;
+	// We can't be sure what the modem's overall mode is now
+	LAST_FUNCTIONAL_MODE.store(UNKNOWN, Ordering::Relaxed);
+	Ok(())
+}
+
+/// Activates GNSS, without changing the state of LTE.
+///
+/// The system mode must include GNSS (see `set_system_mode`).
+#[cfg(target_arch = "arm")]
+pub fn gnss_on() -> Result<(), Error> {
+	debug!("Turning GNSS ON");
+	crate::at::send_at_command("AT+CFUN=31", |_| {})?;
+	// We can't be sure what the modem's overall mode is now
+	LAST_FUNCTIONAL_MODE.store(UNKNOWN, Ordering::Relaxed);
+	Ok(())
+}
+
+/// Deactivates GNSS, without changing the state of LTE.
+#[cfg(target_arch = "arm")]
+pub fn gnss_off() -> Result<(), Error> {
+	debug!("Turning GNSS OFF");
+	crate::at::send_at_command("AT+CFUN=30", |_| {})?;
+	// We can't be sure what the modem's overall mode is now
+	LAST_FUNCTIONAL_MODE.store(UNKNOWN, Ordering::Relaxed);
+	Ok(())
+}
+
+/// Activates the UICC (SIM), without changing the state of LTE or GNSS.
+#[cfg(target_arch = "arm")]
+pub fn activate_sim() -> Result<(), Error> {
+	debug!("Turning UICC ON");
+	crate::at::send_at_command("AT+CFUN=41", |_| {})?;
+	// We can't be sure what the modem's overall mode is now
+	LAST_FUNCTIONAL_MODE.store(UNKNOWN, Ordering::Relaxed);
 	Ok(())
 }
 
+/// Puts the modem into flight mode, but leaves GNSS active.
+///
+/// Useful for trackers which need a fix but have nothing to send.
+#[cfg(target_arch = "arm")]
+pub fn flight_mode_with_gnss() -> Result<(), Error> {
+	flight_mode()?;
+	gnss_on()
+}
+
 /// Set which radios should be active. Only works when modem is off.
+///
+/// In "minimize writes" mode (see `nvm`) nothing is sent if the modem is
+/// already in this system mode.
+#[cfg(target_arch = "arm")]
 pub fn set_system_mode(mode: SystemMode) -> Result<(), Error> {
+	if crate::nvm::minimize_writes() && get_system_mode().ok() == Some(mode) {
+		debug!("Already in system mode {:?}", mode);
+		crate::nvm::record_skipped();
+		return Ok(());
+	}
+	write_system_mode(mode)
+}
+
+/// Send `AT%XSYSTEMMODE` for this mode, without checking it first.
+#[cfg(target_arch = "arm")]
+fn write_system_mode(mode: SystemMode) -> Result<(), Error> {
 	let at_command = match mode {
 		SystemMode::LteM => "AT%XSYSTEMMODE=1,0,0,0",
 		SystemMode::NbIot => "AT%XSYSTEMMODE=0,1,0,0",
@@ -129,7 +781,36 @@ pub fn set_system_mode(mode: SystemMode) -> Result<(), Error> {
 	Ok(())
 }
 
+/// Change which radios should be active, powering the modem off first if
+/// required.
+///
+/// The system mode can only be changed while the modem is off, so this
+/// powers it off, applies the change, then puts it back into the functional
+/// mode it was in before - waiting for it to register again if LTE is active
+/// in that mode. Turning LTE back on re-applies everything `on()` does first
+/// (antenna configuration, startup commands, SIM profile). Nothing happens if
+/// the modem is already in the given system mode.
+#[cfg(target_arch = "arm")]
+pub fn change_system_mode(mode: SystemMode) -> Result<(), Error> {
+	if get_system_mode().ok() == Some(mode) {
+		debug!("Already in system mode {:?}", mode);
+		return Ok(());
+	}
+	let previous = get_functional_mode()?;
+	if previous != FunctionalMode::PowerOff {
+		off()?;
+	}
+	let result = set_system_mode(mode);
+	let restored = match previous {
+		FunctionalMode::PowerOff => Ok(()),
+		FunctionalMode::FlightMode => flight_mode(),
+		mode => restore_mode(mode),
+	};
+	result.and(restored)
+}
+
 /// Get which radios should be active
+#[cfg(target_arch = "arm")]
 pub fn get_system_mode() -> Result<SystemMode, Error> {
 	let mut result = Err(Error::UnrecognisedValue);
 	// Don't care about final digit - that's just the LTE/NB-IOT preference
@@ -149,3 +830,1576 @@ pub fn get_system_mode() -> Result<SystemMode, Error> {
 	})?;
 	result
 }
+
+/// Get the modem's current functional mode.
+#[cfg(target_arch = "arm")]
+pub fn get_functional_mode() -> Result<FunctionalMode, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT+CFUN?", |res| {
+		if let Some(value) = res.strip_prefix("+CFUN:") {
+			if let Some(mode) = value.trim().parse().ok().and_then(FunctionalMode::from_u8) {
+				result = Ok(mode);
+			}
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	if let Ok(mode) = result {
+		LAST_FUNCTIONAL_MODE.store(mode as u8, Ordering::Relaxed);
+	}
+	result
+}
+
+/// Set the modem's functional mode.
+///
+/// `on()`, `off()` and `flight_mode()` are shortcuts for the common cases.
+#[cfg(target_arch = "arm")]
+pub fn set_functional_mode(mode: FunctionalMode) -> Result<(), Error> {
+	use core::fmt::Write;
+	let mut at_command: heapless::String<16> = heapless::String::new();
+	write!(at_command, "AT+CFUN={}", mode as u8)?;
+	debug!("{:?} => {:?}", mode, at_command);
+	crate::at::send_at_command(&at_command, |_| {})?;
+	LAST_FUNCTIONAL_MODE.store(mode as u8, Ordering::Relaxed);
+	Ok(())
+}
+
+/// Put the modem to sleep for a while, keeping as much of its state as we
+/// can, so `resume` can get it back quickly.
+///
+/// If PSM was asked for with `set_psm`, it is asked for again, with the same
+/// timers - `+CPSMS` is left alone if it wasn't, or was turned off with
+/// `set_psm(None)`. If the modem is registered and the network has granted
+/// PSM, the modem is left
+/// to go into PSM by itself, staying registered and keeping its PDN
+/// connections. Otherwise it is put into flight mode - with the UICC still
+/// powered if `keep_uicc`, which saves reading the SIM again on resume.
+///
+/// The network may only grant PSM at the next tracking area update, so the
+/// first suspend after requesting it can end up in flight mode.
+#[cfg(target_arch = "arm")]
+pub fn suspend(keep_uicc: bool) -> Result<Suspended, Error> {
+	let previous = get_functional_mode()?;
+	let registered = matches!(get_registration_status(), Ok(status) if status.is_registered());
+	if let Some(config) = psm_config() {
+		set_psm(Some(config))?;
+	}
+	let mode = if registered && psm_granted()? {
+		SuspendMode::Psm
+	} else if keep_uicc {
+		set_functional_mode(FunctionalMode::FlightModeUiccOn)?;
+		SuspendMode::FlightMode
+	} else {
+		flight_mode()?;
+		SuspendMode::FlightMode
+	};
+	debug!("Suspended in {:?}, from {:?}", mode, previous);
+	Ok(Suspended { mode, previous })
+}
+
+/// Wake the modem up after `suspend`, and make sure it is registered.
+///
+/// After PSM the modem should still be registered, which is checked with
+/// `AT+CEREG?`, and only if it isn't do we wait for it to attach again.
+/// After flight mode it is put back in the mode it was suspended from, and
+/// has to attach. `now_ms` is used to time it all.
+#[cfg(target_arch = "arm")]
+pub fn resume(suspended: Suspended, now_ms: fn() -> u32) -> Result<ResumeReport, Error> {
+	let start = now_ms();
+	let reattached = match suspended.mode {
+		SuspendMode::Psm => {
+			if get_registration_status()?.is_registered() {
+				false
+			} else {
+				wait_for_lte()?;
+				true
+			}
+		}
+		SuspendMode::FlightMode => {
+			restore_mode(suspended.previous)?;
+			true
+		}
+	};
+	let report = ResumeReport {
+		mode: suspended.mode,
+		reattached,
+		duration_ms: now_ms().wrapping_sub(start),
+	};
+	debug!("Resumed: {:?}", report);
+	Ok(report)
+}
+
+/// Ask the modem whether the network has granted PSM - that is, whether
+/// there is an active time, reported by `AT+CEREG?` at level 4, which isn't
+/// deactivated. See `get_psm`.
+#[cfg(target_arch = "arm")]
+pub fn psm_granted() -> Result<bool, Error> {
+	Ok(matches!(
+		get_psm()?,
+		Some(PsmGranted {
+			active_time: Some(_),
+			..
+		})
+	))
+}
+
+/// Ask the network for PSM with these timers (`AT+CPSMS=1`), or, with
+/// `None`, stop asking for it (`AT+CPSMS=0`). The network decides what it
+/// actually grants - see `get_psm`.
+#[cfg(target_arch = "arm")]
+pub fn set_psm(config: Option<PsmConfig>) -> Result<(), Error> {
+	let mut command: heapless::String<40> = heapless::String::new();
+	match config {
+		Some(config) => write!(
+			command,
+			"AT+CPSMS=1,,,\"{}\",\"{}\"",
+			gpp_timers::encode_t3412(config.periodic_tau, Rounding::Up).bit_string(),
+			gpp_timers::encode_t3324(config.active_time, Rounding::Up).bit_string()
+		)?,
+		None => write!(command, "AT+CPSMS=0")?,
+	}
+	crate::at::send_at_command(&command, |_| {})?;
+	cortex_m::interrupt::free(|cs| {
+		*PSM_CONFIG.borrow(cs).borrow_mut() = config;
+	});
+	Ok(())
+}
+
+/// Get the PSM timers last asked for with `set_psm`, if PSM is being asked
+/// for.
+#[cfg(target_arch = "arm")]
+pub fn psm_config() -> Option<PsmConfig> {
+	cortex_m::interrupt::free(|cs| *PSM_CONFIG.borrow(cs).borrow())
+}
+
+/// Get the PSM timers the network has granted, from `AT+CEREG?` at level 4.
+/// Returns `None` if the modem didn't report any (e.g. it isn't registered).
+/// The `AT+CEREG` subscription is put back afterwards.
+#[cfg(target_arch = "arm")]
+pub fn get_psm() -> Result<Option<PsmGranted>, Error> {
+	let level = crate::at::subscriptions().cereg;
+	send_subscription("AT+CEREG", CEREG_PSM_LEVEL)?;
+	let mut granted = None;
+	let result = crate::at::send_at_command("AT+CEREG?", |res| {
+		if let Some(timers) = PsmGranted::parse(res) {
+			granted = Some(timers);
+		}
+		debug!("AT+CEREG? => {:?}", res);
+	});
+	send_subscription("AT+CEREG", level)?;
+	result.map(|_| granted)
+}
+
+/// Ask the network for eDRX with these settings (`AT+CEDRXS=1`, after
+/// `AT%XPTW` if a paging time window is given), or, with `None`, stop using
+/// eDRX (`AT+CEDRXS=3`). See `get_edrx` for what the network grants.
+#[cfg(target_arch = "arm")]
+pub fn set_edrx(config: Option<EdrxConfig>) -> Result<(), Error> {
+	let config = match config {
+		Some(config) => config,
+		None => return crate::at::send_at_command("AT+CEDRXS=3", |_| {}),
+	};
+	let act = config.access_technology;
+	let mut command: heapless::String<32> = heapless::String::new();
+	if let Some(window) = config.paging_time_window {
+		write!(
+			command,
+			"AT%XPTW={},\"{}\"",
+			act as u8,
+			gpp_timers::encode_ptw(act, window, Rounding::Up).bit_string()
+		)?;
+		crate::at::send_at_command(&command, |_| {})?;
+		command.clear();
+	}
+	write!(
+		command,
+		"AT+CEDRXS=1,{},\"{}\"",
+		act as u8,
+		gpp_timers::encode_edrx(act, config.cycle, Rounding::Up).bit_string()
+	)?;
+	crate::at::send_at_command(&command, |_| {})
+}
+
+/// Get the eDRX settings in use in the current cell, with `AT+CEDRXRDP`.
+/// Returns `None` if eDRX isn't in use.
+#[cfg(target_arch = "arm")]
+pub fn get_edrx() -> Result<Option<EdrxGranted>, Error> {
+	let mut granted = None;
+	crate::at::send_at_command("AT+CEDRXRDP", |res| {
+		if let Some(settings) = EdrxGranted::parse(res) {
+			granted = Some(settings);
+		}
+		debug!("AT+CEDRXRDP => {:?}", res);
+	})?;
+	Ok(granted)
+}
+
+/// Set the Release Assistance Indication the modem gives the network
+/// (`AT%XRAI`), so the RRC connection can be released as soon as the
+/// exchange is over, rather than when the network's inactivity timer runs
+/// out - much of the energy of a short NB-IoT upload goes on that wait.
+///
+/// libmodem 1.5.1 has no per-socket or per-packet RAI option, so this
+/// modem-wide setting is the only RAI control there is: it applies to
+/// whatever is sent, on any socket, until it is changed. Set it just before
+/// the last send of an exchange, and back to `Disabled` afterwards.
+///
+/// Returns `Error::UnsupportedFirmware` if the firmware doesn't have
+/// `%XRAI` (see `firmware::command`).
+#[cfg(target_arch = "arm")]
+pub fn set_release_assistance(rai: ReleaseAssistance) -> Result<(), Error> {
+	let command = crate::firmware::command(AtSyntax::ReleaseAssistance)?;
+	let mut at_command: heapless::String<16> = heapless::String::new();
+	write!(at_command, "{}={}", command, rai as u8)?;
+	crate::at::send_at_command(&at_command, |_| {})
+}
+
+/// Get the Release Assistance Indication setting (`AT%XRAI?`). See
+/// `set_release_assistance`.
+#[cfg(target_arch = "arm")]
+pub fn get_release_assistance() -> Result<ReleaseAssistance, Error> {
+	let command = crate::firmware::command(AtSyntax::ReleaseAssistance)?;
+	let mut at_command: heapless::String<16> = heapless::String::new();
+	write!(at_command, "{}?", command)?;
+	let value = read_number(&at_command, "%XRAI:")?;
+	ReleaseAssistance::from_u8(value).ok_or(Error::UnrecognisedValue)
+}
+
+/// Read every setting which matters to battery life - the functional and
+/// system modes, band lock, PSM and eDRX (both requested and granted), RAI
+/// and data profile - for a support engineer to look at. A setting the
+/// modem won't give is left as `None`, and the rest are still read. Nothing
+/// is changed, other than the `AT+CEREG` level briefly (see `get_psm`).
+#[cfg(target_arch = "arm")]
+pub fn power_audit() -> PowerAudit {
+	let audit = PowerAudit {
+		functional_mode: get_functional_mode().ok(),
+		system_mode: get_system_mode().ok(),
+		band_lock: band_lock().ok(),
+		psm_requested: requested_psm().ok().map(|timers| {
+			timers.map(|(tau, active)| PsmGranted {
+				active_time: gpp_timers::decode_t3324(active),
+				periodic_tau: gpp_timers::decode_t3412(tau),
+			})
+		}),
+		psm_granted: get_psm().ok(),
+		edrx_requested: requested_edrx().ok(),
+		edrx_granted: get_edrx().ok(),
+		rai: get_release_assistance().ok(),
+		data_profile: read_number("AT%XDATAPRFL?", "%XDATAPRFL:").ok(),
+	};
+	debug!("Power audit: {:?}", audit);
+	audit
+}
+
+/// Bring the modem's settings to `config`, reading each one first and only
+/// sending the AT commands for those which differ. Settings which only
+/// change when asked (the system mode, band lock and the PSM and eDRX
+/// requests) then survive a reboot without being rewritten to NVM, and
+/// without the network seeing a fresh request.
+///
+/// The values compared are what was last requested (`AT%XSYSTEMMODE?`,
+/// `AT%XBANDLOCK?`, `AT+CPSMS?`, `AT+CEDRXS?` and `AT%XPTW?`), not what the
+/// network granted. The system mode and band lock can only be changed while
+/// the modem is off, so call this before `on()`. Skipped NVM writes are
+/// counted in `nvm::stats`.
+#[cfg(target_arch = "arm")]
+pub fn apply_config_diff(config: &ModemConfig) -> Result<ConfigChanges, Error> {
+	let mut changes = ConfigChanges::default();
+	if let Some(mode) = config.system_mode {
+		if get_system_mode().ok() == Some(mode) {
+			crate::nvm::record_skipped();
+		} else {
+			write_system_mode(mode)?;
+			changes.system_mode = true;
+		}
+	}
+	if let Some(bands) = config.bands {
+		let mut mask: heapless::String<{ crate::sim::MAX_BAND as usize }> = heapless::String::new();
+		if !bands.is_empty() {
+			crate::sim::write_band_mask(&mut mask, bands)?;
+		}
+		if band_lock().ok().as_deref() == Some(mask.trim_start_matches('0')) {
+			crate::nvm::record_skipped();
+		} else {
+			let mut command: heapless::String<{ 20 + crate::sim::MAX_BAND as usize }> =
+				heapless::String::new();
+			if mask.is_empty() {
+				write!(command, "AT%XBANDLOCK=0")?;
+			} else {
+				write!(command, "AT%XBANDLOCK=2,\"{}\"", mask)?;
+			}
+			crate::at::send_at_command(&command, |_| {})?;
+			changes.bands = true;
+		}
+	}
+	if let Some(psm) = config.psm {
+		let wanted = psm.map(|psm| {
+			(
+				gpp_timers::encode_t3412(psm.periodic_tau, Rounding::Up).bits,
+				gpp_timers::encode_t3324(psm.active_time, Rounding::Up).bits,
+			)
+		});
+		if requested_psm().ok() != Some(wanted) {
+			set_psm(psm)?;
+			changes.psm = true;
+		}
+	}
+	if let Some(edrx) = config.edrx {
+		if !edrx_requested(edrx).unwrap_or(false) {
+			set_edrx(edrx)?;
+			changes.edrx = true;
+		}
+	}
+	debug!("Config changes: {:?}", changes);
+	Ok(changes)
+}
+
+/// Get the modem's current network registration status.
+#[cfg(target_arch = "arm")]
+pub fn get_registration_status() -> Result<RegistrationStatus, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT+CEREG?", |res| {
+		record_access_technology(res, true);
+		if let Some(status) = parse_cereg(res, true) {
+			result = Ok(status);
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	if let Ok(status) = result {
+		LAST_REGISTRATION_STATUS.store(status as u8, Ordering::Relaxed);
+	}
+	result
+}
+
+/// The functional mode the modem was last known to be in, without talking to
+/// the modem. Returns `None` if we don't know.
+#[cfg(target_arch = "arm")]
+pub fn last_functional_mode() -> Option<FunctionalMode> {
+	FunctionalMode::from_u8(LAST_FUNCTIONAL_MODE.load(Ordering::Relaxed))
+}
+
+/// The registration status the modem last reported, without talking to the
+/// modem. Returns `None` if we don't know.
+#[cfg(target_arch = "arm")]
+pub fn last_registration_status() -> Option<RegistrationStatus> {
+	RegistrationStatus::from_u8(LAST_REGISTRATION_STATUS.load(Ordering::Relaxed))
+}
+
+/// The access technology the modem last reported in a `+CEREG`, without
+/// talking to the modem. Returns `None` if we don't know, which is the case
+/// until we've seen a `+CEREG` with the `<AcT>` field (level 2 or above).
+#[cfg(target_arch = "arm")]
+pub fn last_access_technology() -> Option<AccessTechnology> {
+	AccessTechnology::from_u8(LAST_ACCESS_TECHNOLOGY.load(Ordering::Relaxed))
+}
+
+/// The coverage enhancement level (0 to 3) the modem last reported in a
+/// `%MDMEV: PRACH CE-LEVEL` event, without talking to the modem. Returns
+/// `None` until we've seen one - they are only seen by `wait_for_lte` or a
+/// `urc::UrcDispatcher`, and only if `AT%MDMEV` has been subscribed to.
+#[cfg(target_arch = "arm")]
+pub fn last_ce_level() -> Option<u8> {
+	match LAST_CE_LEVEL.load(Ordering::Relaxed) {
+		UNKNOWN => None,
+		level => Some(level),
+	}
+}
+
+/// The error cause the network last sent in a `+CNEC_EMM` or `+CNEC_ESM`
+/// report, without talking to the modem. Returns `None` if we haven't seen
+/// one. Only reports read by `wait_for_lte` or a `urc::UrcDispatcher` are
+/// seen, and only if `AT+CNEC` has been subscribed to (see
+/// `set_attach_subscriptions`).
+#[cfg(target_arch = "arm")]
+pub fn last_network_error() -> Option<NetworkError> {
+	cortex_m::interrupt::free(|cs| *LAST_NETWORK_ERROR.borrow(cs).borrow())
+}
+
+/// Ask the modem why the last attach, PDN connection or similar request
+/// failed, using `AT+CEER`.
+///
+/// Returns `None` if the modem has no cause to report.
+#[cfg(target_arch = "arm")]
+pub fn get_error_cause() -> Result<Option<ErrorCause>, Error> {
+	let mut result = None;
+	crate::at::send_at_command("AT+CEER", |res| {
+		if let Some(report) = res.strip_prefix("+CEER:") {
+			result = parse_error_cause(report);
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	Ok(result)
+}
+
+/// Add the network's failure cause (from `AT+CEER`) to an AT error.
+///
+/// For example, `modem::on().map_err(modem::annotate_error)?`. Errors other
+/// than `Error::AtError` are returned unchanged, as are AT errors where the
+/// network gave no cause.
+#[cfg(target_arch = "arm")]
+pub fn annotate_error(error: Error) -> Error {
+	match error {
+		Error::AtError(at_error) => match get_error_cause() {
+			Ok(Some(cause)) => Error::AtErrorWithCause(at_error, cause),
+			_ => Error::AtError(at_error),
+		},
+		other => other,
+	}
+}
+
+/// Is the operator-specific Extended Protocol Configuration Options (ePCO)
+/// behaviour enabled? Reads `AT%XEPCO?`.
+#[cfg(target_arch = "arm")]
+pub fn get_epco() -> Result<bool, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT%XEPCO?", |res| {
+		if let Some(value) = res.strip_prefix("%XEPCO:") {
+			match value.trim() {
+				"0" => result = Ok(false),
+				"1" => result = Ok(true),
+				_ => {}
+			}
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+/// Enable or disable the use of ePCO (rather than PCO) when the modem
+/// connects, with `AT%XEPCO`. Some operators require this to be toggled for
+/// certification.
+///
+/// The setting is kept in NVM and only takes effect when LTE is next
+/// activated, so change it with the modem powered off (e.g. in a
+/// `set_startup_commands` list, or before calling `on()`).
+///
+/// The nRF9160 firmware does not have a general operator configuration
+/// command (`AT%XOPCONF`); ePCO is the operator-specific behaviour which it
+/// does let you change.
+///
+/// In "minimize writes" mode (see `nvm`) nothing is sent if the setting is
+/// already right.
+#[cfg(target_arch = "arm")]
+pub fn set_epco(enabled: bool) -> Result<(), Error> {
+	if crate::nvm::minimize_writes() && get_epco().ok() == Some(enabled) {
+		crate::nvm::record_skipped();
+		return Ok(());
+	}
+	let command = if enabled { "AT%XEPCO=1" } else { "AT%XEPCO=0" };
+	crate::at::send_at_command(command, |res| {
+		debug!("{:?} => {:?}", command, res);
+	})
+}
+
+/// Get the UE mode of operation (`AT+CEMODE?`).
+#[cfg(target_arch = "arm")]
+pub fn get_ue_mode() -> Result<UeMode, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT+CEMODE?", |res| {
+		if let Some(value) = res.strip_prefix("+CEMODE:") {
+			if let Some(mode) = value.trim().parse().ok().and_then(UeMode::from_u8) {
+				result = Ok(mode);
+			}
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+/// Set the UE mode of operation (`AT+CEMODE`), e.g. `UeMode::PsMode2` for
+/// a network which rejects combined attach. The modem keeps it in NVM.
+///
+/// The modem only takes a new mode while LTE is off, so this returns
+/// `Error::NotDetached` (and sends nothing) unless it is powered off, in
+/// flight mode or has LTE deactivated. `change_system_mode` shows how to
+/// get there and back.
+///
+/// The nRF9160 has no voice support, so there are no voice domain settings
+/// (`AT+CEVDP`) to go with this.
+///
+/// In "minimize writes" mode (see `nvm`) nothing is sent if the modem is
+/// already in this mode.
+#[cfg(target_arch = "arm")]
+pub fn set_ue_mode(mode: UeMode) -> Result<(), Error> {
+	let functional_mode = get_functional_mode()?;
+	if !functional_mode.is_lte_off() {
+		debug!("Can't set {:?} in {:?}", mode, functional_mode);
+		return Err(Error::NotDetached);
+	}
+	if crate::nvm::minimize_writes() && get_ue_mode().ok() == Some(mode) {
+		debug!("Already in UE mode {:?}", mode);
+		crate::nvm::record_skipped();
+		return Ok(());
+	}
+	let mut at_command: heapless::String<16> = heapless::String::new();
+	write!(at_command, "AT+CEMODE={}", mode as u8)?;
+	debug!("{:?} => {:?}", mode, at_command);
+	crate::at::send_at_command(&at_command, |_| {})
+}
+
+/// Read which operator the modem thinks it is using (`AT%XOPERID`), which
+/// decides which operator-specific behaviours it applies.
+///
+/// Returns the operator ID - `0` means unknown or no specific operator. See
+/// Nordic's AT command reference for the other values.
+#[cfg(target_arch = "arm")]
+pub fn get_operator_id() -> Result<u8, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT%XOPERID", |res| {
+		if let Some(value) = res.strip_prefix("%XOPERID:") {
+			if let Ok(id) = value.trim().parse() {
+				result = Ok(id);
+			}
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+/// Get the signal quality (`AT+CESQ`).
+#[cfg(target_arch = "arm")]
+pub fn get_signal_quality() -> Result<SignalQuality, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT+CESQ", |res| {
+		if let Some(quality) = SignalQuality::parse(res) {
+			result = Ok(quality);
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+/// Ask the modem to evaluate the cell it is on (`AT%CONEVAL`) - e.g. to
+/// decide whether to send now or wait for better conditions. Returns `None`
+/// if it couldn't (e.g. it isn't registered, or there is no cell), and
+/// `Error::UnsupportedFirmware` if the firmware is too old to (see
+/// `firmware`).
+#[cfg(target_arch = "arm")]
+pub fn evaluate_connection() -> Result<Option<ConnectionEvaluation>, Error> {
+	let command = crate::firmware::command(AtSyntax::ConnectionEvaluation)?;
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command(command, |res| {
+		if res.trim().starts_with("%CONEVAL:") {
+			result = Ok(ConnectionEvaluation::parse(res));
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+/// Get the network operator the modem has selected (`AT+COPS?`). The
+/// operator format is set to numeric (`AT+COPS=3,2`) first.
+#[cfg(target_arch = "arm")]
+pub fn get_operator() -> Result<Operator, Error> {
+	crate::at::send_at_command("AT+COPS=3,2", |_| {})?;
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT+COPS?", |res| {
+		if let Some(operator) = Operator::parse(res) {
+			result = Ok(operator);
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+/// Get the registration status, with the tracking area code and cell ID
+/// (`AT+CEREG?`). The `+CEREG` subscription level is raised while we ask, if
+/// it is too low to give them.
+#[cfg(target_arch = "arm")]
+pub fn get_registration() -> Result<Registration, Error> {
+	let level = crate::at::subscriptions().cereg;
+	if level < CEREG_LOCATION_LEVEL {
+		send_subscription("AT+CEREG", CEREG_LOCATION_LEVEL)?;
+	}
+	let mut registration = Err(Error::UnrecognisedValue);
+	let result = crate::at::send_at_command("AT+CEREG?", |res| {
+		// Skip the `<n>` which starts the read response
+		let fields = res
+			.strip_prefix("+CEREG:")
+			.and_then(|fields| fields.split_once(','));
+		if let Some(found) = fields.and_then(|(_, fields)| Registration::parse(fields)) {
+			registration = Ok(found);
+		}
+		debug!("{:?} => {:?}", res, registration);
+	});
+	if level < CEREG_LOCATION_LEVEL {
+		send_subscription("AT+CEREG", level)?;
+	}
+	result?;
+	if let Ok(found) = registration.as_ref() {
+		record_registration(found);
+	}
+	registration
+}
+
+/// Find out everything we can about the network and the signal. Only a
+/// failure to read the signal quality is an error - the rest is `None` if
+/// the modem can't say (e.g. because it isn't registered).
+#[cfg(target_arch = "arm")]
+pub fn get_signal_info() -> Result<SignalInfo, Error> {
+	Ok(SignalInfo {
+		quality: get_signal_quality()?,
+		evaluation: evaluate_connection().ok().flatten(),
+		registration: get_registration().ok(),
+		operator: get_operator().ok(),
+	})
+}
+
+/// Get the modem's IMEI (`AT+CGSN`). The SIM's ICCID is read with
+/// `sim::read_iccid`.
+#[cfg(target_arch = "arm")]
+pub fn get_imei() -> Result<Imei, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT+CGSN", |res| {
+		let value = res.trim();
+		let mut imei = Imei::new();
+		if value.len() == 15
+			&& value.bytes().all(|b| b.is_ascii_digit())
+			&& imei.push_str(value).is_ok()
+		{
+			result = Ok(imei);
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+/// Get the AT commands the modem supports (`AT+CLAC`). The list is only
+/// read once each time libmodem starts, and kept. Returns
+/// `Error::BufferFull` if it is longer than `MAX_COMMAND_LIST_LEN`.
+#[cfg(target_arch = "arm")]
+pub fn supported_commands() -> Result<CommandList, Error> {
+	load_supported_commands()?;
+	cortex_m::interrupt::free(|cs| match SUPPORTED_COMMANDS.borrow(cs).borrow().as_ref() {
+		Some((_, list)) => Ok(list.clone()),
+		None => Err(Error::UnrecognisedValue),
+	})
+}
+
+/// Does the modem support this AT command, according to
+/// `supported_commands`? The command can be given with or without its `AT`,
+/// and any parameters are ignored, so `"%CONEVAL"`, `"AT%CONEVAL"` and
+/// `"AT%XMODEMSLEEP=1"` all work.
+#[cfg(target_arch = "arm")]
+pub fn supports(command: &str) -> Result<bool, Error> {
+	load_supported_commands()?;
+	Ok(listed(command) == Some(true))
+}
+
+/// Forget the list `supported_commands` read, so it is read again next time
+/// (e.g. after a firmware update without a restart).
+#[cfg(target_arch = "arm")]
+pub fn clear_supported_commands() {
+	cortex_m::interrupt::free(|cs| {
+		*SUPPORTED_COMMANDS.borrow(cs).borrow_mut() = None;
+	});
+}
+
+/// Start collecting connectivity statistics (`AT%XCONNSTAT=1`). The modem
+/// counts SMS messages and data sent and received until
+/// `stop_connectivity_stats` is called.
+#[cfg(target_arch = "arm")]
+pub fn start_connectivity_stats() -> Result<(), Error> {
+	crate::at::send_at_command("AT%XCONNSTAT=1", |res| {
+		debug!("AT%XCONNSTAT=1 => {:?}", res);
+	})
+}
+
+/// Stop collecting connectivity statistics (`AT%XCONNSTAT=0`).
+#[cfg(target_arch = "arm")]
+pub fn stop_connectivity_stats() -> Result<(), Error> {
+	crate::at::send_at_command("AT%XCONNSTAT=0", |res| {
+		debug!("AT%XCONNSTAT=0 => {:?}", res);
+	})
+}
+
+/// Read the connectivity statistics collected since
+/// `start_connectivity_stats` (`AT%XCONNSTAT?`).
+///
+/// The modem returns an error if collection hasn't been started.
+#[cfg(target_arch = "arm")]
+pub fn get_connectivity_stats() -> Result<ConnectivityStats, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT%XCONNSTAT?", |res| {
+		if let Some(stats) = res.strip_prefix("%XCONNSTAT:").and_then(parse_connstat) {
+			result = Ok(stats);
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+impl FunctionalMode {
+	/// Convert the numeric `<fun>` value used by `AT+CFUN`.
+	pub fn from_u8(value: u8) -> Option<FunctionalMode> {
+		match value {
+			0 => Some(FunctionalMode::PowerOff),
+			1 => Some(FunctionalMode::Normal),
+			2 => Some(FunctionalMode::ReceiveOnly),
+			4 => Some(FunctionalMode::FlightMode),
+			20 => Some(FunctionalMode::LteDeactivated),
+			30 => Some(FunctionalMode::GnssDeactivated),
+			40 => Some(FunctionalMode::UiccDeactivated),
+			44 => Some(FunctionalMode::FlightModeUiccOn),
+			_ => None,
+		}
+	}
+
+	/// Is LTE off (and the modem detached) in this mode?
+	pub fn is_lte_off(self) -> bool {
+		matches!(
+			self,
+			FunctionalMode::PowerOff
+				| FunctionalMode::FlightMode
+				| FunctionalMode::LteDeactivated
+				| FunctionalMode::UiccDeactivated
+				| FunctionalMode::FlightModeUiccOn
+		)
+	}
+}
+
+impl UeMode {
+	/// Convert the numeric `<mode>` value used by `AT+CEMODE`.
+	pub fn from_u8(value: u8) -> Option<UeMode> {
+		match value {
+			0 => Some(UeMode::PsMode2),
+			1 => Some(UeMode::CsPsMode1),
+			2 => Some(UeMode::CsPsMode2),
+			3 => Some(UeMode::PsMode1),
+			_ => None,
+		}
+	}
+}
+
+impl SignalQuality {
+	/// Parse a `+CESQ: <rxlev>,<ber>,<rscp>,<ecno>,<rsrq>,<rsrp>` line.
+	pub fn parse(line: &str) -> Option<SignalQuality> {
+		let fields = line.trim().strip_prefix("+CESQ:")?;
+		let mut fields = fields
+			.split(',')
+			.map(|field| field.trim().parse::<u8>().ok());
+		let rsrq = fields.nth(4)??;
+		let rsrp = fields.next()??;
+		Some(SignalQuality {
+			rsrp_dbm: rsrp_dbm(rsrp),
+			rsrq_db: rsrq_db(rsrq),
+		})
+	}
+}
+
+impl ReleaseAssistance {
+	/// Convert the numeric `<rai_config>` value used by `AT%XRAI`.
+	pub fn from_u8(value: u8) -> Option<ReleaseAssistance> {
+		match value {
+			0 => Some(ReleaseAssistance::Disabled),
+			3 => Some(ReleaseAssistance::OneResponse),
+			4 => Some(ReleaseAssistance::NoResponse),
+			_ => None,
+		}
+	}
+}
+
+impl EnergyEstimate {
+	/// Convert the numeric `<energy_estimate>` value used by `%CONEVAL`.
+	pub fn from_u8(value: u8) -> Option<EnergyEstimate> {
+		match value {
+			5 => Some(EnergyEstimate::Bad),
+			6 => Some(EnergyEstimate::Poor),
+			7 => Some(EnergyEstimate::Normal),
+			8 => Some(EnergyEstimate::Good),
+			9 => Some(EnergyEstimate::Excellent),
+			_ => None,
+		}
+	}
+}
+
+impl Plmn {
+	/// Parse the five or six digits of a numeric PLMN, e.g. `"26201"`.
+	pub fn parse(digits: &str) -> Option<Plmn> {
+		let digits = digits.trim().trim_matches('"');
+		if !(5..=6).contains(&digits.len()) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+			return None;
+		}
+		let (mcc, mnc) = digits.split_at(3);
+		Some(Plmn {
+			mcc: mcc.parse().ok()?,
+			mnc: mnc.parse().ok()?,
+			three_digit_mnc: mnc.len() == 3,
+		})
+	}
+}
+
+impl core::fmt::Display for Plmn {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		if self.three_digit_mnc {
+			write!(f, "{:03}{:03}", self.mcc, self.mnc)
+		} else {
+			write!(f, "{:03}{:02}", self.mcc, self.mnc)
+		}
+	}
+}
+
+impl ConnectionEvaluation {
+	/// Parse a `%CONEVAL` line. Returns `None` if this isn't one, or it
+	/// reports that the evaluation failed.
+	pub fn parse(line: &str) -> Option<ConnectionEvaluation> {
+		let mut fields = line
+			.trim()
+			.strip_prefix("%CONEVAL:")?
+			.split(',')
+			.map(str::trim);
+		if fields.next()? != "0" {
+			return None;
+		}
+		let mut number = || fields.next().and_then(|field| field.parse::<i32>().ok());
+		let rrc_state = number()?;
+		let energy_estimate = EnergyEstimate::from_u8(u8::try_from(number()?).ok()?)?;
+		let rsrp = u8::try_from(number()?).ok()?;
+		let rsrq = u8::try_from(number()?).ok()?;
+		let snr = u8::try_from(number()?).ok()?;
+		let cell_id = u32::from_str_radix(fields.next()?.trim_matches('"'), 16).ok()?;
+		let plmn = Plmn::parse(fields.next()?)?;
+		let mut number = || fields.next().and_then(|field| field.parse::<i32>().ok());
+		Some(ConnectionEvaluation {
+			rrc_connected: rrc_state == 1,
+			energy_estimate,
+			rsrp_dbm: rsrp_dbm(rsrp),
+			rsrq_db: rsrq_db(rsrq),
+			snr_db: snr_db(snr),
+			cell_id,
+			plmn,
+			phys_cell_id: u16::try_from(number()?).ok()?,
+			earfcn: u32::try_from(number()?).ok()?,
+			band: u8::try_from(number()?).ok()?,
+			tau_triggered: number()? == 1,
+			ce_level: u8::try_from(number()?).ok()?,
+			tx_power_dbm: i8::try_from(number()?).ok()?,
+			tx_repetitions: u16::try_from(number()?).ok()?,
+			rx_repetitions: u16::try_from(number()?).ok()?,
+			downlink_pathloss_db: u8::try_from(number()?).ok()?,
+		})
+	}
+}
+
+impl Operator {
+	/// Parse a `+COPS: <mode>[,<format>,<oper>[,<AcT>]]` line. `<oper>` is
+	/// only understood in numeric format (`<format>` 2).
+	pub fn parse(line: &str) -> Option<Operator> {
+		let mut fields = line
+			.trim()
+			.strip_prefix("+COPS:")?
+			.split(',')
+			.map(str::trim);
+		let mode: u8 = fields.next()?.parse().ok()?;
+		let format = fields.next();
+		let plmn = match (format, fields.next()) {
+			(Some("2"), Some(oper)) => Plmn::parse(oper),
+			_ => None,
+		};
+		let access_technology = fields
+			.next()
+			.and_then(|act| act.parse().ok())
+			.and_then(AccessTechnology::from_u8);
+		Some(Operator {
+			automatic: mode == 0,
+			plmn,
+			access_technology,
+		})
+	}
+}
+
+impl RegistrationStatus {
+	/// Convert the numeric `<stat>` value used by `+CEREG`.
+	pub fn from_u8(value: u8) -> Option<RegistrationStatus> {
+		match value {
+			0 => Some(RegistrationStatus::NotRegistered),
+			1 => Some(RegistrationStatus::RegisteredHome),
+			2 => Some(RegistrationStatus::Searching),
+			3 => Some(RegistrationStatus::Denied),
+			4 => Some(RegistrationStatus::Unknown),
+			5 => Some(RegistrationStatus::RegisteredRoaming),
+			90 => Some(RegistrationStatus::UiccFailure),
+			_ => None,
+		}
+	}
+
+	/// Are we registered on a network (home or roaming)?
+	pub fn is_registered(self) -> bool {
+		matches!(
+			self,
+			RegistrationStatus::RegisteredHome | RegistrationStatus::RegisteredRoaming
+		)
+	}
+}
+
+impl AccessTechnology {
+	/// Convert the numeric `<AcT>` value used by `+CEREG`.
+	pub fn from_u8(value: u8) -> Option<AccessTechnology> {
+		match value {
+			7 => Some(AccessTechnology::LteM),
+			9 => Some(AccessTechnology::NbIot),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl FlightModeGuard {
+	/// Put the modem into flight mode, remembering which mode it was in.
+	pub fn new() -> Result<FlightModeGuard, Error> {
+		let previous = get_functional_mode()?;
+		if previous != FunctionalMode::FlightMode {
+			flight_mode()?;
+		}
+		Ok(FlightModeGuard {
+			previous,
+			restored: false,
+		})
+	}
+
+	/// The functional mode the modem was in before we entered flight mode.
+	pub fn previous_mode(&self) -> FunctionalMode {
+		self.previous
+	}
+
+	/// Put the modem back in its previous mode (through `on()`, if that was
+	/// normal mode), and wait for it to register if LTE is active in that
+	/// mode.
+	pub fn restore(mut self) -> Result<(), Error> {
+		self.restored = true;
+		restore_mode(self.previous)
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl Suspended {
+	/// How the modem was put to sleep.
+	pub fn mode(&self) -> SuspendMode {
+		self.mode
+	}
+
+	/// The functional mode the modem was in before it was suspended.
+	pub fn previous_mode(&self) -> FunctionalMode {
+		self.previous
+	}
+}
+
+impl PsmGranted {
+	/// Parse the timers from a level 4 `AT+CEREG?` response: the eighth and
+	/// ninth fields after the prefix (`<n>,<stat>,<tac>,<ci>,<AcT>,
+	/// <cause_type>,<reject_cause>,<Active-Time>,<Periodic-TAU-ext>`), as
+	/// quoted strings of bits.
+	pub fn parse(line: &str) -> Option<PsmGranted> {
+		let mut fields = line.strip_prefix("+CEREG:")?.split(',').skip(7);
+		let active_time = parse_bits(fields.next()?)?;
+		let periodic_tau = parse_bits(fields.next()?)?;
+		Some(PsmGranted {
+			active_time: gpp_timers::decode_t3324(active_time),
+			periodic_tau: gpp_timers::decode_t3412(periodic_tau),
+		})
+	}
+}
+
+impl EdrxAccessTechnology {
+	/// Convert the `<AcT-type>` value used by `AT+CEDRXS`.
+	pub fn from_u8(value: u8) -> Option<EdrxAccessTechnology> {
+		match value {
+			4 => Some(EdrxAccessTechnology::LteM),
+			5 => Some(EdrxAccessTechnology::NbIot),
+			_ => None,
+		}
+	}
+}
+
+impl EdrxGranted {
+	/// Parse a `+CEDRXP` URC or `AT+CEDRXRDP` response: `<AcT-type>[,
+	/// "<Requested_eDRX>","<NW-provided_eDRX>","<Paging_time_window>"]`.
+	/// An `<AcT-type>` of 0 (eDRX not in use) gives `None`.
+	pub fn parse(line: &str) -> Option<EdrxGranted> {
+		let line = line.trim();
+		let fields = line
+			.strip_prefix("+CEDRXP:")
+			.or_else(|| line.strip_prefix("+CEDRXRDP:"))?;
+		let mut fields = fields.split(',');
+		let act = EdrxAccessTechnology::from_u8(fields.next()?.trim().parse().ok()?)?;
+		let requested_cycle =
+			parse_bits(fields.next()?).and_then(|bits| gpp_timers::decode_edrx(act, bits));
+		let cycle = gpp_timers::decode_edrx(act, parse_bits(fields.next()?)?)?;
+		let paging_time_window = gpp_timers::decode_ptw(act, parse_bits(fields.next()?)?);
+		Some(EdrxGranted {
+			access_technology: act,
+			requested_cycle,
+			cycle,
+			paging_time_window,
+		})
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl Drop for FlightModeGuard {
+	fn drop(&mut self) {
+		if !self.restored {
+			if let Err(e) = restore_mode(self.previous) {
+				log::warn!("Failed to leave flight mode: {:?}", e);
+			}
+		}
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Handle a line the modem sent while `wait_for_lte` waits, and say
+/// whether it reports that we're registered, on 1 (home) or 5 (roaming).
+#[cfg(target_arch = "arm")]
+fn attach_report(line: &str) -> bool {
+	let line = line.trim();
+	debug!("RX {:?}", line);
+	match Urc::parse(line) {
+		Some(Urc::Registration(registration)) => {
+			record_registration(&registration);
+			registration.status.is_registered()
+		}
+		Some(Urc::NetworkError(error)) => {
+			record_network_error(&error);
+			false
+		}
+		Some(Urc::ModemEvent(ModemEvent::PrachCeLevel(level))) => {
+			record_ce_level(level);
+			false
+		}
+		_ => false,
+	}
+}
+
+/// Get the registration status from a `+CEREG` line.
+///
+/// The response to `AT+CEREG?` starts with the subscription level
+/// (`+CEREG: <n>,<stat>,...`) but the unsolicited version does not (`+CEREG:
+/// <stat>,...`), so we need to be told which sort of line this is.
+#[cfg(any(target_arch = "arm", feature = "std", test))]
+pub(crate) fn parse_cereg(line: &str, read_response: bool) -> Option<RegistrationStatus> {
+	let mut fields = line.strip_prefix("+CEREG:")?.split(',');
+	if read_response {
+		fields.next()?;
+	}
+	let stat = fields.next()?.trim().parse().ok()?;
+	RegistrationStatus::from_u8(stat)
+}
+
+/// Remember the status (and access technology, if given) from a `+CEREG`
+/// report. If we were registered and now aren't, the open sockets are
+/// marked as having lost their link.
+#[cfg(target_arch = "arm")]
+pub(crate) fn record_registration(registration: &Registration) {
+	let previous = LAST_REGISTRATION_STATUS.swap(registration.status as u8, Ordering::Relaxed);
+	let previous = RegistrationStatus::from_u8(previous);
+	let was_registered = matches!(previous, Some(status) if status.is_registered());
+	if was_registered && !registration.status.is_registered() {
+		crate::raw::mark_link_lost();
+	}
+	if let Some(act) = registration.access_technology {
+		LAST_ACCESS_TECHNOLOGY.store(act as u8, Ordering::Relaxed);
+	}
+}
+
+/// Is this command in the `AT+CLAC` list? Unlike `supports`, this never asks
+/// the modem: it returns `None` if the list hasn't been read since libmodem
+/// started.
+#[cfg(target_arch = "arm")]
+pub(crate) fn listed(command: &str) -> Option<bool> {
+	let command = command.trim();
+	let command = match command.get(0..2) {
+		Some(prefix) if prefix.eq_ignore_ascii_case("AT") => &command[2..],
+		_ => command,
+	};
+	let name = command.split(['=', '?']).next().unwrap_or(command);
+	cortex_m::interrupt::free(|cs| match SUPPORTED_COMMANDS.borrow(cs).borrow().as_ref() {
+		Some((starts, list)) if *starts == crate::starts() => {
+			Some(list.lines().any(|line| line.eq_ignore_ascii_case(name)))
+		}
+		_ => None,
+	})
+}
+
+/// Read the `AT+CLAC` list, unless we have it already.
+#[cfg(target_arch = "arm")]
+fn load_supported_commands() -> Result<(), Error> {
+	let starts = crate::starts();
+	let current = cortex_m::interrupt::free(|cs| {
+		let cached = SUPPORTED_COMMANDS.borrow(cs).borrow();
+		matches!(cached.as_ref(), Some((s, _)) if *s == starts)
+	});
+	if current {
+		return Ok(());
+	}
+	let mut list = CommandList::new();
+	let mut result = Ok(());
+	crate::at::send_at_command("AT+CLAC", |res| {
+		let name = match res.trim().strip_prefix("AT") {
+			Some(name) if !name.is_empty() => name,
+			_ => return,
+		};
+		if list.push_str(name).and_then(|()| list.push('\n')).is_err() {
+			result = Err(Error::BufferFull);
+		}
+	})?;
+	result?;
+	debug!("AT+CLAC => {} bytes", list.len());
+	cortex_m::interrupt::free(|cs| {
+		*SUPPORTED_COMMANDS.borrow(cs).borrow_mut() = Some((starts, list));
+	});
+	Ok(())
+}
+
+/// Remember the coverage enhancement level from a `%MDMEV` event.
+#[cfg(target_arch = "arm")]
+pub(crate) fn record_ce_level(level: u8) {
+	debug!("Coverage enhancement level {}", level);
+	LAST_CE_LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// Remember the cause from a `+CNEC` report.
+#[cfg(target_arch = "arm")]
+pub(crate) fn record_network_error(error: &NetworkError) {
+	debug!("Network error {:?}", error);
+	cortex_m::interrupt::free(|cs| {
+		*LAST_NETWORK_ERROR.borrow(cs).borrow_mut() = Some(*error);
+	});
+}
+
+/// Get the runtime band lock mask (from `AT%XBANDLOCK?`), without leading
+/// zeros. It's empty if there's no lock.
+#[cfg(target_arch = "arm")]
+fn band_lock() -> Result<heapless::String<{ crate::sim::MAX_BAND as usize }>, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT%XBANDLOCK?", |res| {
+		// `%XBANDLOCK: <permanent mask>,<runtime mask>`
+		if let Some(fields) = res.strip_prefix("%XBANDLOCK:") {
+			if let Some(runtime) = fields.split(',').nth(1) {
+				let mask = runtime.trim().trim_matches('"').trim_start_matches('0');
+				let mut value = heapless::String::new();
+				if value.push_str(mask).is_ok() {
+					result = Ok(value);
+				}
+			}
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+/// Get the PSM timers (periodic TAU, then active time) last requested with
+/// `AT+CPSMS`, or `None` if PSM isn't requested.
+#[cfg(target_arch = "arm")]
+fn requested_psm() -> Result<Option<(u8, u8)>, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT+CPSMS?", |res| {
+		// `+CPSMS: <mode>,[<RAU>],[<GPRS-READY>],[<TAU>],[<Active-Time>]`
+		if let Some(fields) = res.strip_prefix("+CPSMS:") {
+			let mut fields = fields.split(',');
+			match fields.next().map(str::trim) {
+				Some("0") => result = Ok(None),
+				Some("1") => {
+					let mut timers = fields.skip(2).map(parse_bits);
+					if let (Some(Some(tau)), Some(Some(active))) = (timers.next(), timers.next()) {
+						result = Ok(Some((tau, active)));
+					}
+				}
+				_ => {}
+			}
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+/// Get the eDRX cycles last requested with `AT+CEDRXS`, for each access
+/// technology they are requested for.
+#[cfg(target_arch = "arm")]
+fn requested_edrx() -> Result<heapless::Vec<(EdrxAccessTechnology, Duration), 2>, Error> {
+	let mut requested = heapless::Vec::new();
+	crate::at::send_at_command("AT+CEDRXS?", |res| {
+		// `+CEDRXS: <AcT>,"<value>"`, once for each access technology
+		if let Some(fields) = res.strip_prefix("+CEDRXS:") {
+			let mut fields = fields.split(',');
+			let act = fields
+				.next()
+				.and_then(|act| act.trim().parse::<u8>().ok())
+				.and_then(EdrxAccessTechnology::from_u8);
+			if let Some(act) = act {
+				let cycle = fields
+					.next()
+					.and_then(parse_bits)
+					.and_then(|bits| gpp_timers::decode_edrx(act, bits));
+				if let Some(cycle) = cycle {
+					let _ = requested.push((act, cycle));
+				}
+			}
+		}
+		debug!("AT+CEDRXS? => {:?}", res);
+	})?;
+	Ok(requested)
+}
+
+/// Send a read command whose answer is `<prefix> <number>`, and get the
+/// number.
+#[cfg(target_arch = "arm")]
+fn read_number(command: &str, prefix: &str) -> Result<u8, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command(command, |res| {
+		if let Some(value) = res.strip_prefix(prefix) {
+			if let Some(Ok(value)) = value.split(',').next().map(|v| v.trim().parse()) {
+				result = Ok(value);
+			}
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+/// Is this what was last requested with `AT+CEDRXS` (and `AT%XPTW`)? With
+/// `None`, that's eDRX requested for neither access technology.
+#[cfg(target_arch = "arm")]
+fn edrx_requested(config: Option<EdrxConfig>) -> Result<bool, Error> {
+	let mut requested = false;
+	let mut any = false;
+	crate::at::send_at_command("AT+CEDRXS?", |res| {
+		// `+CEDRXS: <AcT>,"<value>"`, once for each access technology
+		if let Some(fields) = res.strip_prefix("+CEDRXS:") {
+			let mut fields = fields.split(',');
+			let act = fields.next().and_then(|act| act.trim().parse::<u8>().ok());
+			let value = fields.next().and_then(parse_bits);
+			if let (Some(act), Some(value)) = (act, value) {
+				any = true;
+				if let Some(config) = config {
+					let act_wanted = config.access_technology;
+					let wanted = gpp_timers::encode_edrx(act_wanted, config.cycle, Rounding::Up);
+					requested |= act == act_wanted as u8 && value == wanted.bits;
+				}
+			}
+		}
+		debug!("AT+CEDRXS? => {:?}", res);
+	})?;
+	let config = match config {
+		Some(config) => config,
+		None => return Ok(!any),
+	};
+	let window = match config.paging_time_window {
+		Some(window) if requested => window,
+		_ => return Ok(requested),
+	};
+	let act = config.access_technology;
+	let wanted = gpp_timers::encode_ptw(act, window, Rounding::Up).bits;
+	let mut ptw_requested = false;
+	crate::at::send_at_command("AT%XPTW?", |res| {
+		// `%XPTW: <AcT>,"<window>"`, once for each access technology
+		if let Some(fields) = res.strip_prefix("%XPTW:") {
+			let mut fields = fields.split(',');
+			let this_act = fields.next().and_then(|act| act.trim().parse::<u8>().ok());
+			let value = fields.next().and_then(parse_bits);
+			ptw_requested |= this_act == Some(act as u8) && value == Some(wanted);
+		}
+		debug!("AT%XPTW? => {:?}", res);
+	})?;
+	Ok(ptw_requested)
+}
+
+/// If this `+CEREG` line has an `<AcT>` field, remember it. The field comes
+/// after `<stat>`, `<tac>` and `<ci>`.
+#[cfg(target_arch = "arm")]
+fn record_access_technology(line: &str, read_response: bool) {
+	let act = line.strip_prefix("+CEREG:").and_then(|fields| {
+		let skip = if read_response { 4 } else { 3 };
+		fields.split(',').nth(skip)?.trim().parse().ok()
+	});
+	if let Some(act) = act.and_then(AccessTechnology::from_u8) {
+		LAST_ACCESS_TECHNOLOGY.store(act as u8, Ordering::Relaxed);
+	}
+}
+
+/// Parse the fields of a `%XCONNSTAT` response, which are `<SMS Tx>,<SMS
+/// Rx>,<Data Tx>,<Data Rx>,<Packet max>,<Packet average>`.
+#[cfg(any(target_arch = "arm", feature = "std", test))]
+pub(crate) fn parse_connstat(fields: &str) -> Option<ConnectivityStats> {
+	let mut values = fields.split(',').map(|value| value.trim().parse::<u32>());
+	let mut next = || values.next()?.ok();
+	Some(ConnectivityStats {
+		sms_tx: next()?,
+		sms_rx: next()?,
+		data_tx_kib: next()?,
+		data_rx_kib: next()?,
+		packet_max: next()?,
+		packet_average: next()?,
+	})
+}
+
+/// Parse the `<report>` from a `+CEER` response.
+///
+/// The format isn't standardised, but looks something like `EMM cause 15` or
+/// `"ESM cause 33"`. A bare number (other than zero) is reported as
+/// `ErrorCause::Other`.
+#[cfg(any(target_arch = "arm", feature = "std", test))]
+pub(crate) fn parse_error_cause(report: &str) -> Option<ErrorCause> {
+	let report = report.trim().trim_matches('"');
+	let digits_start = report.find(|c: char| c.is_ascii_digit())?;
+	let digits = &report[digits_start..];
+	let digits_end = digits
+		.find(|c: char| !c.is_ascii_digit())
+		.unwrap_or(digits.len());
+	let code: u16 = digits[0..digits_end].parse().ok()?;
+	let prefix = &report[0..digits_start];
+	if prefix.contains("EMM") {
+		Some(ErrorCause::Emm(code))
+	} else if prefix.contains("ESM") {
+		Some(ErrorCause::Esm(code))
+	} else if code != 0 {
+		Some(ErrorCause::Other(code))
+	} else {
+		None
+	}
+}
+
+/// Convert an `<rsrp>` index (`AT+CESQ`, `%CONEVAL`) to dBm. 255 means
+/// unknown.
+fn rsrp_dbm(index: u8) -> Option<i16> {
+	if index <= 97 {
+		Some(i16::from(index) - 140)
+	} else {
+		None
+	}
+}
+
+/// Convert an `<rsrq>` index (`AT+CESQ`, `%CONEVAL`) to dB. 255 means
+/// unknown.
+fn rsrq_db(index: u8) -> Option<f32> {
+	if index <= 34 {
+		Some(f32::from(index) * 0.5 - 20.0)
+	} else {
+		None
+	}
+}
+
+/// Convert an `<snr>` index (`%CONEVAL`) to dB. 127 means unknown.
+fn snr_db(index: u8) -> Option<i8> {
+	if index <= 49 {
+		Some(index as i8 - 24)
+	} else {
+		None
+	}
+}
+
+/// Send a URC subscription command, like `AT+CSCON=1`.
+#[cfg(target_arch = "arm")]
+fn send_subscription(command: &str, level: u8) -> Result<(), Error> {
+	let mut at_command: heapless::String<16> = heapless::String::new();
+	let _ = write!(at_command, "{}={}", command, level);
+	crate::at::send_at_command(&at_command, |res| {
+		debug!("{:?} => {:?}", at_command, res);
+	})
+}
+
+/// Everything `on()` does before it activates LTE: select the antenna
+/// configuration, send the startup commands and apply the SIM profile.
+#[cfg(target_arch = "arm")]
+fn prepare_lte() -> Result<(), Error> {
+	if let Some(config) = antenna_config() {
+		select_antenna(config)?;
+	}
+	for command in startup_commands() {
+		debug!("Sending startup command {:?}", command);
+		crate::at::send_at_command(command, |_| {})?;
+	}
+	if !crate::sim::profiles().is_empty() {
+		crate::sim::configure()?;
+	}
+	Ok(())
+}
+
+/// Put the modem back into the given mode, waiting for it to register if
+/// LTE is active in that mode. Modes with LTE active go through `on()`, or
+/// the same preparation, so nothing it sets up is lost.
+#[cfg(target_arch = "arm")]
+fn restore_mode(mode: FunctionalMode) -> Result<(), Error> {
+	debug!("Restoring functional mode {:?}", mode);
+	match mode {
+		FunctionalMode::FlightMode => return Ok(()),
+		FunctionalMode::Normal => on()?,
+		FunctionalMode::ReceiveOnly | FunctionalMode::GnssDeactivated => {
+			prepare_lte()?;
+			set_functional_mode(mode)?;
+		}
+		mode => set_functional_mode(mode)?,
+	}
+	if matches!(
+		mode,
+		FunctionalMode::Normal | FunctionalMode::GnssDeactivated
+	) {
+		wait_for_lte()?;
+	}
+	Ok(())
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn signal_quality() {
+		assert_eq!(
+			SignalQuality::parse("+CESQ: 99,99,255,255,31,62"),
+			Some(SignalQuality {
+				rsrp_dbm: Some(-78),
+				rsrq_db: Some(-4.5),
+			})
+		);
+		// Out of coverage
+		assert_eq!(
+			SignalQuality::parse("+CESQ: 99,99,255,255,255,255\r\n"),
+			Some(SignalQuality::default())
+		);
+		assert_eq!(SignalQuality::parse("+CESQ: 99,99,255,255,31"), None);
+		assert_eq!(SignalQuality::parse("+CEREG: 1"), None);
+	}
+
+	#[test]
+	fn connection_evaluation() {
+		let line = "%CONEVAL: 0,1,5,8,2,14,\"011B0780\",\"26295\",7,1575,3,1,1,23,16,32,130";
+		assert_eq!(
+			ConnectionEvaluation::parse(line),
+			Some(ConnectionEvaluation {
+				rrc_connected: true,
+				energy_estimate: EnergyEstimate::Bad,
+				rsrp_dbm: Some(-132),
+				rsrq_db: Some(-19.0),
+				snr_db: Some(-10),
+				cell_id: 0x011B_0780,
+				plmn: Plmn {
+					mcc: 262,
+					mnc: 95,
+					three_digit_mnc: false,
+				},
+				phys_cell_id: 7,
+				earfcn: 1575,
+				band: 3,
+				tau_triggered: true,
+				ce_level: 1,
+				tx_power_dbm: 23,
+				tx_repetitions: 16,
+				rx_repetitions: 32,
+				downlink_pathloss_db: 130,
+			})
+		);
+		// The evaluation failed (e.g. because we aren't registered)
+		assert_eq!(ConnectionEvaluation::parse("%CONEVAL: 1"), None);
+		assert_eq!(
+			ConnectionEvaluation::parse("%CONEVAL: 0,1,5,8,2,14,\"011B0780\",\"26295\",7"),
+			None
+		);
+	}
+
+	#[test]
+	fn operator() {
+		assert_eq!(
+			Operator::parse("+COPS: 0,2,\"26201\",7"),
+			Some(Operator {
+				automatic: true,
+				plmn: Some(Plmn {
+					mcc: 262,
+					mnc: 1,
+					three_digit_mnc: false,
+				}),
+				access_technology: Some(AccessTechnology::LteM),
+			})
+		);
+		assert_eq!(
+			Operator::parse("+COPS: 1,2,\"310410\",9"),
+			Some(Operator {
+				automatic: false,
+				plmn: Some(Plmn {
+					mcc: 310,
+					mnc: 410,
+					three_digit_mnc: true,
+				}),
+				access_technology: Some(AccessTechnology::NbIot),
+			})
+		);
+		// Only the numeric format is understood
+		assert_eq!(
+			Operator::parse("+COPS: 0,0,\"Telekom.de\",7"),
+			Some(Operator {
+				automatic: true,
+				plmn: None,
+				access_technology: Some(AccessTechnology::LteM),
+			})
+		);
+		// No operator selected yet
+		assert_eq!(
+			Operator::parse("+COPS: 0"),
+			Some(Operator {
+				automatic: true,
+				plmn: None,
+				access_technology: None,
+			})
+		);
+		assert_eq!(Operator::parse("+COPS: x"), None);
+	}
+
+	#[test]
+	fn plmn_round_trips() {
+		for &digits in ["26201", "310410", "00101"].iter() {
+			let plmn = Plmn::parse(digits);
+			assert!(plmn.is_some(), "{}", digits);
+			if let Some(plmn) = plmn {
+				let mut text: heapless::String<8> = heapless::String::new();
+				assert!(core::fmt::Write::write_fmt(&mut text, format_args!("{}", plmn)).is_ok());
+				assert_eq!(text.as_str(), digits);
+			}
+		}
+		assert_eq!(Plmn::parse("2620"), None);
+		assert_eq!(Plmn::parse("26A01"), None);
+	}
+
+	#[test]
+	fn registration_read_response() {
+		assert_eq!(
+			parse_cereg("+CEREG: 5,1,\"0140\",\"0107E500\",7", true),
+			Some(RegistrationStatus::RegisteredHome)
+		);
+		assert_eq!(
+			parse_cereg("+CEREG: 0,4", true),
+			Some(RegistrationStatus::Unknown)
+		);
+		assert_eq!(parse_cereg("+CEREG: 5", true), None);
+	}
+
+	#[test]
+	fn registration_notification() {
+		assert_eq!(
+			parse_cereg("+CEREG: 2,\"76C1\",\"0102DA04\",7", false),
+			Some(RegistrationStatus::Searching)
+		);
+		assert_eq!(
+			parse_cereg(
+				"+CEREG: 5,\"4321\",\"00ABC123\",9,,,\"11100000\",\"11100000\"",
+				false
+			),
+			Some(RegistrationStatus::RegisteredRoaming)
+		);
+		assert_eq!(
+			parse_cereg("+CEREG: 90", false),
+			Some(RegistrationStatus::UiccFailure)
+		);
+		assert_eq!(parse_cereg("+CEREG: 7", false), None);
+		assert_eq!(parse_cereg("+CESQ: 1", false), None);
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
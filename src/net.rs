@@ -0,0 +1,1047 @@
+//! # IP address types for nrfxlib
+//!
+//! `core::net` isn't available on the toolchains we support, so this module
+//! provides small IPv4/IPv6 address types which can be parsed from, and
+//! formatted as, the text the modem uses in AT responses (e.g. `AT+CGPADDR`
+//! or `AT+CGCONTRDP`).
+//!
+//! Parsing copes with the modem's quirks: addresses may be wrapped in quotes
+//! or `[]` brackets, may carry a `%` zone/interface suffix (which is dropped),
+//! and IPv6 addresses may be in the 3GPP "16 dotted decimals" form
+//! (TS 27.007) as well as the usual colon-separated hex form.
+//!
+//! There is also `udp_latency_probe`, for measuring round-trip times,
+//! `health_check`, for seeing whether a server is reachable, the random
+//! local ports used by `TcpSocket::bind_random` and `UdpSocket::bind_random`,
+//! and `close_all`, for making sure no sockets are left open before the
+//! modem sleeps or shuts down.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+use crate::raw::{htons, lookup_ipv4, SocketRecord, SocketType};
+#[cfg(target_arch = "arm")]
+use crate::tcp::TcpSocket;
+#[cfg(target_arch = "arm")]
+use crate::tls::{PeerVerification, TlsSocket, Version};
+use crate::Error;
+#[cfg(target_arch = "arm")]
+use crate::{sys, udp::UdpSocket, NrfSockAddrIn, PollEntry, PollFlags};
+#[cfg(target_arch = "arm")]
+use core::convert::TryFrom;
+use core::fmt;
+use core::str::FromStr;
+#[cfg(target_arch = "arm")]
+use core::sync::atomic::{AtomicU32, Ordering};
+#[cfg(target_arch = "arm")]
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// An IPv4 address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Ipv4Addr([u8; 4]);
+
+/// An IPv6 address. Any zone/scope is not stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Ipv6Addr([u8; 16]);
+
+/// Either an IPv4 or an IPv6 address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum IpAddr {
+	/// An IPv4 address
+	V4(Ipv4Addr),
+	/// An IPv6 address
+	V6(Ipv6Addr),
+}
+
+/// An IP address family, e.g. to prefer when connecting to a hostname which
+/// has both.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AddressFamily {
+	/// IPv4, from DNS A records
+	Ipv4,
+	/// IPv6, from DNS AAAA records
+	Ipv6,
+}
+
+/// An IP address and a port number.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SocketAddr {
+	ip: IpAddr,
+	port: u16,
+}
+
+/// The results of `udp_latency_probe`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct LatencyReport {
+	/// How many probes were sent
+	pub sent: u32,
+	/// How many probes came back in time
+	pub received: u32,
+	/// The shortest round-trip time, in milliseconds
+	pub min_rtt_ms: Option<u32>,
+	/// The mean round-trip time, in milliseconds
+	pub avg_rtt_ms: Option<u32>,
+	/// The longest round-trip time, in milliseconds
+	pub max_rtt_ms: Option<u32>,
+}
+
+/// What `health_check` should check.
+#[cfg(target_arch = "arm")]
+#[derive(Debug, Copy, Clone)]
+pub struct HealthCheckConfig<'a> {
+	/// The server to check
+	pub hostname: &'a str,
+	/// The HTTPS port (e.g. 443). The TCP connect goes to the same port.
+	pub port: u16,
+	/// The path to `GET` (e.g. `/health`)
+	pub path: &'a str,
+	/// The security tags holding the credentials to use, as for
+	/// `TlsSocket::new`
+	pub security_tags: &'a [u32],
+	/// Whether to verify the server's certificate
+	pub peer_verify: PeerVerification,
+	/// A millisecond counter, for the timings
+	pub now_ms: fn() -> u32,
+}
+
+/// How one step of a health check went.
+#[derive(Debug, Clone)]
+pub struct StepReport {
+	/// Whether it worked
+	pub result: Result<(), Error>,
+	/// How long it took, in milliseconds
+	pub duration_ms: u32,
+}
+
+/// The results of `health_check`. A step is `None` if it wasn't tried,
+/// because an earlier step failed.
+#[derive(Debug, Clone, Default)]
+pub struct HealthReport {
+	/// Looking up the hostname
+	pub dns: Option<StepReport>,
+	/// Making (and closing) a plain TCP connection
+	pub tcp_connect: Option<StepReport>,
+	/// Connecting with TLS, sending a `GET` and reading the status line
+	pub https_get: Option<StepReport>,
+	/// The HTTP status code the server sent, if we got that far
+	pub http_status: Option<u16>,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// A latency probe holds a 32-bit sequence number and a 32-bit timestamp.
+#[cfg(target_arch = "arm")]
+const PROBE_LEN: usize = 8;
+
+/// The lowest ephemeral port - the start of the dynamic range in RFC 6335.
+pub const EPHEMERAL_PORT_MIN: u16 = 49152;
+
+/// Used when seeded with zero, which xorshift can't start from.
+#[cfg(target_arch = "arm")]
+const DEFAULT_PORT_SEED: u32 = 0x9E37_79B9;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The state of the random number generator for local ports.
+#[cfg(target_arch = "arm")]
+static PORT_STATE: AtomicU32 = AtomicU32::new(DEFAULT_PORT_SEED);
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl Ipv4Addr {
+	/// The unspecified address, `0.0.0.0`.
+	pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr([0, 0, 0, 0]);
+
+	/// Create an address from its four octets, e.g. `Ipv4Addr::new(10, 0, 0, 1)`.
+	pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Ipv4Addr {
+		Ipv4Addr([a, b, c, d])
+	}
+
+	/// Get the four octets of this address, most significant first.
+	pub const fn octets(&self) -> [u8; 4] {
+		self.0
+	}
+
+	/// Is this `0.0.0.0`?
+	pub fn is_unspecified(&self) -> bool {
+		self.0 == [0, 0, 0, 0]
+	}
+}
+
+impl FromStr for Ipv4Addr {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Ipv4Addr, Error> {
+		let mut octets = [0u8; 4];
+		if parse_dotted(strip_quirks(s), &mut octets) {
+			Ok(Ipv4Addr(octets))
+		} else {
+			Err(Error::BadDataFormat)
+		}
+	}
+}
+
+impl fmt::Display for Ipv4Addr {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+	}
+}
+
+impl Ipv6Addr {
+	/// The unspecified address, `::`.
+	pub const UNSPECIFIED: Ipv6Addr = Ipv6Addr([0; 16]);
+
+	/// Create an address from its sixteen octets, most significant first.
+	pub const fn from_octets(octets: [u8; 16]) -> Ipv6Addr {
+		Ipv6Addr(octets)
+	}
+
+	/// Create an address from its eight 16-bit segments, most significant
+	/// first.
+	pub fn from_segments(segments: [u16; 8]) -> Ipv6Addr {
+		let mut octets = [0u8; 16];
+		for (chunk, segment) in octets.chunks_mut(2).zip(segments.iter()) {
+			chunk.copy_from_slice(&segment.to_be_bytes());
+		}
+		Ipv6Addr(octets)
+	}
+
+	/// Get the sixteen octets of this address, most significant first.
+	pub const fn octets(&self) -> [u8; 16] {
+		self.0
+	}
+
+	/// Get the eight 16-bit segments of this address, most significant first.
+	pub fn segments(&self) -> [u16; 8] {
+		let mut segments = [0u16; 8];
+		for (segment, chunk) in segments.iter_mut().zip(self.0.chunks_exact(2)) {
+			if let [high, low] = *chunk {
+				*segment = u16::from_be_bytes([high, low]);
+			}
+		}
+		segments
+	}
+
+	/// Is this `::`?
+	pub fn is_unspecified(&self) -> bool {
+		self.0 == [0; 16]
+	}
+
+	/// Is this a link-local (`fe80::/10`) address? The modem reports one of
+	/// these before the IPv6 link is fully up.
+	pub fn is_link_local(&self) -> bool {
+		self.0[0] == 0xFE && (self.0[1] & 0xC0) == 0x80
+	}
+
+	/// If this is an IPv4-mapped address (`::ffff:a.b.c.d`), get the IPv4
+	/// address.
+	pub fn to_ipv4_mapped(&self) -> Option<Ipv4Addr> {
+		if self.0[0..10] == [0; 10] && self.0[10] == 0xFF && self.0[11] == 0xFF {
+			Some(Ipv4Addr::new(
+				self.0[12], self.0[13], self.0[14], self.0[15],
+			))
+		} else {
+			None
+		}
+	}
+}
+
+impl FromStr for Ipv6Addr {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Ipv6Addr, Error> {
+		let s = strip_quirks(s);
+		let mut octets = [0u8; 16];
+		let ok = if s.contains(':') {
+			parse_colon_hex(s, &mut octets)
+		} else {
+			// The 3GPP form, e.g. `32.1.13.184.0.0.0.0.0.0.0.0.0.0.0.1`
+			parse_dotted(s, &mut octets)
+		};
+		if ok {
+			Ok(Ipv6Addr(octets))
+		} else {
+			Err(Error::BadDataFormat)
+		}
+	}
+}
+
+impl fmt::Display for Ipv6Addr {
+	/// Formats the address as recommended by RFC 5952 - lower case, with the
+	/// longest run of zero segments replaced by `::`.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if let Some(ipv4) = self.to_ipv4_mapped() {
+			return write!(f, "::ffff:{}", ipv4);
+		}
+		let segments = self.segments();
+		// Find the longest run of two or more zero segments
+		let mut best = (0, 0);
+		let mut current = (0, 0);
+		for (idx, segment) in segments.iter().enumerate() {
+			if *segment == 0 {
+				if current.1 == 0 {
+					current.0 = idx;
+				}
+				current.1 += 1;
+				if current.1 > best.1 {
+					best = current;
+				}
+			} else {
+				current.1 = 0;
+			}
+		}
+		if best.1 < 2 {
+			best = (segments.len(), 0);
+		}
+		for (idx, segment) in segments.iter().enumerate() {
+			if idx == best.0 {
+				write!(f, "::")?;
+			} else if idx > best.0 && idx < best.0 + best.1 {
+				// Inside the compressed run
+			} else {
+				if idx != 0 && idx != best.0 + best.1 {
+					write!(f, ":")?;
+				}
+				write!(f, "{:x}", segment)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+impl IpAddr {
+	/// Is this an IPv4 address?
+	pub fn is_ipv4(&self) -> bool {
+		matches!(self, IpAddr::V4(_))
+	}
+
+	/// Is this an IPv6 address?
+	pub fn is_ipv6(&self) -> bool {
+		matches!(self, IpAddr::V6(_))
+	}
+}
+
+impl FromStr for IpAddr {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<IpAddr, Error> {
+		if let Ok(ipv4) = s.parse() {
+			Ok(IpAddr::V4(ipv4))
+		} else {
+			s.parse().map(IpAddr::V6)
+		}
+	}
+}
+
+impl fmt::Display for IpAddr {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			IpAddr::V4(ip) => ip.fmt(f),
+			IpAddr::V6(ip) => ip.fmt(f),
+		}
+	}
+}
+
+impl From<Ipv4Addr> for IpAddr {
+	fn from(ip: Ipv4Addr) -> IpAddr {
+		IpAddr::V4(ip)
+	}
+}
+
+impl From<Ipv6Addr> for IpAddr {
+	fn from(ip: Ipv6Addr) -> IpAddr {
+		IpAddr::V6(ip)
+	}
+}
+
+impl SocketAddr {
+	/// Create a new socket address.
+	pub fn new(ip: IpAddr, port: u16) -> SocketAddr {
+		SocketAddr { ip, port }
+	}
+
+	/// Get the IP address.
+	pub fn ip(&self) -> IpAddr {
+		self.ip
+	}
+
+	/// Get the port number.
+	pub fn port(&self) -> u16 {
+		self.port
+	}
+}
+
+impl FromStr for SocketAddr {
+	type Err = Error;
+
+	/// Parse `a.b.c.d:port` or `[v6]:port`.
+	fn from_str(s: &str) -> Result<SocketAddr, Error> {
+		let s = s.trim().trim_matches('"');
+		let (ip, port) = if let Some(rest) = s.strip_prefix('[') {
+			let end = rest.find(']').ok_or(Error::BadDataFormat)?;
+			let port = rest[end + 1..]
+				.strip_prefix(':')
+				.ok_or(Error::BadDataFormat)?;
+			(IpAddr::V6(rest[0..end].parse()?), port)
+		} else {
+			let split = s.rfind(':').ok_or(Error::BadDataFormat)?;
+			(IpAddr::V4(s[0..split].parse()?), &s[split + 1..])
+		};
+		let port = parse_decimal(port).ok_or(Error::BadDataFormat)?;
+		Ok(SocketAddr::new(ip, port))
+	}
+}
+
+impl fmt::Display for SocketAddr {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.ip {
+			IpAddr::V4(ip) => write!(f, "{}:{}", ip, self.port),
+			IpAddr::V6(ip) => write!(f, "[{}]:{}", ip, self.port),
+		}
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl From<&NrfSockAddrIn> for SocketAddr {
+	fn from(addr: &NrfSockAddrIn) -> SocketAddr {
+		// `s_addr` holds the octets in network order
+		let octets = addr.0.sin_addr.s_addr.to_ne_bytes();
+		SocketAddr::new(IpAddr::V4(Ipv4Addr(octets)), u16::from_be(addr.0.sin_port))
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl TryFrom<SocketAddr> for NrfSockAddrIn {
+	type Error = Error;
+
+	/// Convert an IPv4 socket address to a Nordic one. Returns
+	/// `Error::BadDataFormat` for an IPv6 address.
+	fn try_from(addr: SocketAddr) -> Result<NrfSockAddrIn, Error> {
+		match addr.ip {
+			IpAddr::V4(ip) => Ok(NrfSockAddrIn(sys::nrf_sockaddr_in {
+				sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
+				sin_family: sys::NRF_AF_INET as i32,
+				sin_port: htons(addr.port),
+				sin_addr: sys::nrf_in_addr {
+					s_addr: u32::from_ne_bytes(ip.0),
+				},
+			})),
+			IpAddr::V6(_) => Err(Error::BadDataFormat),
+		}
+	}
+}
+
+/// Measure the round-trip time to a UDP echo server.
+///
+/// Sends `count` probes, one at a time, each holding a sequence number and
+/// the time it was sent (from `now_ms`, a millisecond counter). Waits up to
+/// `timeout_ms` for each to be echoed back before counting it as lost. Late
+/// replies to earlier probes are ignored.
+///
+/// This is useful during site surveys, to see how slow (e.g.) NB-IoT really
+/// is before choosing application timeouts.
+#[cfg(target_arch = "arm")]
+pub fn udp_latency_probe(
+	hostname: &str,
+	port: u16,
+	count: u32,
+	timeout_ms: u16,
+	now_ms: fn() -> u32,
+) -> Result<LatencyReport, Error> {
+	let socket = UdpSocket::new()?;
+	socket.connect(hostname, port)?;
+	let mut report = LatencyReport::default();
+	let mut total_rtt_ms: u64 = 0;
+	for sequence in 0..count {
+		let sent_at = now_ms();
+		let mut probe = [0u8; PROBE_LEN];
+		probe[0..4].copy_from_slice(&sequence.to_be_bytes());
+		probe[4..8].copy_from_slice(&sent_at.to_be_bytes());
+		socket.write(&probe)?;
+		report.sent += 1;
+		if let Some(rtt_ms) = wait_for_probe(&socket, &probe, sent_at, timeout_ms, now_ms)? {
+			debug!("Probe {} took {} ms", sequence, rtt_ms);
+			report.received += 1;
+			total_rtt_ms += u64::from(rtt_ms);
+			report.min_rtt_ms = Some(report.min_rtt_ms.map_or(rtt_ms, |m| m.min(rtt_ms)));
+			report.max_rtt_ms = Some(report.max_rtt_ms.map_or(rtt_ms, |m| m.max(rtt_ms)));
+		} else {
+			debug!("Probe {} lost", sequence);
+		}
+	}
+	if report.received > 0 {
+		report.avg_rtt_ms = Some((total_rtt_ms / u64::from(report.received)) as u32);
+	}
+	Ok(report)
+}
+
+/// Check that a server is reachable - the usual check before a big upload.
+///
+/// Looks the hostname up, makes a TCP connection to it, and then makes an
+/// HTTPS `GET` request and reads the status line of the response. Each step
+/// is timed, and each is only tried if the one before worked. How long each
+/// may take is set by `timeouts`.
+#[cfg(target_arch = "arm")]
+pub fn health_check(config: &HealthCheckConfig) -> HealthReport {
+	let mut report = HealthReport::default();
+
+	let dns = timed_step(config.now_ms, || {
+		lookup_ipv4(config.hostname, SocketType::Stream, config.port).map(|_| ())
+	});
+	let ok = dns.result.is_ok();
+	report.dns = Some(dns);
+	if !ok {
+		return report;
+	}
+
+	let tcp_connect = timed_step(config.now_ms, || {
+		let socket = TcpSocket::new()?;
+		socket.connect(config.hostname, config.port)
+	});
+	let ok = tcp_connect.result.is_ok();
+	report.tcp_connect = Some(tcp_connect);
+	if !ok {
+		return report;
+	}
+
+	let mut http_status = None;
+	report.https_get = Some(timed_step(config.now_ms, || {
+		http_status = Some(https_get(config)?);
+		Ok(())
+	}));
+	report.http_status = http_status;
+	debug!("Health check of {} => {:?}", config.hostname, report);
+	report
+}
+
+impl HealthReport {
+	/// Did every step work, with a 2xx or 3xx status from the server?
+	pub fn is_healthy(&self) -> bool {
+		let step_ok =
+			|step: &Option<StepReport>| matches!(step, Some(StepReport { result: Ok(()), .. }));
+		step_ok(&self.dns)
+			&& step_ok(&self.tcp_connect)
+			&& step_ok(&self.https_get)
+			&& matches!(self.http_status, Some(200..=399))
+	}
+}
+
+/// Close every open socket, of any kind, e.g. before entering PSM or
+/// powering the modem off. Returns how many were closed. `open_sockets()`
+/// lists them beforehand, if you want to know which.
+///
+/// The socket objects themselves are left as they were. Anything done with
+/// them now fails, as the socket is closed, and dropping them does nothing
+/// more - even if libmodem has given their socket ID to a new socket since.
+#[cfg(target_arch = "arm")]
+pub fn close_all() -> usize {
+	crate::raw::close_sockets(|_| true)
+}
+
+/// Close the open sockets for which `filter` returns true, as `close_all`
+/// does, e.g. to close everything except AT sockets. Returns how many were
+/// closed.
+#[cfg(target_arch = "arm")]
+pub fn close_matching(filter: fn(&SocketRecord) -> bool) -> usize {
+	crate::raw::close_sockets(filter)
+}
+
+/// Seed the random local port choice. This is only used if no
+/// `entropy::EntropySource` has been set. Without either, a device picks the
+/// same sequence of ports after every reset, so seed it from something that
+/// differs between resets.
+#[cfg(target_arch = "arm")]
+pub fn seed_ephemeral_ports(seed: u32) {
+	let seed = if seed == 0 { DEFAULT_PORT_SEED } else { seed };
+	PORT_STATE.store(seed, Ordering::Relaxed);
+}
+
+impl LatencyReport {
+	/// How many probes were lost, as a percentage of those sent.
+	pub fn loss_percent(&self) -> u8 {
+		if self.sent == 0 {
+			0
+		} else {
+			(u64::from(self.sent - self.received) * 100 / u64::from(self.sent)) as u8
+		}
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Run one step of a health check, timing it.
+#[cfg(target_arch = "arm")]
+fn timed_step<F>(now_ms: fn() -> u32, step: F) -> StepReport
+where
+	F: FnOnce() -> Result<(), Error>,
+{
+	let started_at = now_ms();
+	let result = step();
+	StepReport {
+		result,
+		duration_ms: now_ms().wrapping_sub(started_at),
+	}
+}
+
+/// Make an HTTPS `GET` request and return the response's status code.
+#[cfg(target_arch = "arm")]
+fn https_get(config: &HealthCheckConfig) -> Result<u16, Error> {
+	use core::fmt::Write;
+
+	let socket = TlsSocket::new(config.peer_verify, config.security_tags, Version::Tls1v2)?;
+	socket.connect(config.hostname, config.port)?;
+	let mut request: heapless::String<256> = heapless::String::new();
+	write!(
+		request,
+		"GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+		config.path, config.hostname
+	)
+	.map_err(|_| Error::BufferFull)?;
+	socket.write(request.as_bytes())?;
+	// We only need the status line
+	let mut buffer = [0u8; 64];
+	let mut length = 0;
+	while let Some(free) = buffer.get_mut(length..).filter(|free| !free.is_empty()) {
+		let received = socket.recv_wait(free)?;
+		length += received;
+		if free.iter().take(received).any(|&b| b == b'\n') {
+			break;
+		}
+	}
+	parse_status_line(buffer.get(0..length).unwrap_or(&[])).ok_or(Error::BadDataFormat)
+}
+
+/// Get the status code from an HTTP status line, such as `HTTP/1.1 200 OK`.
+#[cfg(any(target_arch = "arm", feature = "std", test))]
+pub(crate) fn parse_status_line(data: &[u8]) -> Option<u16> {
+	let line = core::str::from_utf8(data.split(|&b| b == b'\n').next()?).ok()?;
+	let mut parts = line.split_whitespace();
+	if !parts.next()?.starts_with("HTTP/") {
+		return None;
+	}
+	parts.next()?.parse().ok()
+}
+
+/// Pick a random port in the ephemeral range, using the entropy source if
+/// there is one.
+#[cfg(target_arch = "arm")]
+pub(crate) fn random_ephemeral_port() -> u16 {
+	let range = u32::from(u16::MAX - EPHEMERAL_PORT_MIN) + 1;
+	if let Ok(random) = crate::entropy::random_u32() {
+		return EPHEMERAL_PORT_MIN + (random % range) as u16;
+	}
+	// xorshift32 - not cryptographic, but plenty to spread our ports about
+	let step = |mut x: u32| {
+		x ^= x << 13;
+		x ^= x >> 17;
+		x ^= x << 5;
+		x
+	};
+	let previous = PORT_STATE
+		.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(step(x)))
+		.unwrap_or(DEFAULT_PORT_SEED);
+	EPHEMERAL_PORT_MIN + (step(previous) % range) as u16
+}
+
+/// Wait for `probe` to come back, returning the round-trip time, or `None` if
+/// it doesn't arrive within `timeout_ms`.
+#[cfg(target_arch = "arm")]
+fn wait_for_probe(
+	socket: &UdpSocket,
+	probe: &[u8; PROBE_LEN],
+	sent_at: u32,
+	timeout_ms: u16,
+	now_ms: fn() -> u32,
+) -> Result<Option<u32>, Error> {
+	loop {
+		let elapsed_ms = now_ms().wrapping_sub(sent_at);
+		if elapsed_ms >= u32::from(timeout_ms) {
+			return Ok(None);
+		}
+		let mut poll_list = [PollEntry::new(socket, PollFlags::Read)];
+		if crate::poll(&mut poll_list, timeout_ms - elapsed_ms as u16)? == 0 {
+			return Ok(None);
+		}
+		let mut buffer = [0u8; PROBE_LEN];
+		if let Some(length) = socket.recv(&mut buffer)? {
+			if length == PROBE_LEN && buffer == *probe {
+				return Ok(Some(now_ms().wrapping_sub(sent_at)));
+			}
+		}
+	}
+}
+
+/// Remove the quotes, brackets and zone suffix the modem may wrap an address
+/// in.
+fn strip_quirks(s: &str) -> &str {
+	let s = s.trim().trim_matches('"');
+	let s = s
+		.strip_prefix('[')
+		.and_then(|s| s.strip_suffix(']'))
+		.unwrap_or(s);
+	match s.find('%') {
+		Some(idx) => &s[0..idx],
+		None => s,
+	}
+}
+
+/// Parse a decimal number with no sign or other decoration.
+fn parse_decimal<T: FromStr>(s: &str) -> Option<T> {
+	if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	s.parse().ok()
+}
+
+/// Parse dot-separated decimal octets, filling exactly `octets.len()` of
+/// them. Leading zeros are rejected, as `core::net` does, as some stacks read
+/// `010` as octal.
+fn parse_dotted(s: &str, octets: &mut [u8]) -> bool {
+	let mut parts = s.split('.');
+	for octet in octets.iter_mut() {
+		match parts
+			.next()
+			.filter(|p| p.len() <= 3 && !(p.len() > 1 && p.starts_with('0')))
+			.and_then(parse_decimal)
+		{
+			Some(value) => *octet = value,
+			None => return false,
+		}
+	}
+	parts.next().is_none()
+}
+
+/// Parse the usual `2001:db8::1` form, including an embedded IPv4 address at
+/// the end (e.g. `::ffff:10.0.0.1`).
+fn parse_colon_hex(s: &str, octets: &mut [u8; 16]) -> bool {
+	let mut head = [0u16; 8];
+	let mut tail = [0u16; 8];
+	let (head_len, tail_len) = match s.find("::") {
+		Some(idx) => {
+			let tail_str = &s[idx + 2..];
+			let head_len = match parse_segments(&s[0..idx], &mut head, false) {
+				Some(n) => n,
+				None => return false,
+			};
+			let tail_len = match parse_segments(tail_str, &mut tail, true) {
+				Some(n) => n,
+				None => return false,
+			};
+			// `::` must stand for at least one zero segment
+			if head_len + tail_len > 7 {
+				return false;
+			}
+			(head_len, tail_len)
+		}
+		None => match parse_segments(s, &mut head, true) {
+			Some(8) => (8, 0),
+			_ => return false,
+		},
+	};
+	let mut segments = [0u16; 8];
+	// The head fills from the front, and the tail from the back
+	for (segment, value) in segments.iter_mut().zip(head.iter().take(head_len)) {
+		*segment = *value;
+	}
+	let tail = tail.iter().take(tail_len).rev();
+	for (segment, value) in segments.iter_mut().rev().zip(tail) {
+		*segment = *value;
+	}
+	*octets = Ipv6Addr::from_segments(segments).0;
+	true
+}
+
+/// Parse colon-separated hex segments into `out`, returning how many were
+/// found. If `allow_ipv4` is set, the last segment may be a dotted IPv4
+/// address, which fills two segments.
+fn parse_segments(s: &str, out: &mut [u16; 8], allow_ipv4: bool) -> Option<usize> {
+	if s.is_empty() {
+		return Some(0);
+	}
+	let mut count = 0;
+	let mut parts = s.split(':').peekable();
+	while let Some(part) = parts.next() {
+		let is_last = parts.peek().is_none();
+		if is_last && allow_ipv4 && part.contains('.') {
+			let mut ipv4 = [0u8; 4];
+			if count > 6 || !parse_dotted(part, &mut ipv4) {
+				return None;
+			}
+			let [a, b, c, d] = ipv4;
+			*out.get_mut(count)? = u16::from_be_bytes([a, b]);
+			*out.get_mut(count + 1)? = u16::from_be_bytes([c, d]);
+			count += 2;
+		} else {
+			if count > 7
+				|| part.is_empty()
+				|| part.len() > 4
+				|| !part.bytes().all(|b| b.is_ascii_hexdigit())
+			{
+				return None;
+			}
+			*out.get_mut(count)? = u16::from_str_radix(part, 16).ok()?;
+			count += 1;
+		}
+	}
+	Some(count)
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Format `value` into a fixed buffer, so we can compare it.
+	fn format<T: fmt::Display>(value: T) -> heapless::String<64> {
+		let mut out = heapless::String::new();
+		assert!(fmt::write(&mut out, format_args!("{}", value)).is_ok());
+		out
+	}
+
+	#[test]
+	fn ipv4_parse() {
+		assert_eq!(
+			"10.0.0.1".parse::<Ipv4Addr>().ok(),
+			Some(Ipv4Addr::new(10, 0, 0, 1))
+		);
+		assert_eq!(
+			"255.255.255.255".parse::<Ipv4Addr>().ok(),
+			Some(Ipv4Addr::new(255, 255, 255, 255))
+		);
+		// As the modem quotes it
+		assert_eq!(
+			"\"10.0.0.1\"".parse::<Ipv4Addr>().ok(),
+			Some(Ipv4Addr::new(10, 0, 0, 1))
+		);
+		for bad in &[
+			"",
+			"10.0.0",
+			"10.0.0.1.2",
+			"10.0.0.256",
+			"10.0.0.-1",
+			"10.0..1",
+			"10.0.0.1 2",
+			"0x0a.0.0.1",
+			"+10.0.0.1",
+			"0010.0.0.1",
+		] {
+			assert!(bad.parse::<Ipv4Addr>().is_err(), "{:?}", bad);
+		}
+	}
+
+	#[test]
+	fn ipv4_leading_zeros() {
+		assert!("010.0.0.1".parse::<Ipv4Addr>().is_err());
+		assert!("10.0.0.01".parse::<Ipv4Addr>().is_err());
+		assert!("10.00.0.1".parse::<Ipv4Addr>().is_err());
+		// A single zero is fine
+		assert_eq!(
+			"0.0.0.0".parse::<Ipv4Addr>().ok(),
+			Some(Ipv4Addr::UNSPECIFIED)
+		);
+	}
+
+	#[test]
+	fn ipv4_format() {
+		assert_eq!(format(Ipv4Addr::new(192, 168, 0, 10)), "192.168.0.10");
+		assert_eq!(format(Ipv4Addr::UNSPECIFIED), "0.0.0.0");
+	}
+
+	#[test]
+	fn ipv6_parse() {
+		let expected = Ipv6Addr::from_segments([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]);
+		for text in &[
+			"2001:db8::1",
+			"2001:DB8::1",
+			"2001:db8:0:0:0:0:0:1",
+			"2001:0db8:0000:0000:0000:0000:0000:0001",
+			"[2001:db8::1]",
+		] {
+			assert_eq!(text.parse::<Ipv6Addr>().ok(), Some(expected), "{:?}", text);
+		}
+		assert_eq!("::".parse::<Ipv6Addr>().ok(), Some(Ipv6Addr::UNSPECIFIED));
+		assert_eq!(
+			"::1".parse::<Ipv6Addr>().ok(),
+			Some(Ipv6Addr::from_segments([0, 0, 0, 0, 0, 0, 0, 1]))
+		);
+		assert_eq!(
+			"1::".parse::<Ipv6Addr>().ok(),
+			Some(Ipv6Addr::from_segments([1, 0, 0, 0, 0, 0, 0, 0]))
+		);
+		// With a zone, which we drop
+		assert_eq!(
+			"fe80::1%wlan0".parse::<Ipv6Addr>().ok(),
+			Some(Ipv6Addr::from_segments([0xfe80, 0, 0, 0, 0, 0, 0, 1]))
+		);
+		// An embedded IPv4 address
+		let mapped = "::ffff:10.0.0.1".parse::<Ipv6Addr>().ok();
+		assert_eq!(
+			mapped.and_then(|ip| ip.to_ipv4_mapped()),
+			Some(Ipv4Addr::new(10, 0, 0, 1))
+		);
+		for bad in &[
+			"",
+			":",
+			":::",
+			"1:2:3:4:5:6:7",
+			"1:2:3:4:5:6:7:8:9",
+			"1:2:3:4::5:6:7:8",
+			"1::2::3",
+			"12345::1",
+			"g::1",
+			"::ffff:10.0.0",
+			"::ffff:010.0.0.1",
+			"10.0.0.1::",
+		] {
+			assert!(bad.parse::<Ipv6Addr>().is_err(), "{:?}", bad);
+		}
+	}
+
+	#[test]
+	fn ipv6_parse_3gpp() {
+		// How `+CGPADDR` and `+CGCONTRDP` give IPv6 addresses
+		assert_eq!(
+			"32.1.13.184.0.0.0.0.0.0.0.0.0.0.0.1"
+				.parse::<Ipv6Addr>()
+				.ok(),
+			Some(Ipv6Addr::from_segments([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]))
+		);
+		assert_eq!(
+			"\"254.128.0.0.0.0.0.0.18.52.86.120.154.188.222.240\""
+				.parse::<Ipv6Addr>()
+				.ok(),
+			Some(Ipv6Addr::from_segments([
+				0xfe80, 0, 0, 0, 0x1234, 0x5678, 0x9abc, 0xdef0
+			]))
+		);
+		assert!("32.1.13.184.0.0.0.0.0.0.0.0.0.0.1"
+			.parse::<Ipv6Addr>()
+			.is_err());
+		assert!("32.1.13.184.0.0.0.0.0.0.0.0.0.0.0.1.0"
+			.parse::<Ipv6Addr>()
+			.is_err());
+		assert!("32.1.13.184.0.0.0.0.0.0.0.0.0.0.0.256"
+			.parse::<Ipv6Addr>()
+			.is_err());
+		assert!("032.1.13.184.0.0.0.0.0.0.0.0.0.0.0.1"
+			.parse::<Ipv6Addr>()
+			.is_err());
+	}
+
+	#[test]
+	fn ipv6_format() {
+		let cases: &[([u16; 8], &str)] = &[
+			([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1], "2001:db8::1"),
+			([0; 8], "::"),
+			([0, 0, 0, 0, 0, 0, 0, 1], "::1"),
+			([1, 0, 0, 0, 0, 0, 0, 0], "1::"),
+			// A single zero segment isn't compressed
+			([0x2001, 0xdb8, 0, 1, 1, 1, 1, 1], "2001:db8:0:1:1:1:1:1"),
+			// The longest run is compressed...
+			([0x2001, 0, 0, 1, 0, 0, 0, 1], "2001:0:0:1::1"),
+			// ... or the first, if there is a tie
+			([0x2001, 0xdb8, 0, 0, 1, 0, 0, 1], "2001:db8::1:0:0:1"),
+			(
+				[0xfe80, 0, 0, 0, 0x1234, 0x5678, 0x9ABC, 0xDEF0],
+				"fe80::1234:5678:9abc:def0",
+			),
+			([0, 0, 0, 0, 0, 0xffff, 0x0a00, 0x0001], "::ffff:10.0.0.1"),
+		];
+		for (segments, text) in cases {
+			let ip = Ipv6Addr::from_segments(*segments);
+			assert_eq!(format(ip), *text);
+			// And back again
+			assert_eq!(text.parse::<Ipv6Addr>().ok(), Some(ip));
+		}
+	}
+
+	#[test]
+	fn ip_addr_parse() {
+		assert_eq!(
+			"10.0.0.1".parse::<IpAddr>().ok(),
+			Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+		);
+		assert_eq!(
+			"::1".parse::<IpAddr>().ok().map(|ip| ip.is_ipv6()),
+			Some(true)
+		);
+		assert!("10.0.0".parse::<IpAddr>().is_err());
+	}
+
+	#[test]
+	fn socket_addr_parse() {
+		let v4 = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 5683);
+		assert_eq!("10.0.0.1:5683".parse::<SocketAddr>().ok(), Some(v4));
+		assert_eq!("\"10.0.0.1:5683\"".parse::<SocketAddr>().ok(), Some(v4));
+		let v6 = SocketAddr::new(
+			Ipv6Addr::from_segments([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]).into(),
+			443,
+		);
+		assert_eq!("[2001:db8::1]:443".parse::<SocketAddr>().ok(), Some(v6));
+		for bad in &[
+			"10.0.0.1",
+			"10.0.0.1:",
+			"10.0.0.1:65536",
+			"10.0.0.1:-1",
+			"2001:db8::1:443",
+			"[2001:db8::1]",
+			"[2001:db8::1]443",
+			"[2001:db8::1:443",
+			"[10.0.0.1]:443",
+		] {
+			assert!(bad.parse::<SocketAddr>().is_err(), "{:?}", bad);
+		}
+	}
+
+	#[test]
+	fn socket_addr_format() {
+		let v4 = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 5683);
+		assert_eq!(format(v4), "10.0.0.1:5683");
+		let v6 = SocketAddr::new(
+			Ipv6Addr::from_segments([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]).into(),
+			443,
+		);
+		assert_eq!(format(v6), "[2001:db8::1]:443");
+		for addr in &[v4, v6] {
+			assert_eq!(format(addr).parse::<SocketAddr>().ok(), Some(*addr));
+		}
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
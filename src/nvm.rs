@@ -0,0 +1,131 @@
+//! # Modem NVM write tracking for nrfxlib
+//!
+//! Some AT commands make the modem write to its flash - changing the system
+//! mode or band lock, writing credentials, or `AT+CFUN=0` (which flushes
+//! pending settings to NVM). That flash has a limited number of write cycles,
+//! so an application which changes settings at every boot can wear it out.
+//!
+//! Every NVM-writing command sent through this crate is counted, so you can
+//! see how many there have been. In "minimize writes" mode, `modem`
+//! functions which would write a setting first read it back, and skip the
+//! write if it wouldn't change anything.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// How many NVM-writing commands have been sent, and skipped, since
+/// start-up.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct NvmStats {
+	/// The number of NVM-writing commands sent to the modem
+	pub writes: u32,
+	/// The number of writes skipped because the value was unchanged
+	pub skipped: u32,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The start of each AT command which writes to the modem's NVM.
+const NVM_WRITE_COMMANDS: [&str; 7] = [
+	"AT%XSYSTEMMODE=",
+	"AT%XBANDLOCK=",
+	"AT+CFUN=0",
+	"AT%XEPCO=",
+	"AT%CMNG=0,",
+	"AT%CMNG=3,",
+	"AT+CGDCONT=",
+];
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The number of NVM-writing commands sent.
+static WRITES: AtomicU32 = AtomicU32::new(0);
+
+/// The number of NVM writes skipped.
+static SKIPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Are we in "minimize writes" mode?
+static MINIMIZE: AtomicBool = AtomicBool::new(false);
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Get the NVM write counts.
+pub fn stats() -> NvmStats {
+	NvmStats {
+		writes: WRITES.load(Ordering::Relaxed),
+		skipped: SKIPPED.load(Ordering::Relaxed),
+	}
+}
+
+/// Turn "minimize writes" mode on or off. While it is on,
+/// `modem::set_system_mode`, `modem::set_epco` and `modem::off` check the
+/// modem's current setting first and skip the write if it is already right.
+/// This costs an extra (read-only) AT command each time.
+pub fn set_minimize_writes(minimize: bool) {
+	MINIMIZE.store(minimize, Ordering::Relaxed);
+}
+
+/// Are we in "minimize writes" mode?
+pub fn minimize_writes() -> bool {
+	MINIMIZE.load(Ordering::Relaxed)
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Count this AT command, if it writes to NVM.
+pub(crate) fn record(command: &str) {
+	let command = command.trim_start();
+	if NVM_WRITE_COMMANDS
+		.iter()
+		.any(|prefix| command.starts_with(prefix))
+	{
+		record_write();
+	}
+}
+
+/// Count an NVM-writing command which doesn't go through
+/// `AtSocket::send_command`.
+pub(crate) fn record_write() {
+	WRITES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count a write we skipped in "minimize writes" mode.
+pub(crate) fn record_skipped() {
+	SKIPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,116 @@
+//! # Socket lifecycle observer for nrfxlib
+//!
+//! Give `set_observer` something which implements `SocketObserver`, and it
+//! will be told whenever a socket is opened, connected, sent on, received on
+//! or closed. Each method can emit a marker for an RTOS trace tool such as
+//! SEGGER SystemView or Tracealyzer, so that modem activity shows up on the
+//! same timeline as your tasks and interrupts.
+//!
+//! The observer is called from whichever context made the socket call, just
+//! after the call to libmodem returns. It should be quick and must not call
+//! back into this crate. For example:
+//!
+//! ```ignore
+//! struct Markers;
+//!
+//! impl observer::SocketObserver for Markers {
+//!     fn sent(&self, fd: i32, length: usize) {
+//!         systemview::mark(SEND_MARKER, fd, length);
+//!     }
+//! }
+//!
+//! static MARKERS: Markers = Markers;
+//! observer::set_observer(Some(&MARKERS));
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::{NrfSockAddrIn, SocketProtocol};
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Something which wants to know what the sockets are doing. Every method
+/// does nothing by default, so implement just the ones you need. `fd` is
+/// the underlying socket ID, as in `SocketRecord`.
+pub trait SocketObserver: Sync {
+	/// A socket has been opened
+	fn opened(&self, _fd: i32, _protocol: SocketProtocol) {}
+	/// A socket has connected (or, for UDP, set its default peer)
+	fn connected(&self, _fd: i32, _peer: &NrfSockAddrIn) {}
+	/// `length` bytes have been sent on a socket
+	fn sent(&self, _fd: i32, _length: usize) {}
+	/// `length` bytes have been received on a socket
+	fn received(&self, _fd: i32, _length: usize) {}
+	/// A socket has been closed
+	fn closed(&self, _fd: i32) {}
+}
+
+/// Somewhere to keep the observer, which we can share with every socket.
+type WrappedObserver = Mutex<RefCell<Option<&'static dyn SocketObserver>>>;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The observer given to `set_observer`.
+static OBSERVER: WrappedObserver = Mutex::new(RefCell::new(None));
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Set the observer which is told about socket activity, or `None` to stop
+/// telling anyone.
+pub fn set_observer(observer: Option<&'static dyn SocketObserver>) {
+	cortex_m::interrupt::free(|cs| {
+		*OBSERVER.borrow(cs).borrow_mut() = observer;
+	});
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Tell the observer, if we have one, about something. It is called outside
+/// the critical section, so it doesn't hold up interrupts.
+pub(crate) fn notify<F>(f: F)
+where
+	F: FnOnce(&dyn SocketObserver),
+{
+	if let Some(observer) = cortex_m::interrupt::free(|cs| *OBSERVER.borrow(cs).borrow()) {
+		f(observer);
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
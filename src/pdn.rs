@@ -0,0 +1,679 @@
+//! # PDN helpers for nrfxlib
+//!
+//! Packet Data Network (PDN) related code. The modem reports changes to its
+//! PDN connections and bearers with `+CGEV` unsolicited result codes, once
+//! enabled with `AT+CGEREP=1`.
+//!
+//! The modem brings up the default PDN connection (context 0) itself when it
+//! attaches. Others - for a private APN, or to keep some traffic apart - are
+//! set up here:
+//!
+//! ```ignore
+//! let cid = pdn::create_context(
+//!     pdn::PdpType::Ipv4v6,
+//!     "private.apn",
+//!     pdn::Authentication::Pap { username: "user", password: "secret" },
+//! )?;
+//! pdn::activate(cid)?;
+//! let addresses = pdn::addresses(cid)?;
+//! let socket = TcpSocket::new()?;
+//! socket.bind_to_pdn(pdn::pdn_id(cid)?)?;
+//! socket.connect("server.private", 8080)?;
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+use crate::at::AtSocket;
+use crate::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+#[cfg(target_arch = "arm")]
+use crate::Error;
+#[cfg(target_arch = "arm")]
+use core::fmt::Write;
+#[cfg(target_arch = "arm")]
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Which side caused a PDN event.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Initiator {
+	/// The modem (`ME`) caused this event.
+	Modem,
+	/// The network (`NW`) caused this event.
+	Network,
+}
+
+/// A packet domain event, as reported by `+CGEV`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PdnEvent {
+	/// A PDN connection has been activated (`ME PDN ACT`). The `reason`, if
+	/// given, explains why the network didn't give us all the IP families we
+	/// asked for.
+	PdnActivated {
+		/// The context ID
+		cid: u8,
+		/// The reason code, if any
+		reason: Option<u8>,
+	},
+	/// A PDN connection has been deactivated (`ME PDN DEACT` / `NW PDN DEACT`).
+	PdnDeactivated {
+		/// Who deactivated it
+		initiator: Initiator,
+		/// The context ID
+		cid: u8,
+	},
+	/// A dedicated bearer has been activated (`ME ACT` / `NW ACT`).
+	BearerActivated {
+		/// Who activated it
+		initiator: Initiator,
+		/// The context ID of the PDN connection this bearer belongs to
+		parent_cid: u8,
+		/// The context ID of the bearer
+		cid: u8,
+	},
+	/// A dedicated bearer has been deactivated (`ME DEACT` / `NW DEACT`).
+	BearerDeactivated {
+		/// Who deactivated it
+		initiator: Initiator,
+		/// The context ID of the PDN connection this bearer belonged to
+		parent_cid: u8,
+		/// The context ID of the bearer
+		cid: u8,
+	},
+	/// A context has been modified, e.g. its QoS has changed (`ME MODIFY` /
+	/// `NW MODIFY`).
+	Modified {
+		/// Who modified it
+		initiator: Initiator,
+		/// The context ID
+		cid: u8,
+		/// Bit 0 is set for a TFT change, bit 1 for a QoS change, and bit 2 for
+		/// a WLAN offload change.
+		change_reason: u8,
+	},
+	/// The modem has been detached from the packet domain (`ME DETACH` / `NW
+	/// DETACH`).
+	Detached {
+		/// Who detached us
+		initiator: Initiator,
+	},
+	/// The IPv6 link on this context is up, so an IPv6 address can now be
+	/// used (`IPV6`).
+	Ipv6LinkUp {
+		/// The context ID
+		cid: u8,
+	},
+	/// The IPv6 link on this context has failed (`IPV6 FAIL`).
+	Ipv6LinkFailed {
+		/// The context ID
+		cid: u8,
+	},
+}
+
+/// The IP families a PDP context asks for (the `<PDP_type>` of
+/// `AT+CGDCONT`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PdpType {
+	/// IPv4 only (`IP`)
+	Ipv4,
+	/// IPv6 only (`IPV6`)
+	Ipv6,
+	/// IPv4 and IPv6 (`IPV4V6`)
+	Ipv4v6,
+	/// Non-IP data delivery (`Non-IP`)
+	NonIp,
+}
+
+/// How the modem authenticates with the network when it activates a PDP
+/// context (`AT+CGAUTH`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Authentication<'a> {
+	/// No authentication
+	None,
+	/// Password Authentication Protocol
+	Pap {
+		/// The user name
+		username: &'a str,
+		/// The password
+		password: &'a str,
+	},
+	/// Challenge Handshake Authentication Protocol
+	Chap {
+		/// The user name
+		username: &'a str,
+		/// The password
+		password: &'a str,
+	},
+}
+
+/// The IP addresses the network gave a PDN connection, from `AT+CGPADDR`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Addresses {
+	/// The IPv4 address, if there is one
+	pub ipv4: Option<Ipv4Addr>,
+	/// The IPv6 address, if there is one. This is link-local until the
+	/// network has sent a router advertisement (see `PdnEvent::Ipv6LinkUp`).
+	pub ipv6: Option<Ipv6Addr>,
+}
+
+/// Keeps track of which PDN connections are up, based on the `PdnEvent`s you
+/// give it.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PdnTracker {
+	/// Bit N is set if context N is active
+	active: u32,
+	/// Bit N is set if context N has a usable IPv6 link
+	ipv6_ready: u32,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The longest APN we accept, as in 3GPP TS 23.003.
+#[cfg(target_arch = "arm")]
+pub(crate) const MAX_APN_LEN: usize = 100;
+
+/// The longest user name or password the modem accepts for `AT+CGAUTH`.
+#[cfg(target_arch = "arm")]
+const MAX_CREDENTIAL_LEN: usize = 99;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Ask the modem to send `+CGEV` indications to the given AT socket.
+///
+/// Read indications from the socket and pass each line to `PdnEvent::parse`.
+#[cfg(target_arch = "arm")]
+pub fn subscribe_events(at_socket: &mut AtSocket) -> Result<(), Error> {
+	at_socket.send_command("AT+CGEREP=1")?;
+	at_socket.poll_response(|_| {})
+}
+
+/// Ask the modem for a context ID not yet in use (`AT%XNEWCID?`), for a new
+/// PDP context. Configure it with `configure` before activating it.
+#[cfg(target_arch = "arm")]
+pub fn new_context() -> Result<u8, Error> {
+	let mut cid = None;
+	crate::at::send_at_command("AT%XNEWCID?", |res| {
+		if let Some(id) = parse_number(res, "%XNEWCID:") {
+			cid = Some(id);
+		}
+	})?;
+	cid.ok_or(Error::BadDataFormat)
+}
+
+/// Set the PDP type and APN of a context (`AT+CGDCONT`).
+#[cfg(target_arch = "arm")]
+pub fn configure(cid: u8, pdp_type: PdpType, apn: &str) -> Result<(), Error> {
+	check_quotable(apn, MAX_APN_LEN)?;
+	let mut command: heapless::String<{ MAX_APN_LEN + 32 }> = heapless::String::new();
+	write!(
+		command,
+		"AT+CGDCONT={},\"{}\",\"{}\"",
+		cid,
+		pdp_type.as_str(),
+		apn
+	)?;
+	crate::at::send_at_command(&command, |_| {})
+}
+
+/// Set how the modem authenticates when it activates a context
+/// (`AT+CGAUTH`).
+#[cfg(target_arch = "arm")]
+pub fn set_authentication(cid: u8, authentication: Authentication) -> Result<(), Error> {
+	let mut command: heapless::String<{ 2 * MAX_CREDENTIAL_LEN + 32 }> = heapless::String::new();
+	let (protocol, username, password) = match authentication {
+		Authentication::None => (0, "", ""),
+		Authentication::Pap { username, password } => (1, username, password),
+		Authentication::Chap { username, password } => (2, username, password),
+	};
+	if protocol == 0 {
+		write!(command, "AT+CGAUTH={},0", cid)?;
+	} else {
+		check_quotable(username, MAX_CREDENTIAL_LEN)?;
+		check_quotable(password, MAX_CREDENTIAL_LEN)?;
+		write!(
+			command,
+			"AT+CGAUTH={},{},\"{}\",\"{}\"",
+			cid, protocol, username, password
+		)?;
+	}
+	let result = crate::at::send_at_command(&command, |_| {});
+	// NOTE(unsafe) - we're only overwriting it, and it's not used again
+	crate::wipe(unsafe { command.as_mut_vec() });
+	result
+}
+
+/// Set up a new PDP context - `new_context`, then `configure` and
+/// `set_authentication` - and return its context ID. It still needs
+/// activating.
+#[cfg(target_arch = "arm")]
+pub fn create_context(
+	pdp_type: PdpType,
+	apn: &str,
+	authentication: Authentication,
+) -> Result<u8, Error> {
+	let cid = new_context()?;
+	configure(cid, pdp_type, apn)?;
+	if authentication != Authentication::None {
+		set_authentication(cid, authentication)?;
+	}
+	debug!("Created PDP context {} for {:?}", cid, apn);
+	Ok(cid)
+}
+
+/// Bring up the PDN connection on a context (`AT+CGACT=1`). The modem must
+/// be attached.
+#[cfg(target_arch = "arm")]
+pub fn activate(cid: u8) -> Result<(), Error> {
+	let mut command: heapless::String<16> = heapless::String::new();
+	write!(command, "AT+CGACT=1,{}", cid)?;
+	crate::at::send_at_command(&command, |_| {})
+}
+
+/// Take down the PDN connection on a context (`AT+CGACT=0`).
+#[cfg(target_arch = "arm")]
+pub fn deactivate(cid: u8) -> Result<(), Error> {
+	let mut command: heapless::String<16> = heapless::String::new();
+	write!(command, "AT+CGACT=0,{}", cid)?;
+	crate::at::send_at_command(&command, |_| {})
+}
+
+/// Forget a context's configuration (`AT+CGDCONT=<cid>`), so its ID can be
+/// used again. Deactivate it first.
+#[cfg(target_arch = "arm")]
+pub fn remove_context(cid: u8) -> Result<(), Error> {
+	let mut command: heapless::String<16> = heapless::String::new();
+	write!(command, "AT+CGDCONT={}", cid)?;
+	crate::at::send_at_command(&command, |_| {})
+}
+
+/// Get the PDN ID of the PDN connection on an active context
+/// (`AT%XGETPDNID`). Sockets are bound to a PDN connection by this ID - see
+/// `Socket::bind_to_pdn`.
+#[cfg(target_arch = "arm")]
+pub fn pdn_id(cid: u8) -> Result<u8, Error> {
+	let mut command: heapless::String<16> = heapless::String::new();
+	write!(command, "AT%XGETPDNID={}", cid)?;
+	let mut id = None;
+	crate::at::send_at_command(&command, |res| {
+		if let Some(value) = parse_number(res, "%XGETPDNID:") {
+			id = Some(value);
+		}
+	})?;
+	id.ok_or(Error::BadDataFormat)
+}
+
+/// Get the IP addresses of the PDN connection on a context
+/// (`AT+CGPADDR`). Both are `None` if it isn't active.
+#[cfg(target_arch = "arm")]
+pub fn addresses(cid: u8) -> Result<Addresses, Error> {
+	let mut command: heapless::String<16> = heapless::String::new();
+	write!(command, "AT+CGPADDR={}", cid)?;
+	let mut addresses = Addresses::default();
+	crate::at::send_at_command(&command, |res| {
+		if let Some((line_cid, found)) = Addresses::parse(res) {
+			if line_cid == cid {
+				addresses = found;
+			}
+		}
+	})?;
+	Ok(addresses)
+}
+
+impl PdpType {
+	/// The name `AT+CGDCONT` uses for this PDP type.
+	pub fn as_str(self) -> &'static str {
+		match self {
+			PdpType::Ipv4 => "IP",
+			PdpType::Ipv6 => "IPV6",
+			PdpType::Ipv4v6 => "IPV4V6",
+			PdpType::NonIp => "Non-IP",
+		}
+	}
+}
+
+impl Addresses {
+	/// Parse a `+CGPADDR: <cid>[,<addr_1>[,<addr_2>]]` line, into the context
+	/// ID and its addresses. Returns `None` if this isn't a `+CGPADDR` line.
+	pub fn parse(line: &str) -> Option<(u8, Addresses)> {
+		let mut fields = line.trim().strip_prefix("+CGPADDR:")?.split(',');
+		let cid = fields.next()?.trim().parse().ok()?;
+		let mut addresses = Addresses::default();
+		for field in fields {
+			match field.trim().trim_matches('"').parse::<IpAddr>() {
+				Ok(IpAddr::V4(addr)) => addresses.ipv4 = Some(addr),
+				Ok(IpAddr::V6(addr)) => addresses.ipv6 = Some(addr),
+				Err(_) => {}
+			}
+		}
+		Some((cid, addresses))
+	}
+}
+
+impl PdnEvent {
+	/// Parse a `+CGEV` line from the modem. Returns `None` if this isn't a
+	/// `+CGEV` line, or it's one we don't understand.
+	pub fn parse(line: &str) -> Option<PdnEvent> {
+		let event = line.trim().strip_prefix("+CGEV:")?.trim();
+		// The arguments start at the first word beginning with a digit
+		let split = event
+			.char_indices()
+			.find(|&(idx, c)| c.is_ascii_digit() && event[0..idx].ends_with(' '));
+		let (name, args) = match split {
+			Some((idx, _)) => (event[0..idx].trim(), &event[idx..]),
+			None => (event, ""),
+		};
+		let mut args = args.split(',').map(|arg| arg.trim().parse::<u8>());
+		let mut next_arg = || args.next().and_then(|arg| arg.ok());
+		let event = match name {
+			"ME PDN ACT" => PdnEvent::PdnActivated {
+				cid: next_arg()?,
+				reason: next_arg(),
+			},
+			"ME PDN DEACT" => PdnEvent::PdnDeactivated {
+				initiator: Initiator::Modem,
+				cid: next_arg()?,
+			},
+			"NW PDN DEACT" => PdnEvent::PdnDeactivated {
+				initiator: Initiator::Network,
+				cid: next_arg()?,
+			},
+			"ME ACT" | "NW ACT" => PdnEvent::BearerActivated {
+				initiator: Initiator::from_prefix(name),
+				parent_cid: next_arg()?,
+				cid: next_arg()?,
+			},
+			"ME DEACT" | "NW DEACT" => PdnEvent::BearerDeactivated {
+				initiator: Initiator::from_prefix(name),
+				parent_cid: next_arg()?,
+				cid: next_arg()?,
+			},
+			"ME MODIFY" | "NW MODIFY" => PdnEvent::Modified {
+				initiator: Initiator::from_prefix(name),
+				cid: next_arg()?,
+				change_reason: next_arg()?,
+			},
+			"ME DETACH" | "NW DETACH" => PdnEvent::Detached {
+				initiator: Initiator::from_prefix(name),
+			},
+			"IPV6" => PdnEvent::Ipv6LinkUp { cid: next_arg()? },
+			"IPV6 FAIL" => PdnEvent::Ipv6LinkFailed { cid: next_arg()? },
+			_ => return None,
+		};
+		Some(event)
+	}
+}
+
+impl PdnTracker {
+	/// Create a new tracker, with no active contexts.
+	pub fn new() -> PdnTracker {
+		PdnTracker::default()
+	}
+
+	/// Update the tracker with an event from the modem.
+	pub fn update(&mut self, event: &PdnEvent) {
+		match *event {
+			PdnEvent::PdnActivated { cid, .. } => {
+				self.active |= Self::bit(cid);
+			}
+			PdnEvent::PdnDeactivated { cid, .. } => {
+				self.active &= !Self::bit(cid);
+				self.ipv6_ready &= !Self::bit(cid);
+			}
+			PdnEvent::Detached { .. } => {
+				self.active = 0;
+				self.ipv6_ready = 0;
+			}
+			PdnEvent::Ipv6LinkUp { cid } => {
+				self.ipv6_ready |= Self::bit(cid);
+			}
+			PdnEvent::Ipv6LinkFailed { cid } => {
+				self.ipv6_ready &= !Self::bit(cid);
+			}
+			PdnEvent::BearerActivated { .. }
+			| PdnEvent::BearerDeactivated { .. }
+			| PdnEvent::Modified { .. } => {
+				// Doesn't change whether the PDN connection is usable
+			}
+		}
+	}
+
+	/// Is the PDN connection on the given context active?
+	pub fn is_active(&self, cid: u8) -> bool {
+		(self.active & Self::bit(cid)) != 0
+	}
+
+	/// Can the IPv6 address on the given context be used yet?
+	pub fn is_ipv6_ready(&self, cid: u8) -> bool {
+		(self.ipv6_ready & Self::bit(cid)) != 0
+	}
+
+	/// Get the bit used for a context ID. We ignore IDs we can't track.
+	fn bit(cid: u8) -> u32 {
+		1u32.checked_shl(u32::from(cid)).unwrap_or(0)
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Parse a `<prefix> <number>` response line.
+#[cfg(target_arch = "arm")]
+fn parse_number(line: &str, prefix: &str) -> Option<u8> {
+	line.trim().strip_prefix(prefix)?.trim().parse().ok()
+}
+
+/// Check a string can go in quotes in an AT command.
+#[cfg(target_arch = "arm")]
+fn check_quotable(value: &str, max_len: usize) -> Result<(), Error> {
+	if value.len() > max_len {
+		Err(Error::BufferFull)
+	} else if value.contains('"') {
+		Err(Error::BadDataFormat)
+	} else {
+		Ok(())
+	}
+}
+
+impl Initiator {
+	/// Work out who caused an event from the `ME`/`NW` prefix.
+	fn from_prefix(name: &str) -> Initiator {
+		if name.starts_with("NW") {
+			Initiator::Network
+		} else {
+			Initiator::Modem
+		}
+	}
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pdn_activated() {
+		assert_eq!(
+			PdnEvent::parse("+CGEV: ME PDN ACT 0"),
+			Some(PdnEvent::PdnActivated {
+				cid: 0,
+				reason: None,
+			})
+		);
+		// IPv4 only, as the network wouldn't give us IPv6
+		assert_eq!(
+			PdnEvent::parse("+CGEV: ME PDN ACT 1,1\r\n"),
+			Some(PdnEvent::PdnActivated {
+				cid: 1,
+				reason: Some(1),
+			})
+		);
+		assert_eq!(PdnEvent::parse("+CGEV: ME PDN ACT"), None);
+	}
+
+	#[test]
+	fn pdn_deactivated() {
+		assert_eq!(
+			PdnEvent::parse("+CGEV: ME PDN DEACT 0"),
+			Some(PdnEvent::PdnDeactivated {
+				initiator: Initiator::Modem,
+				cid: 0,
+			})
+		);
+		assert_eq!(
+			PdnEvent::parse("+CGEV: NW PDN DEACT 2"),
+			Some(PdnEvent::PdnDeactivated {
+				initiator: Initiator::Network,
+				cid: 2,
+			})
+		);
+	}
+
+	#[test]
+	fn bearers() {
+		assert_eq!(
+			PdnEvent::parse("+CGEV: NW ACT 0,2"),
+			Some(PdnEvent::BearerActivated {
+				initiator: Initiator::Network,
+				parent_cid: 0,
+				cid: 2,
+			})
+		);
+		assert_eq!(
+			PdnEvent::parse("+CGEV: ME DEACT 0,2"),
+			Some(PdnEvent::BearerDeactivated {
+				initiator: Initiator::Modem,
+				parent_cid: 0,
+				cid: 2,
+			})
+		);
+		assert_eq!(
+			PdnEvent::parse("+CGEV: NW MODIFY 0,2"),
+			Some(PdnEvent::Modified {
+				initiator: Initiator::Network,
+				cid: 0,
+				change_reason: 2,
+			})
+		);
+		assert_eq!(PdnEvent::parse("+CGEV: NW ACT 0"), None);
+	}
+
+	#[test]
+	fn detach_and_ipv6() {
+		assert_eq!(
+			PdnEvent::parse("+CGEV: NW DETACH"),
+			Some(PdnEvent::Detached {
+				initiator: Initiator::Network,
+			})
+		);
+		assert_eq!(
+			PdnEvent::parse("+CGEV: ME DETACH"),
+			Some(PdnEvent::Detached {
+				initiator: Initiator::Modem,
+			})
+		);
+		assert_eq!(
+			PdnEvent::parse("+CGEV: IPV6 0"),
+			Some(PdnEvent::Ipv6LinkUp { cid: 0 })
+		);
+		assert_eq!(
+			PdnEvent::parse("+CGEV: IPV6 FAIL 0"),
+			Some(PdnEvent::Ipv6LinkFailed { cid: 0 })
+		);
+	}
+
+	#[test]
+	fn not_pdn_events() {
+		for line in &["+CGEV: ME SOMETHING 1", "+CGEV:", "+CEREG: 1", "OK"] {
+			assert_eq!(PdnEvent::parse(line), None, "{:?}", line);
+		}
+	}
+
+	#[test]
+	fn addresses() {
+		// How older firmware gives IPv6 addresses
+		assert_eq!(
+			Addresses::parse(
+				"+CGPADDR: 0,\"10.145.192.136\",\"254.128.0.0.0.0.0.0.18.52.86.120.154.188.222.240\""
+			),
+			Some((
+				0,
+				Addresses {
+					ipv4: Some(Ipv4Addr::new(10, 145, 192, 136)),
+					ipv6: Some(Ipv6Addr::from_segments([
+						0xfe80, 0, 0, 0, 0x1234, 0x5678, 0x9abc, 0xdef0
+					])),
+				}
+			))
+		);
+		// ... and how newer firmware does
+		assert_eq!(
+			Addresses::parse("+CGPADDR: 1,\"10.145.192.136\",\"2001:DB8:0:0:0:0:0:1\"\r\n"),
+			Some((
+				1,
+				Addresses {
+					ipv4: Some(Ipv4Addr::new(10, 145, 192, 136)),
+					ipv6: Some(Ipv6Addr::from_segments([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1])),
+				}
+			))
+		);
+		assert_eq!(
+			Addresses::parse("+CGPADDR: 0,\"FE80::1234:5678:9ABC:DEF0\""),
+			Some((
+				0,
+				Addresses {
+					ipv4: None,
+					ipv6: Some(Ipv6Addr::from_segments([
+						0xfe80, 0, 0, 0, 0x1234, 0x5678, 0x9abc, 0xdef0
+					])),
+				}
+			))
+		);
+		// Not active, so no addresses
+		assert_eq!(
+			Addresses::parse("+CGPADDR: 0"),
+			Some((0, Addresses::default()))
+		);
+		assert_eq!(Addresses::parse("+CGPADDR: x,\"10.0.0.1\""), None);
+		assert_eq!(Addresses::parse("+CGDCONT: 0,\"IP\""), None);
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
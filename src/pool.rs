@@ -0,0 +1,174 @@
+//! # Buffer pool for nrfxlib
+//!
+//! A small pool of fixed-size blocks, used for the receive buffers in the AT,
+//! GNSS and LTE-wait paths instead of large per-call stack arrays. This keeps
+//! worst-case stack usage down (and easy to analyse), and `stats` shows how
+//! busy the pool has been.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::Error;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A block taken from the pool. It goes back to the pool when dropped.
+#[derive(Debug)]
+pub struct Buffer {
+	index: usize,
+}
+
+/// How the pool is being used.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+	/// How many blocks the pool holds
+	pub capacity: usize,
+	/// How many blocks are in use right now
+	pub in_use: usize,
+	/// The most blocks that have ever been in use at once
+	pub peak_in_use: usize,
+	/// How many times a block was wanted but none were free
+	pub failures: u32,
+}
+
+/// One block of storage, aligned so it can hold any of the Nordic structures.
+#[repr(C, align(8))]
+struct Block([u8; BLOCK_SIZE]);
+
+/// The pool itself.
+struct Pool {
+	blocks: [UnsafeCell<Block>; NUM_BLOCKS],
+	/// Bit N is set if block N is in use
+	in_use: AtomicU32,
+	peak_in_use: AtomicUsize,
+	failures: AtomicU32,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The size of each block, in bytes.
+pub const BLOCK_SIZE: usize = 256;
+
+/// How many blocks are in the pool.
+pub const NUM_BLOCKS: usize = 4;
+
+/// Used to initialise the blocks.
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_BLOCK: UnsafeCell<Block> = UnsafeCell::new(Block([0u8; BLOCK_SIZE]));
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The blocks shared across the crate.
+static POOL: Pool = Pool {
+	blocks: [EMPTY_BLOCK; NUM_BLOCKS],
+	in_use: AtomicU32::new(0),
+	peak_in_use: AtomicUsize::new(0),
+	failures: AtomicU32::new(0),
+};
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Get the pool's usage statistics.
+pub fn stats() -> PoolStats {
+	PoolStats {
+		capacity: NUM_BLOCKS,
+		in_use: POOL.in_use.load(Ordering::Relaxed).count_ones() as usize,
+		peak_in_use: POOL.peak_in_use.load(Ordering::Relaxed),
+		failures: POOL.failures.load(Ordering::Relaxed),
+	}
+}
+
+impl Buffer {
+	/// Take a free block from the pool. Returns `Error::PoolExhausted` if
+	/// they're all in use.
+	pub fn take() -> Result<Buffer, Error> {
+		let all_blocks = (1u32 << NUM_BLOCKS) - 1;
+		let result = POOL
+			.in_use
+			.fetch_update(Ordering::Acquire, Ordering::Relaxed, |in_use| {
+				if in_use == all_blocks {
+					None
+				} else {
+					Some(in_use | (1 << (!in_use).trailing_zeros()))
+				}
+			});
+		match result {
+			Ok(previous) => {
+				let index = (!previous).trailing_zeros() as usize;
+				let in_use = previous.count_ones() as usize + 1;
+				POOL.peak_in_use.fetch_max(in_use, Ordering::Relaxed);
+				Ok(Buffer { index })
+			}
+			Err(_) => {
+				POOL.failures.fetch_add(1, Ordering::Relaxed);
+				Err(Error::PoolExhausted)
+			}
+		}
+	}
+}
+
+impl core::ops::Deref for Buffer {
+	type Target = [u8];
+	#[allow(clippy::indexing_slicing)] // `take` only hands out indices into the pool
+	fn deref(&self) -> &[u8] {
+		// NOTE(unsafe) - our bit in `in_use` means only we can see this block
+		unsafe { &(*POOL.blocks[self.index].get()).0 }
+	}
+}
+
+impl core::ops::DerefMut for Buffer {
+	#[allow(clippy::indexing_slicing)] // `take` only hands out indices into the pool
+	fn deref_mut(&mut self) -> &mut [u8] {
+		// NOTE(unsafe) - our bit in `in_use` means only we can see this block
+		unsafe { &mut (*POOL.blocks[self.index].get()).0 }
+	}
+}
+
+impl Drop for Buffer {
+	/// Give the block back, wiped first if the `zeroize` feature is enabled
+	/// (AT responses can hold credentials).
+	fn drop(&mut self) {
+		crate::wipe(self);
+		POOL.in_use.fetch_and(!(1 << self.index), Ordering::Release);
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// The blocks are only accessed through a `Buffer`, which has exclusive use
+/// of its block.
+unsafe impl Sync for Pool {}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -18,6 +18,14 @@
 //******************************************************************************
 
 use super::{get_last_error, Error};
+pub(crate) use crate::errno::is_stale_address_error;
+use crate::errno::recv_status;
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+use cortex_m::interrupt::Mutex;
+use log::debug;
 use nrfxlib_sys as sys;
 
 //******************************************************************************
@@ -29,6 +37,12 @@ use nrfxlib_sys as sys;
 #[derive(Debug)]
 pub struct Socket {
 	pub(crate) fd: i32,
+	/// Our entry in the `SOCKET_REGISTRY` (see `SocketRecord::id`)
+	id: u32,
+	skt_type: SocketType,
+	/// The send timeout to go back to after connecting (see
+	/// `set_send_timeout`)
+	send_timeout_ms: Cell<u32>,
 }
 
 /// The options that can be passed to a socket.
@@ -43,6 +57,8 @@ pub(crate) enum SocketOption<'a> {
 	TlsSessionCache(sys::nrf_sec_session_cache_t),
 	/// A list of the TLS security/key tags you want to use
 	TlsTagList(&'a [sys::nrf_sec_tag_t]),
+	/// 0 means we are the TLS client, 1 means we are the server.
+	TlsRole(sys::nrf_sec_role_t),
 	/// Defines the interval between each fix in seconds. The default is 1. A
 	/// value of 0 means single-fix mode.
 	GnssFixInterval(sys::nrf_gnss_fix_interval_t),
@@ -51,10 +67,32 @@ pub(crate) enum SocketOption<'a> {
 	GnssFixRetry(sys::nrf_gnss_fix_retry_t),
 	/// Controls which, if any, NMEA frames are provided by the GNSS system
 	GnssNmeaMask(sys::nrf_gnss_nmea_mask_t),
+	/// Satellites below this elevation (in degrees, 0 to 30) aren't tracked
+	GnssElevationMask(sys::nrf_gnss_elevation_mask_t),
+	/// The start performance and accuracy the GNSS system aims for
+	GnssUseCase(sys::nrf_gnss_use_case_t),
+	/// Whether the GNSS system may duty-cycle its tracking to save power
+	GnssPowerSaveMode(sys::nrf_gnss_power_save_mode_t),
 	/// Starts the GNSS system, after deleting the specified non-volatile values.
 	GnssStart(sys::nrf_gnss_delete_mask_t),
 	/// Stops the GNSS system
 	GnssStop,
+	/// Schedules the received modem firmware image to be applied at the
+	/// next boot
+	DfuApply,
+	/// Schedules a rollback to the previous modem firmware at the next boot
+	DfuRevert,
+	/// Erases the modem's DFU scratch area (and with it, the backup image)
+	DfuBackupDelete,
+	/// Sets how much of the firmware image the modem has, to resume a
+	/// download
+	DfuOffset(sys::nrf_dfu_fw_offset_t),
+	/// How long a blocking receive waits. Zero means wait forever.
+	ReceiveTimeout(sys::nrf_timeval),
+	/// How long a blocking send waits. Zero means wait forever.
+	SendTimeout(sys::nrf_timeval),
+	/// Which network interface (PDN connection) the socket uses, by name
+	BindToDevice(&'a str),
 }
 
 /// The domain for a socket
@@ -64,6 +102,8 @@ pub(crate) enum SocketDomain {
 	Lte,
 	/// Corresponds to NRF_AF_INET. Used for IPv4 sockets.
 	Inet,
+	/// Corresponds to NRF_AF_INET6. Used for IPv6 sockets.
+	Inet6,
 	/// Corresponds to NRF_AF_LOCAL. Used for talking to the Nordic library (e.g. GNSS functions).
 	Local,
 }
@@ -79,7 +119,7 @@ pub(crate) enum SocketType {
 
 /// The protocol used on this socket.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub(crate) enum SocketProtocol {
+pub enum SocketProtocol {
 	/// Used with `SocketDomain::Lte`
 	At,
 	/// Plain TCP stream socket
@@ -94,6 +134,48 @@ pub(crate) enum SocketProtocol {
 	Dtls1v2,
 	/// A connection to the GPS/GNSS sub-system
 	Gnss,
+	/// A connection to the modem's firmware update (DFU) service
+	Dfu,
+}
+
+/// Describes a socket which is currently open.
+#[derive(Debug, Clone)]
+pub struct SocketRecord {
+	/// The underlying socket ID
+	pub fd: i32,
+	/// A number unique to this socket since start-up. libmodem re-uses `fd`
+	/// values once a socket is closed, so this tells sockets apart over time.
+	pub id: u32,
+	/// The protocol the socket was opened with
+	pub protocol: SocketProtocol,
+	/// The address the socket is connected to, if any
+	pub peer: Option<crate::NrfSockAddrIn>,
+	/// Whether registration or the default PDN connection was lost while the
+	/// socket was open. If so, it fails with `Error::LinkDown` from then on.
+	pub link_lost: bool,
+	/// Whether receiving is paused (see `Socket::pause_receive`)
+	pub receive_paused: bool,
+}
+
+/// What we know about some received data, besides its contents (see
+/// `UdpSocket::recv_with_meta` and `GnssSocket::get_fix_with_meta`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RxMeta {
+	/// When it arrived
+	pub timestamp: RxTimestamp,
+	/// Who sent it, if the modem said
+	pub source: Option<crate::net::SocketAddr>,
+}
+
+/// When some received data arrived.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RxTimestamp {
+	/// The time the modem gave with the data (the UTC time of a GNSS PVT
+	/// frame)
+	Modem(crate::gnss::DateTime),
+	/// The `now_ms` tick when we read the data, as the modem gave no time
+	/// with it
+	TickMs(u32),
 }
 
 /// Describes something we can poll on.
@@ -103,6 +185,28 @@ pub trait Pollable {
 	fn get_fd(&self) -> i32;
 }
 
+/// The results of a DNS lookup (`nrf_getaddrinfo`), which are freed when this
+/// is dropped.
+#[derive(Debug)]
+pub(crate) struct AddrInfoList {
+	/// The first record. Never null.
+	head: *mut sys::nrf_addrinfo,
+}
+
+/// Iterates through the IPv4 addresses in an `AddrInfoList`.
+#[derive(Debug, Clone)]
+pub(crate) struct Addresses<'a> {
+	record: Option<&'a sys::nrf_addrinfo>,
+	port: u16,
+}
+
+/// Iterates through the IPv6 addresses in an `AddrInfoList`.
+#[derive(Debug, Clone)]
+pub(crate) struct Ipv6Addresses<'a> {
+	record: Option<&'a sys::nrf_addrinfo>,
+	port: u16,
+}
+
 /// Describes a socket you wish to poll, and the result of polling it.
 pub struct PollEntry<'a> {
 	socket: &'a dyn Pollable,
@@ -122,6 +226,27 @@ pub enum PollFlags {
 	ReadOrWrite = sys::NRF_POLLIN as i16 + sys::NRF_POLLOUT as i16,
 }
 
+/// How a connect started with `connect_start` is going.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectStatus {
+	/// Still connecting (e.g. the TLS handshake hasn't finished). Call
+	/// `connect_poll` again later.
+	InProgress,
+	/// The socket is connected, and blocking again.
+	Connected,
+}
+
+/// Where a TCP, TLS or DTLS socket has got to with `connect_start`.
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Connecting {
+	/// There is no connect in progress
+	Idle,
+	/// Connecting to this address
+	InProgress(sys::nrf_sockaddr_in),
+	/// The connect failed, with this `NRF_E*` error
+	Failed(i32),
+}
+
 /// The ways a socket can respond to a poll.
 #[derive(Debug, Copy, Clone)]
 pub struct PollResult(u32);
@@ -132,11 +257,25 @@ pub struct PollResult(u32);
 
 const MAX_SOCKETS_POLL: usize = 8;
 
+/// The most sockets the library will let us have open at once.
+pub(crate) const MAX_SOCKETS: usize = sys::NRF_MODEM_MAX_SOCKET_COUNT as usize;
+
+/// How many random ports `bind_random_port` tries before giving up.
+const BIND_ATTEMPTS: usize = 8;
+
+/// An unused slot in the `SOCKET_REGISTRY`.
+const EMPTY_RECORD: Option<SocketRecord> = None;
+
 //******************************************************************************
 // Global Variables
 //******************************************************************************
 
-// None
+/// Every socket which is currently open.
+static SOCKET_REGISTRY: Mutex<RefCell<[Option<SocketRecord>; MAX_SOCKETS]>> =
+	Mutex::new(RefCell::new([EMPTY_RECORD; MAX_SOCKETS]));
+
+/// The `SocketRecord::id` for the next socket opened.
+static NEXT_SOCKET_ID: AtomicU32 = AtomicU32::new(1);
 
 //******************************************************************************
 // Macros
@@ -158,7 +297,14 @@ impl Socket {
 		if result < 0 {
 			Err(Error::Nordic("new_socket", result, get_last_error()))
 		} else {
-			Ok(Socket { fd: result })
+			let id = register_socket(result, protocol);
+			crate::observer::notify(|o| o.opened(result, protocol));
+			Ok(Socket {
+				fd: result,
+				id,
+				skt_type,
+				send_timeout_ms: Cell::new(0),
+			})
 		}
 	}
 
@@ -180,8 +326,385 @@ impl Socket {
 		}
 	}
 
+	/// Set how long blocking receives and sends wait, from
+	/// `timeouts::current()`. The modem's default (wait forever) is left
+	/// alone for any that are zero.
+	pub(crate) fn apply_default_timeouts(&self) -> Result<(), Error> {
+		let timeouts = crate::timeouts::current();
+		if timeouts.receive_ms != 0 {
+			self.set_receive_timeout(timeouts.receive_ms)?;
+		}
+		if timeouts.send_ms != 0 {
+			self.set_send_timeout(timeouts.send_ms)?;
+		}
+		Ok(())
+	}
+
+	/// Set how long blocking receives (`recv_wait`) on this socket wait,
+	/// instead of the default from `timeouts`. Zero means wait forever.
+	pub fn set_receive_timeout(&self, timeout_ms: u32) -> Result<(), Error> {
+		self.set_option(SocketOption::ReceiveTimeout(timeval(timeout_ms)))
+	}
+
+	/// Set how long blocking sends (`write`) on this socket wait, instead of
+	/// the default from `timeouts`. Zero means wait forever. Connects use
+	/// their own timeout, and then go back to this one.
+	pub fn set_send_timeout(&self, timeout_ms: u32) -> Result<(), Error> {
+		self.set_option(SocketOption::SendTimeout(timeval(timeout_ms)))?;
+		self.send_timeout_ms.set(timeout_ms);
+		Ok(())
+	}
+
+	/// Get how long blocking receives on this socket wait, as the modem has
+	/// it. Zero means wait forever.
+	pub fn receive_timeout(&self) -> Result<Duration, Error> {
+		let value = self.get_option(
+			"receive_timeout",
+			sys::NRF_SOL_SOCKET,
+			sys::NRF_SO_RCVTIMEO,
+			timeval(0),
+		)?;
+		Ok(duration(value))
+	}
+
+	/// Get how long blocking sends on this socket wait, as the modem has it.
+	/// Zero means wait forever. A connect in progress has its own timeout.
+	pub fn send_timeout(&self) -> Result<Duration, Error> {
+		let value = self.get_option(
+			"send_timeout",
+			sys::NRF_SOL_SOCKET,
+			sys::NRF_SO_SNDTIMEO,
+			timeval(0),
+		)?;
+		Ok(duration(value))
+	}
+
+	/// Send and receive on this socket through the PDN connection with this
+	/// PDN ID (see `pdn::pdn_id`), instead of the default one. Do this
+	/// before connecting.
+	pub fn bind_to_pdn(&self, pdn_id: u8) -> Result<(), Error> {
+		let mut name: heapless::String<8> = heapless::String::new();
+		write!(name, "pdn{}", pdn_id)?;
+		self.set_option(SocketOption::BindToDevice(&name))
+	}
+
+	/// Limit how long a connect can take, until `end_connect_timeout`.
+	pub(crate) fn start_connect_timeout(&self, timeout_ms: u32) -> Result<(), Error> {
+		self.set_option(SocketOption::SendTimeout(timeval(timeout_ms)))
+	}
+
+	/// Go back to the send timeout we had before connecting.
+	pub(crate) fn end_connect_timeout(&self) -> Result<(), Error> {
+		let timeout_ms = self.send_timeout_ms.get();
+		self.set_option(SocketOption::SendTimeout(timeval(timeout_ms)))
+	}
+
+	/// Bind the socket to this local port, on any local address.
+	pub(crate) fn bind_port(&self, port: u16) -> Result<(), Error> {
+		let addr = sys::nrf_sockaddr_in {
+			sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
+			sin_family: sys::NRF_AF_INET as i32,
+			sin_port: htons(port),
+			sin_addr: sys::nrf_in_addr { s_addr: 0 },
+		};
+		let result = unsafe {
+			sys::nrf_bind(
+				self.fd,
+				&addr as *const sys::nrf_sockaddr_in as *const _,
+				addr.sin_len as u32,
+			)
+		};
+		if result < 0 {
+			Err(Error::Nordic("bind", result, get_last_error()))
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Bind the socket to a random ephemeral port, trying another if it is
+	/// in use. Returns the port.
+	pub(crate) fn bind_random_port(&self) -> Result<u16, Error> {
+		let mut attempts = 0;
+		loop {
+			let port = crate::net::random_ephemeral_port();
+			attempts += 1;
+			match self.bind_port(port) {
+				Err(Error::Nordic(_, _, errno))
+					if errno == sys::NRF_EADDRINUSE as i32 && attempts < BIND_ATTEMPTS => {}
+				result => return result.map(|_| port),
+			}
+		}
+	}
+
+	/// Try to connect to each of the looked-up addresses in turn (with the
+	/// given port), until one works. Returns the result of the last
+	/// `nrf_connect`, or -1 if there were no addresses to try. Addresses the
+	/// destination policy denies are skipped, and if that was all of them
+	/// this fails with `Error::DestinationDenied`.
+	pub(crate) fn connect_any(&self, addresses: &AddrInfoList, port: u16) -> Result<i32, Error> {
+		let mut result = -1;
+		let mut denied = false;
+		let mut tried = false;
+		for addr in addresses.addresses(port) {
+			let peer = crate::net::SocketAddr::from(&crate::NrfSockAddrIn(addr));
+			if !crate::destinations::resolved_allowed(peer.ip(), port) {
+				denied = true;
+				continue;
+			}
+			debug!("Trying IP address {}", &crate::NrfSockAddrIn(addr));
+			tried = true;
+			result = unsafe {
+				sys::nrf_connect(
+					self.fd,
+					&addr as *const sys::nrf_sockaddr_in as *const _,
+					addr.sin_len as u32,
+				)
+			};
+			if result == 0 {
+				record_peer(self.fd, &addr);
+				break;
+			}
+		}
+		if denied && !tried {
+			Err(Error::DestinationDenied)
+		} else {
+			Ok(result)
+		}
+	}
+
+	/// Mark the socket as listening for incoming connections, queueing up to
+	/// `backlog` of them.
+	pub(crate) fn listen(&self, backlog: i32) -> Result<(), Error> {
+		let result = unsafe { sys::nrf_listen(self.fd, backlog) };
+		if result < 0 {
+			Err(Error::Nordic("listen", result, get_last_error()))
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Wait for an incoming connection on a listening socket. Returns a new
+	/// socket for the connection (using `protocol`, as the listening socket
+	/// does), and the address it came from.
+	pub(crate) fn accept(
+		&self,
+		protocol: SocketProtocol,
+	) -> Result<(Socket, sys::nrf_sockaddr_in), Error> {
+		let mut addr = sys::nrf_sockaddr_in {
+			sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
+			sin_family: sys::NRF_AF_INET as i32,
+			sin_port: 0,
+			sin_addr: sys::nrf_in_addr { s_addr: 0 },
+		};
+		let mut length = core::mem::size_of::<sys::nrf_sockaddr_in>() as u32;
+		let result = unsafe {
+			sys::nrf_accept(
+				self.fd,
+				&mut addr as *mut sys::nrf_sockaddr_in as *mut _,
+				&mut length,
+			)
+		};
+		if result < 0 {
+			return Err(Error::Nordic("accept", result, get_last_error()));
+		}
+		let id = register_socket(result, protocol);
+		crate::observer::notify(|o| o.opened(result, protocol));
+		record_peer(result, &addr);
+		let socket = Socket {
+			fd: result,
+			id,
+			skt_type: self.skt_type,
+			send_timeout_ms: Cell::new(0),
+		};
+		socket.apply_default_timeouts()?;
+		Ok((socket, addr))
+	}
+
+	/// Make calls on this socket return straight away (with `EAGAIN` or
+	/// `EINPROGRESS`) instead of blocking, or make them block again.
+	pub(crate) fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+		let flags = if nonblocking {
+			sys::NRF_O_NONBLOCK as i32
+		} else {
+			0
+		};
+		let result = unsafe { sys::nrf_fcntl(self.fd, sys::NRF_F_SETFL as i32, flags) };
+		if result < 0 {
+			Err(Error::Nordic("fcntl", result, get_last_error()))
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Read a socket option, starting from `value`, which also sets how big
+	/// it is.
+	pub(crate) fn get_option<T>(
+		&self,
+		what: &'static str,
+		level: u32,
+		name: u32,
+		mut value: T,
+	) -> Result<T, Error> {
+		let mut length = core::mem::size_of::<T>() as u32;
+		let result = unsafe {
+			sys::nrf_getsockopt(
+				self.fd,
+				level as i32,
+				name as i32,
+				&mut value as *mut T as *mut sys::ctypes::c_void,
+				&mut length,
+			)
+		};
+		if result < 0 {
+			Err(Error::Nordic(what, result, get_last_error()))
+		} else {
+			Ok(value)
+		}
+	}
+
+	/// Get (and clear) the error from the last operation on this socket, as
+	/// an `NRF_E*` value. Zero means no error.
+	pub(crate) fn take_error(&self) -> Result<i32, Error> {
+		let mut error: i32 = 0;
+		let mut length = core::mem::size_of::<i32>() as u32;
+		let result = unsafe {
+			sys::nrf_getsockopt(
+				self.fd,
+				sys::NRF_SOL_SOCKET as i32,
+				sys::NRF_SO_ERROR as i32,
+				&mut error as *mut i32 as *mut _,
+				&mut length,
+			)
+		};
+		if result < 0 {
+			Err(Error::Nordic("get_error", result, get_last_error()))
+		} else {
+			Ok(error)
+		}
+	}
+
+	/// Start connecting to the given address, without blocking. The socket
+	/// stays non-blocking until `connect_poll` says it is connected, or
+	/// either of them fails. Fails with `Error::DestinationDenied` if the
+	/// destination policy denies the address.
+	pub(crate) fn connect_start(
+		&self,
+		addr: &sys::nrf_sockaddr_in,
+	) -> Result<ConnectStatus, Error> {
+		let peer = crate::net::SocketAddr::from(&crate::NrfSockAddrIn(*addr));
+		if !crate::destinations::resolved_allowed(peer.ip(), peer.port()) {
+			return Err(Error::DestinationDenied);
+		}
+		let status = self.nonblocking_connect(addr, addr.sin_len)?;
+		if status == ConnectStatus::Connected {
+			record_peer(self.fd, addr);
+		}
+		Ok(status)
+	}
+
+	/// As `connect_start`, but to an IPv6 address, on an IPv6 socket. Follow
+	/// it with `connect_poll_ipv6`. The peer isn't recorded, as
+	/// `SocketRecord` only holds IPv4 addresses.
+	pub(crate) fn connect_start_ipv6(
+		&self,
+		addr: &sys::nrf_sockaddr_in6,
+	) -> Result<ConnectStatus, Error> {
+		let ip = crate::net::Ipv6Addr::from_octets(addr.sin6_addr.s6_addr);
+		if !crate::destinations::resolved_allowed(ip.into(), u16::from_be(addr.sin6_port)) {
+			return Err(Error::DestinationDenied);
+		}
+		self.nonblocking_connect(addr, addr.sin6_len)
+	}
+
+	/// See how a connect started with `connect_start` is going. `addr` is
+	/// the address given to `connect_start`.
+	pub(crate) fn connect_poll(&self, addr: &sys::nrf_sockaddr_in) -> Result<ConnectStatus, Error> {
+		let status = self.connect_finished()?;
+		if status == ConnectStatus::Connected {
+			record_peer(self.fd, addr);
+		}
+		Ok(status)
+	}
+
+	/// See how a connect started with `connect_start_ipv6` is going.
+	pub(crate) fn connect_poll_ipv6(&self) -> Result<ConnectStatus, Error> {
+		self.connect_finished()
+	}
+
+	/// Start a non-blocking `nrf_connect` to `addr`, which is `len` bytes
+	/// long. The socket is left non-blocking only if the connect is still
+	/// in progress.
+	fn nonblocking_connect<A>(&self, addr: &A, len: u8) -> Result<ConnectStatus, Error> {
+		self.set_nonblocking(true)?;
+		let result =
+			unsafe { sys::nrf_connect(self.fd, addr as *const A as *const _, u32::from(len)) };
+		if result == 0 {
+			self.set_nonblocking(false)?;
+			Ok(ConnectStatus::Connected)
+		} else if get_last_error() == sys::NRF_EINPROGRESS as i32 {
+			Ok(ConnectStatus::InProgress)
+		} else {
+			let error = get_last_error();
+			// Blocking again, as before - the connect's error is the one to report
+			let _ = self.set_nonblocking(false);
+			Err(Error::Nordic("connect_start", result, error))
+		}
+	}
+
+	/// See whether a non-blocking connect has finished, making the socket
+	/// blocking again if it has, whether it worked or not.
+	fn connect_finished(&self) -> Result<ConnectStatus, Error> {
+		let result = self.connect_progress();
+		match result {
+			Ok(ConnectStatus::InProgress) => {}
+			Ok(ConnectStatus::Connected) => self.set_nonblocking(false)?,
+			// Blocking again, as before - the connect's error is the one to report
+			Err(_) => {
+				let _ = self.set_nonblocking(false);
+			}
+		}
+		result
+	}
+
+	/// Has a non-blocking connect finished, and if so, did it work?
+	fn connect_progress(&self) -> Result<ConnectStatus, Error> {
+		let mut poll_list = [PollEntry::new(self, PollFlags::Write)];
+		if poll(&mut poll_list, 0)? == 0 {
+			return Ok(ConnectStatus::InProgress);
+		}
+		let error = self.take_error()?;
+		if error != 0 {
+			return Err(Error::Nordic("connect_poll", -1, error));
+		}
+		Ok(ConnectStatus::Connected)
+	}
+
+	/// Step a `connect_start` along, remembering in `connecting` where it
+	/// has got to.
+	pub(crate) fn poll_connecting(
+		&self,
+		connecting: &Cell<Connecting>,
+	) -> Result<ConnectStatus, Error> {
+		match connecting.get() {
+			// Either we connected in `connect_start` or we've already said so
+			Connecting::Idle => Ok(ConnectStatus::Connected),
+			Connecting::Failed(error) => Err(Error::Nordic("connect_poll", -1, error)),
+			Connecting::InProgress(addr) => {
+				let result = self.connect_poll(&addr);
+				match result {
+					Ok(ConnectStatus::InProgress) => {}
+					Ok(ConnectStatus::Connected) => connecting.set(Connecting::Idle),
+					Err(Error::Nordic(_, _, error)) => connecting.set(Connecting::Failed(error)),
+					Err(_) => {}
+				}
+				result
+			}
+		}
+	}
+
 	/// Perform a non-blocking write on the socket.
 	pub fn send(&self, buf: &[u8]) -> Result<Option<usize>, Error> {
+		self.check_link()?;
 		let length = buf.len();
 		let ptr = buf.as_ptr();
 		let result = unsafe {
@@ -198,26 +721,72 @@ impl Socket {
 		} else if result < 0 {
 			Err(Error::Nordic("send", result as i32, get_last_error()))
 		} else {
+			crate::observer::notify(|o| o.sent(self.fd, result as usize));
 			Ok(Some(result as usize))
 		}
 	}
 
-	/// Perform a blocking write on the socket.
+	/// Perform a blocking write on the socket. Fails with `Error::TimedOut`
+	/// if the send timeout runs out first.
 	pub fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+		self.check_link()?;
 		let length = buf.len();
 		let ptr = buf.as_ptr();
 		let result = unsafe { sys::nrf_write(self.fd, ptr as *const _, length as u32) };
-		if result < 0 {
+		if result == -1 && get_last_error() == sys::NRF_EAGAIN as i32 {
+			// Blocking, so this means the send timeout ran out
+			Err(Error::TimedOut)
+		} else if result < 0 {
 			Err(Error::Nordic("write", result as i32, get_last_error()))
 		} else {
+			crate::observer::notify(|o| o.sent(self.fd, result as usize));
 			Ok(result as usize)
 		}
 	}
 
+	/// Do blocking writes until all of `buf` has been sent. Fails with
+	/// `Error::Disconnected` if a write sends nothing, rather than trying
+	/// for ever.
+	pub fn write_all(&self, mut buf: &[u8]) -> Result<(), Error> {
+		while !buf.is_empty() {
+			let written = self.write(buf)?;
+			if written == 0 {
+				return Err(Error::Disconnected);
+			}
+			buf = buf.get(written..).unwrap_or(&[]);
+		}
+		Ok(())
+	}
+
 	/// Perform a non-blocking read on the socket. Will fill up none, some or
 	/// all of the given buffer. You must slice the buffer using the returned
 	/// `usize` value.
+	///
+	/// All the socket types report the same things in the same way:
+	///
+	/// * `Ok(None)` - there's no data yet. Try again later.
+	/// * `Ok(Some(n))` - `n` bytes were received. For a datagram socket `n`
+	///   can be zero, if an empty datagram arrived. For a stream socket it
+	///   is only zero if `buf` is empty, which libmodem isn't asked about.
+	/// * `Err(Error::Disconnected)` - the peer closed or reset the
+	///   connection.
+	/// * `Err(Error::LinkDown)` - the network link (e.g. the PDN connection)
+	///   went down. Once a `+CEREG` or `+CGEV` report handled by a
+	///   `UrcDispatcher` says so, every IP socket open at the time returns
+	///   this straight away, rather than waiting for libmodem to notice.
+	/// * `Err(Error::Nordic(..))` - some other error.
+	///
+	/// While receiving is paused (see `pause_receive`), this returns
+	/// `Ok(None)` without asking libmodem.
 	pub fn recv(&self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+		self.check_link()?;
+		if self.is_receive_paused() {
+			return Ok(None);
+		}
+		if buf.is_empty() && self.skt_type == SocketType::Stream {
+			// Zero bytes from a stream would otherwise look like the end
+			return Ok(Some(0));
+		}
 		let length = buf.len();
 		let ptr = buf.as_mut_ptr();
 		let result = unsafe {
@@ -228,28 +797,179 @@ impl Socket {
 				sys::NRF_MSG_DONTWAIT as i32,
 			)
 		};
-		if result == -1 && get_last_error() == sys::NRF_EAGAIN as i32 {
-			// This is EAGAIN
-			Ok(None)
-		} else if result < 0 {
-			Err(Error::Nordic("recv", result as i32, get_last_error()))
-		} else {
-			Ok(Some(result as usize))
-		}
+		self.recv_result("recv", result)
 	}
 
 	/// Perform a blocking read on the socket. Will fill up some or all of the
 	/// given buffer. You must slice the buffer using the returned `usize`
 	/// value.
+	///
+	/// The results are as for `recv`, except that this waits for data
+	/// rather than returning `Ok(None)`, and fails with `Error::TimedOut` if
+	/// the receive timeout runs out first. While receiving is paused, it
+	/// fails with `Error::ReceivePaused`, as it would otherwise wait for
+	/// ever.
 	pub fn recv_wait(&self, buf: &mut [u8]) -> Result<usize, Error> {
+		self.check_link()?;
+		if self.is_receive_paused() {
+			return Err(Error::ReceivePaused);
+		}
+		if buf.is_empty() && self.skt_type == SocketType::Stream {
+			return Ok(0);
+		}
 		let length = buf.len();
 		let ptr = buf.as_mut_ptr();
 		let result = unsafe { sys::nrf_recv(self.fd, ptr as *mut _, length as u32, 0) };
-		if result < 0 {
-			Err(Error::Nordic("recv_wait", result as i32, get_last_error()))
+		// Blocking, so running out of time is an error, not "no data"
+		self.recv_result("recv_wait", result)?
+			.ok_or(Error::TimedOut)
+	}
+
+	/// Perform a non-blocking read on the socket as `recv` does, also
+	/// returning the address the data came from, if the modem gives one.
+	pub(crate) fn recv_from(
+		&self,
+		buf: &mut [u8],
+	) -> Result<Option<(usize, Option<crate::net::SocketAddr>)>, Error> {
+		self.check_link()?;
+		if self.is_receive_paused() {
+			return Ok(None);
+		}
+		let length = buf.len();
+		let ptr = buf.as_mut_ptr();
+		// Big enough for either family
+		let mut addr = sys::nrf_sockaddr_in6 {
+			sin6_len: 0,
+			sin6_family: 0,
+			sin6_port: 0,
+			sin6_flowinfo: 0,
+			sin6_addr: sys::nrf_in6_addr { s6_addr: [0; 16] },
+			sin6_scope_id: 0,
+		};
+		let mut addr_len = core::mem::size_of::<sys::nrf_sockaddr_in6>() as sys::nrf_socklen_t;
+		let result = unsafe {
+			sys::nrf_recvfrom(
+				self.fd,
+				ptr as *mut _,
+				length as u32,
+				sys::NRF_MSG_DONTWAIT as i32,
+				&mut addr as *mut sys::nrf_sockaddr_in6 as *mut _,
+				&mut addr_len,
+			)
+		};
+		Ok(self
+			.recv_result("recv_from", result)?
+			.map(|length| (length, source_address(&addr))))
+	}
+
+	/// Look at what has been received, without taking it: the next `recv`
+	/// gets the same data. Fills as much of `buf` as it can (on a datagram
+	/// socket, with the next datagram) and returns how much, or `Ok(None)`
+	/// if nothing is waiting. Unlike `recv`, this works while receiving is
+	/// paused. See also `flow::RxWatermark`.
+	pub fn peek(&self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+		self.check_link()?;
+		let length = buf.len();
+		let ptr = buf.as_mut_ptr();
+		let result = unsafe {
+			sys::nrf_recv(
+				self.fd,
+				ptr as *mut _,
+				length as u32,
+				(sys::NRF_MSG_DONTWAIT | sys::NRF_MSG_PEEK) as i32,
+			)
+		};
+		recv_status(
+			"peek",
+			self.skt_type == SocketType::Stream,
+			result,
+			get_last_error(),
+		)
+	}
+
+	/// Stop reading from this socket, e.g. because the queue its data goes
+	/// into is full. Whatever arrives stays in the modem, and for a TCP
+	/// socket the receive window closes once the modem's buffer is full, so
+	/// the peer stops sending. `recv` returns `Ok(None)` until
+	/// `resume_receive` is called. See also `flow::RxFlowControl`.
+	pub fn pause_receive(&self) {
+		self.set_receive_paused(true);
+	}
+
+	/// Start reading from this socket again, after `pause_receive`. As data
+	/// may have been waiting all along, the application is told data may be
+	/// ready (see `set_data_ready_notification`), so async tasks try again.
+	pub fn resume_receive(&self) {
+		if self.set_receive_paused(false) {
+			crate::api::notify_data_ready();
+		}
+	}
+
+	/// Is receiving paused (see `pause_receive`)?
+	pub fn is_receive_paused(&self) -> bool {
+		cortex_m::interrupt::free(|cs| {
+			SOCKET_REGISTRY
+				.borrow(cs)
+				.borrow()
+				.iter()
+				.flatten()
+				.any(|record| record.fd == self.fd && record.id == self.id && record.receive_paused)
+		})
+	}
+
+	/// Pause or resume receiving. Returns whether that changed anything.
+	fn set_receive_paused(&self, paused: bool) -> bool {
+		let changed = cortex_m::interrupt::free(|cs| {
+			let mut registry = SOCKET_REGISTRY.borrow(cs).borrow_mut();
+			match registry
+				.iter_mut()
+				.flatten()
+				.find(|record| record.fd == self.fd && record.id == self.id)
+			{
+				Some(record) if record.receive_paused != paused => {
+					record.receive_paused = paused;
+					true
+				}
+				_ => false,
+			}
+		});
+		if changed {
+			debug!("Socket {} receive paused: {}", self.fd, paused);
+		}
+		changed
+	}
+
+	/// Fail with `Error::LinkDown` if the link went down while this socket
+	/// was open (see `mark_link_lost`).
+	fn check_link(&self) -> Result<(), Error> {
+		let lost = cortex_m::interrupt::free(|cs| {
+			SOCKET_REGISTRY
+				.borrow(cs)
+				.borrow()
+				.iter()
+				.flatten()
+				.any(|record| record.fd == self.fd && record.id == self.id && record.link_lost)
+		});
+		if lost {
+			Err(Error::LinkDown)
 		} else {
-			Ok(result as usize)
+			Ok(())
+		}
+	}
+
+	/// Turn what `nrf_recv` returned into our `recv` model (see
+	/// `errno::recv_status`), and tell the observer what was received.
+	fn recv_result(&self, name: &'static str, result: i32) -> Result<Option<usize>, Error> {
+		let status = recv_status(
+			name,
+			self.skt_type == SocketType::Stream,
+			result,
+			get_last_error(),
+		)?;
+		if let Some(length) = status {
+			crate::observer::notify(|o| o.received(self.fd, length));
 		}
+		Ok(status)
 	}
 }
 
@@ -264,8 +984,13 @@ impl core::fmt::Write for Socket {
 
 impl Drop for Socket {
 	fn drop(&mut self) {
-		unsafe {
-			let _ = sys::nrf_close(self.fd);
+		// If `close_sockets` got here first, the socket is closed already,
+		// and `fd` may now belong to someone else.
+		if unregister_socket(self.fd, self.id) {
+			unsafe {
+				let _ = sys::nrf_close(self.fd);
+			}
+			crate::observer::notify(|o| o.closed(self.fd));
 		}
 	}
 }
@@ -277,11 +1002,22 @@ impl<'a> SocketOption<'a> {
 			SocketOption::TlsPeerVerify(_) => sys::NRF_SOL_SECURE as i32,
 			SocketOption::TlsSessionCache(_) => sys::NRF_SOL_SECURE as i32,
 			SocketOption::TlsTagList(_) => sys::NRF_SOL_SECURE as i32,
+			SocketOption::TlsRole(_) => sys::NRF_SOL_SECURE as i32,
 			SocketOption::GnssFixInterval(_) => sys::NRF_SOL_GNSS as i32,
 			SocketOption::GnssFixRetry(_) => sys::NRF_SOL_GNSS as i32,
 			SocketOption::GnssNmeaMask(_) => sys::NRF_SOL_GNSS as i32,
+			SocketOption::GnssElevationMask(_) => sys::NRF_SOL_GNSS as i32,
+			SocketOption::GnssUseCase(_) => sys::NRF_SOL_GNSS as i32,
+			SocketOption::GnssPowerSaveMode(_) => sys::NRF_SOL_GNSS as i32,
 			SocketOption::GnssStart(_) => sys::NRF_SOL_GNSS as i32,
 			SocketOption::GnssStop => sys::NRF_SOL_GNSS as i32,
+			SocketOption::DfuApply => sys::NRF_SOL_DFU as i32,
+			SocketOption::DfuRevert => sys::NRF_SOL_DFU as i32,
+			SocketOption::DfuBackupDelete => sys::NRF_SOL_DFU as i32,
+			SocketOption::DfuOffset(_) => sys::NRF_SOL_DFU as i32,
+			SocketOption::ReceiveTimeout(_) => sys::NRF_SOL_SOCKET as i32,
+			SocketOption::SendTimeout(_) => sys::NRF_SOL_SOCKET as i32,
+			SocketOption::BindToDevice(_) => sys::NRF_SOL_SOCKET as i32,
 		}
 	}
 
@@ -291,11 +1027,22 @@ impl<'a> SocketOption<'a> {
 			SocketOption::TlsPeerVerify(_) => sys::NRF_SO_SEC_PEER_VERIFY as i32,
 			SocketOption::TlsSessionCache(_) => sys::NRF_SO_SEC_SESSION_CACHE as i32,
 			SocketOption::TlsTagList(_) => sys::NRF_SO_SEC_TAG_LIST as i32,
+			SocketOption::TlsRole(_) => sys::NRF_SO_SEC_ROLE as i32,
 			SocketOption::GnssFixInterval(_) => sys::NRF_SO_GNSS_FIX_INTERVAL as i32,
 			SocketOption::GnssFixRetry(_) => sys::NRF_SO_GNSS_FIX_RETRY as i32,
 			SocketOption::GnssNmeaMask(_) => sys::NRF_SO_GNSS_NMEA_MASK as i32,
+			SocketOption::GnssElevationMask(_) => sys::NRF_SO_GNSS_ELEVATION_MASK as i32,
+			SocketOption::GnssUseCase(_) => sys::NRF_SO_GNSS_USE_CASE as i32,
+			SocketOption::GnssPowerSaveMode(_) => sys::NRF_SO_GNSS_POWER_SAVE_MODE as i32,
 			SocketOption::GnssStart(_) => sys::NRF_SO_GNSS_START as i32,
 			SocketOption::GnssStop => sys::NRF_SO_GNSS_STOP as i32,
+			SocketOption::DfuApply => sys::NRF_SO_DFU_APPLY as i32,
+			SocketOption::DfuRevert => sys::NRF_SO_DFU_REVERT as i32,
+			SocketOption::DfuBackupDelete => sys::NRF_SO_DFU_BACKUP_DELETE as i32,
+			SocketOption::DfuOffset(_) => sys::NRF_SO_DFU_OFFSET as i32,
+			SocketOption::ReceiveTimeout(_) => sys::NRF_SO_RCVTIMEO as i32,
+			SocketOption::SendTimeout(_) => sys::NRF_SO_SNDTIMEO as i32,
+			SocketOption::BindToDevice(_) => sys::NRF_SO_BINDTODEVICE as i32,
 		}
 	}
 
@@ -305,11 +1052,22 @@ impl<'a> SocketOption<'a> {
 			SocketOption::TlsPeerVerify(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::TlsSessionCache(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::TlsTagList(x) => x.as_ptr() as *const sys::ctypes::c_void,
+			SocketOption::TlsRole(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::GnssFixInterval(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::GnssFixRetry(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::GnssNmeaMask(x) => x as *const _ as *const sys::ctypes::c_void,
+			SocketOption::GnssElevationMask(x) => x as *const _ as *const sys::ctypes::c_void,
+			SocketOption::GnssUseCase(x) => x as *const _ as *const sys::ctypes::c_void,
+			SocketOption::GnssPowerSaveMode(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::GnssStart(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::GnssStop => core::ptr::null(),
+			SocketOption::DfuApply => core::ptr::null(),
+			SocketOption::DfuRevert => core::ptr::null(),
+			SocketOption::DfuBackupDelete => core::ptr::null(),
+			SocketOption::DfuOffset(x) => x as *const _ as *const sys::ctypes::c_void,
+			SocketOption::ReceiveTimeout(x) => x as *const _ as *const sys::ctypes::c_void,
+			SocketOption::SendTimeout(x) => x as *const _ as *const sys::ctypes::c_void,
+			SocketOption::BindToDevice(s) => s.as_ptr() as *const sys::ctypes::c_void,
 		}
 	}
 
@@ -319,11 +1077,22 @@ impl<'a> SocketOption<'a> {
 			SocketOption::TlsPeerVerify(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::TlsSessionCache(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::TlsTagList(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::TlsRole(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::GnssFixInterval(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::GnssFixRetry(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::GnssNmeaMask(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::GnssElevationMask(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::GnssUseCase(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::GnssPowerSaveMode(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::GnssStart(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::GnssStop => 0u32,
+			SocketOption::DfuApply => 0u32,
+			SocketOption::DfuRevert => 0u32,
+			SocketOption::DfuBackupDelete => 0u32,
+			SocketOption::DfuOffset(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::ReceiveTimeout(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::SendTimeout(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::BindToDevice(s) => s.len() as u32,
 		}
 	}
 }
@@ -335,6 +1104,7 @@ impl From<SocketDomain> for i32 {
 			Local => sys::NRF_AF_LOCAL as i32,
 			Lte => sys::NRF_AF_LTE as i32,
 			Inet => sys::NRF_AF_INET as i32,
+			Inet6 => sys::NRF_AF_INET6 as i32,
 		}
 	}
 }
@@ -360,6 +1130,7 @@ impl From<SocketProtocol> for i32 {
 			Tls1v3 => sys::NRF_SPROTO_TLS1v3 as i32,
 			Dtls1v2 => sys::NRF_SPROTO_DTLS1v2 as i32,
 			Gnss => sys::NRF_PROTO_GNSS as i32,
+			Dfu => sys::NRF_PROTO_DFU as i32,
 		}
 	}
 }
@@ -478,10 +1249,369 @@ pub fn poll(poll_list: &mut [PollEntry], timeout_ms: u16) -> Result<i32, Error>
 	}
 }
 
+/// Get a list of all the sockets which are currently open.
+pub fn open_sockets() -> heapless::Vec<SocketRecord, MAX_SOCKETS> {
+	cortex_m::interrupt::free(|cs| {
+		SOCKET_REGISTRY
+			.borrow(cs)
+			.borrow()
+			.iter()
+			.flatten()
+			.cloned()
+			.collect()
+	})
+}
+
 //******************************************************************************
 // Private Functions and Impl on Private Types
 //******************************************************************************
 
+impl AddrInfoList {
+	/// Look up a hostname's IPv4 addresses, for sockets of the given type.
+	/// If it fails, the error uses `name` to say what was being done.
+	pub(crate) fn lookup(
+		hostname: &str,
+		skt_type: SocketType,
+		name: &'static str,
+	) -> Result<AddrInfoList, Error> {
+		Self::lookup_in(SocketDomain::Inet, hostname, skt_type, name)
+	}
+
+	/// Look up a hostname's addresses in the given family (`Inet` or
+	/// `Inet6`), for sockets of the given type.
+	pub(crate) fn lookup_in(
+		domain: SocketDomain,
+		hostname: &str,
+		skt_type: SocketType,
+		name: &'static str,
+	) -> Result<AddrInfoList, Error> {
+		Self::lookup_on(domain, hostname, skt_type, None, name)
+	}
+
+	/// Look up a hostname's addresses as `lookup_in` does, asking the DNS
+	/// servers of the PDN connection to this APN, or of the default one if
+	/// `apn` is `None`.
+	pub(crate) fn lookup_on(
+		domain: SocketDomain,
+		hostname: &str,
+		skt_type: SocketType,
+		apn: Option<&str>,
+		name: &'static str,
+	) -> Result<AddrInfoList, Error> {
+		// Make a null-terminated hostname
+		let mut hostname_smallstring: heapless::String<64> = heapless::String::new();
+		write!(hostname_smallstring, "{}\0", hostname).map_err(|_| Error::HostnameTooLong)?;
+		// And APN, which libmodem takes from a second hints record
+		let mut apn_smallstring: heapless::String<{ crate::pdn::MAX_APN_LEN + 1 }> =
+			heapless::String::new();
+		let pdn_hints = match apn {
+			Some(apn) => {
+				write!(apn_smallstring, "{}\0", apn).map_err(|_| Error::BufferFull)?;
+				Some(sys::nrf_addrinfo {
+					ai_flags: 0,
+					ai_family: sys::NRF_AF_LTE as i32,
+					ai_socktype: sys::NRF_SOCK_MGMT as i32,
+					ai_protocol: sys::NRF_PROTO_PDN as i32,
+					ai_addrlen: 0,
+					ai_addr: core::ptr::null_mut(),
+					ai_canonname: apn_smallstring.as_ptr() as *mut _,
+					ai_next: core::ptr::null_mut(),
+				})
+			}
+			None => None,
+		};
+		let hints = sys::nrf_addrinfo {
+			ai_flags: 0,
+			ai_family: domain.into(),
+			ai_socktype: skt_type.into(),
+			ai_protocol: 0,
+			ai_addrlen: 0,
+			ai_addr: core::ptr::null_mut(),
+			ai_canonname: core::ptr::null_mut(),
+			ai_next: match pdn_hints.as_ref() {
+				// libmodem only reads the hints
+				Some(pdn_hints) => pdn_hints as *const _ as *mut _,
+				None => core::ptr::null_mut(),
+			},
+		};
+		let mut output_ptr: *mut sys::nrf_addrinfo = core::ptr::null_mut();
+		crate::dns::with_timeout(|| {
+			let result = unsafe {
+				sys::nrf_getaddrinfo(
+					hostname_smallstring.as_ptr(),
+					core::ptr::null(),
+					&hints,
+					&mut output_ptr,
+				)
+			};
+			if output_ptr.is_null() {
+				return Err(Error::Nordic(name, result, get_last_error()));
+			}
+			// Whatever `result` says, we own the list now, and free it on drop
+			let list = AddrInfoList { head: output_ptr };
+			if result != 0 {
+				return Err(Error::Nordic(name, result, get_last_error()));
+			}
+			Ok(list)
+		})
+	}
+
+	/// Iterate through the IPv4 addresses found, each with the given port.
+	pub(crate) fn addresses(&self, port: u16) -> Addresses<'_> {
+		Addresses {
+			// Safe, as `head` is never null, and the list lives as long as we
+			// do
+			record: Some(unsafe { &*self.head }),
+			port,
+		}
+	}
+
+	/// Iterate through the IPv6 addresses found, each with the given port.
+	pub(crate) fn ipv6_addresses(&self, port: u16) -> Ipv6Addresses<'_> {
+		Ipv6Addresses {
+			// Safe, as for `addresses`
+			record: Some(unsafe { &*self.head }),
+			port,
+		}
+	}
+}
+
+impl Drop for AddrInfoList {
+	fn drop(&mut self) {
+		unsafe {
+			sys::nrf_freeaddrinfo(self.head);
+		}
+	}
+}
+
+impl<'a> Iterator for Addresses<'a> {
+	type Item = sys::nrf_sockaddr_in;
+
+	fn next(&mut self) -> Option<sys::nrf_sockaddr_in> {
+		while let Some(record) = self.record {
+			// Safe, as every `ai_next` is null or points into the same list
+			self.record = unsafe { record.ai_next.as_ref() };
+			if record.ai_family != sys::NRF_AF_INET as i32 || record.ai_addr.is_null() {
+				continue;
+			}
+			let dns_addr: &sys::nrf_sockaddr_in =
+				unsafe { &*(record.ai_addr as *const sys::nrf_sockaddr_in) };
+			// Create a new sockaddr_in with the right port
+			return Some(sys::nrf_sockaddr_in {
+				sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
+				sin_family: sys::NRF_AF_INET as i32,
+				sin_port: htons(self.port),
+				sin_addr: dns_addr.sin_addr,
+			});
+		}
+		None
+	}
+}
+
+impl<'a> Iterator for Ipv6Addresses<'a> {
+	type Item = sys::nrf_sockaddr_in6;
+
+	fn next(&mut self) -> Option<sys::nrf_sockaddr_in6> {
+		while let Some(record) = self.record {
+			// Safe, as every `ai_next` is null or points into the same list
+			self.record = unsafe { record.ai_next.as_ref() };
+			if record.ai_family != sys::NRF_AF_INET6 as i32 || record.ai_addr.is_null() {
+				continue;
+			}
+			let dns_addr: &sys::nrf_sockaddr_in6 =
+				unsafe { &*(record.ai_addr as *const sys::nrf_sockaddr_in6) };
+			// Copy it with the right port
+			return Some(sys::nrf_sockaddr_in6 {
+				sin6_len: core::mem::size_of::<sys::nrf_sockaddr_in6>() as u8,
+				sin6_family: sys::NRF_AF_INET6 as i32,
+				sin6_port: htons(self.port),
+				..*dns_addr
+			});
+		}
+		None
+	}
+}
+
+/// Look up a hostname, and return the first IPv4 address found with the
+/// given port.
+pub(crate) fn lookup_ipv4(
+	hostname: &str,
+	skt_type: SocketType,
+	port: u16,
+) -> Result<sys::nrf_sockaddr_in, Error> {
+	AddrInfoList::lookup(hostname, skt_type, "dns")?
+		.addresses(port)
+		.next()
+		.ok_or(Error::BadDataFormat)
+}
+
+/// Remember the address a socket has connected to, and tell the observer.
+pub(crate) fn record_peer(fd: i32, peer: &sys::nrf_sockaddr_in) {
+	let peer = crate::NrfSockAddrIn(*peer);
+	cortex_m::interrupt::free(|cs| {
+		let mut registry = SOCKET_REGISTRY.borrow(cs).borrow_mut();
+		for record in registry.iter_mut().flatten() {
+			if record.fd == fd {
+				record.peer = Some(peer.clone());
+			}
+		}
+	});
+	crate::observer::notify(|o| o.connected(fd, &peer));
+}
+
+/// Close every open socket for which `filter` returns true, as
+/// `net::close_all` describes. Returns how many were closed.
+pub(crate) fn close_sockets<F>(filter: F) -> usize
+where
+	F: Fn(&SocketRecord) -> bool,
+{
+	// Take them out of the registry first, so their `Socket`s know not to
+	// close them again when dropped.
+	let closing: heapless::Vec<SocketRecord, MAX_SOCKETS> = cortex_m::interrupt::free(|cs| {
+		let mut registry = SOCKET_REGISTRY.borrow(cs).borrow_mut();
+		registry
+			.iter_mut()
+			.filter(|slot| matches!(slot, Some(record) if filter(record)))
+			.filter_map(|slot| slot.take())
+			.collect()
+	});
+	for record in closing.iter() {
+		debug!("Closing socket {} ({:?})", record.fd, record.protocol);
+		unsafe {
+			let _ = sys::nrf_close(record.fd);
+		}
+		crate::observer::notify(|o| o.closed(record.fd));
+	}
+	closing.len()
+}
+
+/// Read the address `nrf_recvfrom` filled in, if it did.
+fn source_address(addr: &sys::nrf_sockaddr_in6) -> Option<crate::net::SocketAddr> {
+	if addr.sin6_family as u32 == sys::NRF_AF_INET6 {
+		let ip = crate::net::Ipv6Addr::from_octets(addr.sin6_addr.s6_addr);
+		Some(crate::net::SocketAddr::new(
+			crate::net::IpAddr::V6(ip),
+			u16::from_be(addr.sin6_port),
+		))
+	} else if addr.sin6_family as u32 == sys::NRF_AF_INET {
+		// NOTE(unsafe) - an IPv4 address is smaller than an IPv6 one, and
+		// starts with the same fields
+		let addr =
+			unsafe { &*(addr as *const sys::nrf_sockaddr_in6 as *const sys::nrf_sockaddr_in) };
+		Some(crate::net::SocketAddr::from(&crate::NrfSockAddrIn(*addr)))
+	} else {
+		None
+	}
+}
+
+/// Mark every open IP socket as having lost its link, so it fails with
+/// `Error::LinkDown` from now on. Called when registration or the default
+/// PDN connection is lost. Returns how many were marked.
+pub(crate) fn mark_link_lost() -> usize {
+	let marked = cortex_m::interrupt::free(|cs| {
+		let mut registry = SOCKET_REGISTRY.borrow(cs).borrow_mut();
+		let mut marked = 0;
+		for record in registry.iter_mut().flatten() {
+			let ip = !matches!(
+				record.protocol,
+				SocketProtocol::At | SocketProtocol::Gnss | SocketProtocol::Dfu
+			);
+			if ip && !record.link_lost {
+				record.link_lost = true;
+				marked += 1;
+			}
+		}
+		marked
+	});
+	debug!("Link lost: {} socket(s) marked", marked);
+	marked
+}
+
+/// Add a newly opened socket to the registry. Returns its `SocketRecord::id`.
+fn register_socket(fd: i32, protocol: SocketProtocol) -> u32 {
+	let id = NEXT_SOCKET_ID.fetch_add(1, Ordering::Relaxed);
+	cortex_m::interrupt::free(|cs| {
+		let mut registry = SOCKET_REGISTRY.borrow(cs).borrow_mut();
+		// There is a slot for as many sockets as libmodem allows
+		if let Some(slot) = registry.iter_mut().find(|slot| slot.is_none()) {
+			*slot = Some(SocketRecord {
+				fd,
+				id,
+				protocol,
+				peer: None,
+				link_lost: false,
+				receive_paused: false,
+			});
+		}
+	});
+	id
+}
+
+/// Remove a socket from the registry, as it is closed. Returns false if it
+/// wasn't there - because `close_sockets` has closed it already.
+fn unregister_socket(fd: i32, id: u32) -> bool {
+	cortex_m::interrupt::free(|cs| {
+		let mut registry = SOCKET_REGISTRY.borrow(cs).borrow_mut();
+		for slot in registry.iter_mut() {
+			if matches!(slot, Some(record) if record.fd == fd && record.id == id) {
+				*slot = None;
+				return true;
+			}
+		}
+		false
+	})
+}
+
+/// Wait for up to `timeout_ms` for a socket to become readable. Zero means
+/// wait forever. Returns `Error::TimedOut` if it doesn't.
+///
+/// When waiting forever this returns straight away, and the caller tries
+/// again - unless there is a watchdog feeder, in which case we wait for up to
+/// `watchdog::FEED_INTERVAL_MS` and feed the watchdog first.
+pub(crate) fn wait_readable(socket: &dyn Pollable, timeout_ms: u32) -> Result<(), Error> {
+	let feeding = crate::watchdog::is_set();
+	let chunk_ms = if feeding {
+		crate::watchdog::FEED_INTERVAL_MS
+	} else {
+		u16::MAX
+	};
+	if timeout_ms == 0 {
+		if feeding {
+			let mut poll_list = [PollEntry::new(socket, PollFlags::Read)];
+			poll(&mut poll_list, chunk_ms)?;
+			crate::watchdog::feed();
+		}
+		return Ok(());
+	}
+	// `poll` takes at most a `u16` of milliseconds, so go round as often as
+	// we need to.
+	let mut remaining_ms = timeout_ms;
+	while remaining_ms > 0 {
+		let wait_ms = core::cmp::min(remaining_ms, u32::from(chunk_ms)) as u16;
+		let mut poll_list = [PollEntry::new(socket, PollFlags::Read)];
+		let ready = poll(&mut poll_list, wait_ms)? != 0;
+		crate::watchdog::feed();
+		if ready {
+			return Ok(());
+		}
+		remaining_ms -= u32::from(wait_ms);
+	}
+	Err(Error::TimedOut)
+}
+
+/// Convert milliseconds into the structure the timeout socket options take.
+pub(crate) fn timeval(ms: u32) -> sys::nrf_timeval {
+	sys::nrf_timeval {
+		tv_sec: ms / 1000,
+		tv_usec: (ms % 1000) * 1000,
+	}
+}
+
+/// Convert what the timeout socket options give back into a `Duration`.
+fn duration(value: sys::nrf_timeval) -> Duration {
+	Duration::from_secs(u64::from(value.tv_sec)) + Duration::from_micros(u64::from(value.tv_usec))
+}
+
 pub(crate) fn htons(input: u16) -> u16 {
 	let top: u16 = (input >> 8) & 0xFF;
 	let bottom: u16 = input & 0xFF;
@@ -0,0 +1,264 @@
+//! # Deferring sends while roaming for nrfxlib
+//!
+//! Data sent while the SIM is roaming can cost many times what it does at
+//! home. A `RoamingGate` sits in front of a connected socket and holds back
+//! the sends you mark `Urgency::Deferrable` for as long as the modem is
+//! registered on a roaming network, sending them once it is back on the home
+//! network - or, if that takes too long, once they have waited `ttl_ms`:
+//!
+//! ```ignore
+//! let mut gate: RoamingGate<_, 8, 512> =
+//!     RoamingGate::new(&socket, 6 * 3600 * 1000, OnExpiry::Send, now_ms);
+//! gate.send(&alarm, Urgency::Urgent)?;
+//! gate.send(&reading, Urgency::Deferrable)?;
+//! // Later, from the main loop
+//! gate.poll()?;
+//! ```
+//!
+//! Roaming is judged from the registration status the modem last reported
+//! (see `modem::last_registration_status`), so nothing is sent to the modem
+//! to find out. Until a status has been seen, we assume we are at home.
+//!
+//! Urgent sends always go straight away. A deferrable send made while at
+//! home first sends anything held back, so the other end sees the payloads
+//! in the order they were given. Each payload is sent on its own, so on a
+//! UDP socket each is one datagram.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::modem::RegistrationStatus;
+use crate::raw::Socket;
+use crate::Error;
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Whether a send can wait until we are back on the home network.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Urgency {
+	/// Send it now, wherever we are
+	Urgent,
+	/// Hold it back while roaming
+	Deferrable,
+}
+
+/// What to do with a payload which has been held back for `ttl_ms`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OnExpiry {
+	/// Send it anyway, roaming or not
+	Send,
+	/// Throw it away
+	Drop,
+}
+
+/// What `RoamingGate::send` did with a payload.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+	/// It has been sent
+	Sent,
+	/// It is being held back until we are home, or it expires
+	Deferred,
+}
+
+/// Holds back deferrable sends on a socket while roaming. See the module
+/// documentation.
+///
+/// Up to `N` payloads can be held back, sharing `SIZE` bytes of storage.
+#[derive(Debug)]
+pub struct RoamingGate<'s, S, const N: usize, const SIZE: usize>
+where
+	S: core::ops::Deref<Target = Socket>,
+{
+	socket: &'s S,
+	data: heapless::Vec<u8, SIZE>,
+	held: heapless::Vec<Held, N>,
+	ttl_ms: u32,
+	on_expiry: OnExpiry,
+	now_ms: fn() -> u32,
+}
+
+/// A payload being held back, oldest first.
+#[derive(Debug, Copy, Clone)]
+struct Held {
+	/// How many bytes of `RoamingGate::data` it takes
+	len: usize,
+	/// When it was given to `send`
+	deferred_ms: u32,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Is the modem registered on a roaming network, as far as we know?
+pub fn is_roaming() -> bool {
+	crate::modem::last_registration_status() == Some(RegistrationStatus::RegisteredRoaming)
+}
+
+impl<'s, S, const N: usize, const SIZE: usize> RoamingGate<'s, S, N, SIZE>
+where
+	S: core::ops::Deref<Target = Socket>,
+{
+	/// Gate sends on the given socket (e.g. a `UdpSocket` or `TlsSocket`),
+	/// which should already be connected. A deferrable payload is held back
+	/// for at most `ttl_ms`, as measured by `now_ms`, and then dealt with as
+	/// `on_expiry` says.
+	pub fn new(socket: &'s S, ttl_ms: u32, on_expiry: OnExpiry, now_ms: fn() -> u32) -> Self {
+		RoamingGate {
+			socket,
+			data: heapless::Vec::new(),
+			held: heapless::Vec::new(),
+			ttl_ms,
+			on_expiry,
+			now_ms,
+		}
+	}
+
+	/// Send a payload, or hold it back if it is deferrable and we are
+	/// roaming. Fails with `Error::BufferFull`, holding nothing back, if
+	/// there is no room for it.
+	pub fn send(&mut self, payload: &[u8], urgency: Urgency) -> Result<Outcome, Error> {
+		if urgency == Urgency::Urgent {
+			self.socket.write_all(payload)?;
+			return Ok(Outcome::Sent);
+		}
+		if !is_roaming() {
+			self.flush()?;
+			self.socket.write_all(payload)?;
+			return Ok(Outcome::Sent);
+		}
+		if self.held.is_full() || (SIZE - self.data.len()) < payload.len() {
+			return Err(Error::BufferFull);
+		}
+		// We checked there was room above
+		let _ = self.data.extend_from_slice(payload);
+		let _ = self.held.push(Held {
+			len: payload.len(),
+			deferred_ms: (self.now_ms)(),
+		});
+		debug!(
+			"Roaming, deferred {} bytes ({} payloads held)",
+			payload.len(),
+			self.held.len()
+		);
+		Ok(Outcome::Deferred)
+	}
+
+	/// Send everything held back if we are home, or deal with any payloads
+	/// which have expired if we are still roaming. Returns how many payloads
+	/// were sent or dropped.
+	pub fn poll(&mut self) -> Result<usize, Error> {
+		if self.held.is_empty() {
+			return Ok(0);
+		}
+		if !is_roaming() {
+			return self.flush();
+		}
+		let now_ms = (self.now_ms)();
+		let expired = self
+			.held
+			.iter()
+			.take_while(|held| now_ms.wrapping_sub(held.deferred_ms) >= self.ttl_ms)
+			.count();
+		if expired > 0 {
+			debug!(
+				"{} deferred payloads expired ({:?})",
+				expired, self.on_expiry
+			);
+		}
+		self.release(expired, self.on_expiry == OnExpiry::Send)
+	}
+
+	/// Send everything held back now, roaming or not. Returns how many
+	/// payloads were sent.
+	pub fn flush(&mut self) -> Result<usize, Error> {
+		self.release(self.held.len(), true)
+	}
+
+	/// How many payloads are being held back.
+	pub fn pending(&self) -> usize {
+		self.held.len()
+	}
+
+	/// How many milliseconds until the oldest payload held back expires, or
+	/// `None` if nothing is held back.
+	pub fn next_expiry_ms(&self) -> Option<u32> {
+		self.held.first().map(|held| {
+			let waited_ms = (self.now_ms)().wrapping_sub(held.deferred_ms);
+			self.ttl_ms.saturating_sub(waited_ms)
+		})
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl<'s, S, const N: usize, const SIZE: usize> RoamingGate<'s, S, N, SIZE>
+where
+	S: core::ops::Deref<Target = Socket>,
+{
+	/// Take the oldest `count` payloads off the queue, sending them if
+	/// `send` is set. A payload which fails to send stays queued, along with
+	/// everything after it.
+	fn release(&mut self, count: usize, send: bool) -> Result<usize, Error> {
+		let mut released = 0;
+		let mut offset = 0;
+		let mut result = Ok(());
+		for held in self.held.iter().take(count) {
+			if send {
+				let data = self.data.get(offset..offset + held.len).unwrap_or(&[]);
+				result = self.socket.write_all(data);
+				if result.is_err() {
+					break;
+				}
+			}
+			offset += held.len;
+			released += 1;
+		}
+		if released > 0 {
+			self.data.copy_within(offset.., 0);
+			self.data.truncate(self.data.len() - offset);
+			self.held.rotate_left(released);
+			self.held.truncate(self.held.len() - released);
+		}
+		result.map(|_| released)
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
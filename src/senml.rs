@@ -0,0 +1,328 @@
+//! # SenML encoding for nrfxlib
+//!
+//! Encodes measurements as SenML-CBOR (RFC 8428), the compact format used by
+//! LwM2M and IPSO style backends. Everything is written straight into a
+//! caller-supplied buffer, so no heap is required.
+//!
+//! For example:
+//!
+//! ```ignore
+//! use nrfxlib::senml::{self, Record};
+//! let mut buf = [0u8; 64];
+//! let records = [Record::temperature("temp", 21.5), Record::battery("batt", 87)];
+//! let len = senml::encode(Some("urn:dev:imei:123456789012345:"), None, &records, &mut buf)?;
+//! socket.write(&buf[0..len])?;
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::Error;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A single SenML record.
+#[derive(Debug, Copy, Clone)]
+pub struct Record<'a> {
+	/// The name of the measurement. This is appended to any base name.
+	pub name: &'a str,
+	/// The SenML unit (e.g. `"Cel"`), if any.
+	pub unit: Option<&'a str>,
+	/// The measured value.
+	pub value: Value<'a>,
+	/// The time of the measurement in seconds, relative to any base time. A
+	/// negative value means "this many seconds ago".
+	pub time: Option<f64>,
+}
+
+/// The value held in a SenML record.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Value<'a> {
+	/// A numeric value. Encoded as an integer, or as the smallest float that
+	/// holds it exactly.
+	Number(f64),
+	/// A boolean value
+	Bool(bool),
+	/// A UTF-8 string value
+	String(&'a str),
+	/// An opaque data value
+	Data(&'a [u8]),
+}
+
+/// Writes CBOR items into a fixed buffer.
+struct Writer<'b> {
+	buffer: &'b mut [u8],
+	position: usize,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// SenML-CBOR label for the base name
+const LABEL_BASE_NAME: i64 = -2;
+/// SenML-CBOR label for the base time
+const LABEL_BASE_TIME: i64 = -3;
+/// SenML-CBOR label for the name
+const LABEL_NAME: i64 = 0;
+/// SenML-CBOR label for the unit
+const LABEL_UNIT: i64 = 1;
+/// SenML-CBOR label for a numeric value
+const LABEL_VALUE: i64 = 2;
+/// SenML-CBOR label for a string value
+const LABEL_STRING_VALUE: i64 = 3;
+/// SenML-CBOR label for a boolean value
+const LABEL_BOOL_VALUE: i64 = 4;
+/// SenML-CBOR label for the time
+const LABEL_TIME: i64 = 6;
+/// SenML-CBOR label for a data value
+const LABEL_DATA_VALUE: i64 = 8;
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+
+const CBOR_FALSE: u8 = 0xF4;
+const CBOR_TRUE: u8 = 0xF5;
+const CBOR_FLOAT32: u8 = 0xFA;
+const CBOR_FLOAT64: u8 = 0xFB;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl<'a> Record<'a> {
+	/// Create a record with a numeric value and no unit or time.
+	pub fn new(name: &'a str, value: f64) -> Record<'a> {
+		Record {
+			name,
+			unit: None,
+			value: Value::Number(value),
+			time: None,
+		}
+	}
+
+	/// A temperature, in degrees Celsius.
+	pub fn temperature(name: &'a str, celsius: f64) -> Record<'a> {
+		Record::new(name, celsius).with_unit("Cel")
+	}
+
+	/// A relative humidity, in percent.
+	pub fn humidity(name: &'a str, percent: f64) -> Record<'a> {
+		Record::new(name, percent).with_unit("%RH")
+	}
+
+	/// A pressure, in Pascals.
+	pub fn pressure(name: &'a str, pascals: f64) -> Record<'a> {
+		Record::new(name, pascals).with_unit("Pa")
+	}
+
+	/// A voltage, in Volts.
+	pub fn voltage(name: &'a str, volts: f64) -> Record<'a> {
+		Record::new(name, volts).with_unit("V")
+	}
+
+	/// A current, in Amps.
+	pub fn current(name: &'a str, amps: f64) -> Record<'a> {
+		Record::new(name, amps).with_unit("A")
+	}
+
+	/// A remaining battery level, in percent.
+	pub fn battery(name: &'a str, percent: u8) -> Record<'a> {
+		Record::new(name, f64::from(percent)).with_unit("%EL")
+	}
+
+	/// A count of events.
+	pub fn count(name: &'a str, count: u32) -> Record<'a> {
+		Record::new(name, f64::from(count)).with_unit("count")
+	}
+
+	/// Set the unit on this record.
+	pub fn with_unit(self, unit: &'a str) -> Record<'a> {
+		Record {
+			unit: Some(unit),
+			..self
+		}
+	}
+
+	/// Set the time on this record, in seconds.
+	pub fn with_time(self, time: f64) -> Record<'a> {
+		Record {
+			time: Some(time),
+			..self
+		}
+	}
+}
+
+/// Encode a SenML pack into `buffer`, returning the number of bytes used.
+///
+/// The `base_name` and `base_time` (if given) are added to the first record
+/// only, as SenML allows. Returns `Error::BufferFull` if the pack does not
+/// fit.
+pub fn encode(
+	base_name: Option<&str>,
+	base_time: Option<f64>,
+	records: &[Record],
+	buffer: &mut [u8],
+) -> Result<usize, Error> {
+	let mut writer = Writer {
+		buffer,
+		position: 0,
+	};
+	writer.head(MAJOR_ARRAY, records.len() as u64)?;
+	for (idx, record) in records.iter().enumerate() {
+		let (base_name, base_time) = if idx == 0 {
+			(base_name, base_time)
+		} else {
+			(None, None)
+		};
+		let num_fields = 2
+			+ base_name.is_some() as u64
+			+ base_time.is_some() as u64
+			+ record.unit.is_some() as u64
+			+ record.time.is_some() as u64;
+		writer.head(MAJOR_MAP, num_fields)?;
+		if let Some(base_name) = base_name {
+			writer.integer(LABEL_BASE_NAME)?;
+			writer.text(base_name)?;
+		}
+		if let Some(base_time) = base_time {
+			writer.integer(LABEL_BASE_TIME)?;
+			writer.number(base_time)?;
+		}
+		writer.integer(LABEL_NAME)?;
+		writer.text(record.name)?;
+		if let Some(unit) = record.unit {
+			writer.integer(LABEL_UNIT)?;
+			writer.text(unit)?;
+		}
+		match record.value {
+			Value::Number(n) => {
+				writer.integer(LABEL_VALUE)?;
+				writer.number(n)?;
+			}
+			Value::Bool(b) => {
+				writer.integer(LABEL_BOOL_VALUE)?;
+				writer.byte(if b { CBOR_TRUE } else { CBOR_FALSE })?;
+			}
+			Value::String(s) => {
+				writer.integer(LABEL_STRING_VALUE)?;
+				writer.text(s)?;
+			}
+			Value::Data(d) => {
+				writer.integer(LABEL_DATA_VALUE)?;
+				writer.head(MAJOR_BYTES, d.len() as u64)?;
+				writer.bytes(d)?;
+			}
+		}
+		if let Some(time) = record.time {
+			writer.integer(LABEL_TIME)?;
+			writer.number(time)?;
+		}
+	}
+	Ok(writer.position)
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl<'b> Writer<'b> {
+	/// Write a single byte
+	fn byte(&mut self, value: u8) -> Result<(), Error> {
+		self.bytes(&[value])
+	}
+
+	/// Write some raw bytes
+	fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+		let end = self.position + data.len();
+		self.buffer
+			.get_mut(self.position..end)
+			.ok_or(Error::BufferFull)?
+			.copy_from_slice(data);
+		self.position = end;
+		Ok(())
+	}
+
+	/// Write a CBOR item header, using the shortest encoding for `value`.
+	fn head(&mut self, major: u8, value: u64) -> Result<(), Error> {
+		let major = major << 5;
+		if value < 24 {
+			self.byte(major | value as u8)
+		} else if value <= u64::from(u8::MAX) {
+			self.bytes(&[major | 24, value as u8])
+		} else if value <= u64::from(u16::MAX) {
+			self.byte(major | 25)?;
+			self.bytes(&(value as u16).to_be_bytes())
+		} else if value <= u64::from(u32::MAX) {
+			self.byte(major | 26)?;
+			self.bytes(&(value as u32).to_be_bytes())
+		} else {
+			self.byte(major | 27)?;
+			self.bytes(&value.to_be_bytes())
+		}
+	}
+
+	/// Write a signed integer
+	fn integer(&mut self, value: i64) -> Result<(), Error> {
+		if value < 0 {
+			// CBOR encodes -1 as 0, -2 as 1, etc.
+			self.head(MAJOR_NEGATIVE, !(value as u64))
+		} else {
+			self.head(MAJOR_UNSIGNED, value as u64)
+		}
+	}
+
+	/// Write a UTF-8 string
+	fn text(&mut self, value: &str) -> Result<(), Error> {
+		self.head(MAJOR_TEXT, value.len() as u64)?;
+		self.bytes(value.as_bytes())
+	}
+
+	/// Write a number in the most compact form which loses no precision.
+	fn number(&mut self, value: f64) -> Result<(), Error> {
+		let as_int = value as i64;
+		if as_int as f64 == value {
+			self.integer(as_int)
+		} else if f64::from(value as f32) == value {
+			self.byte(CBOR_FLOAT32)?;
+			self.bytes(&(value as f32).to_be_bytes())
+		} else {
+			self.byte(CBOR_FLOAT64)?;
+			self.bytes(&value.to_be_bytes())
+		}
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
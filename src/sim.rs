@@ -0,0 +1,234 @@
+//! # SIM profiles for nrfxlib
+//!
+//! A product which may ship with SIMs from several vendors needs different
+//! settings for each - the APN, the LTE bands to use, whether to ask for PSM.
+//! Register a `SimProfile` for each vendor with `set_profiles`, matched
+//! against the start of the SIM's ICCID (its issuer identification number).
+//! Then every time `modem::on()` powers up the modem it reads the ICCID,
+//! picks the profile with the longest matching prefix, and applies it.
+//!
+//! For example:
+//!
+//! ```ignore
+//! static PROFILES: [SimProfile; 2] = [
+//!     SimProfile {
+//!         iccid_prefix: "8988280",
+//!         apn: Some("iot.vendor-a"),
+//!         bands: Some(&[3, 8, 20]),
+//!         psm: Some(Psm::Requested { periodic_tau: "00100001", active_time: "00000001" }),
+//!     },
+//!     // The fallback, for any other SIM
+//!     SimProfile { iccid_prefix: "", apn: None, bands: None, psm: None },
+//! ];
+//!
+//! sim::set_profiles(&PROFILES);
+//! modem::on()?;
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::Error;
+use core::cell::RefCell;
+use core::fmt::Write;
+use cortex_m::interrupt::Mutex;
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// An ICCID, as digits.
+pub type Iccid = heapless::String<MAX_ICCID_LEN>;
+
+/// The settings to use with a particular kind of SIM.
+#[derive(Debug, Copy, Clone)]
+pub struct SimProfile {
+	/// Used for SIMs whose ICCID starts with this. An empty prefix matches
+	/// every SIM.
+	pub iccid_prefix: &'static str,
+	/// The APN for the default PDP context (`AT+CGDCONT=0`), or `None` to
+	/// leave it alone.
+	pub apn: Option<&'static str>,
+	/// The LTE bands to lock to (`AT%XBANDLOCK`), or `None` to leave the band
+	/// lock alone.
+	pub bands: Option<&'static [u8]>,
+	/// What to ask the network for in the way of PSM (`AT+CPSMS`), or `None`
+	/// to leave it alone.
+	pub psm: Option<Psm>,
+}
+
+/// A Power Saving Mode request.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Psm {
+	/// Don't use PSM
+	Disabled,
+	/// Ask for PSM with these timers. Each is a GPRS timer value, as a string
+	/// of eight `0`s and `1`s (3GPP TS 24.008 sections 10.5.7.4a and
+	/// 10.5.7.3), e.g. `"00100001"` for one hour.
+	Requested {
+		/// The requested periodic TAU (T3412 extended)
+		periodic_tau: &'static str,
+		/// The requested active time (T3324)
+		active_time: &'static str,
+	},
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The longest ICCID there is.
+pub const MAX_ICCID_LEN: usize = 22;
+
+/// The highest band `AT%XBANDLOCK` takes.
+pub const MAX_BAND: u8 = 88;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The profiles given to `set_profiles`.
+static PROFILES: Mutex<RefCell<&'static [SimProfile]>> = Mutex::new(RefCell::new(&[]));
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Set the profiles which `modem::on()` chooses between. With none (the
+/// default), it doesn't read the ICCID at all.
+pub fn set_profiles(profiles: &'static [SimProfile]) {
+	cortex_m::interrupt::free(|cs| {
+		*PROFILES.borrow(cs).borrow_mut() = profiles;
+	});
+}
+
+/// Get the profiles given to `set_profiles`.
+pub fn profiles() -> &'static [SimProfile] {
+	cortex_m::interrupt::free(|cs| *PROFILES.borrow(cs).borrow())
+}
+
+/// Read the SIM's ICCID, using `AT%XICCID`. The SIM must be active (e.g.
+/// `AT+CFUN=41`).
+pub fn read_iccid() -> Result<Iccid, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT%XICCID", |res| {
+		if let Some(value) = res.strip_prefix("%XICCID:") {
+			// A 19 digit ICCID is padded with an `F`
+			let value = value.trim().trim_end_matches(['F', 'f']);
+			let mut iccid = Iccid::new();
+			if !value.is_empty()
+				&& value.bytes().all(|b| b.is_ascii_digit())
+				&& iccid.push_str(value).is_ok()
+			{
+				result = Ok(iccid);
+			}
+		}
+		debug!("AT%XICCID => {:?}", result);
+	})?;
+	result
+}
+
+/// Find the profile for this ICCID: the one with the longest matching
+/// prefix.
+pub fn select_profile(iccid: &str) -> Option<&'static SimProfile> {
+	profiles()
+		.iter()
+		.filter(|profile| iccid.starts_with(profile.iccid_prefix))
+		.max_by_key(|profile| profile.iccid_prefix.len())
+}
+
+/// Activate the SIM, read its ICCID, and apply the profile which matches
+/// it. Returns the profile applied, or `None` if there wasn't one.
+///
+/// This is called by `modem::on()`, before it powers up the modem, if any
+/// profiles have been set. The modem must not be attached, as the APN of
+/// the default PDP context can't be changed while it is in use.
+pub fn configure() -> Result<Option<&'static SimProfile>, Error> {
+	crate::modem::activate_sim()?;
+	let iccid = read_iccid()?;
+	let profile = select_profile(&iccid);
+	debug!("SIM {} => {:?}", iccid, profile);
+	if let Some(profile) = profile {
+		profile.apply()?;
+	}
+	Ok(profile)
+}
+
+impl SimProfile {
+	/// Send the AT commands for this profile's settings.
+	pub fn apply(&self) -> Result<(), Error> {
+		if let Some(apn) = self.apn {
+			let mut command: heapless::String<96> = heapless::String::new();
+			write!(command, "AT+CGDCONT=0,\"IP\",\"{}\"", apn)?;
+			crate::at::send_at_command(&command, |_| {})?;
+		}
+		if let Some(bands) = self.bands {
+			let mut command: heapless::String<{ 20 + MAX_BAND as usize }> = heapless::String::new();
+			write!(command, "AT%XBANDLOCK=2,\"")?;
+			write_band_mask(&mut command, bands)?;
+			write!(command, "\"")?;
+			crate::at::send_at_command(&command, |_| {})?;
+		}
+		match self.psm {
+			Some(Psm::Disabled) => {
+				crate::at::send_at_command("AT+CPSMS=0", |_| {})?;
+			}
+			Some(Psm::Requested {
+				periodic_tau,
+				active_time,
+			}) => {
+				let mut command: heapless::String<48> = heapless::String::new();
+				write!(
+					command,
+					"AT+CPSMS=1,,,\"{}\",\"{}\"",
+					periodic_tau, active_time
+				)?;
+				crate::at::send_at_command(&command, |_| {})?;
+			}
+			None => {}
+		}
+		Ok(())
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Write the `AT%XBANDLOCK` bit mask for these bands: one character per
+/// band, highest first, ending with band 1. Returns
+/// `Error::UnrecognisedValue` for band 0 or anything above `MAX_BAND`.
+pub(crate) fn write_band_mask<W: Write>(out: &mut W, bands: &[u8]) -> Result<(), Error> {
+	if bands.iter().any(|&band| band == 0 || band > MAX_BAND) {
+		return Err(Error::UnrecognisedValue);
+	}
+	let highest = bands.iter().copied().max().unwrap_or(1);
+	for band in (1..=highest).rev() {
+		let bit = if bands.contains(&band) { '1' } else { '0' };
+		out.write_char(bit)?;
+	}
+	Ok(())
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
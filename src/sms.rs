@@ -0,0 +1,1316 @@
+//! # SMS helpers for nrfxlib
+//!
+//! Short Message Service (SMS) related code.
+//!
+//! A single SMS carries at most 140 octets of user data, so longer payloads
+//! are sent as a concatenated message: several parts, each starting with a
+//! User Data Header (UDH, 3GPP TS 23.040 section 9.2.3.24) which says which
+//! message the part belongs to and where it goes. `split` cuts a payload into
+//! parts for sending, and a `Reassembler` puts received parts back together.
+//!
+//! When sending several parts, use `set_more_messages` so the modem keeps the
+//! radio link up between them (`AT+CMMS`).
+//!
+//! `send_text` sends text to a number, in the GSM 7-bit alphabet if it can
+//! and UCS-2 if it can't, splitting it into parts if it needs to. `send`
+//! does the same for a payload you have already encoded, and `send_pdu`
+//! sends an SMS-SUBMIT you have built yourself. The modem only takes PDUs
+//! (`AT+CMGS` in PDU mode), so text is always encoded here.
+//!
+//! To receive messages, call `start_receiving`. Each message then arrives on
+//! an AT socket as a `+CMT` URC carrying an SMS-DELIVER PDU in hex, which
+//! `Deliver::parse` decodes. Every message must be confirmed with
+//! `acknowledge`, or the network will send it again. An `Inbox` does all of
+//! that on its own AT socket, and either queues each message or calls a
+//! handler with it:
+//!
+//! ```ignore
+//! let mut inbox: sms::Inbox<2> = sms::Inbox::new()?;
+//! loop {
+//!     inbox.wait(1000)?;
+//!     while let Some(message) = inbox.receive() {
+//!         info!("SMS from {} at {:?}: {:?}", message.sender, message.timestamp, message.text());
+//!     }
+//! }
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+use crate::at::AtSocket;
+#[cfg(target_arch = "arm")]
+use crate::raw::Pollable;
+use crate::Error;
+use core::convert::{TryFrom, TryInto};
+#[cfg(target_arch = "arm")]
+use core::fmt::Write;
+#[cfg(target_arch = "arm")]
+use core::sync::atomic::{AtomicU8, Ordering};
+use log::debug;
+#[cfg(target_arch = "arm")]
+use log::warn;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// How the user data of a message is encoded. This decides how much fits in
+/// each part.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encoding {
+	/// GSM 7-bit default alphabet. The payload is given as one byte per
+	/// septet (unpacked), including any `0x1B` escape bytes.
+	Gsm7,
+	/// 8-bit binary data.
+	Data8,
+	/// UCS-2 (UTF-16BE) text.
+	Ucs2,
+}
+
+/// Where a part fits in a concatenated message, as carried in its UDH.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConcatInfo {
+	/// Identifies which message this part belongs to. The same reference is
+	/// used for every part of a message.
+	pub reference: u16,
+	/// How many parts there are in the message.
+	pub total: u8,
+	/// Which part this is, starting at 1.
+	pub sequence: u8,
+}
+
+/// The `AT+CMMS` setting, which controls whether the modem keeps the link to
+/// the SMS centre open between messages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MoreMessages {
+	/// Close the link after each message (the default).
+	Disabled = 0,
+	/// Keep the link open for the next message, then go back to `Disabled`
+	/// if there's a gap of more than a few seconds.
+	Once = 1,
+	/// Keep the link open until told otherwise.
+	Enabled = 2,
+}
+
+/// Iterates through the parts of a payload, as returned by `split`.
+#[derive(Debug, Clone)]
+pub struct Parts<'a> {
+	remaining: &'a [u8],
+	encoding: Encoding,
+	reference: u16,
+	total: u8,
+	sequence: u8,
+}
+
+/// A received message (an SMS-DELIVER), as decoded by `Deliver::parse`.
+#[derive(Debug, Clone)]
+pub struct Deliver {
+	/// Who sent it - digits, with a leading `+` for an international number,
+	/// or the alphanumeric sender name.
+	pub sender: heapless::String<MAX_ADDRESS_LEN>,
+	/// When the SMS centre received it.
+	pub timestamp: Timestamp,
+	/// How the user data is encoded.
+	pub encoding: Encoding,
+	/// Where it fits in a concatenated message, if it is part of one.
+	pub concat: Option<ConcatInfo>,
+	/// The user data, without its UDH. GSM 7-bit data is unpacked, one byte
+	/// per septet, as `split` expects.
+	pub data: heapless::Vec<u8, MAX_PART_LEN>,
+}
+
+/// A time stamp from the SMS centre (TS 23.040 section 9.2.3.11).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timestamp {
+	/// The year, e.g. 2021
+	pub year: u16,
+	/// The month, 1 to 12
+	pub month: u8,
+	/// The day of the month, 1 to 31
+	pub day: u8,
+	/// The hour, 0 to 23
+	pub hour: u8,
+	/// The minute, 0 to 59
+	pub minute: u8,
+	/// The second, 0 to 59
+	pub second: u8,
+	/// How far the local time given is ahead of UTC, in minutes
+	pub utc_offset_minutes: i16,
+}
+
+/// Which kinds of message the modem supports, as reported by `AT+CSMS`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ServiceSupport {
+	/// Messages to the device (which we receive)
+	pub mobile_terminated: bool,
+	/// Messages from the device (which we send)
+	pub mobile_originated: bool,
+	/// Cell broadcast messages
+	pub broadcast: bool,
+}
+
+/// Called by an `Inbox` with each message it receives.
+pub type InboxHandler = fn(&Deliver);
+
+/// Receives messages on its own AT socket. Each one is acknowledged, then
+/// given to the handler (see `set_handler`) or, if there isn't one, queued
+/// for `receive`. Holds up to `QUEUE` messages; while the queue is full, new
+/// ones are rejected, and the network sends them again later.
+#[cfg(target_arch = "arm")]
+#[derive(Debug)]
+pub struct Inbox<const QUEUE: usize> {
+	socket: AtSocket,
+	state: InboxState<QUEUE>,
+}
+
+/// Puts a concatenated message back together from its parts (which may
+/// arrive in any order). Holds up to `PARTS` parts of one message at a time.
+#[derive(Debug, Clone)]
+pub struct Reassembler<const PARTS: usize> {
+	reference: Option<u16>,
+	total: u8,
+	parts: [[u8; MAX_PART_LEN]; PARTS],
+	lengths: [Option<u8>; PARTS],
+}
+
+/// Reads the fields of a PDU in turn.
+struct PduReader<'a> {
+	pdu: &'a [u8],
+	offset: usize,
+}
+
+/// What an `Inbox` is in the middle of.
+#[cfg(target_arch = "arm")]
+#[derive(Debug)]
+struct InboxState<const QUEUE: usize> {
+	handler: Option<InboxHandler>,
+	queue: heapless::Deque<Deliver, QUEUE>,
+	/// We've seen a `+CMT` URC, so the next line is its PDU
+	awaiting_pdu: bool,
+	/// How many messages we need to acknowledge
+	acks: u8,
+	/// How many messages we need to reject
+	rejects: u8,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// UDH Information Element: concatenated message, 8-bit reference
+const IEI_CONCAT_8BIT: u8 = 0x00;
+/// UDH Information Element: concatenated message, 16-bit reference
+const IEI_CONCAT_16BIT: u8 = 0x08;
+
+/// The most user data, in octets, a single message can carry
+const MAX_USER_DATA: usize = 140;
+
+/// The largest part (in payload bytes) we ever produce or accept
+pub const MAX_PART_LEN: usize = 160;
+
+/// The longest sender we keep (a 20 digit number, or 11 character name)
+pub const MAX_ADDRESS_LEN: usize = 21;
+
+/// The longest text `Deliver::text` produces: 70 UCS-2 characters, each up
+/// to three bytes of UTF-8.
+pub const MAX_TEXT_LEN: usize = 210;
+
+/// The largest PDU we accept: a 12 octet SMS centre address, and a TPDU of
+/// at most 164 octets.
+const MAX_PDU_LEN: usize = 176;
+
+/// The TP-MTI value for an SMS-DELIVER
+const MTI_DELIVER: u8 = 0b00;
+
+/// The TP-MTI value for an SMS-SUBMIT
+#[cfg(target_arch = "arm")]
+const MTI_SUBMIT: u8 = 0b01;
+
+/// The largest SMS-SUBMIT TPDU we build: the first octet, TP-MR, a 20 digit
+/// TP-DA, TP-PID, TP-DCS, TP-UDL and the user data.
+#[cfg(target_arch = "arm")]
+const MAX_SUBMIT_LEN: usize = 157;
+
+/// The longest `AT+CMGS` command we send: the command, an empty SMS centre
+/// address, the TPDU in hex and the Ctrl-Z.
+#[cfg(target_arch = "arm")]
+const MAX_CMGS_LEN: usize = 340;
+
+/// The most digits a destination number can have
+#[cfg(target_arch = "arm")]
+const MAX_NUMBER_DIGITS: usize = 20;
+
+/// The largest payload `send_text` encodes, which is enough for four parts
+pub const MAX_SEND_PAYLOAD: usize = 4 * MAX_USER_DATA;
+
+/// Type-of-address octet: international number, ISDN numbering plan
+#[cfg(target_arch = "arm")]
+const TOA_INTERNATIONAL: u8 = 0x91;
+
+/// Type-of-address octet: unknown type of number, ISDN numbering plan
+#[cfg(target_arch = "arm")]
+const TOA_UNKNOWN: u8 = 0x81;
+
+/// The TP-UDHI bit in the first octet: there is a UDH
+const FIRST_OCTET_UDHI: u8 = 0x40;
+
+/// Type-of-address: international number
+const TON_INTERNATIONAL: u8 = 0b001;
+
+/// Type-of-address: alphanumeric (GSM 7-bit packed)
+const TON_ALPHANUMERIC: u8 = 0b101;
+
+/// The GSM 7-bit escape character, which must stay with the character after
+/// it
+const GSM7_ESCAPE: u8 = 0x1B;
+
+/// The URC a received message arrives in
+#[cfg(target_arch = "arm")]
+const CMT_URC: &str = "+CMT:";
+
+/// Passes received messages straight to us as `+CMT` URCs
+#[cfg(target_arch = "arm")]
+const CNMI_START: &str = "AT+CNMI=3,2,0,1";
+
+/// Confirms we have received a message
+#[cfg(target_arch = "arm")]
+const CNMA_ACK: &str = "AT+CNMA=1";
+
+/// Tells the network we couldn't take a message (RP-ERROR)
+#[cfg(target_arch = "arm")]
+const CNMA_REJECT: &str = "AT+CNMA=2";
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The reference `send` gives its next concatenated message
+#[cfg(target_arch = "arm")]
+static NEXT_REFERENCE: AtomicU8 = AtomicU8::new(0);
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Tell the modem whether to keep the link to the SMS centre open between
+/// messages, using `AT+CMMS`. Use this around sending the parts of a
+/// concatenated message.
+#[cfg(target_arch = "arm")]
+pub fn set_more_messages(mode: MoreMessages) -> Result<(), Error> {
+	let mut command: heapless::String<16> = heapless::String::new();
+	write!(command, "AT+CMMS={}", mode as u8).map_err(|_| Error::WriteError)?;
+	crate::at::send_at_command(&command, |res| {
+		debug!("{:?} => {:?}", command, res);
+	})
+}
+
+/// Ask the modem to pass received messages straight to us as `+CMT` URCs,
+/// using `AT+CNMI`. Each one must be confirmed with `acknowledge`.
+#[cfg(target_arch = "arm")]
+pub fn start_receiving() -> Result<(), Error> {
+	crate::at::send_at_command(CNMI_START, |res| {
+		debug!("AT+CNMI => {:?}", res);
+	})
+}
+
+/// Stop the modem passing received messages to us.
+#[cfg(target_arch = "arm")]
+pub fn stop_receiving() -> Result<(), Error> {
+	crate::at::send_at_command("AT+CNMI=0,0,0,0", |res| {
+		debug!("AT+CNMI => {:?}", res);
+	})
+}
+
+/// Confirm that we have received a message, using `AT+CNMA`.
+#[cfg(target_arch = "arm")]
+pub fn acknowledge() -> Result<(), Error> {
+	crate::at::send_at_command(CNMA_ACK, |res| {
+		debug!("AT+CNMA => {:?}", res);
+	})
+}
+
+/// Tell the network we couldn't take a message (e.g. because we have
+/// nowhere to put it), using `AT+CNMA`. It will send it again later.
+#[cfg(target_arch = "arm")]
+pub fn reject() -> Result<(), Error> {
+	crate::at::send_at_command(CNMA_REJECT, |res| {
+		debug!("AT+CNMA => {:?}", res);
+	})
+}
+
+/// Select the messaging service of 3GPP TS 23.040 and 23.041 using
+/// `AT+CSMS=0`, and find out which kinds of message it supports.
+#[cfg(target_arch = "arm")]
+pub fn select_message_service() -> Result<ServiceSupport, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT+CSMS=0", |res| {
+		if let Some(support) = ServiceSupport::parse(res) {
+			result = Ok(support);
+		}
+	})?;
+	debug!("AT+CSMS => {:?}", result);
+	result
+}
+
+/// Send text to a number (digits, with a leading `+` for an international
+/// number). It is sent in the GSM 7-bit alphabet if every character is in
+/// it, and as UCS-2 otherwise, in as many parts as it needs. Returns
+/// `Error::BufferFull` if it encodes to more than `MAX_SEND_PAYLOAD` bytes.
+#[cfg(target_arch = "arm")]
+pub fn send_text(number: &str, text: &str) -> Result<(), Error> {
+	let mut payload: heapless::Vec<u8, MAX_SEND_PAYLOAD> = heapless::Vec::new();
+	let encoding = if text.chars().all(|c| gsm7_from_char(c).is_some()) {
+		for (escaped, value) in text.chars().filter_map(gsm7_from_char) {
+			if escaped {
+				payload.push(GSM7_ESCAPE).map_err(|_| Error::BufferFull)?;
+			}
+			payload.push(value).map_err(|_| Error::BufferFull)?;
+		}
+		Encoding::Gsm7
+	} else {
+		for unit in text.encode_utf16() {
+			payload
+				.extend_from_slice(&unit.to_be_bytes())
+				.map_err(|_| Error::BufferFull)?;
+		}
+		Encoding::Ucs2
+	};
+	send(number, &payload, encoding)
+}
+
+/// Send a payload to a number, as `send_text` does, but already encoded
+/// (as `split` takes it). A payload which needs more than one part is sent
+/// as a concatenated message, with the link to the SMS centre held open
+/// until the last part has gone.
+#[cfg(target_arch = "arm")]
+pub fn send(number: &str, payload: &[u8], encoding: Encoding) -> Result<(), Error> {
+	let reference = u16::from(NEXT_REFERENCE.fetch_add(1, Ordering::Relaxed));
+	let parts = split(payload, encoding, reference)?;
+	let concatenated = parts.total() > 1;
+	if concatenated {
+		set_more_messages(MoreMessages::Enabled)?;
+	}
+	let mut result = Ok(());
+	for (concat, data) in parts {
+		result = submit_pdu(number, encoding, concat.as_ref(), data).and_then(|pdu| send_pdu(&pdu));
+		if result.is_err() {
+			break;
+		}
+	}
+	if concatenated {
+		// Close the link again, even if a part failed
+		result = result.and(set_more_messages(MoreMessages::Disabled));
+	}
+	result
+}
+
+/// Send an SMS-SUBMIT TPDU you have built yourself, given in hex (without
+/// an SMS centre address - the default one is used), with `AT+CMGS`.
+/// Returns `Error::BadDataFormat` if it isn't hex.
+#[cfg(target_arch = "arm")]
+pub fn send_pdu(tpdu_hex: &str) -> Result<(), Error> {
+	let tpdu_hex = tpdu_hex.trim();
+	if tpdu_hex.len() & 1 != 0 || !tpdu_hex.bytes().all(|digit| hex_digit(digit).is_ok()) {
+		return Err(Error::BadDataFormat);
+	}
+	let mut command: heapless::String<MAX_CMGS_LEN> = heapless::String::new();
+	write!(
+		command,
+		"AT+CMGS={}\r00{}\x1A",
+		tpdu_hex.len() / 2,
+		tpdu_hex
+	)
+	.map_err(|_| Error::WriteError)?;
+	crate::at::send_at_command(&command, |res| {
+		debug!("AT+CMGS => {:?}", res);
+	})
+}
+
+/// Split a payload into the parts needed to send it.
+///
+/// If the payload fits in a single message, you get one part with no
+/// `ConcatInfo`. Otherwise every part comes with the `ConcatInfo` to put in
+/// its UDH (see `ConcatInfo::write_udh`), using the given `reference`.
+/// Returns `Error::BufferFull` if the payload needs more than 255 parts.
+pub fn split(payload: &[u8], encoding: Encoding, reference: u16) -> Result<Parts<'_>, Error> {
+	let mut parts = Parts {
+		remaining: payload,
+		encoding,
+		reference,
+		total: 1,
+		sequence: 0,
+	};
+	if payload.len() > encoding.single_part_len() {
+		let mut total = 0usize;
+		let mut counter = parts.clone();
+		while !counter.remaining.is_empty() {
+			counter.take_part(encoding.concat_part_len(reference));
+			total += 1;
+		}
+		if total > usize::from(u8::MAX) {
+			return Err(Error::BufferFull);
+		}
+		parts.total = total as u8;
+	}
+	Ok(parts)
+}
+
+impl Encoding {
+	/// How much payload fits in a message that isn't concatenated.
+	pub fn single_part_len(self) -> usize {
+		match self {
+			Encoding::Gsm7 => MAX_USER_DATA * 8 / 7,
+			Encoding::Data8 | Encoding::Ucs2 => MAX_USER_DATA,
+		}
+	}
+
+	/// How much payload fits in each part of a concatenated message, given
+	/// its reference (which decides the size of the UDH).
+	pub fn concat_part_len(self, reference: u16) -> usize {
+		let udh_len = ConcatInfo::udh_len(reference);
+		match self {
+			Encoding::Gsm7 => ((MAX_USER_DATA - udh_len) * 8) / 7,
+			Encoding::Data8 => MAX_USER_DATA - udh_len,
+			// Must be a whole number of UCS-2 characters
+			Encoding::Ucs2 => (MAX_USER_DATA - udh_len) & !1,
+		}
+	}
+}
+
+impl ConcatInfo {
+	/// Find the concatenation information in a User Data Header. The `udh`
+	/// starts with the UDH length byte (UDHL). Returns `None` if there isn't
+	/// any.
+	pub fn parse_udh(udh: &[u8]) -> Option<ConcatInfo> {
+		let (&udh_len, rest) = udh.split_first()?;
+		let mut elements = rest.get(0..usize::from(udh_len))?;
+		while let [iei, length, rest @ ..] = elements {
+			let data = rest.get(0..usize::from(*length))?;
+			let info = match (*iei, data) {
+				(IEI_CONCAT_8BIT, &[reference, total, sequence]) => Some(ConcatInfo {
+					reference: u16::from(reference),
+					total,
+					sequence,
+				}),
+				(IEI_CONCAT_16BIT, &[ref_hi, ref_lo, total, sequence]) => Some(ConcatInfo {
+					reference: u16::from_be_bytes([ref_hi, ref_lo]),
+					total,
+					sequence,
+				}),
+				_ => None,
+			};
+			if info.is_some() {
+				return info;
+			}
+			elements = rest.get(usize::from(*length)..)?;
+		}
+		None
+	}
+
+	/// Write a User Data Header holding this information into `buffer`,
+	/// starting with the UDH length byte. Returns how many bytes were
+	/// written. An 8-bit reference is used if `reference` fits in one.
+	pub fn write_udh(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+		let udh_len = Self::udh_len(self.reference);
+		let udh = buffer.get_mut(0..udh_len).ok_or(Error::BufferFull)?;
+		match u8::try_from(self.reference) {
+			Ok(reference) => {
+				udh.copy_from_slice(&[5, IEI_CONCAT_8BIT, 3, reference, self.total, self.sequence]);
+			}
+			Err(_) => {
+				let reference = self.reference.to_be_bytes();
+				udh.copy_from_slice(&[
+					6,
+					IEI_CONCAT_16BIT,
+					4,
+					reference[0],
+					reference[1],
+					self.total,
+					self.sequence,
+				]);
+			}
+		}
+		Ok(udh_len)
+	}
+
+	/// How many bytes `write_udh` writes (including the length byte) for the
+	/// given reference.
+	pub fn udh_len(reference: u16) -> usize {
+		if reference <= u16::from(u8::MAX) {
+			6
+		} else {
+			7
+		}
+	}
+}
+
+impl<'a> Parts<'a> {
+	/// How many parts the payload was split into.
+	pub fn total(&self) -> u8 {
+		self.total
+	}
+
+	/// Take the next `max` (or fewer) bytes, without splitting a GSM 7-bit
+	/// escape sequence or a UTF-16 surrogate pair across parts.
+	fn take_part(&mut self, max: usize) -> &'a [u8] {
+		let mut len = core::cmp::min(max, self.remaining.len());
+		if len < self.remaining.len() {
+			match self.encoding {
+				Encoding::Gsm7 if len >= 1 && self.remaining.get(len - 1) == Some(&GSM7_ESCAPE) => {
+					len -= 1;
+				}
+				Encoding::Ucs2
+					if len >= 2
+						&& matches!(self.remaining.get(len - 2), Some(b) if b & 0xFC == 0xD8) =>
+				{
+					len -= 2;
+				}
+				_ => {}
+			}
+		}
+		let (part, rest) = self.remaining.split_at(len);
+		self.remaining = rest;
+		part
+	}
+}
+
+impl<'a> Iterator for Parts<'a> {
+	type Item = (Option<ConcatInfo>, &'a [u8]);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.sequence >= self.total {
+			return None;
+		}
+		self.sequence += 1;
+		if self.total == 1 {
+			let part = self.remaining;
+			self.remaining = &[];
+			Some((None, part))
+		} else {
+			let part = self.take_part(self.encoding.concat_part_len(self.reference));
+			let info = ConcatInfo {
+				reference: self.reference,
+				total: self.total,
+				sequence: self.sequence,
+			};
+			Some((Some(info), part))
+		}
+	}
+}
+
+impl Deliver {
+	/// Decode an SMS-DELIVER PDU, given in hex as on the line after a `+CMT`
+	/// URC (starting with the SMS centre address).
+	///
+	/// Returns `Error::BadDataFormat` if it isn't valid hex, isn't an
+	/// SMS-DELIVER, or is cut short.
+	pub fn parse(pdu_hex: &str) -> Result<Deliver, Error> {
+		let mut pdu: heapless::Vec<u8, MAX_PDU_LEN> = heapless::Vec::new();
+		let mut hex = pdu_hex.trim().as_bytes();
+		if hex.len() & 1 != 0 {
+			return Err(Error::BadDataFormat);
+		}
+		while let [high, low, rest @ ..] = hex {
+			let byte = (hex_digit(*high)? << 4) | hex_digit(*low)?;
+			pdu.push(byte).map_err(|_| Error::BadDataFormat)?;
+			hex = rest;
+		}
+		let mut reader = PduReader {
+			pdu: &pdu,
+			offset: 0,
+		};
+
+		// The SMS centre address - we don't need it
+		let smsc_len = reader.octet()?;
+		reader.take(usize::from(smsc_len))?;
+
+		let first_octet = reader.octet()?;
+		if first_octet & 0b11 != MTI_DELIVER {
+			return Err(Error::BadDataFormat);
+		}
+		let sender = reader.address()?;
+		let _protocol_id = reader.octet()?;
+		let encoding = Encoding::from_dcs(reader.octet()?);
+		let timestamp = Timestamp::from_octets(reader.take(7)?)?;
+		let udl = usize::from(reader.octet()?);
+		let user_data = reader.rest();
+
+		let mut data = heapless::Vec::new();
+		let mut concat = None;
+		let mut udh_len = 0;
+		if first_octet & FIRST_OCTET_UDHI != 0 {
+			let udhl = *user_data.first().ok_or(Error::BadDataFormat)?;
+			udh_len = usize::from(udhl) + 1;
+			let udh = user_data.get(0..udh_len).ok_or(Error::BadDataFormat)?;
+			concat = ConcatInfo::parse_udh(udh);
+		}
+		match encoding {
+			Encoding::Gsm7 => {
+				// The UDH is padded out to a whole number of septets
+				let skip = (udh_len * 8).div_ceil(7);
+				if udl > user_data.len() * 8 / 7 || skip > udl {
+					return Err(Error::BadDataFormat);
+				}
+				for index in skip..udl {
+					data.push(septet(user_data, index))
+						.map_err(|_| Error::BadDataFormat)?;
+				}
+			}
+			Encoding::Data8 | Encoding::Ucs2 => {
+				let body = user_data.get(udh_len..udl).ok_or(Error::BadDataFormat)?;
+				data.extend_from_slice(body)
+					.map_err(|_| Error::BadDataFormat)?;
+			}
+		}
+		Ok(Deliver {
+			sender,
+			timestamp,
+			encoding,
+			concat,
+			data,
+		})
+	}
+
+	/// Get the user data as text. GSM 7-bit characters which have no ASCII
+	/// equivalent come out as `?`. Returns `Error::BadDataFormat` if UCS-2
+	/// or 8-bit data isn't valid text.
+	pub fn text(&self) -> Result<heapless::String<MAX_TEXT_LEN>, Error> {
+		let mut text = heapless::String::new();
+		match self.encoding {
+			Encoding::Gsm7 => {
+				let mut septets = self.data.iter();
+				while let Some(&value) = septets.next() {
+					let c = if value == GSM7_ESCAPE {
+						septets.next().map_or('?', |&value| gsm7_extension(value))
+					} else {
+						gsm7_basic(value)
+					};
+					text.push(c).map_err(|_| Error::BufferFull)?;
+				}
+			}
+			Encoding::Data8 => {
+				let s = core::str::from_utf8(&self.data).map_err(|_| Error::BadDataFormat)?;
+				text.push_str(s).map_err(|_| Error::BufferFull)?;
+			}
+			Encoding::Ucs2 => {
+				let units = self
+					.data
+					.chunks_exact(2)
+					.filter_map(|pair| <[u8; 2]>::try_from(pair).ok())
+					.map(u16::from_be_bytes);
+				for c in core::char::decode_utf16(units) {
+					let c = c.map_err(|_| Error::BadDataFormat)?;
+					text.push(c).map_err(|_| Error::BufferFull)?;
+				}
+			}
+		}
+		Ok(text)
+	}
+}
+
+impl ServiceSupport {
+	/// Parse the response to `AT+CSMS`: `+CSMS: <mt>,<mo>,<bm>`. Returns
+	/// `None` if it isn't one.
+	pub fn parse(line: &str) -> Option<ServiceSupport> {
+		let mut fields = line
+			.trim()
+			.strip_prefix("+CSMS:")?
+			.split(',')
+			.map(str::trim);
+		let mut supported = || -> Option<bool> { Some(fields.next()?.parse::<u8>().ok()? == 1) };
+		Some(ServiceSupport {
+			mobile_terminated: supported()?,
+			mobile_originated: supported()?,
+			broadcast: supported()?,
+		})
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl<const QUEUE: usize> Inbox<QUEUE> {
+	/// Open an AT socket, and ask the modem to pass received messages to it
+	/// (`AT+CNMI`). Received messages are queued until you `set_handler`.
+	pub fn new() -> Result<Self, Error> {
+		let mut inbox = Inbox {
+			socket: AtSocket::new()?,
+			state: InboxState {
+				handler: None,
+				queue: heapless::Deque::new(),
+				awaiting_pdu: false,
+				acks: 0,
+				rejects: 0,
+			},
+		};
+		inbox.command(CNMI_START)?;
+		Ok(inbox)
+	}
+
+	/// Call `handler` with each message received from now on, instead of
+	/// queueing it, or `None` to go back to queueing. Messages already
+	/// queued stay queued.
+	pub fn set_handler(&mut self, handler: Option<InboxHandler>) {
+		self.state.handler = handler;
+	}
+
+	/// Take the oldest queued message, if there is one.
+	pub fn receive(&mut self) -> Option<Deliver> {
+		self.state.queue.pop_front()
+	}
+
+	/// Read whatever has arrived, without waiting, and acknowledge, queue or
+	/// handle each message. Returns how many messages were received.
+	pub fn dispatch(&mut self) -> Result<usize, Error> {
+		let mut received = 0;
+		{
+			let mut buf = crate::pool::Buffer::take()?;
+			while let Some(length) = self.socket.recv(&mut buf)? {
+				// Skip anything which isn't text, rather than give up
+				let text = buf
+					.get(0..length)
+					.and_then(|data| crate::at::response_text(data).ok())
+					.unwrap_or("");
+				for line in text.lines() {
+					if self.state.handle_line(line) {
+						received += 1;
+					}
+				}
+			}
+		}
+		// Acknowledging may bring in more messages, which need the same
+		while let Some(command) = self.state.next_ack() {
+			received += self.command(command)?;
+		}
+		Ok(received)
+	}
+
+	/// Wait up to `timeout_ms` for something to arrive, then handle it as
+	/// `dispatch` does. Returns `Ok(0)` if nothing arrived in time.
+	pub fn wait(&mut self, timeout_ms: u32) -> Result<usize, Error> {
+		match crate::raw::wait_readable(&self.socket, timeout_ms) {
+			Ok(()) | Err(Error::TimedOut) => self.dispatch(),
+			Err(e) => Err(e),
+		}
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl<const QUEUE: usize> Pollable for Inbox<QUEUE> {
+	/// Get the underlying socket ID for this inbox's AT socket.
+	fn get_fd(&self) -> i32 {
+		self.socket.get_fd()
+	}
+}
+
+impl<const PARTS: usize> Reassembler<PARTS> {
+	/// Create a new, empty, reassembler.
+	pub fn new() -> Self {
+		Reassembler {
+			reference: None,
+			total: 0,
+			parts: [[0u8; MAX_PART_LEN]; PARTS],
+			lengths: [None; PARTS],
+		}
+	}
+
+	/// Add a received part. Returns `true` once every part of the message
+	/// has arrived, at which point you can call `assemble`.
+	///
+	/// A part from a different message throws away whatever was held before.
+	/// Returns `Error::BufferFull` if the message has more than `PARTS` parts
+	/// (or a part is too big), and `Error::BadDataFormat` if the
+	/// `ConcatInfo` doesn't make sense.
+	pub fn push(&mut self, info: ConcatInfo, data: &[u8]) -> Result<bool, Error> {
+		if info.sequence == 0 || info.sequence > info.total {
+			return Err(Error::BadDataFormat);
+		}
+		if usize::from(info.total) > PARTS || data.len() > MAX_PART_LEN {
+			return Err(Error::BufferFull);
+		}
+		if self.reference != Some(info.reference) || self.total != info.total {
+			debug!("Starting new concatenated SMS {}", info.reference);
+			self.reset();
+			self.reference = Some(info.reference);
+			self.total = info.total;
+		}
+		let idx = usize::from(info.sequence - 1);
+		let (part, length) = self
+			.parts
+			.get_mut(idx)
+			.zip(self.lengths.get_mut(idx))
+			.ok_or(Error::BufferFull)?;
+		part.get_mut(0..data.len())
+			.ok_or(Error::BufferFull)?
+			.copy_from_slice(data);
+		*length = Some(data.len() as u8);
+		Ok(self.is_complete())
+	}
+
+	/// Have all the parts of the current message arrived?
+	pub fn is_complete(&self) -> bool {
+		self.reference.is_some()
+			&& self
+				.lengths
+				.get(..usize::from(self.total))
+				.is_some_and(|lengths| lengths.iter().all(|length| length.is_some()))
+	}
+
+	/// Copy the complete message into `buffer`, returning its length. Returns
+	/// `Error::BadDataFormat` if parts are still missing, or
+	/// `Error::BufferFull` if it doesn't fit.
+	pub fn assemble(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+		if !self.is_complete() {
+			return Err(Error::BadDataFormat);
+		}
+		let mut offset = 0;
+		for (part, length) in self.parts.iter().zip(self.lengths.iter()) {
+			if let Some(length) = length {
+				let length = usize::from(*length);
+				buffer
+					.get_mut(offset..offset + length)
+					.ok_or(Error::BufferFull)?
+					.copy_from_slice(part.get(0..length).ok_or(Error::BadDataFormat)?);
+				offset += length;
+			}
+		}
+		Ok(offset)
+	}
+
+	/// Throw away any parts held.
+	pub fn reset(&mut self) {
+		self.reference = None;
+		self.total = 0;
+		self.lengths = [None; PARTS];
+	}
+}
+
+impl<const PARTS: usize> Default for Reassembler<PARTS> {
+	fn default() -> Self {
+		Reassembler::new()
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl<'a> PduReader<'a> {
+	/// Read one octet.
+	fn octet(&mut self) -> Result<u8, Error> {
+		self.take(1)?.first().copied().ok_or(Error::BadDataFormat)
+	}
+
+	/// Read `length` octets.
+	fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+		let data = self
+			.pdu
+			.get(self.offset..self.offset + length)
+			.ok_or(Error::BadDataFormat)?;
+		self.offset += length;
+		Ok(data)
+	}
+
+	/// Everything not yet read.
+	fn rest(&self) -> &'a [u8] {
+		self.pdu.get(self.offset..).unwrap_or(&[])
+	}
+
+	/// Read an address field (TS 23.040 section 9.1.2.5).
+	fn address(&mut self) -> Result<heapless::String<MAX_ADDRESS_LEN>, Error> {
+		// The length is in digits (semi-octets), not octets
+		let digits = usize::from(self.octet()?);
+		let type_of_address = self.octet()?;
+		let value = self.take(digits.div_ceil(2))?;
+		let mut address = heapless::String::new();
+		match (type_of_address >> 4) & 0b111 {
+			TON_ALPHANUMERIC => {
+				for index in 0..(digits * 4 / 7) {
+					address
+						.push(gsm7_basic(septet(value, index)))
+						.map_err(|_| Error::BadDataFormat)?;
+				}
+			}
+			ton => {
+				if ton == TON_INTERNATIONAL {
+					address.push('+').map_err(|_| Error::BadDataFormat)?;
+				}
+				for index in 0..digits {
+					let octet = value.get(index / 2).ok_or(Error::BadDataFormat)?;
+					let digit = (octet >> ((index % 2) * 4)) & 0x0F;
+					// 0xF is filler
+					if let Some(c) = semi_octet(digit) {
+						address.push(c).map_err(|_| Error::BadDataFormat)?;
+					}
+				}
+			}
+		}
+		Ok(address)
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl<const QUEUE: usize> Inbox<QUEUE> {
+	/// Send a command on our socket. Messages which arrive before its final
+	/// response are handled as usual. Returns how many there were.
+	fn command(&mut self, command: &str) -> Result<usize, Error> {
+		let mut received = 0;
+		self.socket.send_command(command)?;
+		let state = &mut self.state;
+		self.socket.poll_response(|line| {
+			if state.handle_line(line) {
+				received += 1;
+			}
+		})?;
+		Ok(received)
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl<const QUEUE: usize> InboxState<QUEUE> {
+	/// Handle a line from the AT socket. Returns `true` if it completed a
+	/// message, which has been handled or queued.
+	fn handle_line(&mut self, line: &str) -> bool {
+		let line = line.trim();
+		if line.is_empty() {
+			return false;
+		}
+		if line.starts_with(CMT_URC) {
+			self.awaiting_pdu = true;
+			return false;
+		}
+		if !core::mem::replace(&mut self.awaiting_pdu, false) {
+			return false;
+		}
+		match Deliver::parse(line) {
+			Ok(message) => {
+				match self.handler {
+					Some(handler) => handler(&message),
+					None => {
+						if self.queue.push_back(message).is_err() {
+							warn!("SMS inbox full - rejecting message");
+							self.rejects = self.rejects.saturating_add(1);
+							return false;
+						}
+					}
+				}
+				self.acks = self.acks.saturating_add(1);
+				true
+			}
+			Err(e) => {
+				// Sending it again won't help
+				warn!("Dropping SMS we can't decode: {:?}", e);
+				self.acks = self.acks.saturating_add(1);
+				false
+			}
+		}
+	}
+
+	/// Get the next `AT+CNMA` we owe the network, if any.
+	fn next_ack(&mut self) -> Option<&'static str> {
+		if self.acks > 0 {
+			self.acks -= 1;
+			Some(CNMA_ACK)
+		} else if self.rejects > 0 {
+			self.rejects -= 1;
+			Some(CNMA_REJECT)
+		} else {
+			None
+		}
+	}
+}
+
+impl Timestamp {
+	/// Decode a TP-SCTS field: seven octets of swapped BCD digits, the last
+	/// giving the time zone in quarter hours.
+	fn from_octets(octets: &[u8]) -> Result<Timestamp, Error> {
+		let octets: &[u8; 7] = octets.try_into().map_err(|_| Error::BadDataFormat)?;
+		let bcd = |octet: u8| -> Result<u8, Error> {
+			let (tens, units) = (octet & 0x0F, octet >> 4);
+			if tens > 9 || units > 9 {
+				return Err(Error::BadDataFormat);
+			}
+			Ok(tens * 10 + units)
+		};
+		// Bit 3 of the time zone's first digit is its sign
+		let zone = octets[6];
+		let utc_offset_minutes = i16::from(bcd(zone & !0x08)?) * 15;
+		Ok(Timestamp {
+			year: 2000 + u16::from(bcd(octets[0])?),
+			month: bcd(octets[1])?,
+			day: bcd(octets[2])?,
+			hour: bcd(octets[3])?,
+			minute: bcd(octets[4])?,
+			second: bcd(octets[5])?,
+			utc_offset_minutes: if zone & 0x08 != 0 {
+				-utc_offset_minutes
+			} else {
+				utc_offset_minutes
+			},
+		})
+	}
+}
+
+impl Encoding {
+	/// Work out the encoding from a TP-DCS octet (TS 23.038 section 4).
+	/// Anything we don't understand is treated as GSM 7-bit.
+	fn from_dcs(dcs: u8) -> Encoding {
+		let alphabet = match dcs & 0xF0 {
+			0x00..=0x70 => (dcs >> 2) & 0b11,
+			0xF0 => (dcs >> 2) & 0b01,
+			_ => 0b00,
+		};
+		match alphabet {
+			0b01 => Encoding::Data8,
+			0b10 => Encoding::Ucs2,
+			_ => Encoding::Gsm7,
+		}
+	}
+}
+
+/// Convert one hex digit.
+fn hex_digit(digit: u8) -> Result<u8, Error> {
+	match digit {
+		b'0'..=b'9' => Ok(digit - b'0'),
+		b'a'..=b'f' => Ok(digit - b'a' + 10),
+		b'A'..=b'F' => Ok(digit - b'A' + 10),
+		_ => Err(Error::BadDataFormat),
+	}
+}
+
+/// Convert one semi-octet of an address (TS 23.040 section 9.1.2.3). Only
+/// 0xF, the filler, has no character.
+fn semi_octet(digit: u8) -> Option<char> {
+	match digit {
+		0x0..=0x9 => core::char::from_digit(u32::from(digit), 10),
+		0xA => Some('*'),
+		0xB => Some('#'),
+		0xC => Some('a'),
+		0xD => Some('b'),
+		0xE => Some('c'),
+		_ => None,
+	}
+}
+
+/// Get septet `index` from GSM 7-bit packed data.
+fn septet(packed: &[u8], index: usize) -> u8 {
+	let bit = index * 7;
+	let low = u16::from(packed.get(bit / 8).copied().unwrap_or(0));
+	let high = u16::from(packed.get(bit / 8 + 1).copied().unwrap_or(0));
+	((((high << 8) | low) >> (bit % 8)) & 0x7F) as u8
+}
+
+/// Put septet `value` at `index` in GSM 7-bit packed data.
+#[cfg(target_arch = "arm")]
+fn set_septet(packed: &mut [u8], index: usize, value: u8) {
+	let bit = index * 7;
+	let value = u16::from(value & 0x7F) << (bit % 8);
+	if let Some(low) = packed.get_mut(bit / 8) {
+		*low |= value as u8;
+	}
+	if let Some(high) = packed.get_mut(bit / 8 + 1) {
+		*high |= (value >> 8) as u8;
+	}
+}
+
+/// Build an SMS-SUBMIT TPDU (TS 23.040 section 9.2.2.2), in hex, carrying
+/// one part of a message to `number`.
+#[cfg(target_arch = "arm")]
+fn submit_pdu(
+	number: &str,
+	encoding: Encoding,
+	concat: Option<&ConcatInfo>,
+	data: &[u8],
+) -> Result<heapless::String<{ MAX_SUBMIT_LEN * 2 }>, Error> {
+	let (type_of_address, digits) = match number.trim().strip_prefix('+') {
+		Some(digits) => (TOA_INTERNATIONAL, digits),
+		None => (TOA_UNKNOWN, number.trim()),
+	};
+	if digits.is_empty()
+		|| digits.len() > MAX_NUMBER_DIGITS
+		|| !digits.bytes().all(|digit| digit.is_ascii_digit())
+	{
+		return Err(Error::BadDataFormat);
+	}
+
+	// The UDH, then the payload
+	let mut user_data = [0u8; MAX_USER_DATA];
+	let udh_len = match concat {
+		Some(info) => info.write_udh(&mut user_data)?,
+		None => 0,
+	};
+	let (udl, ud_len) = match encoding {
+		Encoding::Gsm7 => {
+			// The payload starts on the septet after the UDH
+			let skip = (udh_len * 8).div_ceil(7);
+			let udl = skip + data.len();
+			if udl * 7 > MAX_USER_DATA * 8 {
+				return Err(Error::BufferFull);
+			}
+			for (index, &value) in data.iter().enumerate() {
+				set_septet(&mut user_data, skip + index, value);
+			}
+			(udl, (udl * 7).div_ceil(8))
+		}
+		Encoding::Data8 | Encoding::Ucs2 => {
+			let udl = udh_len + data.len();
+			user_data
+				.get_mut(udh_len..udl)
+				.ok_or(Error::BufferFull)?
+				.copy_from_slice(data);
+			(udl, udl)
+		}
+	};
+	let dcs: u8 = match encoding {
+		Encoding::Gsm7 => 0x00,
+		Encoding::Data8 => 0x04,
+		Encoding::Ucs2 => 0x08,
+	};
+
+	let mut first_octet = MTI_SUBMIT;
+	if concat.is_some() {
+		first_octet |= FIRST_OCTET_UDHI;
+	}
+	let mut pdu = heapless::String::new();
+	// TP-MR is zero, for the modem to fill in
+	write!(
+		pdu,
+		"{:02X}00{:02X}{:02X}",
+		first_octet,
+		digits.len(),
+		type_of_address
+	)
+	.map_err(|_| Error::WriteError)?;
+	// The number in swapped semi-octets, padded with an F
+	for pair in digits.as_bytes().chunks(2) {
+		let (low, high) = match *pair {
+			[low, high] => (low, high),
+			[low] => (low, b'F'),
+			_ => continue,
+		};
+		write!(pdu, "{}{}", char::from(high), char::from(low)).map_err(|_| Error::WriteError)?;
+	}
+	// TP-PID is zero: a plain message
+	write!(pdu, "00{:02X}{:02X}", dcs, udl).map_err(|_| Error::WriteError)?;
+	for octet in user_data.get(0..ud_len).ok_or(Error::BufferFull)? {
+		write!(pdu, "{:02X}", octet).map_err(|_| Error::WriteError)?;
+	}
+	Ok(pdu)
+}
+
+/// Find a character in the GSM 7-bit alphabet. Returns whether it is in the
+/// extension table (so needs a `0x1B` escape first), and its value. The
+/// inverse of `gsm7_basic` and `gsm7_extension`.
+#[cfg(target_arch = "arm")]
+fn gsm7_from_char(c: char) -> Option<(bool, u8)> {
+	let value = match c {
+		'@' => 0x00,
+		'$' => 0x02,
+		'\n' => 0x0A,
+		'\r' => 0x0D,
+		'_' => 0x11,
+		' '..='#' | '%'..='?' | 'A'..='Z' | 'a'..='z' => c as u8,
+		_ => {
+			let extension = match c {
+				'^' => 0x14,
+				'{' => 0x28,
+				'}' => 0x29,
+				'\\' => 0x2F,
+				'[' => 0x3C,
+				'~' => 0x3D,
+				']' => 0x3E,
+				'|' => 0x40,
+				_ => return None,
+			};
+			return Some((true, extension));
+		}
+	};
+	Some((false, value))
+}
+
+/// Convert a character from the GSM 7-bit default alphabet. Those without
+/// an ASCII equivalent become `?`.
+fn gsm7_basic(value: u8) -> char {
+	match value {
+		0x00 => '@',
+		0x02 => '$',
+		0x0A => '\n',
+		0x0D => '\r',
+		0x11 => '_',
+		0x20..=0x23 | 0x25..=0x3F | 0x41..=0x5A | 0x61..=0x7A => char::from(value),
+		_ => '?',
+	}
+}
+
+/// Convert a character from the GSM 7-bit extension table (the one after a
+/// `0x1B` escape).
+fn gsm7_extension(value: u8) -> char {
+	match value {
+		0x14 => '^',
+		0x28 => '{',
+		0x29 => '}',
+		0x2F => '\\',
+		0x3C => '[',
+		0x3D => '~',
+		0x3E => ']',
+		0x40 => '|',
+		_ => '?',
+	}
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn deliver_from_a_service_number() {
+		// From "*123#", "hi"
+		let message = Deliver::parse("07911326040000F00405811A32FB00002080629173140802E834");
+		assert!(matches!(&message, Ok(m) if m.sender == "*123#"));
+		let text = message.and_then(|m| m.text());
+		assert!(matches!(text, Ok(text) if text == "hi"));
+	}
+
+	#[test]
+	fn deliver_from_an_international_number() {
+		let message = Deliver::parse(
+			"07911326040000F0040B911346610089F60000208062917314080CC8F71D14969741F977FD07",
+		);
+		assert!(matches!(message, Ok(m) if m.sender == "+31641600986"));
+	}
+
+	#[test]
+	fn semi_octets() {
+		assert_eq!(semi_octet(0x0), Some('0'));
+		assert_eq!(semi_octet(0x9), Some('9'));
+		assert_eq!(semi_octet(0xA), Some('*'));
+		assert_eq!(semi_octet(0xB), Some('#'));
+		assert_eq!(semi_octet(0xE), Some('c'));
+		assert_eq!(semi_octet(0xF), None);
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,206 @@
+//! # SMS command channel for nrfxlib
+//!
+//! A device which is asleep, or whose data connection has broken, can often
+//! still receive an SMS. A `CommandChannel` turns those into commands: each
+//! handler is registered against a keyword (e.g. `CALLHOME` or `RECOVER`),
+//! and is called when a message starting with that keyword arrives from one
+//! of the allowed senders. Anything else is ignored.
+//!
+//! Call `sms::start_receiving` first, then pass each `+CMT` URC you read from
+//! an AT socket to `CommandChannel::handle_urc`. For example:
+//!
+//! ```ignore
+//! fn call_home(command: &sms_command::Command) {
+//!     CALL_HOME.store(true, Ordering::Relaxed);
+//! }
+//!
+//! static OPERATORS: [&str; 1] = ["+447700900123"];
+//! let mut channel: CommandChannel<4> = CommandChannel::new(&OPERATORS);
+//! channel.register("CALLHOME", call_home)?;
+//! sms::start_receiving()?;
+//! // ... for each URC read from an AT socket
+//! channel.handle_urc(urc)?;
+//! ```
+//!
+//! The sender of an SMS is easily spoofed, so the allow-list keeps out
+//! accidents rather than attackers. Don't register handlers for anything
+//! which would do harm if a stranger triggered it.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::sms::{self, Deliver};
+use crate::Error;
+use log::{debug, warn};
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A command received by SMS, as given to its handler.
+#[derive(Debug, Copy, Clone)]
+pub struct Command<'a> {
+	/// Who sent it
+	pub sender: &'a str,
+	/// The keyword it matched, as registered
+	pub keyword: &'a str,
+	/// The rest of the message after the keyword, with surrounding
+	/// whitespace removed
+	pub arguments: &'a str,
+}
+
+/// Called when a command arrives.
+pub type Handler = fn(&Command);
+
+/// What happened to a message passed to a `CommandChannel`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Dispatched {
+	/// A handler was called
+	Handled,
+	/// The sender isn't on the allow-list, so the message was ignored
+	SenderNotAllowed,
+	/// No handler is registered for the message's first word
+	UnknownCommand,
+	/// The message is part of a concatenated message, which we don't treat
+	/// as a command
+	NotACommand,
+}
+
+/// Matches received messages against registered keywords, for up to
+/// `HANDLERS` keywords.
+#[derive(Debug, Clone)]
+pub struct CommandChannel<'a, const HANDLERS: usize> {
+	allowed_senders: &'a [&'a str],
+	handlers: heapless::Vec<(&'a str, Handler), HANDLERS>,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The URC a received message arrives in
+const CMT_URC: &str = "+CMT:";
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl<'a, const HANDLERS: usize> CommandChannel<'a, HANDLERS> {
+	/// Create a channel which accepts commands from the given senders (as
+	/// they appear in `Deliver::sender`, e.g. `+447700900123`). With an empty
+	/// list, every message is ignored.
+	pub fn new(allowed_senders: &'a [&'a str]) -> Self {
+		CommandChannel {
+			allowed_senders,
+			handlers: heapless::Vec::new(),
+		}
+	}
+
+	/// Call `handler` for messages whose first word is `keyword`. Keywords
+	/// are matched ignoring ASCII case. Registering a keyword again replaces
+	/// its handler. Returns `Error::BufferFull` if `HANDLERS` keywords are
+	/// already registered.
+	pub fn register(&mut self, keyword: &'a str, handler: Handler) -> Result<(), Error> {
+		if let Some(entry) = self
+			.handlers
+			.iter_mut()
+			.find(|(registered, _)| registered.eq_ignore_ascii_case(keyword))
+		{
+			entry.1 = handler;
+			return Ok(());
+		}
+		self.handlers
+			.push((keyword, handler))
+			.map_err(|_| Error::BufferFull)
+	}
+
+	/// Handle a message which has already been decoded.
+	pub fn dispatch(&self, sender: &str, text: &str) -> Dispatched {
+		if !self.allowed_senders.contains(&sender) {
+			warn!("Ignoring SMS from {:?}", sender);
+			return Dispatched::SenderNotAllowed;
+		}
+		let text = text.trim();
+		let (word, arguments) = match text.find(char::is_whitespace) {
+			Some(index) => (&text[..index], text[index..].trim()),
+			None => (text, ""),
+		};
+		match self
+			.handlers
+			.iter()
+			.find(|(keyword, _)| keyword.eq_ignore_ascii_case(word))
+		{
+			Some((keyword, handler)) => {
+				debug!("SMS command {:?} from {:?}", keyword, sender);
+				handler(&Command {
+					sender,
+					keyword,
+					arguments,
+				});
+				Dispatched::Handled
+			}
+			None => Dispatched::UnknownCommand,
+		}
+	}
+
+	/// Handle a received message, given as an SMS-DELIVER PDU in hex.
+	pub fn handle_pdu(&self, pdu_hex: &str) -> Result<Dispatched, Error> {
+		let message = Deliver::parse(pdu_hex)?;
+		if message.concat.is_some() {
+			return Ok(Dispatched::NotACommand);
+		}
+		let text = message.text()?;
+		Ok(self.dispatch(&message.sender, &text))
+	}
+
+	/// Look for a `+CMT` URC in what was read from an AT socket, and handle
+	/// the message it carries. The message is acknowledged with
+	/// `sms::acknowledge` whether or not it was a command, so the network
+	/// doesn't send it again. Returns `None` if there's no `+CMT` URC.
+	pub fn handle_urc(&self, urc: &str) -> Result<Option<Dispatched>, Error> {
+		let mut lines = urc.lines().map(str::trim);
+		if lines.find(|line| line.starts_with(CMT_URC)).is_none() {
+			return Ok(None);
+		}
+		let pdu_hex = lines
+			.find(|line| !line.is_empty())
+			.ok_or(Error::BadDataFormat)?;
+		let result = self.handle_pdu(pdu_hex);
+		sms::acknowledge()?;
+		result.map(Some)
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// End of File
+//******************************************************************************
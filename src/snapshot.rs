@@ -0,0 +1,95 @@
+//! # State snapshots for nrfxlib
+//!
+//! Gathers up everything this crate knows about the modem and its sockets, so
+//! it can be logged (e.g. over RTT) when things go wrong.
+//!
+//! Taking a snapshot does not talk to the modem - it only reports what the
+//! crate last saw - so it is safe to call even when the modem has stopped
+//! responding.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::{
+	at::Subscriptions,
+	modem::{FunctionalMode, RegistrationStatus},
+	pool::PoolStats,
+	raw::{SocketRecord, MAX_SOCKETS},
+};
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A snapshot of the crate's view of the modem.
+#[derive(Debug, Clone)]
+pub struct StateSnapshot {
+	/// The functional mode (`AT+CFUN`) the modem was last known to be in.
+	pub functional_mode: Option<FunctionalMode>,
+	/// The registration status (`+CEREG`) the modem last reported.
+	pub registration: Option<RegistrationStatus>,
+	/// Every socket currently open, with its type and peer.
+	pub sockets: heapless::Vec<SocketRecord, MAX_SOCKETS>,
+	/// The URC subscriptions made through this crate.
+	pub subscriptions: Subscriptions,
+	/// How many AT commands are waiting for a final response.
+	pub pending_at_commands: usize,
+	/// How the buffer pool is being used.
+	pub pool: PoolStats,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Take a snapshot of the crate's state.
+pub fn state_snapshot() -> StateSnapshot {
+	StateSnapshot {
+		functional_mode: crate::modem::last_functional_mode(),
+		registration: crate::modem::last_registration_status(),
+		sockets: crate::raw::open_sockets(),
+		subscriptions: crate::at::subscriptions(),
+		pending_at_commands: crate::at::pending_commands(),
+		pool: crate::pool::stats(),
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// End of File
+//******************************************************************************
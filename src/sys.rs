@@ -0,0 +1,88 @@
+//! # Raw libmodem bindings for nrfxlib
+//!
+//! Everything `nrfxlib-sys` generates from `nrf_socket.h`, `nrf_modem.h`
+//! and friends, for reaching a modem feature this crate doesn't wrap yet.
+//! These are the very bindings this crate links against, so there is no
+//! second copy of libmodem (and no second `nrf_modem_os_*` implementation)
+//! to clash with. Only built with the `sys` feature.
+//!
+//! Every function here is `unsafe`, and the crate relies on these rules
+//! being kept:
+//!
+//! * Only call libmodem between `nrfxlib::init` (or
+//!   `NrfxlibBuilder::init`) and `nrfxlib::shutdown`, and never call
+//!   `nrf_modem_init` or `nrf_modem_shutdown` yourself.
+//! * Never close a socket this crate opened. Its descriptor belongs to the
+//!   `Socket` (and is tracked in `open_sockets`); closing it underneath
+//!   leaves the `Socket` holding a number libmodem may give to someone
+//!   else. Open your own with `nrf_socket`, and close them yourself.
+//! * Send AT commands with `at::send_at_command`, not an `NRF_PROTO_AT`
+//!   socket of your own, so the changes the crate keeps track of
+//!   (functional mode, registration, NVM writes) aren't missed.
+//! * Pointers given to libmodem must stay valid for as long as the call
+//!   says. Buffers for `nrf_recv` and friends must be at least as long as
+//!   the length given, and must not be touched from an interrupt while a
+//!   call is using them.
+//! * The error from a call which returns `-1` is only meaningful straight
+//!   afterwards, before anything else calls libmodem - read it with
+//!   `get_last_error`.
+//!
+//! The bindings follow the libmodem version this crate pins (1.5.1), and
+//! change with it. Nothing here is covered by this crate's semver promises.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+pub use nrfxlib_sys::*;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -18,9 +18,10 @@
 //******************************************************************************
 
 use super::{get_last_error, Error};
+use crate::net::AddressFamily;
 use crate::raw::*;
+use core::cell::Cell;
 use log::debug;
-use nrfxlib_sys as sys;
 
 //******************************************************************************
 // Types
@@ -30,13 +31,34 @@ use nrfxlib_sys as sys;
 #[derive(Debug)]
 pub struct TcpSocket {
 	socket: Socket,
+	connecting: Cell<Connecting>,
+}
+
+/// An address for `connect_by_hostname` to try, in either family.
+#[derive(Copy, Clone)]
+enum Target {
+	Ipv4(crate::sys::nrf_sockaddr_in),
+	Ipv6(crate::sys::nrf_sockaddr_in6),
+}
+
+/// One address family's side of a `connect_by_hostname` race: the
+/// addresses it found, and the socket trying one of them, if any.
+struct Attempt {
+	domain: SocketDomain,
+	targets: heapless::Vec<Target, { crate::dns::MAX_ADDRESSES }>,
+	/// The next of `targets` to try
+	next: usize,
+	connecting: Option<TcpSocket>,
+	/// Why the lookup, or the last address tried, failed
+	error: Option<Error>,
 }
 
 //******************************************************************************
 // Constants
 //******************************************************************************
 
-// None
+/// How long `connect_by_hostname` waits at a time for a connect to finish.
+const RACE_POLL_MS: u16 = 100;
 
 //******************************************************************************
 // Global Variables
@@ -63,88 +85,291 @@ pub struct TcpSocket {
 impl TcpSocket {
 	/// Create a new TCP socket.
 	pub fn new() -> Result<TcpSocket, Error> {
-		let socket = Socket::new(SocketDomain::Inet, SocketType::Stream, SocketProtocol::Tcp)?;
+		Self::new_in(SocketDomain::Inet)
+	}
+
+	/// Create a new TCP socket and connect it to a hostname which may have
+	/// both IPv4 and IPv6 addresses, trying the `prefer`red family first
+	/// ("happy eyeballs", RFC 8305).
+	///
+	/// If the preferred family hasn't connected within
+	/// `timeouts::current().fallback_ms`, the other family starts
+	/// connecting too, and the two race: whichever connects first is
+	/// returned, and the other is closed. If the preferred family has no
+	/// addresses, or they all fail, the other starts straight away. Each
+	/// family tries its addresses one at a time. This saves waiting out the
+	/// whole connect timeout when a carrier's IPv6 (say) is broken.
+	///
+	/// All of it must be done within `connect_ms`, or this fails with
+	/// `Error::TimedOut`. Only time spent waiting with no connect finishing
+	/// counts, in steps of 100 ms, and the DNS lookups themselves aren't
+	/// limited (see `timeouts`).
+	///
+	/// The peer of an IPv6 connection isn't recorded in `SocketRecord`, and
+	/// isn't reported to the observer.
+	pub fn connect_by_hostname(
+		hostname: &str,
+		port: u16,
+		prefer: AddressFamily,
+	) -> Result<TcpSocket, Error> {
+		debug!(
+			"Connecting via TCP to {}:{}, preferring {:?}",
+			hostname, port, prefer
+		);
+		crate::destinations::check_host(hostname, port)?;
+		let (first, second) = match prefer {
+			AddressFamily::Ipv4 => (SocketDomain::Inet, SocketDomain::Inet6),
+			AddressFamily::Ipv6 => (SocketDomain::Inet6, SocketDomain::Inet),
+		};
+		let timeouts = crate::timeouts::current();
+		let mut preferred = Attempt::lookup(first, hostname, port);
+		let mut other: Option<Attempt> = None;
+		let mut waited_ms = 0u32;
+		loop {
+			if let Some(socket) = preferred.step() {
+				return Ok(socket);
+			}
+			let fallback_due = timeouts.fallback_ms != 0 && waited_ms >= timeouts.fallback_ms;
+			if other.is_none() && (preferred.is_finished() || fallback_due) {
+				debug!("Falling back to {:?} as well", second);
+				other = Some(Attempt::lookup(second, hostname, port));
+			}
+			if let Some(socket) = other.as_mut().and_then(Attempt::step) {
+				return Ok(socket);
+			}
+			if let Some(other) = other.as_mut() {
+				if preferred.is_finished() && other.is_finished() {
+					return Err(other
+						.error
+						.take()
+						.or_else(|| preferred.error.take())
+						.unwrap_or(Error::BadDataFormat));
+				}
+			}
+			if timeouts.connect_ms != 0 && waited_ms >= timeouts.connect_ms {
+				debug!("Connect to {} timed out", hostname);
+				return Err(Error::TimedOut);
+			}
+			let wait_ms = match timeouts.connect_ms {
+				0 => RACE_POLL_MS,
+				connect_ms => {
+					let left_ms = connect_ms - waited_ms;
+					core::cmp::min(u32::from(RACE_POLL_MS), left_ms) as u16
+				}
+			};
+			let sockets = preferred
+				.connecting
+				.iter()
+				.chain(other.iter().flat_map(|o| o.connecting.iter()));
+			let mut poll_list: heapless::Vec<PollEntry, 2> = sockets
+				.map(|socket| PollEntry::new(socket, PollFlags::Write))
+				.collect();
+			if poll_list.is_empty() {
+				continue;
+			}
+			if poll(&mut poll_list, wait_ms)? == 0 {
+				waited_ms = waited_ms.saturating_add(u32::from(wait_ms));
+			}
+		}
+	}
+
+	/// Create a new TCP socket in the given domain.
+	fn new_in(domain: SocketDomain) -> Result<TcpSocket, Error> {
+		let socket = Socket::new(domain, SocketType::Stream, SocketProtocol::Tcp)?;
 
 		// Now configure this socket
 
-		Ok(TcpSocket { socket })
+		// Limit how long blocking receives and sends wait (see `timeouts`)
+		socket.apply_default_timeouts()?;
+
+		Ok(TcpSocket {
+			socket,
+			connecting: Cell::new(Connecting::Idle),
+		})
+	}
+
+	/// Bind to this local port before connecting, rather than letting the
+	/// modem pick one.
+	pub fn bind(&self, port: u16) -> Result<(), Error> {
+		self.socket.bind_port(port)
+	}
+
+	/// Bind to a random ephemeral port before connecting, and return it. The
+	/// modem picks its ports predictably, so after a PSM sleep a reconnect
+	/// can reuse a port some carrier NATs still have a stale binding for.
+	/// The port comes from the `entropy` source, if one has been set.
+	pub fn bind_random(&self) -> Result<u16, Error> {
+		self.socket.bind_random_port()
 	}
 
 	/// Look up the hostname and for each result returned, try to connect to
 	/// it.
+	///
+	/// If that fails in a way that suggests the address was stale, the
+	/// hostname is looked up again and we try once more. The modem's own DNS
+	/// cache can't be bypassed, but it will have dropped an expired record
+	/// by then. The two attempts get half of
+	/// `timeouts::current().connect_ms` each.
 	pub fn connect(&self, hostname: &str, port: u16) -> Result<(), Error> {
-		use core::fmt::Write;
-
 		debug!("Connecting via TCP to {}:{}", hostname, port);
+		crate::destinations::check_host(hostname, port)?;
 
-		// Now, make a null-terminated hostname
-		let mut hostname_smallstring: heapless::String<64> = heapless::String::new();
-		write!(hostname_smallstring, "{}\0", hostname).map_err(|_| Error::HostnameTooLong)?;
-		// Now call getaddrinfo with some hints
-		let hints = sys::nrf_addrinfo {
-			ai_flags: 0,
-			ai_family: sys::NRF_AF_INET as i32,
-			ai_socktype: sys::NRF_SOCK_STREAM as i32,
-			ai_protocol: 0,
-			ai_addrlen: 0,
-			ai_addr: core::ptr::null_mut(),
-			ai_canonname: core::ptr::null_mut(),
-			ai_next: core::ptr::null_mut(),
-		};
-		let mut output_ptr: *mut sys::nrf_addrinfo = core::ptr::null_mut();
-		let mut result = unsafe {
-			sys::nrf_getaddrinfo(
-				// hostname
-				hostname_smallstring.as_ptr(),
-				// service
-				core::ptr::null(),
-				// hints
-				&hints,
-				// output pointer
-				&mut output_ptr,
-			)
+		// Limit how long connecting can take (see `timeouts`)
+		let (first_ms, retry_ms) =
+			crate::timeouts::split_for_retry(crate::timeouts::current().connect_ms);
+		self.socket.start_connect_timeout(first_ms)?;
+
+		let mut result = self.connect_once(hostname, port);
+		if let Some(retry_ms) = retry_ms {
+			if matches!(result, Ok(r) if r != 0) && is_stale_address_error(get_last_error()) {
+				// The address may be stale (e.g. carrier NAT or DNS churn), so
+				// look it up again and have one more go, in the time left.
+				debug!("Connect failed ({}), retrying lookup", get_last_error());
+				self.socket.start_connect_timeout(retry_ms)?;
+				result = self.connect_once(hostname, port);
+			}
+		}
+		// Sends go back to their own timeout, once connected. Read the error
+		// first, in case this changes it.
+		let error = get_last_error();
+		self.socket.end_connect_timeout()?;
+		match result? {
+			0 => Ok(()),
+			result => Err(Error::Nordic("tcp_connect", result, error)),
+		}
+	}
+
+	/// Start connecting to the hostname, without waiting for the connection
+	/// to be made. Call `connect_poll` until it returns
+	/// `ConnectStatus::Connected`.
+	///
+	/// As for `TlsSocket::connect_start`, the DNS lookup still blocks, only
+	/// the first address found is tried, and the `timeouts` don't apply.
+	pub fn connect_start(&self, hostname: &str, port: u16) -> Result<ConnectStatus, Error> {
+		debug!("Starting TCP connect to {}:{}", hostname, port);
+		crate::destinations::check_host(hostname, port)?;
+		let addr = lookup_ipv4(hostname, SocketType::Stream, port)?;
+		self.connect_start_addr(&addr)
+	}
+
+	/// See whether a connect started with `connect_start` has finished. This
+	/// doesn't block.
+	pub fn connect_poll(&self) -> Result<ConnectStatus, Error> {
+		self.socket.poll_connecting(&self.connecting)
+	}
+
+	/// Start connecting to an address we already have, as `connect_start`
+	/// does.
+	pub(crate) fn connect_start_addr(
+		&self,
+		addr: &crate::sys::nrf_sockaddr_in,
+	) -> Result<ConnectStatus, Error> {
+		debug!("Trying IP address {}", &crate::NrfSockAddrIn(*addr));
+		let status = self.socket.connect_start(addr)?;
+		if status == ConnectStatus::InProgress {
+			self.connecting.set(Connecting::InProgress(*addr));
+		}
+		Ok(status)
+	}
+
+	/// Start connecting to a `connect_by_hostname` target, without blocking.
+	fn connect_start_target(&self, target: &Target) -> Result<ConnectStatus, Error> {
+		match target {
+			Target::Ipv4(addr) => self.connect_start_addr(addr),
+			Target::Ipv6(addr) => {
+				let ip = crate::net::Ipv6Addr::from_octets(addr.sin6_addr.s6_addr);
+				debug!(
+					"Trying IP address [{}]:{}",
+					ip,
+					u16::from_be(addr.sin6_port)
+				);
+				self.socket.connect_start_ipv6(addr)
+			}
+		}
+	}
+
+	/// See whether a connect started with `connect_start_target` has
+	/// finished.
+	fn connect_poll_target(&self, domain: SocketDomain) -> Result<ConnectStatus, Error> {
+		match domain {
+			SocketDomain::Inet6 => self.socket.connect_poll_ipv6(),
+			_ => self.connect_poll(),
+		}
+	}
+
+	/// Look up the hostname and try to connect to each address returned, as
+	/// `connect` does. Returns the result of the last `nrf_connect`.
+	fn connect_once(&self, hostname: &str, port: u16) -> Result<i32, Error> {
+		let addresses = AddrInfoList::lookup(hostname, SocketType::Stream, "tcp_dns")?;
+		self.socket.connect_any(&addresses, port)
+	}
+}
+
+impl Attempt {
+	/// Look up the hostname's addresses in this family, ready to try them.
+	fn lookup(domain: SocketDomain, hostname: &str, port: u16) -> Attempt {
+		let mut attempt = Attempt {
+			domain,
+			targets: heapless::Vec::new(),
+			next: 0,
+			connecting: None,
+			error: None,
 		};
-		if (result == 0) && (!output_ptr.is_null()) {
-			let mut record: &sys::nrf_addrinfo = unsafe { &*output_ptr };
-			loop {
-				let dns_addr: &sys::nrf_sockaddr_in =
-					unsafe { &*(record.ai_addr as *const sys::nrf_sockaddr_in) };
-				// Create a new sockaddr_in with the right port
-				let connect_addr = sys::nrf_sockaddr_in {
-					sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
-					sin_family: sys::NRF_AF_INET as i32,
-					sin_port: htons(port),
-					sin_addr: dns_addr.sin_addr,
+		match AddrInfoList::lookup_in(domain, hostname, SocketType::Stream, "tcp_dns") {
+			Ok(addresses) => {
+				let targets = &mut attempt.targets;
+				let _ = match domain {
+					SocketDomain::Inet6 => addresses
+						.ipv6_addresses(port)
+						.try_for_each(|addr| targets.push(Target::Ipv6(addr)).map_err(|_| ())),
+					_ => addresses
+						.addresses(port)
+						.try_for_each(|addr| targets.push(Target::Ipv4(addr)).map_err(|_| ())),
 				};
+			}
+			Err(e) => {
+				debug!("{:?} lookup failed ({:?})", domain, e);
+				attempt.error = Some(e);
+			}
+		}
+		attempt
+	}
 
-				debug!("Trying IP address {}", &crate::NrfSockAddrIn(connect_addr));
+	/// Has every address been tried, and failed?
+	fn is_finished(&self) -> bool {
+		self.connecting.is_none() && self.next >= self.targets.len()
+	}
 
-				// try and connect to this result
-				result = unsafe {
-					sys::nrf_connect(
-						self.socket.fd,
-						&connect_addr as *const sys::nrf_sockaddr_in as *const _,
-						connect_addr.sin_len as u32,
-					)
-				};
-				if result == 0 {
-					break;
-				}
-				if !record.ai_next.is_null() {
-					record = unsafe { &*record.ai_next };
-				} else {
-					break;
+	/// See how the connect in progress is going, moving on to the next
+	/// address if it has failed. Returns the socket once one connects.
+	fn step(&mut self) -> Option<TcpSocket> {
+		loop {
+			if let Some(socket) = self.connecting.take() {
+				match socket.connect_poll_target(self.domain) {
+					Ok(ConnectStatus::Connected) => return Some(socket),
+					Ok(ConnectStatus::InProgress) => {
+						self.connecting = Some(socket);
+						return None;
+					}
+					Err(e) => {
+						debug!("Connect failed ({:?})", e);
+						self.error = Some(e);
+					}
 				}
 			}
-			unsafe {
-				sys::nrf_freeaddrinfo(output_ptr);
+			let target = *self.targets.get(self.next)?;
+			self.next += 1;
+			let started = TcpSocket::new_in(self.domain)
+				.and_then(|socket| Ok((socket.connect_start_target(&target)?, socket)));
+			match started {
+				Ok((ConnectStatus::Connected, socket)) => return Some(socket),
+				Ok((ConnectStatus::InProgress, socket)) => self.connecting = Some(socket),
+				Err(e) => {
+					debug!("Connect failed ({:?})", e);
+					self.error = Some(e);
+				}
 			}
 		}
-		if result != 0 {
-			Err(Error::Nordic("tcp_connect", result, get_last_error()))
-		} else {
-			Ok(())
-		}
 	}
 }
 
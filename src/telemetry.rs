@@ -0,0 +1,218 @@
+//! # Telemetry batching for nrfxlib
+//!
+//! Collects small, pre-encoded application payloads (e.g. CBOR records) and
+//! sends them over a UDP socket in one burst, so the radio wakes up once per
+//! batch rather than once per measurement. Delivery can optionally be
+//! confirmed by an application-level acknowledgement, and each payload can
+//! carry a message ID (see `push_with_id`) so the server can drop the
+//! duplicates a retry sends.
+//!
+//! Release Assistance Indication (RAI) is modem-wide rather than per packet
+//! (see `modem::set_release_assistance`), so `send` can't flag just the final
+//! datagram of a batch. Sending everything back-to-back still lets the
+//! network release the RRC connection at the end of the burst.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::message_ids::{IdStore, MessageId, MessageIds};
+use crate::{udp::UdpSocket, Error};
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// A batch of up to `N` payloads, sharing `SIZE` bytes of storage.
+///
+/// Each payload is sent as its own datagram.
+#[derive(Debug, Clone)]
+pub struct Telemetry<const N: usize, const SIZE: usize> {
+	data: heapless::Vec<u8, SIZE>,
+	lengths: heapless::Vec<usize, N>,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl<const N: usize, const SIZE: usize> Telemetry<N, SIZE> {
+	/// Create a new, empty, batch.
+	pub fn new() -> Self {
+		Telemetry {
+			data: heapless::Vec::new(),
+			lengths: heapless::Vec::new(),
+		}
+	}
+
+	/// Add an encoded payload to the batch.
+	///
+	/// Returns `Error::BufferFull` if there is no room for it, in which case
+	/// the batch is unchanged and you probably want to `send` it.
+	pub fn push(&mut self, payload: &[u8]) -> Result<(), Error> {
+		if self.lengths.is_full() || (self.data.capacity() - self.data.len()) < payload.len() {
+			return Err(Error::BufferFull);
+		}
+		// Both of these were checked above
+		let _ = self.data.extend_from_slice(payload);
+		let _ = self.lengths.push(payload.len());
+		Ok(())
+	}
+
+	/// Add a payload carrying a new message ID to the batch. `encode` is
+	/// given the ID and the room left in the batch, writes the payload
+	/// there, and returns how long it is.
+	///
+	/// As the ID is part of the payload, retrying a batch which failed to
+	/// send (or wasn't acknowledged) sends the same ID again, and the server
+	/// can drop the duplicates. See `message_ids`. Returns the ID, or
+	/// `Error::BufferFull` if there is no room, in which case the batch is
+	/// unchanged.
+	pub fn push_with_id<S, F>(
+		&mut self,
+		ids: &mut MessageIds<S>,
+		encode: F,
+	) -> Result<MessageId, Error>
+	where
+		S: IdStore,
+		F: FnOnce(MessageId, &mut [u8]) -> Result<usize, Error>,
+	{
+		if self.lengths.is_full() {
+			return Err(Error::BufferFull);
+		}
+		let id = ids.next_id()?;
+		let start = self.data.len();
+		// Can't fail, as it is the capacity
+		let _ = self.data.resize(SIZE, 0);
+		let result = encode(id, self.data.get_mut(start..).unwrap_or_default());
+		match result {
+			Ok(length) if length <= SIZE - start => {
+				self.data.truncate(start + length);
+				// This was checked above
+				let _ = self.lengths.push(length);
+				Ok(id)
+			}
+			Ok(_) => {
+				self.data.truncate(start);
+				Err(Error::BufferFull)
+			}
+			Err(e) => {
+				self.data.truncate(start);
+				Err(e)
+			}
+		}
+	}
+
+	/// How many payloads are waiting in this batch.
+	pub fn len(&self) -> usize {
+		self.lengths.len()
+	}
+
+	/// Is this batch empty?
+	pub fn is_empty(&self) -> bool {
+		self.lengths.is_empty()
+	}
+
+	/// Throw away everything in the batch.
+	pub fn clear(&mut self) {
+		self.data.clear();
+		self.lengths.clear();
+	}
+
+	/// Iterate through the payloads in this batch, in the order they were
+	/// pushed.
+	pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+		let mut offset = 0;
+		self.lengths.iter().map(move |length| {
+			let payload = self.data.get(offset..offset + length).unwrap_or(&[]);
+			offset += length;
+			payload
+		})
+	}
+
+	/// Send every payload in the batch, in order, on the given (connected)
+	/// socket.
+	///
+	/// The batch is only cleared if every payload was sent. If a send fails,
+	/// the whole batch is kept so you can retry it later - which means the
+	/// server may see some payloads twice.
+	pub fn send(&mut self, socket: &UdpSocket) -> Result<usize, Error> {
+		let count = self.len();
+		debug!("Sending telemetry batch of {} payloads", count);
+		for payload in self.iter() {
+			socket.write(payload)?;
+		}
+		self.clear();
+		Ok(count)
+	}
+
+	/// Send every payload in the batch and then wait for the application
+	/// layer to confirm delivery.
+	///
+	/// After the batch has been sent, `ack` is called with the socket. It
+	/// should read whatever acknowledgement your server sends and return
+	/// `Ok(true)` if the batch was received. If it returns `Ok(false)`, the
+	/// batch is kept and `Error::NotAcknowledged` is returned.
+	pub fn send_confirmed<F>(&mut self, socket: &UdpSocket, mut ack: F) -> Result<usize, Error>
+	where
+		F: FnMut(&UdpSocket) -> Result<bool, Error>,
+	{
+		let count = self.len();
+		debug!("Sending confirmed telemetry batch of {} payloads", count);
+		for payload in self.iter() {
+			socket.write(payload)?;
+		}
+		if ack(socket)? {
+			self.clear();
+			Ok(count)
+		} else {
+			Err(Error::NotAcknowledged)
+		}
+	}
+}
+
+impl<const N: usize, const SIZE: usize> Default for Telemetry<N, SIZE> {
+	fn default() -> Self {
+		Telemetry::new()
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,227 @@
+//! # Timeouts for nrfxlib
+//!
+//! The timeouts the crate uses when connecting sockets, receiving from them
+//! and waiting for AT responses.
+//!
+//! NB-IoT is a lot slower than LTE-M - a TLS handshake which takes a couple
+//! of seconds on LTE-M can take tens of seconds on NB-IoT - so the configured
+//! timeouts are multiplied by `nb_iot_multiplier` whenever the modem last
+//! reported (in `+CEREG`) that it is using NB-IoT. Coverage enhancement
+//! slows things down too, as the network repeats everything to reach a
+//! device in poor coverage, so they are multiplied by `ce_level_multiplier`
+//! again for each coverage enhancement level above 0 the modem last
+//! reported (in `%MDMEV: PRACH CE-LEVEL`, see `modem::last_ce_level`).
+//!
+//! The receive and send timeouts are applied to every new TCP, UDP, TLS and
+//! DTLS socket, and each socket can override them with
+//! `set_receive_timeout` and `set_send_timeout`. If every timeout here is
+//! non-zero, and shorter than your watchdog period once scaled, then no
+//! connect, send, receive or AT command can block for longer than that -
+//! except for DNS lookups (see below).
+//!
+//! The libmodem version we use has no way to set a DNS timeout, or to cancel
+//! a lookup, so `dns_ms` is enforced from `nrf_modem_os_timedwait`, where
+//! libmodem waits for the answer: once the time is up, the wait reports a
+//! timeout, and the lookup fails with `Error::DnsTimeout`. Only time the
+//! lookup spends waiting counts, so it may take a little longer than
+//! `dns_ms`, and the modem may still be resolving the name after we have
+//! given up. Only one lookup at a time is timed: one started while another
+//! is in progress goes without. With
+//! `dns_ms` zero, lookups take as long as the modem's resolver does - tens
+//! of seconds, on NB-IoT.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use crate::modem::AccessTechnology;
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// The timeouts used by the crate, in milliseconds. Zero means wait forever.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timeouts {
+	/// How long a TCP connect may take. Applied as the socket's send timeout
+	/// while connecting. `TcpSocket::connect` gives half of it to the
+	/// retry it makes if the address looks stale.
+	pub connect_ms: u32,
+	/// How much of `connect_ms` `TcpSocket::connect_by_hostname` gives the
+	/// preferred address family on its own, before the other one starts
+	/// connecting too. Zero means the other family only starts if the
+	/// preferred one fails.
+	pub fallback_ms: u32,
+	/// How long a TLS or DTLS connect (including the handshake) may take.
+	/// Applied as the socket's send timeout while connecting, and shared
+	/// with any retry as for `connect_ms`.
+	pub tls_handshake_ms: u32,
+	/// How long to wait for each line of an AT response.
+	pub at_ms: u32,
+	/// How long a blocking receive (`recv_wait`) on a new TCP, UDP, TLS or
+	/// DTLS socket may wait.
+	pub receive_ms: u32,
+	/// How long a blocking send (`write`) on a new TCP, UDP, TLS or DTLS
+	/// socket may wait.
+	pub send_ms: u32,
+	/// How long each DNS lookup (`nrf_getaddrinfo`) may take, whether from
+	/// the `dns` module or a connect to a hostname.
+	pub dns_ms: u32,
+	/// What to multiply the other timeouts by when the modem is using
+	/// NB-IoT.
+	pub nb_iot_multiplier: u32,
+	/// What to multiply the other timeouts by for each coverage
+	/// enhancement level above 0.
+	pub ce_level_multiplier: u32,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The timeouts set with `set`.
+static TIMEOUTS: Mutex<RefCell<Timeouts>> = Mutex::new(RefCell::new(Timeouts::DEFAULT));
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Change the timeouts. These are the LTE-M values - they are scaled up
+/// automatically on NB-IoT.
+pub fn set(timeouts: Timeouts) {
+	cortex_m::interrupt::free(|cs| {
+		*TIMEOUTS.borrow(cs).borrow_mut() = timeouts;
+	});
+}
+
+/// Get the timeouts, as given to `set`.
+pub fn get() -> Timeouts {
+	cortex_m::interrupt::free(|cs| *TIMEOUTS.borrow(cs).borrow())
+}
+
+/// Get the timeouts to use right now, scaled for the access technology and
+/// coverage enhancement level the modem last reported.
+pub fn current() -> Timeouts {
+	get().scaled_for(
+		crate::modem::last_access_technology(),
+		crate::modem::last_ce_level(),
+	)
+}
+
+/// Scale a timeout of your own (say, a protocol's retransmission timeout)
+/// as `current` scales ours, for the access technology and coverage
+/// enhancement level the modem last reported.
+pub fn scaled(ms: u32) -> u32 {
+	let multiplier = get().multiplier(
+		crate::modem::last_access_technology(),
+		crate::modem::last_ce_level(),
+	);
+	ms.saturating_mul(multiplier)
+}
+
+impl Timeouts {
+	/// The default timeouts. AT commands, receives and sends wait forever,
+	/// as they always have, but connects and DNS lookups are limited.
+	pub const DEFAULT: Timeouts = Timeouts {
+		connect_ms: 30_000,
+		fallback_ms: 5_000,
+		tls_handshake_ms: 30_000,
+		at_ms: 0,
+		receive_ms: 0,
+		send_ms: 0,
+		dns_ms: 15_000,
+		nb_iot_multiplier: 4,
+		ce_level_multiplier: 2,
+	};
+
+	/// Get these timeouts scaled for the given access technology and
+	/// coverage enhancement level. Whichever we don't know is left out.
+	pub fn scaled_for(
+		&self,
+		access_technology: Option<AccessTechnology>,
+		ce_level: Option<u8>,
+	) -> Timeouts {
+		let multiplier = self.multiplier(access_technology, ce_level);
+		let scale = |ms: u32| ms.saturating_mul(multiplier);
+		Timeouts {
+			connect_ms: scale(self.connect_ms),
+			fallback_ms: scale(self.fallback_ms),
+			tls_handshake_ms: scale(self.tls_handshake_ms),
+			at_ms: scale(self.at_ms),
+			receive_ms: scale(self.receive_ms),
+			send_ms: scale(self.send_ms),
+			dns_ms: scale(self.dns_ms),
+			nb_iot_multiplier: self.nb_iot_multiplier,
+			ce_level_multiplier: self.ce_level_multiplier,
+		}
+	}
+
+	/// What to multiply the timeouts by, for this access technology and
+	/// coverage enhancement level.
+	pub fn multiplier(
+		&self,
+		access_technology: Option<AccessTechnology>,
+		ce_level: Option<u8>,
+	) -> u32 {
+		let access_technology = match access_technology {
+			Some(AccessTechnology::NbIot) => self.nb_iot_multiplier,
+			Some(AccessTechnology::LteM) | None => 1,
+		};
+		let ce_level = self
+			.ce_level_multiplier
+			.saturating_pow(u32::from(ce_level.unwrap_or(0)));
+		access_technology.saturating_mul(ce_level)
+	}
+}
+
+impl Default for Timeouts {
+	fn default() -> Timeouts {
+		Timeouts::DEFAULT
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Share a connect timeout between a first attempt and one retry, so the
+/// two together can't take longer than `timeout_ms`. There is no time for
+/// a retry if the first attempt needs all of it. With no limit (zero),
+/// neither has one.
+pub(crate) fn split_for_retry(timeout_ms: u32) -> (u32, Option<u32>) {
+	let retry_ms = timeout_ms / 2;
+	match (timeout_ms, retry_ms) {
+		(0, _) => (0, Some(0)),
+		(_, 0) => (timeout_ms, None),
+		_ => (timeout_ms - retry_ms, Some(retry_ms)),
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -17,9 +17,10 @@
 // Imports
 //******************************************************************************
 
-use super::{get_last_error, AtError, Error};
+use super::{get_last_error, Error};
 use crate::raw::*;
-use core::fmt::Write;
+use crate::tls_credentials::{CredentialType, StoreError};
+use core::cell::Cell;
 use log::debug;
 use nrfxlib_sys as sys;
 
@@ -31,6 +32,21 @@ use nrfxlib_sys as sys;
 #[derive(Debug)]
 pub struct TlsSocket {
 	socket: Socket,
+	/// How far `connect_start` has got
+	connecting: Cell<Connecting>,
+	/// Whether `set_tls_hostname` has chosen the hostname, so connecting
+	/// doesn't set it
+	custom_hostname: Cell<bool>,
+}
+
+/// Listens for incoming TLS connections, with us as the TLS server.
+///
+/// Not every modem firmware supports the server role - if yours doesn't,
+/// `TlsListener::bind` fails.
+#[derive(Debug)]
+pub struct TlsListener {
+	socket: Socket,
+	protocol: SocketProtocol,
 }
 
 /// Specify which version of the TLS standard to use
@@ -54,24 +70,23 @@ pub enum PeerVerification {
 	Disabled,
 }
 
-#[derive(Debug, Copy, Clone)]
-enum CredentialType {
-	RootCA = 0,
-	ClientCert = 1,
-	ClientPrivate = 2,
-}
-
-#[derive(Debug, Copy, Clone)]
-enum CredentialOpcode {
-	Write = 0,
-	Delete = 3,
-}
-
 //******************************************************************************
 // Constants
 //******************************************************************************
 
-// None
+/// `NRF_SO_SEC_ROLE` value for the server role
+const ROLE_SERVER: sys::nrf_sec_role_t = 1;
+
+/// `NRF_SO_SEC_SESSION_CACHE` value to cache sessions
+pub(crate) const SESSION_CACHE_ENABLED: sys::nrf_sec_session_cache_t = 1;
+
+/// `NRF_SO_SEC_SESSION_CACHE` value not to cache sessions (the modem's
+/// default)
+pub(crate) const SESSION_CACHE_DISABLED: sys::nrf_sec_session_cache_t = 0;
+
+/// How many incoming connections a `TlsListener` queues. The modem has few
+/// sockets to spare, so we don't queue more than one.
+const LISTEN_BACKLOG: i32 = 1;
 
 //******************************************************************************
 // Global Variables
@@ -105,12 +120,14 @@ impl TlsSocket {
 
 		// Now configure this socket
 
+		// Limit how long blocking receives and sends wait (see `timeouts`)
+		socket.apply_default_timeouts()?;
+
 		// Set whether we verify the peer
 		socket.set_option(SocketOption::TlsPeerVerify(peer_verify.as_integer()))?;
 
-		// Always enable session caching to speed up connecting. 0 = enabled, 1
-		// = disabled (the default).
-		socket.set_option(SocketOption::TlsSessionCache(0))?;
+		// Always enable session caching to speed up connecting
+		socket.set_option(SocketOption::TlsSessionCache(SESSION_CACHE_ENABLED))?;
 
 		// We don't set the cipher list, and assume the defaults are sensible.
 
@@ -120,91 +137,200 @@ impl TlsSocket {
 			socket.set_option(SocketOption::TlsTagList(security_tags))?;
 		}
 
-		Ok(TlsSocket { socket })
+		Ok(TlsSocket {
+			socket,
+			connecting: Cell::new(Connecting::Idle),
+			custom_hostname: Cell::new(false),
+		})
 	}
 
 	/// Look up the hostname and for each result returned, try to connect to
 	/// it.
+	///
+	/// If that fails in a way that suggests the address was stale, the
+	/// hostname is looked up again and we try once more. The modem's own DNS
+	/// cache can't be bypassed, but it will have dropped an expired record
+	/// by then. The two attempts get half of
+	/// `timeouts::current().tls_handshake_ms` each.
 	pub fn connect(&self, hostname: &str, port: u16) -> Result<(), Error> {
 		debug!("Connecting via TLS to {}:{}", hostname, port);
+		crate::destinations::check_host(hostname, port)?;
 
-		// First we set the hostname
-		self.socket
-			.set_option(SocketOption::TlsHostName(hostname))?;
-
-		let mut result;
-		// Now, make a null-terminated hostname
-		let mut hostname_smallstring: heapless::String<64> = heapless::String::new();
-		write!(hostname_smallstring, "{}\0", hostname).map_err(|_| Error::HostnameTooLong)?;
-		// Now call getaddrinfo with some hints
-		let hints = sys::nrf_addrinfo {
-			ai_flags: 0,
-			ai_family: sys::NRF_AF_INET as i32,
-			ai_socktype: sys::NRF_SOCK_STREAM as i32,
-			ai_protocol: 0,
-			ai_addrlen: 0,
-			ai_addr: core::ptr::null_mut(),
-			ai_canonname: core::ptr::null_mut(),
-			ai_next: core::ptr::null_mut(),
-		};
-		let mut output_ptr: *mut sys::nrf_addrinfo = core::ptr::null_mut();
-		result = unsafe {
-			sys::nrf_getaddrinfo(
-				// hostname
-				hostname_smallstring.as_ptr(),
-				// service
-				core::ptr::null(),
-				// hints
-				&hints,
-				// output pointer
-				&mut output_ptr,
-			)
-		};
+		// Limit how long connecting can take (see `timeouts`)
+		let (first_ms, retry_ms) =
+			crate::timeouts::split_for_retry(crate::timeouts::current().tls_handshake_ms);
+		self.socket.start_connect_timeout(first_ms)?;
 
-		if (result != 0) && output_ptr.is_null() {
-			return Err(Error::Nordic("tls_dns", result, get_last_error()));
-		} else {
-			let mut record: &sys::nrf_addrinfo = unsafe { &*output_ptr };
-			loop {
-				let dns_addr: &sys::nrf_sockaddr_in =
-					unsafe { &*(record.ai_addr as *const sys::nrf_sockaddr_in) };
-				// Create a new sockaddr_in with the right port
-				let connect_addr = sys::nrf_sockaddr_in {
-					sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
-					sin_family: sys::NRF_AF_INET as i32,
-					sin_port: htons(port),
-					sin_addr: dns_addr.sin_addr,
-				};
-
-				debug!("Trying IP address {}", &crate::NrfSockAddrIn(connect_addr));
-
-				// try and connect to this result
-				result = unsafe {
-					sys::nrf_connect(
-						self.socket.fd,
-						&connect_addr as *const sys::nrf_sockaddr_in as *const _,
-						connect_addr.sin_len as u32,
-					)
-				};
-				if result == 0 {
-					break;
-				}
-				if !record.ai_next.is_null() {
-					record = unsafe { &*record.ai_next };
-				} else {
-					break;
-				}
-			}
-			unsafe {
-				sys::nrf_freeaddrinfo(output_ptr);
+		// First we set the hostname, unless `set_tls_hostname` chose one
+		if !self.custom_hostname.get() {
+			self.socket
+				.set_option(SocketOption::TlsHostName(hostname))?;
+		}
+
+		let mut result = self.connect_once(hostname, port);
+		if let Some(retry_ms) = retry_ms {
+			if matches!(result, Ok(r) if r != 0) && is_stale_address_error(get_last_error()) {
+				// The address may be stale (e.g. carrier NAT or DNS churn), so
+				// look it up again and have one more go, in the time left.
+				debug!("Connect failed ({}), retrying lookup", get_last_error());
+				self.socket.start_connect_timeout(retry_ms)?;
+				result = self.connect_once(hostname, port);
 			}
 		}
-		if result != 0 {
-			Err(Error::Nordic("tls_connect", result, get_last_error()))
-		} else {
-			Ok(())
+		// Sends go back to their own timeout, once connected. Read the error
+		// first, in case this changes it.
+		let error = get_last_error();
+		self.socket.end_connect_timeout()?;
+		match result? {
+			0 => Ok(()),
+			result => Err(Error::Nordic("tls_connect", result, error)),
 		}
 	}
+
+	/// Start connecting to the hostname, without waiting for the TLS
+	/// handshake to finish. Call `connect_poll` until it returns
+	/// `ConnectStatus::Connected`.
+	///
+	/// Over NB-IoT a handshake can take tens of seconds, so this lets the
+	/// application get on with other things in the meantime. The DNS lookup
+	/// still blocks, and only the first address found is tried. The
+	/// `timeouts` don't apply - give up (by dropping the socket) whenever you
+	/// like.
+	pub fn connect_start(&self, hostname: &str, port: u16) -> Result<ConnectStatus, Error> {
+		debug!("Starting TLS connect to {}:{}", hostname, port);
+		crate::destinations::check_host(hostname, port)?;
+
+		// First we set the hostname, unless `set_tls_hostname` chose one
+		if !self.custom_hostname.get() {
+			self.socket
+				.set_option(SocketOption::TlsHostName(hostname))?;
+		}
+
+		let addr = lookup_ipv4(hostname, SocketType::Stream, port)?;
+		debug!("Trying IP address {}", &crate::NrfSockAddrIn(addr));
+		let status = self.socket.connect_start(&addr)?;
+		if status == ConnectStatus::InProgress {
+			self.connecting.set(Connecting::InProgress(addr));
+		}
+		Ok(status)
+	}
+
+	/// See whether a connect started with `connect_start` has finished. This
+	/// doesn't block.
+	pub fn connect_poll(&self) -> Result<ConnectStatus, Error> {
+		self.socket.poll_connecting(&self.connecting)
+	}
+
+	/// Change whether we verify the peer, e.g. before connecting again.
+	pub fn set_peer_verification(&self, peer_verify: PeerVerification) -> Result<(), Error> {
+		self.socket
+			.set_option(SocketOption::TlsPeerVerify(peer_verify.as_integer()))
+	}
+
+	/// Get whether we verify the peer, as the modem has it.
+	pub fn peer_verification(&self) -> Result<PeerVerification, Error> {
+		let value = self.socket.get_option(
+			"peer_verification",
+			sys::NRF_SOL_SECURE,
+			sys::NRF_SO_SEC_PEER_VERIFY,
+			0 as sys::nrf_sec_peer_verify_t,
+		)?;
+		PeerVerification::from_integer(value).ok_or(Error::UnrecognisedValue)
+	}
+
+	/// Turn session caching on or off. It is on for a new socket, so that
+	/// the modem can resume the session when reconnecting to the same
+	/// server, rather than do a full handshake. Set this before connecting.
+	pub fn set_session_cache(&self, enabled: bool) -> Result<(), Error> {
+		let value = if enabled {
+			SESSION_CACHE_ENABLED
+		} else {
+			SESSION_CACHE_DISABLED
+		};
+		self.socket.set_option(SocketOption::TlsSessionCache(value))
+	}
+
+	/// Get whether session caching is on, as the modem has it.
+	pub fn session_cache(&self) -> Result<bool, Error> {
+		let value = self.socket.get_option(
+			"session_cache",
+			sys::NRF_SOL_SECURE,
+			sys::NRF_SO_SEC_SESSION_CACHE,
+			SESSION_CACHE_DISABLED,
+		)?;
+		Ok(value != SESSION_CACHE_DISABLED)
+	}
+
+	/// Choose the hostname sent to the server (as SNI) and checked against
+	/// its certificate, instead of the one given to `connect` - e.g. when
+	/// connecting by IP address, or through a name which isn't on the
+	/// certificate. `None` sends no hostname, and doesn't check it. This
+	/// lasts as long as the socket.
+	pub fn set_tls_hostname(&self, hostname: Option<&str>) -> Result<(), Error> {
+		self.socket
+			.set_option(SocketOption::TlsHostName(hostname.unwrap_or("")))?;
+		self.custom_hostname.set(true);
+		Ok(())
+	}
+}
+
+impl TlsListener {
+	/// Listen for TLS connections on the given local port.
+	///
+	/// The server certificate and its private key are taken from
+	/// `security_tag` (stored as the "client" certificate and key with
+	/// `provision_certificates`). Use `client_auth` to ask clients for a
+	/// certificate - it is checked against the CA chain stored with the same
+	/// tag.
+	pub fn bind(
+		port: u16,
+		security_tag: u32,
+		client_auth: PeerVerification,
+		version: Version,
+	) -> Result<TlsListener, Error> {
+		let protocol = match version {
+			Version::Tls1v2 => SocketProtocol::Tls1v2,
+			Version::Tls1v3 => SocketProtocol::Tls1v3,
+		};
+		let socket = Socket::new(SocketDomain::Inet, SocketType::Stream, protocol)?;
+		socket.set_option(SocketOption::TlsRole(ROLE_SERVER))?;
+		socket.set_option(SocketOption::TlsPeerVerify(client_auth.as_integer()))?;
+		socket.set_option(SocketOption::TlsTagList(&[security_tag]))?;
+		socket.bind_port(port)?;
+		socket.listen(LISTEN_BACKLOG)?;
+		debug!("Listening for TLS on port {}", port);
+		Ok(TlsListener { socket, protocol })
+	}
+
+	/// Wait for a client to connect, and return the connection along with
+	/// the client's address. The TLS handshake happens as part of this. Use
+	/// `poll` (for `PollFlags::Read`) to find out if a client is waiting
+	/// without blocking.
+	pub fn accept(&self) -> Result<(TlsSocket, crate::NrfSockAddrIn), Error> {
+		let (socket, addr) = self.socket.accept(self.protocol)?;
+		let addr = crate::NrfSockAddrIn(addr);
+		debug!("Accepted TLS connection from {}", &addr);
+		let socket = TlsSocket {
+			socket,
+			connecting: Cell::new(Connecting::Idle),
+			custom_hostname: Cell::new(false),
+		};
+		Ok((socket, addr))
+	}
+}
+
+impl Pollable for TlsListener {
+	/// Get the underlying socket ID for this socket.
+	fn get_fd(&self) -> i32 {
+		self.socket.fd
+	}
+}
+
+impl core::ops::Deref for TlsListener {
+	type Target = Socket;
+	fn deref(&self) -> &Socket {
+		&self.socket
+	}
 }
 
 impl Pollable for TlsSocket {
@@ -237,6 +363,16 @@ impl PeerVerification {
 			PeerVerification::Disabled => 0,
 		}
 	}
+
+	/// The reverse of `as_integer`.
+	fn from_integer(value: u32) -> Option<PeerVerification> {
+		match value {
+			2 => Some(PeerVerification::Enabled),
+			1 => Some(PeerVerification::Optional),
+			0 => Some(PeerVerification::Disabled),
+			_ => None,
+		}
+	}
 }
 
 /// Store SSL certificates in the modem NVRAM for use with a subsequent TLS
@@ -257,61 +393,35 @@ pub fn provision_certificates(
 	public_cert: Option<&'static str>,
 	key: Option<&'static str>,
 ) -> Result<(), Error> {
-	let mut at_socket = crate::at::AtSocket::new()?;
-	for (key, var) in &[
-		(CredentialType::RootCA, ca_chain),
-		(CredentialType::ClientCert, public_cert),
-		(CredentialType::ClientPrivate, key),
+	for (kind, content) in [
+		(CredentialType::CaChain, ca_chain),
+		(CredentialType::ClientCertificate, public_cert),
+		(CredentialType::ClientPrivateKey, key),
 	] {
-		write!(
-			at_socket,
-			"AT%CMNG={},{},{}\r\n",
-			CredentialOpcode::Delete,
-			tag,
-			key
-		)?;
-		match at_socket.poll_response(|_| {}) {
-			Ok(_) => {}
-			Err(Error::AtError(AtError::CmeError(513))) => {
-				// 513 is NOT FOUND. We can ignore this
-			}
-			Err(e) => {
-				return Err(e);
-			}
+		match crate::tls_credentials::delete(tag, kind) {
+			Ok(()) | Err(Error::CredentialStore(StoreError::NotFound)) => {}
+			Err(e) => return Err(e),
 		}
-		if let Some(string) = var {
-			write!(
-				at_socket,
-				"AT%CMNG={},{},{},\"{}\"\r\n",
-				CredentialOpcode::Write,
-				tag,
-				key,
-				string
-			)?;
-			at_socket.poll_response(|_| {})?;
+		if let Some(content) = content {
+			crate::tls_credentials::store_certificate(tag, kind, content)?;
 		}
 	}
 
 	Ok(())
 }
 
-impl core::fmt::Display for CredentialOpcode {
-	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-		write!(f, "{}", *self as i32)
-	}
-}
-
-impl core::fmt::Display for CredentialType {
-	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-		write!(f, "{}", *self as i32)
-	}
-}
-
 //******************************************************************************
 // Private Functions and Impl on Private Types
 //******************************************************************************
 
-// None
+impl TlsSocket {
+	/// Look up the hostname and try to connect to each address returned, as
+	/// `connect` does. Returns the result of the last `nrf_connect`.
+	fn connect_once(&self, hostname: &str, port: u16) -> Result<i32, Error> {
+		let addresses = AddrInfoList::lookup(hostname, SocketType::Stream, "tls_dns")?;
+		self.socket.connect_any(&addresses, port)
+	}
+}
 
 //******************************************************************************
 // End of File
@@ -0,0 +1,337 @@
+//! # TLS credential storage for nrfxlib
+//!
+//! The modem keeps the certificates, keys and PSKs used by TLS and DTLS
+//! sockets in its own secure storage, managed with `AT%CMNG`. Each
+//! credential is stored under a security tag (the number given to
+//! `TlsSocket::new`) and a `CredentialType`:
+//!
+//! ```ignore
+//! let _flight_mode = modem::FlightModeGuard::new()?;
+//! tls_credentials::store_certificate(SERVER_TAG, CredentialType::CaChain, CA_PEM)?;
+//! ```
+//!
+//! The modem only changes its credentials while LTE is off, e.g. in flight
+//! mode (`modem::FlightModeGuard`). Otherwise it refuses with
+//! `StoreError::NotAllowedWhileActive`. A `credential_queue::CredentialQueue`
+//! can hold changes until then.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+use crate::{AtError, Error};
+#[cfg(target_arch = "arm")]
+use core::fmt::Write;
+#[cfg(target_arch = "arm")]
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// The kinds of credential the modem stores.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CredentialType {
+	/// The certificates of the CAs we trust, in PEM format
+	CaChain = 0,
+	/// Our certificate, for client (or server) authentication, in PEM format
+	ClientCertificate = 1,
+	/// The private key for `ClientCertificate`, in PEM format. Can't be read
+	/// back.
+	ClientPrivateKey = 2,
+	/// A pre-shared key, in hex
+	Psk = 3,
+	/// The identity to give with `Psk`
+	PskIdentity = 4,
+	/// A public key, in PEM format
+	PublicKey = 5,
+}
+
+/// The reasons the modem gives for refusing a `%CMNG` operation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StoreError {
+	/// There is no credential with that tag and type (`+CME ERROR: 513`)
+	NotFound,
+	/// That credential can't be accessed, e.g. a private key can't be read
+	/// back (`+CME ERROR: 514`)
+	NoAccess,
+	/// The credential storage is full (`+CME ERROR: 515`)
+	MemoryFull,
+	/// Credentials can't be changed while LTE is active - use flight mode
+	/// (`+CME ERROR: 518`)
+	NotAllowedWhileActive,
+}
+
+/// A credential in the modem's storage, as listed by `list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialInfo {
+	/// The security tag it is stored under
+	pub tag: u32,
+	/// What kind of credential it is
+	pub kind: CredentialType,
+	/// The SHA-256 digest of its contents, in hex, if the modem gave one
+	pub sha256: Option<heapless::String<SHA256_HEX_LEN>>,
+}
+
+/// The `%CMNG` operations
+#[cfg(target_arch = "arm")]
+#[derive(Debug, Copy, Clone)]
+enum Opcode {
+	Write = 0,
+	List = 1,
+	Read = 2,
+	Delete = 3,
+}
+
+/// Where we've got to reading a credential's contents, which may be spread
+/// over several lines of the response.
+#[cfg(target_arch = "arm")]
+#[derive(Debug)]
+struct ContentReader<const N: usize> {
+	content: heapless::String<N>,
+	started: bool,
+	finished: bool,
+	result: Result<(), Error>,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The length of a SHA-256 digest, in hex.
+pub const SHA256_HEX_LEN: usize = 64;
+
+/// The prefix of each `%CMNG` response line
+const CMNG_RESPONSE: &str = "%CMNG:";
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Store a certificate or key (in PEM format) under the given security tag,
+/// replacing any credential of the same type already there.
+///
+/// PEM can't contain a `"`, nor can the modem be sent one inside a
+/// credential, so content with one in gives `Error::BadDataFormat`.
+#[cfg(target_arch = "arm")]
+pub fn store_certificate(tag: u32, kind: CredentialType, content: &str) -> Result<(), Error> {
+	write_credential(tag, kind, content)
+}
+
+/// Store a pre-shared key (in hex) and its identity under the given security
+/// tag, replacing any already there. A key which isn't hex gives
+/// `Error::BadDataFormat`.
+#[cfg(target_arch = "arm")]
+pub fn store_psk(tag: u32, psk_hex: &str, identity: &str) -> Result<(), Error> {
+	if psk_hex.is_empty() || !psk_hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+		return Err(Error::BadDataFormat);
+	}
+	write_credential(tag, CredentialType::Psk, psk_hex)?;
+	write_credential(tag, CredentialType::PskIdentity, identity)
+}
+
+/// Read back a stored credential. Private keys and PSKs can't be read
+/// (`StoreError::NoAccess`). Gives `Error::BufferFull` if the contents are
+/// more than `N` bytes long.
+#[cfg(target_arch = "arm")]
+pub fn read<const N: usize>(tag: u32, kind: CredentialType) -> Result<heapless::String<N>, Error> {
+	let mut command: heapless::String<32> = heapless::String::new();
+	write!(
+		command,
+		"AT%CMNG={},{},{}",
+		Opcode::Read as u8,
+		tag,
+		kind as u8
+	)?;
+	let mut reader = ContentReader::<N>::new();
+	crate::at::send_at_command(&command, |line| reader.line(line)).map_err(map_error)?;
+	reader.finish()
+}
+
+/// List the credentials stored, up to `N` of them. Gives
+/// `Error::BufferFull` if there are more.
+#[cfg(target_arch = "arm")]
+pub fn list<const N: usize>() -> Result<heapless::Vec<CredentialInfo, N>, Error> {
+	let mut command: heapless::String<16> = heapless::String::new();
+	write!(command, "AT%CMNG={}", Opcode::List as u8)?;
+	let mut credentials = heapless::Vec::new();
+	let mut result = Ok(());
+	crate::at::send_at_command(&command, |line| {
+		if let Some(info) = CredentialInfo::parse(line) {
+			if credentials.push(info).is_err() {
+				result = Err(Error::BufferFull);
+			}
+		}
+	})
+	.map_err(map_error)?;
+	result.map(|_| credentials)
+}
+
+/// Delete a stored credential. Gives `StoreError::NotFound` if there isn't
+/// one.
+#[cfg(target_arch = "arm")]
+pub fn delete(tag: u32, kind: CredentialType) -> Result<(), Error> {
+	let mut command: heapless::String<32> = heapless::String::new();
+	write!(
+		command,
+		"AT%CMNG={},{},{}",
+		Opcode::Delete as u8,
+		tag,
+		kind as u8
+	)?;
+	crate::at::send_at_command(&command, |_| {}).map_err(map_error)
+}
+
+impl CredentialType {
+	/// Convert the numeric `<type>` value used by `%CMNG`.
+	pub fn from_u8(value: u8) -> Option<CredentialType> {
+		match value {
+			0 => Some(CredentialType::CaChain),
+			1 => Some(CredentialType::ClientCertificate),
+			2 => Some(CredentialType::ClientPrivateKey),
+			3 => Some(CredentialType::Psk),
+			4 => Some(CredentialType::PskIdentity),
+			5 => Some(CredentialType::PublicKey),
+			_ => None,
+		}
+	}
+}
+
+impl CredentialInfo {
+	/// Parse a line of the `AT%CMNG=1` response: `%CMNG:
+	/// <tag>,<type>[,"<sha256>"]`.
+	pub fn parse(line: &str) -> Option<CredentialInfo> {
+		let mut fields = line.trim().strip_prefix(CMNG_RESPONSE)?.split(',');
+		let tag = fields.next()?.trim().parse().ok()?;
+		let kind = CredentialType::from_u8(fields.next()?.trim().parse().ok()?)?;
+		let sha256 = fields.next().and_then(|field| {
+			let mut digest = heapless::String::new();
+			digest.push_str(field.trim().trim_matches('"')).ok()?;
+			Some(digest)
+		});
+		Some(CredentialInfo { tag, kind, sha256 })
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Write a credential with `AT%CMNG=0`. The command goes straight to the
+/// socket, rather than through a buffer, as certificates can be several
+/// kilobytes - which also keeps keys out of the AT transcript.
+#[cfg(target_arch = "arm")]
+pub(crate) fn write_credential(tag: u32, kind: CredentialType, content: &str) -> Result<(), Error> {
+	if content.contains('"') {
+		return Err(Error::BadDataFormat);
+	}
+	debug!("Storing credential {:?} in tag {}", kind, tag);
+	let mut at_socket = crate::at::AtSocket::new()?;
+	at_socket.send_command_fmt(format_args!(
+		"AT%CMNG={},{},{},\"{}\"\r\n",
+		Opcode::Write as u8,
+		tag,
+		kind as u8,
+		content
+	))?;
+	crate::nvm::record_write();
+	at_socket.poll_response(|_| {}).map_err(map_error)
+}
+
+/// Turn the `+CME ERROR`s which `%CMNG` defines into a `StoreError`.
+#[cfg(target_arch = "arm")]
+fn map_error(error: Error) -> Error {
+	let store_error = match error {
+		Error::AtError(AtError::CmeError(513)) => StoreError::NotFound,
+		Error::AtError(AtError::CmeError(514)) => StoreError::NoAccess,
+		Error::AtError(AtError::CmeError(515)) => StoreError::MemoryFull,
+		Error::AtError(AtError::CmeError(518)) => StoreError::NotAllowedWhileActive,
+		error => return error,
+	};
+	Error::CredentialStore(store_error)
+}
+
+#[cfg(target_arch = "arm")]
+impl<const N: usize> ContentReader<N> {
+	fn new() -> Self {
+		ContentReader {
+			content: heapless::String::new(),
+			started: false,
+			finished: false,
+			result: Ok(()),
+		}
+	}
+
+	/// Take the next line of an `AT%CMNG=2` response. The contents are the
+	/// fourth field of the `%CMNG:` line, in quotes, and continue over the
+	/// following lines until the closing quote.
+	fn line(&mut self, line: &str) {
+		if self.finished || self.result.is_err() {
+			return;
+		}
+		let piece = if self.started {
+			if self.content.push('\n').is_err() {
+				self.result = Err(Error::BufferFull);
+				return;
+			}
+			line
+		} else {
+			let field = match line
+				.strip_prefix(CMNG_RESPONSE)
+				.and_then(|fields| fields.splitn(4, ',').nth(3))
+			{
+				Some(field) => field.trim(),
+				None => return,
+			};
+			self.started = true;
+			field.strip_prefix('"').unwrap_or(field)
+		};
+		let piece = match piece.strip_suffix('"') {
+			Some(piece) => {
+				self.finished = true;
+				piece
+			}
+			None => piece,
+		};
+		if self.content.push_str(piece).is_err() {
+			self.result = Err(Error::BufferFull);
+		}
+	}
+
+	/// Get the contents, once the response is over.
+	fn finish(self) -> Result<heapless::String<N>, Error> {
+		self.result?;
+		if !self.started {
+			return Err(Error::BadDataFormat);
+		}
+		Ok(self.content)
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,140 @@
+//! # Modem trace delivery for nrfxlib
+//!
+//! When modem traces are turned on (see `NrfxlibBuilder::trace`), libmodem
+//! hands them over from `trace_irq_handler`, and doesn't reuse that part of
+//! the trace buffer until the handler has returned. If the handler stalls -
+//! a UART with nobody listening, a full RTT buffer - the trace buffer fills
+//! up and the modem stalls with it.
+//!
+//! A `TracePolicy` says what to do about that. `Backpressure` waits for the
+//! handler however long it takes, which is fine on the bench.
+//! `Drop` gives the traces to a handler which takes what it can without
+//! waiting, and throws the rest away, so traces can be left on in the field
+//! without putting the modem at risk. `stats` counts what was delivered and
+//! what was dropped.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::interrupt::Mutex;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// What to do with modem traces, and what to do if they can't be passed on
+/// straight away.
+#[derive(Debug, Copy, Clone)]
+pub enum TracePolicy {
+	/// Give every trace to this handler, waiting for it however long it
+	/// takes. The modem waits too.
+	Backpressure(fn(&[u8])),
+	/// Give traces to this handler, which must not wait. It returns how many
+	/// bytes it took, and the rest are dropped.
+	Drop(fn(&[u8]) -> usize),
+}
+
+/// How much trace data has been dealt with since start-up (or
+/// `reset_stats`).
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct TraceStats {
+	/// Bytes given to the handler
+	pub delivered: u32,
+	/// Bytes dropped because the handler couldn't take them
+	pub dropped: u32,
+}
+
+/// Somewhere to keep the policy, which the trace interrupt can reach.
+type WrappedPolicy = Mutex<RefCell<Option<TracePolicy>>>;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The policy given to `NrfxlibBuilder`.
+static POLICY: WrappedPolicy = Mutex::new(RefCell::new(None));
+
+/// Bytes of trace data given to the handler.
+static DELIVERED: AtomicU32 = AtomicU32::new(0);
+
+/// Bytes of trace data dropped.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Get the trace policy in use, if traces are on.
+pub fn policy() -> Option<TracePolicy> {
+	cortex_m::interrupt::free(|cs| *POLICY.borrow(cs).borrow())
+}
+
+/// Get the counts of trace data delivered and dropped.
+pub fn stats() -> TraceStats {
+	TraceStats {
+		delivered: DELIVERED.load(Ordering::Relaxed),
+		dropped: DROPPED.load(Ordering::Relaxed),
+	}
+}
+
+/// Set the counts back to zero.
+pub fn reset_stats() {
+	DELIVERED.store(0, Ordering::Relaxed);
+	DROPPED.store(0, Ordering::Relaxed);
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Set the policy, or `None` to throw traces away (without counting them).
+pub(crate) fn set_policy(policy: Option<TracePolicy>) {
+	cortex_m::interrupt::free(|cs| {
+		*POLICY.borrow(cs).borrow_mut() = policy;
+	});
+}
+
+/// Deal with some trace data from libmodem, according to the policy. The
+/// handler is called outside the critical section.
+pub(crate) fn put(data: &[u8]) {
+	let delivered = match policy() {
+		None => return,
+		Some(TracePolicy::Backpressure(handler)) => {
+			handler(data);
+			data.len()
+		}
+		Some(TracePolicy::Drop(handler)) => core::cmp::min(handler(data), data.len()),
+	};
+	DELIVERED.fetch_add(delivered as u32, Ordering::Relaxed);
+	DROPPED.fetch_add((data.len() - delivered) as u32, Ordering::Relaxed);
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
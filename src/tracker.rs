@@ -0,0 +1,508 @@
+//! # Asset tracking for nrfxlib
+//!
+//! Most nRF9160 applications do the same thing over and over: get a position,
+//! send it somewhere, and sleep. `track_and_report` does one round of that,
+//! with a bound on how long each step can take:
+//!
+//! ```ignore
+//! let config = TrackerConfig {
+//!     fallback: Some(cell_location),
+//!     ..TrackerConfig::new(now_ms, sleep_ms)
+//! };
+//! let gnss = GnssSocket::new()?;
+//! let mut server = UdpSocket::new()?;
+//! server.connect("tracking.example.com", 5683)?;
+//! loop {
+//!     let report = tracker::track_and_report(&gnss, &mut server, &config);
+//!     info!("{:?}", report);
+//! }
+//! ```
+//!
+//! The GNSS module gets `fix_timeout_ms` to find a fix. If it doesn't, the
+//! `fallback` is asked (e.g. a cloud service looking up the serving cell -
+//! the modem can't do that itself), and failing that the `last_position` is
+//! sent. The position is encoded by `encode` - `encode_fix` unless you give
+//! another - and sent over any `Transport`. Then it sleeps until
+//! `report_interval_ms` after the round started, so reports are evenly
+//! spaced however long the fix took.
+//!
+//! If nothing else will be sent until the next round, the report can be
+//! flagged as the last data for a while with `modem::set_release_assistance`
+//! (it is modem-wide - see there) before `track_and_report`. Otherwise the
+//! network releases the RRC connection when its inactivity timer runs out,
+//! as usual.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+use crate::dtls::DtlsSocket;
+#[cfg(target_arch = "arm")]
+use crate::gnss::{DeleteMask, GnssSocket};
+use crate::last_position::{LastKnownPosition, ENCODED_LEN};
+#[cfg(target_arch = "arm")]
+use crate::tcp::TcpSocket;
+#[cfg(target_arch = "arm")]
+use crate::tls::TlsSocket;
+#[cfg(target_arch = "arm")]
+use crate::udp::UdpSocket;
+use crate::Error;
+#[cfg(target_arch = "arm")]
+use core::convert::TryFrom;
+use log::debug;
+#[cfg(target_arch = "arm")]
+use log::warn;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Something a report can be sent over - usually a connected socket.
+pub trait Transport {
+	/// Send one encoded report.
+	fn send_report(&mut self, payload: &[u8]) -> Result<(), Error>;
+}
+
+/// Where a position came from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PositionSource {
+	/// A GNSS fix taken this round
+	Gnss = 0,
+	/// `TrackerConfig::fallback`
+	Fallback = 1,
+	/// The last known position (see `last_position`), from an earlier round
+	LastKnown = 2,
+}
+
+/// A position, and where it came from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Fix {
+	/// The position
+	pub position: LastKnownPosition,
+	/// Where it came from
+	pub source: PositionSource,
+}
+
+/// How `track_and_report` goes about it.
+#[derive(Debug, Copy, Clone)]
+pub struct TrackerConfig {
+	/// How long the GNSS module may take to find a fix
+	pub fix_timeout_ms: u32,
+	/// How long from the start of one round to the start of the next
+	pub report_interval_ms: u32,
+	/// Gets a position some other way, if the GNSS module doesn't find one
+	pub fallback: Option<fn() -> Option<LastKnownPosition>>,
+	/// Encodes a position for sending, returning how many bytes of the
+	/// buffer (`MAX_REPORT_LEN` long) it used
+	pub encode: fn(&Fix, &mut [u8]) -> Result<usize, Error>,
+	/// A millisecond counter, which may wrap
+	pub now_ms: fn() -> u32,
+	/// Sleeps for this many milliseconds
+	pub sleep_ms: fn(u32),
+}
+
+/// What happened in one round of `track_and_report`.
+#[derive(Debug, Clone)]
+pub struct TrackReport {
+	/// The position sent, if there was one
+	pub fix: Option<Fix>,
+	/// How long getting the position took, in milliseconds
+	pub fix_time_ms: u32,
+	/// Whether the report was sent. `Error::TimedOut` means there was no
+	/// position to send.
+	pub sent: Result<(), Error>,
+	/// How long we slept afterwards, in milliseconds
+	pub slept_ms: u32,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The most bytes an encoded report can take.
+pub const MAX_REPORT_LEN: usize = 128;
+
+/// How many bytes `encode_fix` uses.
+pub const FIX_ENCODED_LEN: usize = ENCODED_LEN + 1;
+
+/// How long the GNSS module gets for a fix, by default - enough for a warm
+/// start.
+pub const DEFAULT_FIX_TIMEOUT_MS: u32 = 90_000;
+
+/// How often we report, by default.
+pub const DEFAULT_REPORT_INTERVAL_MS: u32 = 15 * 60_000;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Get a position, send it, and sleep until the next round is due, as the
+/// module documentation describes. Errors along the way are reported in
+/// the `TrackReport` rather than cutting the round short, so the next one
+/// still starts on time.
+#[cfg(target_arch = "arm")]
+pub fn track_and_report<T>(
+	gnss: &GnssSocket,
+	transport: &mut T,
+	config: &TrackerConfig,
+) -> TrackReport
+where
+	T: Transport + ?Sized,
+{
+	let started_ms = (config.now_ms)();
+	let fix = match gnss_fix(gnss, config) {
+		Ok(Some(position)) => Some(Fix {
+			position,
+			source: PositionSource::Gnss,
+		}),
+		result => {
+			debug!("No GNSS fix: {:?}", result);
+			fallback_fix(config)
+		}
+	};
+	send_and_sleep(fix, started_ms, transport, config)
+}
+
+/// Encode a position as its source (one byte) followed by
+/// `LastKnownPosition::encode`, using `FIX_ENCODED_LEN` bytes.
+pub fn encode_fix(fix: &Fix, buffer: &mut [u8]) -> Result<usize, Error> {
+	let (source, position) = buffer
+		.get_mut(0..FIX_ENCODED_LEN)
+		.and_then(<[u8]>::split_first_mut)
+		.ok_or(Error::BufferFull)?;
+	*source = fix.source as u8;
+	position.copy_from_slice(&fix.position.encode());
+	Ok(FIX_ENCODED_LEN)
+}
+
+impl TrackerConfig {
+	/// The default settings, with these clock and sleep functions: no
+	/// fallback, `encode_fix`, `DEFAULT_FIX_TIMEOUT_MS` and
+	/// `DEFAULT_REPORT_INTERVAL_MS`.
+	pub fn new(now_ms: fn() -> u32, sleep_ms: fn(u32)) -> TrackerConfig {
+		TrackerConfig {
+			fix_timeout_ms: DEFAULT_FIX_TIMEOUT_MS,
+			report_interval_ms: DEFAULT_REPORT_INTERVAL_MS,
+			fallback: None,
+			encode: encode_fix,
+			now_ms,
+			sleep_ms,
+		}
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl Transport for UdpSocket {
+	/// Send the report as one datagram.
+	fn send_report(&mut self, payload: &[u8]) -> Result<(), Error> {
+		self.write(payload).map(|_| ())
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl Transport for DtlsSocket {
+	/// Send the report as one datagram.
+	fn send_report(&mut self, payload: &[u8]) -> Result<(), Error> {
+		self.write(payload).map(|_| ())
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl Transport for TcpSocket {
+	/// Send the report, all of it.
+	fn send_report(&mut self, payload: &[u8]) -> Result<(), Error> {
+		self.write_all(payload)
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl Transport for TlsSocket {
+	/// Send the report, all of it.
+	fn send_report(&mut self, payload: &[u8]) -> Result<(), Error> {
+		self.write_all(payload)
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Take a single GNSS fix, giving up after `fix_timeout_ms`. The GNSS module
+/// is stopped again afterwards.
+#[cfg(target_arch = "arm")]
+fn gnss_fix(gnss: &GnssSocket, config: &TrackerConfig) -> Result<Option<LastKnownPosition>, Error> {
+	let retry_s = u16::try_from(config.fix_timeout_ms.div_ceil(1000)).unwrap_or(u16::MAX);
+	gnss.set_fix_interval(0)?;
+	gnss.set_fix_retry(retry_s)?;
+	gnss.start(DeleteMask::new())?;
+	let started_ms = (config.now_ms)();
+	let result = loop {
+		let elapsed_ms = (config.now_ms)().wrapping_sub(started_ms);
+		if elapsed_ms >= config.fix_timeout_ms {
+			break Ok(None);
+		}
+		match crate::raw::wait_readable(gnss, config.fix_timeout_ms - elapsed_ms) {
+			Ok(()) => {}
+			Err(Error::TimedOut) => break Ok(None),
+			Err(e) => break Err(e),
+		}
+		match gnss.get_fix() {
+			Ok(Some(data)) if data.is_valid() => {
+				break Ok(data.pvt().map(|pvt| LastKnownPosition::from(&pvt)));
+			}
+			Ok(_) => {}
+			Err(e) => break Err(e),
+		}
+	};
+	if let Err(e) = gnss.stop() {
+		warn!("Failed to stop the GNSS module: {:?}", e);
+	}
+	result
+}
+
+/// Get a position some other way: from the fallback, or failing that the
+/// last known position.
+#[cfg(target_arch = "arm")]
+fn fallback_fix(config: &TrackerConfig) -> Option<Fix> {
+	if let Some(position) = config.fallback.and_then(|fallback| fallback()) {
+		return Some(Fix {
+			position,
+			source: PositionSource::Fallback,
+		});
+	}
+	crate::last_position::get().map(|position| Fix {
+		position,
+		source: PositionSource::LastKnown,
+	})
+}
+
+/// Send `fix`, then sleep until `report_interval_ms` after `started_ms`.
+fn send_and_sleep<T>(
+	fix: Option<Fix>,
+	started_ms: u32,
+	transport: &mut T,
+	config: &TrackerConfig,
+) -> TrackReport
+where
+	T: Transport + ?Sized,
+{
+	let fix_time_ms = (config.now_ms)().wrapping_sub(started_ms);
+	let sent = match &fix {
+		Some(fix) => {
+			let mut buffer = [0u8; MAX_REPORT_LEN];
+			(config.encode)(fix, &mut buffer).and_then(|length| {
+				let payload = buffer.get(0..length).ok_or(Error::BufferFull)?;
+				transport.send_report(payload)
+			})
+		}
+		None => Err(Error::TimedOut),
+	};
+	debug!("Report from {:?} sent: {:?}", fix.map(|f| f.source), sent);
+	let elapsed_ms = (config.now_ms)().wrapping_sub(started_ms);
+	let slept_ms = config.report_interval_ms.saturating_sub(elapsed_ms);
+	if slept_ms > 0 {
+		(config.sleep_ms)(slept_ms);
+	}
+	TrackReport {
+		fix,
+		fix_time_ms,
+		sent,
+		slept_ms,
+	}
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::last_position::DateTime;
+	use core::convert::TryFrom;
+	use core::sync::atomic::{AtomicU32, Ordering};
+
+	/// The fake clock, which only `send_and_sleep_timing` uses.
+	static NOW_MS: AtomicU32 = AtomicU32::new(0);
+
+	/// Everything the fake `sleep_ms` has been asked to sleep for.
+	static SLEPT_MS: AtomicU32 = AtomicU32::new(0);
+
+	/// How long the fake transport takes to send a report.
+	const SEND_MS: u32 = 3_000;
+
+	fn now_ms() -> u32 {
+		NOW_MS.load(Ordering::Relaxed)
+	}
+
+	fn sleep_ms(ms: u32) {
+		SLEPT_MS.fetch_add(ms, Ordering::Relaxed);
+		NOW_MS.fetch_add(ms, Ordering::Relaxed);
+	}
+
+	/// Keeps the last report, and takes `SEND_MS` to send it.
+	#[derive(Default)]
+	struct Recorder {
+		sent: heapless::Vec<u8, MAX_REPORT_LEN>,
+	}
+
+	impl Transport for Recorder {
+		fn send_report(&mut self, payload: &[u8]) -> Result<(), Error> {
+			NOW_MS.fetch_add(SEND_MS, Ordering::Relaxed);
+			self.sent.clear();
+			self.sent
+				.extend_from_slice(payload)
+				.map_err(|_| Error::BufferFull)
+		}
+	}
+
+	fn fix(source: PositionSource) -> Fix {
+		Fix {
+			position: LastKnownPosition {
+				latitude: 52.2053,
+				longitude: 0.1218,
+				altitude: 12.5,
+				accuracy: 4.0,
+				datetime: DateTime {
+					year: 2021,
+					month: 6,
+					day: 30,
+					hour: 14,
+					minute: 5,
+					second: 9,
+					millisecond: 250,
+				},
+			},
+			source,
+		}
+	}
+
+	/// Start a round at `started_ms`, with the fix taking `fix_ms`.
+	fn round(
+		fix: Option<Fix>,
+		started_ms: u32,
+		fix_ms: u32,
+		transport: &mut Recorder,
+	) -> TrackReport {
+		let config = TrackerConfig {
+			report_interval_ms: 60_000,
+			..TrackerConfig::new(now_ms, sleep_ms)
+		};
+		NOW_MS.store(started_ms.wrapping_add(fix_ms), Ordering::Relaxed);
+		SLEPT_MS.store(0, Ordering::Relaxed);
+		send_and_sleep(fix, started_ms, transport, &config)
+	}
+
+	#[test]
+	fn encode_fix_layout() {
+		let fix = fix(PositionSource::Fallback);
+		let mut buffer = [0xAAu8; MAX_REPORT_LEN];
+		assert!(matches!(encode_fix(&fix, &mut buffer), Ok(FIX_ENCODED_LEN)));
+		assert_eq!(buffer.first(), Some(&(PositionSource::Fallback as u8)));
+		assert_eq!(
+			buffer.get(1..FIX_ENCODED_LEN),
+			Some(&fix.position.encode()[..])
+		);
+		// Nothing after the fix is touched
+		assert_eq!(buffer.get(FIX_ENCODED_LEN), Some(&0xAA));
+		// ... and it decodes again
+		let encoded = buffer
+			.get(1..FIX_ENCODED_LEN)
+			.and_then(|encoded| <[u8; ENCODED_LEN]>::try_from(encoded).ok());
+		assert_eq!(
+			encoded.and_then(|encoded| LastKnownPosition::decode(&encoded)),
+			Some(fix.position)
+		);
+	}
+
+	#[test]
+	fn encode_fix_short_buffer() {
+		let mut buffer = [0u8; FIX_ENCODED_LEN - 1];
+		assert!(matches!(
+			encode_fix(&fix(PositionSource::Gnss), &mut buffer),
+			Err(Error::BufferFull)
+		));
+	}
+
+	#[test]
+	fn send_and_sleep_timing() {
+		// Sleeps for what is left of the interval after the fix and the send
+		let mut transport = Recorder::default();
+		let report = round(
+			Some(fix(PositionSource::Gnss)),
+			1_000,
+			20_000,
+			&mut transport,
+		);
+		assert_eq!(report.fix_time_ms, 20_000);
+		assert!(report.sent.is_ok());
+		assert_eq!(report.slept_ms, 60_000 - 20_000 - SEND_MS);
+		assert_eq!(SLEPT_MS.load(Ordering::Relaxed), report.slept_ms);
+		assert_eq!(now_ms(), 61_000);
+		let mut expected = [0u8; MAX_REPORT_LEN];
+		assert!(matches!(
+			encode_fix(&fix(PositionSource::Gnss), &mut expected),
+			Ok(FIX_ENCODED_LEN)
+		));
+		assert_eq!(Some(&transport.sent[..]), expected.get(..FIX_ENCODED_LEN));
+
+		// The same across the clock wrapping
+		let started_ms = u32::MAX - 9_999;
+		let report = round(
+			Some(fix(PositionSource::Gnss)),
+			started_ms,
+			20_000,
+			&mut transport,
+		);
+		assert_eq!(report.fix_time_ms, 20_000);
+		assert_eq!(report.slept_ms, 60_000 - 20_000 - SEND_MS);
+		assert_eq!(now_ms(), started_ms.wrapping_add(60_000));
+
+		// Doesn't sleep at all if the round has overrun
+		let report = round(
+			Some(fix(PositionSource::LastKnown)),
+			1_000,
+			59_000,
+			&mut transport,
+		);
+		assert!(report.sent.is_ok());
+		assert_eq!(report.slept_ms, 0);
+		assert_eq!(SLEPT_MS.load(Ordering::Relaxed), 0);
+
+		// Nothing to send, but still sleeps until the next round
+		let mut transport = Recorder::default();
+		let report = round(None, 1_000, 90_000 - 60_000, &mut transport);
+		assert!(matches!(report.sent, Err(Error::TimedOut)));
+		assert!(transport.sent.is_empty());
+		assert_eq!(report.slept_ms, 30_000);
+		assert_eq!(now_ms(), 61_000);
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
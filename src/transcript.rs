@@ -0,0 +1,224 @@
+//! # AT transcripts for nrfxlib
+//!
+//! Records all the AT traffic between the crate and the modem, with
+//! timestamps, to a sink of your choosing (e.g. an RTT channel or a log
+//! file). Attach the transcript to a bug report and it can be parsed on the
+//! host, with the `std` feature, and replayed.
+//!
+//! Each command or response line becomes one record, on its own line:
+//!
+//! ```text
+//! 1234 > AT+CFUN=1
+//! 1240 < OK
+//! 5020 < +CEREG: 1,"0A1B","01234567",7
+//! ```
+//!
+//! That is, a millisecond timestamp, `>` for something sent to the modem or
+//! `<` for something received from it, and the text. In the text, `\` is
+//! written as `\\`, CR and LF as `\r` and `\n`, and any other control
+//! characters as `\xNN`, so a record never spans more than one line.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(feature = "std")]
+use crate::Error;
+use core::cell::RefCell;
+use core::fmt;
+#[cfg(target_arch = "arm")]
+use core::fmt::Write;
+use cortex_m::interrupt::Mutex;
+#[cfg(feature = "std")]
+use std::{string::String, vec::Vec};
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Where transcript records go.
+#[derive(Debug, Copy, Clone)]
+pub struct Sink {
+	/// Called with each record, including the trailing newline. It should
+	/// not send AT commands itself.
+	pub write: fn(fmt::Arguments),
+	/// Returns a millisecond counter, for the timestamps.
+	pub now_ms: fn() -> u32,
+}
+
+/// Which way some AT traffic went.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+	/// Sent to the modem (written as `>`)
+	ToModem,
+	/// Received from the modem (written as `<`)
+	FromModem,
+}
+
+/// One record parsed from a transcript.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+	/// When it happened, in milliseconds
+	pub timestamp_ms: u32,
+	/// Which way it went
+	pub direction: Direction,
+	/// The AT command or response line, un-escaped
+	pub text: String,
+}
+
+/// Writes some text in its escaped form.
+#[cfg(target_arch = "arm")]
+struct Escaped<'a>(&'a str);
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The sink given to `set_sink`, if any.
+static SINK: Mutex<RefCell<Option<Sink>>> = Mutex::new(RefCell::new(None));
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Start recording AT traffic to the given sink, or stop recording if it is
+/// `None`.
+pub fn set_sink(sink: Option<Sink>) {
+	cortex_m::interrupt::free(|cs| {
+		*SINK.borrow(cs).borrow_mut() = sink;
+	});
+}
+
+/// Parse one line of a transcript.
+///
+/// Returns `Error::BadDataFormat` if it isn't a valid record.
+#[cfg(feature = "std")]
+pub fn parse_line(line: &str) -> Result<Record, Error> {
+	let mut fields = line.splitn(3, ' ');
+	let timestamp_ms = fields
+		.next()
+		.and_then(|s| s.parse().ok())
+		.ok_or(Error::BadDataFormat)?;
+	let direction = match fields.next() {
+		Some(">") => Direction::ToModem,
+		Some("<") => Direction::FromModem,
+		_ => return Err(Error::BadDataFormat),
+	};
+	let text = unescape(fields.next().unwrap_or(""))?;
+	Ok(Record {
+		timestamp_ms,
+		direction,
+		text,
+	})
+}
+
+/// Parse a whole transcript, skipping any blank lines.
+#[cfg(feature = "std")]
+pub fn parse(transcript: &str) -> Result<Vec<Record>, Error> {
+	transcript
+		.lines()
+		.map(|line| line.trim_end_matches('\r'))
+		.filter(|line| !line.is_empty())
+		.map(parse_line)
+		.collect()
+}
+
+impl Direction {
+	/// The symbol used for this direction in a transcript.
+	pub fn symbol(self) -> char {
+		match self {
+			Direction::ToModem => '>',
+			Direction::FromModem => '<',
+		}
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Record some AT traffic, if there is a sink.
+#[cfg(target_arch = "arm")]
+pub(crate) fn record(direction: Direction, text: &str) {
+	let sink = cortex_m::interrupt::free(|cs| *SINK.borrow(cs).borrow());
+	if let Some(sink) = sink {
+		(sink.write)(format_args!(
+			"{} {} {}\n",
+			(sink.now_ms)(),
+			direction.symbol(),
+			Escaped(text)
+		));
+	}
+}
+
+/// Reverse the escaping done by `Escaped`.
+#[cfg(feature = "std")]
+fn unescape(text: &str) -> Result<String, Error> {
+	let mut output = String::with_capacity(text.len());
+	let mut chars = text.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			output.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('\\') => output.push('\\'),
+			Some('r') => output.push('\r'),
+			Some('n') => output.push('\n'),
+			Some('x') => {
+				let hex: String = chars.by_ref().take(2).collect();
+				if hex.len() != 2 {
+					return Err(Error::BadDataFormat);
+				}
+				let value = u8::from_str_radix(&hex, 16).map_err(|_| Error::BadDataFormat)?;
+				output.push(char::from(value));
+			}
+			_ => return Err(Error::BadDataFormat),
+		}
+	}
+	Ok(output)
+}
+
+#[cfg(target_arch = "arm")]
+impl<'a> fmt::Display for Escaped<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for c in self.0.chars() {
+			match c {
+				'\\' => f.write_str("\\\\")?,
+				'\r' => f.write_str("\\r")?,
+				'\n' => f.write_str("\\n")?,
+				c if c.is_ascii_control() => write!(f, "\\x{:02x}", c as u8)?,
+				c => f.write_char(c)?,
+			}
+		}
+		Ok(())
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
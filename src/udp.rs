@@ -20,7 +20,6 @@
 use super::{get_last_error, Error};
 use crate::raw::*;
 use log::debug;
-use nrfxlib_sys as sys;
 
 //******************************************************************************
 // Types
@@ -71,87 +70,58 @@ impl UdpSocket {
 
 		// Now configure this socket
 
+		// Limit how long blocking receives and sends wait (see `timeouts`)
+		socket.apply_default_timeouts()?;
+
 		Ok(UdpSocket { socket })
 	}
 
+	/// Bind to this local port before connecting, rather than letting the
+	/// modem pick one.
+	pub fn bind(&self, port: u16) -> Result<(), Error> {
+		self.socket.bind_port(port)
+	}
+
+	/// Bind to a random ephemeral port before connecting, and return it. The
+	/// modem picks its ports predictably, so after a PSM sleep a reconnect
+	/// can reuse a port some carrier NATs still have a stale binding for.
+	/// The port comes from the `entropy` source, if one has been set.
+	pub fn bind_random(&self) -> Result<u16, Error> {
+		self.socket.bind_random_port()
+	}
+
 	/// Look up the hostname and for each result returned, try to connect to
 	/// it.
 	pub fn connect(&self, hostname: &str, port: u16) -> Result<(), Error> {
-		use core::fmt::Write;
-
 		debug!("Connecting via UDP to {}:{}", hostname, port);
+		crate::destinations::check_host(hostname, port)?;
 
-		// Now, make a null-terminated hostname
-		let mut hostname_smallstring: heapless::String<64> = heapless::String::new();
-		write!(hostname_smallstring, "{}\0", hostname).map_err(|_| Error::HostnameTooLong)?;
-		
-		// Now call getaddrinfo with some hints
-		let hints = sys::nrf_addrinfo {
-			ai_flags: 0,
-			ai_family: sys::NRF_AF_INET as i32,
-			ai_socktype: sys::NRF_SOCK_DGRAM as i32,
-			ai_protocol: 0,
-			ai_addrlen: 0,
-			ai_addr: core::ptr::null_mut(),
-			ai_canonname: core::ptr::null_mut(),
-			ai_next: core::ptr::null_mut(),
-		};
-
-		let mut output_ptr: *mut sys::nrf_addrinfo = core::ptr::null_mut();
-		let mut result = unsafe {
-			sys::nrf_getaddrinfo(
-				// hostname
-				hostname_smallstring.as_ptr(),
-				// service
-				core::ptr::null(),
-				// hints
-				&hints,
-				// output pointer
-				&mut output_ptr,
-			)
-		};
-		if (result == 0) && (!output_ptr.is_null()) {
-			let mut record: &sys::nrf_addrinfo = unsafe { &*output_ptr };
-			loop {
-				let dns_addr: &sys::nrf_sockaddr_in =
-					unsafe { &*(record.ai_addr as *const sys::nrf_sockaddr_in) };
-				// Create a new sockaddr_in with the right port
-				let connect_addr = sys::nrf_sockaddr_in {
-					sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
-					sin_family: sys::NRF_AF_INET as i32,
-					sin_port: htons(port),
-					sin_addr: dns_addr.sin_addr,
-				};
-
-				debug!("Trying IP address {}", &crate::NrfSockAddrIn(connect_addr));
-
-				// try and connect to this result
-				result = unsafe {
-					sys::nrf_connect(
-						self.socket.fd,
-						&connect_addr as *const sys::nrf_sockaddr_in as *const _,
-						connect_addr.sin_len as u32,
-					)
-				};
-				if result == 0 {
-					break;
-				}
-				if !record.ai_next.is_null() {
-					record = unsafe { &*record.ai_next };
-				} else {
-					break;
-				}
-			}
-			unsafe {
-				sys::nrf_freeaddrinfo(output_ptr);
-			}
-		}
+		let addresses = AddrInfoList::lookup(hostname, SocketType::Datagram, "udp_dns")?;
+		let result = self.socket.connect_any(&addresses, port)?;
 		if result != 0 {
 			Err(Error::Nordic("udp_connect", result, get_last_error()))
 		} else {
 			Ok(())
 		}
 	}
+
+	/// Perform a non-blocking read, as `Socket::recv` does, and say when the
+	/// datagram was read (by `now_ms`, as the modem doesn't timestamp
+	/// datagrams) and who sent it.
+	pub fn recv_with_meta(
+		&self,
+		buf: &mut [u8],
+		now_ms: fn() -> u32,
+	) -> Result<Option<(usize, RxMeta)>, Error> {
+		let result = self.socket.recv_from(buf)?;
+		Ok(result.map(|(length, source)| {
+			let meta = RxMeta {
+				timestamp: RxTimestamp::TickMs(now_ms()),
+				source,
+			};
+			(length, meta)
+		}))
+	}
 }
 
 impl Pollable for UdpSocket {
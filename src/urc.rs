@@ -0,0 +1,620 @@
+//! # Unsolicited result codes for nrfxlib
+//!
+//! The modem reports what it is doing - registering, going to sleep,
+//! overheating - with unsolicited result codes (URCs) on AT sockets. This
+//! module parses them into typed `Urc`s, and a `UrcDispatcher` subscribes to
+//! the ones you want and calls a handler for each one that arrives:
+//!
+//! ```ignore
+//! fn on_registration(urc: &Urc) {
+//!     if let Urc::Registration(registration) = urc {
+//!         LINK_UP.store(registration.status.is_registered(), Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! let mut urcs: UrcDispatcher<4> = UrcDispatcher::new()?;
+//! urcs.subscribe(UrcKind::Registration, on_registration)?;
+//! loop {
+//!     urcs.wait(1000)?;
+//!     // ... other work
+//! }
+//! ```
+//!
+//! `UrcDispatcher` is `Pollable`, so it can also be polled alongside other
+//! sockets and then told to `dispatch` what has arrived.
+//!
+//! The modem forgets its subscriptions when it restarts. So if libmodem has
+//! been started again since the dispatcher subscribed, or its AT socket
+//! fails, `dispatch` opens a new socket and re-sends every subscription
+//! (unless `set_auto_resubscribe(false)` says not to). Handlers subscribed
+//! to `UrcKind::Resubscribed` are then called, as URCs may have been missed.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+#[cfg(target_arch = "arm")]
+use crate::at::AtSocket;
+#[cfg(target_arch = "arm")]
+use crate::firmware::AtSyntax;
+use crate::modem::{AccessTechnology, RegistrationStatus};
+use crate::pdn::PdnEvent;
+#[cfg(target_arch = "arm")]
+use crate::raw::Pollable;
+#[cfg(target_arch = "arm")]
+use crate::Error;
+use crate::ErrorCause;
+use core::convert::TryFrom;
+#[cfg(target_arch = "arm")]
+use log::debug;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// An unsolicited result code from the modem.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Urc {
+	/// The network registration status has changed (`+CEREG`)
+	Registration(Registration),
+	/// The RRC signalling connection has been set up or released (`+CSCON`).
+	/// `true` means connected.
+	SignallingConnection(bool),
+	/// The modem is going to sleep, or has woken up (`%XMODEMSLEEP`)
+	ModemSleep {
+		/// The type of sleep, as given in the AT command reference (e.g. `1`
+		/// for PSM)
+		sleep_type: u8,
+		/// How long the modem will sleep for, in milliseconds. Zero means it
+		/// has woken up.
+		duration_ms: u32,
+	},
+	/// Something has happened to the modem (`%MDMEV`)
+	ModemEvent(ModemEvent),
+	/// A packet domain event (`+CGEV`)
+	Pdn(PdnEvent),
+	/// The network rejected a registration (`+CNEC_EMM`) or a PDN connection
+	/// (`+CNEC_ESM`)
+	NetworkError(NetworkError),
+	/// The dispatcher has re-sent its subscriptions after the modem
+	/// restarted (see `UrcDispatcher::resubscribe`). Not sent by the modem.
+	Resubscribed,
+}
+
+/// The kinds of `Urc`, for subscribing to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UrcKind {
+	/// `Urc::Registration`, from `AT+CEREG=2`
+	Registration,
+	/// `Urc::SignallingConnection`, from `AT+CSCON=1`
+	SignallingConnection,
+	/// `Urc::ModemSleep`, from `AT%XMODEMSLEEP=1`
+	ModemSleep,
+	/// `Urc::ModemEvent`, from `AT%MDMEV=1`
+	ModemEvent,
+	/// `Urc::Pdn`, from `AT+CGEREP=1`
+	Pdn,
+	/// `Urc::NetworkError`, from `AT+CNEC=24` (both EMM and ESM causes)
+	NetworkError,
+	/// `Urc::Resubscribed`, which the dispatcher makes itself. Subscribing
+	/// just sends `AT`.
+	Resubscribed,
+}
+
+/// A `+CEREG` report.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Registration {
+	/// The registration status
+	pub status: RegistrationStatus,
+	/// The tracking area code, if given
+	pub tac: Option<u16>,
+	/// The E-UTRAN cell ID, if given
+	pub cell_id: Option<u32>,
+	/// The access technology, if given
+	pub access_technology: Option<AccessTechnology>,
+}
+
+/// An error cause sent by the network, as reported by `+CNEC_EMM` or
+/// `+CNEC_ESM`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NetworkError {
+	/// The cause. `ErrorCause::Emm` is for a registration (attach or
+	/// tracking area update), `ErrorCause::Esm` for a PDN connection.
+	pub cause: ErrorCause,
+	/// The context ID of the PDN connection, for an ESM cause which gives one
+	pub cid: Option<u8>,
+}
+
+/// A modem event, as reported by `%MDMEV`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ModemEvent {
+	/// The modem is too hot, and has stopped using the radio (`ME
+	/// OVERHEATED`)
+	Overheated,
+	/// The supply voltage is too low, and the modem has stopped using the
+	/// radio (`ME BATTERY LOW`)
+	BatteryLow,
+	/// The modem has been reset too often, and has stopped attaching for a
+	/// while (`RESET LOOP`)
+	ResetLoop,
+	/// A network search has finished (`SEARCH STATUS <n>`). `1` means the
+	/// light search is done, `2` the whole search is.
+	SearchStatus(u8),
+	/// The coverage enhancement level for PRACH has changed (`PRACH CE-LEVEL
+	/// <n>`)
+	PrachCeLevel(u8),
+	/// The modem has no IMEI (`NO IMEI`)
+	NoImei,
+}
+
+/// Called with each URC of the kind it was subscribed to.
+pub type Handler = fn(&Urc);
+
+/// Reads URCs from its own AT socket, and calls the handlers subscribed to
+/// them. Holds up to `HANDLERS` subscriptions.
+#[cfg(target_arch = "arm")]
+#[derive(Debug)]
+pub struct UrcDispatcher<const HANDLERS: usize> {
+	socket: AtSocket,
+	handlers: heapless::Vec<(UrcKind, Handler), HANDLERS>,
+	/// What `crate::starts` was when we last (re-)subscribed
+	starts: u32,
+	auto_resubscribe: bool,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl Urc {
+	/// Parse a line from an AT socket. Returns `None` if it isn't a URC we
+	/// know, or we can't make sense of it.
+	pub fn parse(line: &str) -> Option<Urc> {
+		let line = line.trim();
+		if let Some(fields) = line.strip_prefix("+CEREG:") {
+			Registration::parse(fields).map(Urc::Registration)
+		} else if let Some(fields) = line.strip_prefix("+CSCON:") {
+			let mode: u8 = fields.split(',').next()?.trim().parse().ok()?;
+			Some(Urc::SignallingConnection(mode == 1))
+		} else if let Some(fields) = line.strip_prefix("%XMODEMSLEEP:") {
+			let mut fields = fields.split(',').map(str::trim);
+			let sleep_type = fields.next()?.parse().ok()?;
+			let duration_ms = match fields.next() {
+				Some(duration) => duration.parse().ok()?,
+				None => 0,
+			};
+			Some(Urc::ModemSleep {
+				sleep_type,
+				duration_ms,
+			})
+		} else if let Some(event) = line.strip_prefix("%MDMEV:") {
+			ModemEvent::parse(event).map(Urc::ModemEvent)
+		} else if let Some(fields) = line.strip_prefix("+CNEC_EMM:") {
+			NetworkError::parse(fields, ErrorCause::Emm).map(Urc::NetworkError)
+		} else if let Some(fields) = line.strip_prefix("+CNEC_ESM:") {
+			NetworkError::parse(fields, ErrorCause::Esm).map(Urc::NetworkError)
+		} else {
+			PdnEvent::parse(line).map(Urc::Pdn)
+		}
+	}
+
+	/// Which kind of URC this is.
+	pub fn kind(&self) -> UrcKind {
+		match self {
+			Urc::Registration(_) => UrcKind::Registration,
+			Urc::SignallingConnection(_) => UrcKind::SignallingConnection,
+			Urc::ModemSleep { .. } => UrcKind::ModemSleep,
+			Urc::ModemEvent(_) => UrcKind::ModemEvent,
+			Urc::Pdn(_) => UrcKind::Pdn,
+			Urc::NetworkError(_) => UrcKind::NetworkError,
+			Urc::Resubscribed => UrcKind::Resubscribed,
+		}
+	}
+}
+
+impl UrcKind {
+	/// The AT command which subscribes to this kind of URC, on the modem's
+	/// firmware (see `firmware`).
+	#[cfg(target_arch = "arm")]
+	pub fn subscribe_command(self) -> &'static str {
+		match self {
+			UrcKind::Registration => "AT+CEREG=2",
+			UrcKind::SignallingConnection => "AT+CSCON=1",
+			UrcKind::ModemSleep => crate::firmware::command(AtSyntax::ModemSleepReports)
+				.unwrap_or_else(|_| AtSyntax::ModemSleepReports.default_command()),
+			UrcKind::ModemEvent => "AT%MDMEV=1",
+			UrcKind::Pdn => "AT+CGEREP=1",
+			UrcKind::NetworkError => "AT+CNEC=24",
+			UrcKind::Resubscribed => "AT",
+		}
+	}
+}
+
+impl Registration {
+	/// Parse the fields of an unsolicited `+CEREG`: `<stat>[,<tac>,<ci>,<AcT>
+	/// ...]`, where `<tac>` and `<ci>` are quoted hex.
+	pub fn parse(fields: &str) -> Option<Registration> {
+		let mut fields = fields.split(',').map(str::trim);
+		let status = RegistrationStatus::from_u8(fields.next()?.parse().ok()?)?;
+		let mut hex = || u32::from_str_radix(fields.next()?.trim_matches('"'), 16).ok();
+		let tac = hex().and_then(|tac| u16::try_from(tac).ok());
+		let cell_id = hex();
+		let access_technology = fields
+			.next()
+			.and_then(|act| act.parse().ok())
+			.and_then(AccessTechnology::from_u8);
+		Some(Registration {
+			status,
+			tac,
+			cell_id,
+			access_technology,
+		})
+	}
+}
+
+impl NetworkError {
+	/// Parse what follows `+CNEC_EMM:` or `+CNEC_ESM:`: `<cause>[,<cid>]`.
+	/// `category` is `ErrorCause::Emm` or `ErrorCause::Esm`, to say which.
+	pub fn parse(fields: &str, category: fn(u16) -> ErrorCause) -> Option<NetworkError> {
+		let mut fields = fields.split(',').map(str::trim);
+		let cause = category(fields.next()?.parse().ok()?);
+		let cid = fields.next().and_then(|cid| cid.parse().ok());
+		Some(NetworkError { cause, cid })
+	}
+}
+
+impl ModemEvent {
+	/// Parse what follows `%MDMEV:`.
+	pub fn parse(event: &str) -> Option<ModemEvent> {
+		let event = event.trim();
+		let number = |prefix: &str| event.strip_prefix(prefix)?.trim().parse().ok();
+		match event {
+			"ME OVERHEATED" => Some(ModemEvent::Overheated),
+			"ME BATTERY LOW" => Some(ModemEvent::BatteryLow),
+			"RESET LOOP" => Some(ModemEvent::ResetLoop),
+			"NO IMEI" => Some(ModemEvent::NoImei),
+			_ => {
+				if let Some(status) = number("SEARCH STATUS") {
+					Some(ModemEvent::SearchStatus(status))
+				} else {
+					number("PRACH CE-LEVEL").map(ModemEvent::PrachCeLevel)
+				}
+			}
+		}
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl<const HANDLERS: usize> UrcDispatcher<HANDLERS> {
+	/// Open an AT socket to receive URCs on, with no subscriptions.
+	pub fn new() -> Result<Self, Error> {
+		Ok(UrcDispatcher {
+			socket: AtSocket::new()?,
+			handlers: heapless::Vec::new(),
+			starts: crate::starts(),
+			auto_resubscribe: true,
+		})
+	}
+
+	/// Choose whether `dispatch` re-subscribes by itself when the modem has
+	/// restarted (see the module documentation). It does by default.
+	pub fn set_auto_resubscribe(&mut self, enabled: bool) {
+		self.auto_resubscribe = enabled;
+	}
+
+	/// Open a new AT socket and send every subscription again, e.g. after
+	/// the modem has restarted. Then call the handlers subscribed to
+	/// `UrcKind::Resubscribed`.
+	pub fn resubscribe(&mut self) -> Result<(), Error> {
+		debug!("Re-subscribing to {} URC kind(s)", self.handlers.len());
+		self.socket = AtSocket::new()?;
+		self.starts = crate::starts();
+		for (index, (kind, _)) in self.handlers.iter().enumerate() {
+			// Each kind only needs subscribing once
+			if self.handlers.iter().take(index).any(|(k, _)| k == kind) {
+				continue;
+			}
+			self.socket.send_command(kind.subscribe_command())?;
+			let handlers = &self.handlers;
+			self.socket.poll_response(|line| {
+				dispatch_line(handlers, line);
+			})?;
+		}
+		let urc = Urc::Resubscribed;
+		for (_, handler) in self.handlers.iter().filter(|(k, _)| *k == urc.kind()) {
+			handler(&urc);
+		}
+		Ok(())
+	}
+
+	/// Call `handler` for each URC of this kind, and ask the modem to send
+	/// them. A kind can have more than one handler. Returns
+	/// `Error::BufferFull` if there are already `HANDLERS` subscriptions.
+	pub fn subscribe(&mut self, kind: UrcKind, handler: Handler) -> Result<(), Error> {
+		self.handlers
+			.push((kind, handler))
+			.map_err(|_| Error::BufferFull)?;
+		self.socket.send_command(kind.subscribe_command())?;
+		// Anything else which arrives before the `OK` is passed on as usual
+		let handlers = &self.handlers;
+		self.socket.poll_response(|line| {
+			dispatch_line(handlers, line);
+		})
+	}
+
+	/// Read whatever has arrived, without waiting, and call the handlers for
+	/// each URC. Returns how many URCs were handled.
+	///
+	/// If the modem has restarted, or the AT socket fails, this re-subscribes
+	/// first (see `set_auto_resubscribe`). If that fails, so does this, with
+	/// the socket's error if there was one.
+	pub fn dispatch(&mut self) -> Result<usize, Error> {
+		if self.auto_resubscribe && self.starts != crate::starts() {
+			self.resubscribe()?;
+		}
+		let mut buf = crate::pool::Buffer::take()?;
+		let mut handled = 0;
+		loop {
+			let length = match self.socket.recv(&mut buf) {
+				Ok(Some(length)) => length,
+				Ok(None) => break,
+				Err(e) if self.auto_resubscribe => {
+					debug!("URC socket failed: {:?}", e);
+					self.resubscribe().map_err(|_| e)?;
+					break;
+				}
+				Err(e) => return Err(e),
+			};
+			// Skip anything which isn't text, rather than give up
+			let text = buf
+				.get(0..length)
+				.and_then(|data| crate::at::response_text(data).ok())
+				.unwrap_or("");
+			for line in text.lines() {
+				if dispatch_line(&self.handlers, line) {
+					handled += 1;
+				}
+			}
+		}
+		Ok(handled)
+	}
+
+	/// Wait up to `timeout_ms` for something to arrive, then handle it as
+	/// `dispatch` does. Returns `Ok(0)` if nothing arrived in time.
+	pub fn wait(&mut self, timeout_ms: u32) -> Result<usize, Error> {
+		match crate::raw::wait_readable(&self.socket, timeout_ms) {
+			Ok(()) | Err(Error::TimedOut) => self.dispatch(),
+			Err(e) => Err(e),
+		}
+	}
+}
+
+#[cfg(target_arch = "arm")]
+impl<const HANDLERS: usize> Pollable for UrcDispatcher<HANDLERS> {
+	/// Get the underlying socket ID for this dispatcher's AT socket.
+	fn get_fd(&self) -> i32 {
+		self.socket.get_fd()
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Parse a line, and if it's a URC, call the handlers subscribed to it.
+/// Registration reports also update `modem::last_registration_status` and
+/// `modem::last_access_technology`, network errors
+/// `modem::last_network_error`, and coverage enhancement level events
+/// `modem::last_ce_level`. Losing registration, the default PDN
+/// connection (context 0) or the packet domain marks the open sockets as
+/// having lost their link. Returns whether the line was a URC.
+#[cfg(target_arch = "arm")]
+fn dispatch_line(handlers: &[(UrcKind, Handler)], line: &str) -> bool {
+	let urc = match Urc::parse(line) {
+		Some(urc) => urc,
+		None => return false,
+	};
+	debug!("URC {:?}", urc);
+	match &urc {
+		Urc::Registration(registration) => crate::modem::record_registration(registration),
+		Urc::NetworkError(error) => crate::modem::record_network_error(error),
+		Urc::ModemEvent(ModemEvent::PrachCeLevel(level)) => crate::modem::record_ce_level(*level),
+		Urc::Pdn(PdnEvent::PdnDeactivated { cid: 0, .. }) | Urc::Pdn(PdnEvent::Detached { .. }) => {
+			crate::raw::mark_link_lost();
+		}
+		_ => {}
+	}
+	let kind = urc.kind();
+	for (_, handler) in handlers.iter().filter(|(k, _)| *k == kind) {
+		handler(&urc);
+	}
+	true
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::pdn::Initiator;
+
+	#[test]
+	fn registration() {
+		assert_eq!(
+			Urc::parse("+CEREG: 1,\"0140\",\"0107E500\",7\r\n"),
+			Some(Urc::Registration(Registration {
+				status: RegistrationStatus::RegisteredHome,
+				tac: Some(0x0140),
+				cell_id: Some(0x0107_E500),
+				access_technology: Some(AccessTechnology::LteM),
+			}))
+		);
+		// With the PSM timers, from `AT+CEREG=5`
+		assert_eq!(
+			Urc::parse("+CEREG: 5,\"4321\",\"00ABC123\",9,,,\"11100000\",\"11100000\""),
+			Some(Urc::Registration(Registration {
+				status: RegistrationStatus::RegisteredRoaming,
+				tac: Some(0x4321),
+				cell_id: Some(0x00AB_C123),
+				access_technology: Some(AccessTechnology::NbIot),
+			}))
+		);
+		// Out of coverage, with no cell
+		assert_eq!(
+			Urc::parse("+CEREG: 2"),
+			Some(Urc::Registration(Registration {
+				status: RegistrationStatus::Searching,
+				tac: None,
+				cell_id: None,
+				access_technology: None,
+			}))
+		);
+		assert_eq!(Urc::parse("+CEREG: 7"), None);
+		assert_eq!(Urc::parse("+CEREG:"), None);
+	}
+
+	#[test]
+	fn signalling_connection() {
+		assert_eq!(
+			Urc::parse("+CSCON: 1"),
+			Some(Urc::SignallingConnection(true))
+		);
+		assert_eq!(
+			Urc::parse("+CSCON: 0\r\n"),
+			Some(Urc::SignallingConnection(false))
+		);
+		assert_eq!(Urc::parse("+CSCON: x"), None);
+	}
+
+	#[test]
+	fn modem_sleep() {
+		assert_eq!(
+			Urc::parse("%XMODEMSLEEP: 1,3599000"),
+			Some(Urc::ModemSleep {
+				sleep_type: 1,
+				duration_ms: 3_599_000,
+			})
+		);
+		// Woken up
+		assert_eq!(
+			Urc::parse("%XMODEMSLEEP: 4"),
+			Some(Urc::ModemSleep {
+				sleep_type: 4,
+				duration_ms: 0,
+			})
+		);
+		assert_eq!(Urc::parse("%XMODEMSLEEP: 1,soon"), None);
+	}
+
+	#[test]
+	fn modem_event() {
+		assert_eq!(
+			Urc::parse("%MDMEV: ME OVERHEATED"),
+			Some(Urc::ModemEvent(ModemEvent::Overheated))
+		);
+		assert_eq!(
+			Urc::parse("%MDMEV: ME BATTERY LOW"),
+			Some(Urc::ModemEvent(ModemEvent::BatteryLow))
+		);
+		assert_eq!(
+			Urc::parse("%MDMEV: RESET LOOP"),
+			Some(Urc::ModemEvent(ModemEvent::ResetLoop))
+		);
+		assert_eq!(
+			Urc::parse("%MDMEV: NO IMEI"),
+			Some(Urc::ModemEvent(ModemEvent::NoImei))
+		);
+		assert_eq!(
+			Urc::parse("%MDMEV: SEARCH STATUS 2"),
+			Some(Urc::ModemEvent(ModemEvent::SearchStatus(2)))
+		);
+		assert_eq!(
+			Urc::parse("%MDMEV: PRACH CE-LEVEL 1"),
+			Some(Urc::ModemEvent(ModemEvent::PrachCeLevel(1)))
+		);
+		assert_eq!(Urc::parse("%MDMEV: SOMETHING NEW"), None);
+	}
+
+	#[test]
+	fn network_error() {
+		assert_eq!(
+			Urc::parse("+CNEC_EMM: 15"),
+			Some(Urc::NetworkError(NetworkError {
+				cause: ErrorCause::Emm(15),
+				cid: None,
+			}))
+		);
+		assert_eq!(
+			Urc::parse("+CNEC_ESM: 33,0"),
+			Some(Urc::NetworkError(NetworkError {
+				cause: ErrorCause::Esm(33),
+				cid: Some(0),
+			}))
+		);
+		assert_eq!(Urc::parse("+CNEC_ESM:"), None);
+	}
+
+	#[test]
+	fn pdn() {
+		assert_eq!(
+			Urc::parse("+CGEV: ME PDN ACT 0,0"),
+			Some(Urc::Pdn(PdnEvent::PdnActivated {
+				cid: 0,
+				reason: Some(0),
+			}))
+		);
+		assert_eq!(
+			Urc::parse("+CGEV: NW DETACH").map(|urc| urc.kind()),
+			Some(UrcKind::Pdn)
+		);
+		assert_eq!(
+			Urc::parse("+CGEV: NW PDN DEACT 1"),
+			Some(Urc::Pdn(PdnEvent::PdnDeactivated {
+				initiator: Initiator::Network,
+				cid: 1,
+			}))
+		);
+	}
+
+	#[test]
+	fn not_urcs() {
+		for line in &["OK", "ERROR", "", "+CESQ: 99,99,255,255,31,62", "%XSIM: 1"] {
+			assert_eq!(Urc::parse(line), None, "{:?}", line);
+		}
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,89 @@
+//! # Watchdog integration for nrfxlib
+//!
+//! Attaching to the network, a TLS handshake or a DNS lookup can each
+//! legitimately take longer than a typical watchdog period, especially on
+//! NB-IoT. Give `set_feeder` a function which feeds your watchdog, and the
+//! crate will call it at least every `FEED_INTERVAL_MS` or so while it is
+//! blocked waiting for the modem - including while libmodem itself is
+//! blocked, as it waits through `nrf_modem_os_timedwait`.
+//!
+//! The feeder may be called often, and from inside libmodem calls, so it
+//! should be quick and must not call back into this crate.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Somewhere to keep the feeder, which we can share with libmodem's waits.
+type WrappedFeeder = Mutex<RefCell<Option<fn()>>>;
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The longest we wait, when a feeder is set, before feeding the watchdog.
+pub const FEED_INTERVAL_MS: u16 = 1_000;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The function given to `set_feeder`.
+static FEEDER: WrappedFeeder = Mutex::new(RefCell::new(None));
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Set the function which feeds the watchdog during long blocking
+/// operations, or `None` to stop feeding it.
+pub fn set_feeder(feeder: Option<fn()>) {
+	cortex_m::interrupt::free(|cs| {
+		*FEEDER.borrow(cs).borrow_mut() = feeder;
+	});
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Feed the watchdog, if we have a feeder.
+pub(crate) fn feed() {
+	if let Some(feeder) = cortex_m::interrupt::free(|cs| *FEEDER.borrow(cs).borrow()) {
+		feeder();
+	}
+}
+
+/// Has a feeder been set? If so, our waits need to wake up every
+/// `FEED_INTERVAL_MS` to call it.
+pub(crate) fn is_set() -> bool {
+	cortex_m::interrupt::free(|cs| FEEDER.borrow(cs).borrow().is_some())
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,93 @@
+//! # Tests for the host-side sockets
+//!
+//! Connects a `StdTcpConnector` to a listener on the loopback interface, and
+//! checks data goes both ways through the `embedded-io-async` traits.
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+#![cfg(all(feature = "std", feature = "embedded-nal-async"))]
+
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use core::time::Duration;
+use embedded_io_async::{Read, Write};
+use embedded_nal_async::{Ipv4Addr, SocketAddr, TcpConnect};
+use nrfxlib::host::StdTcpConnector;
+use nrfxlib::Error;
+use std::net::TcpListener;
+
+/// A waker that does nothing, built by hand as `Waker::noop` is newer than
+/// our minimum Rust version.
+fn noop_waker() -> Waker {
+	fn clone(_: *const ()) -> RawWaker {
+		RawWaker::new(core::ptr::null(), &VTABLE)
+	}
+	fn noop(_: *const ()) {}
+	static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+	// Safe: none of the functions in the table touch the data pointer
+	unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) }
+}
+
+/// Run a future to completion. `StdTcpConnector` blocks rather than
+/// returning `Pending`, so nothing ever needs waking.
+fn block_on<F: Future>(future: F) -> F::Output {
+	let mut future = pin!(future);
+	let waker = noop_waker();
+	let mut context = Context::from_waker(&waker);
+	loop {
+		if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+			return output;
+		}
+	}
+}
+
+#[test]
+fn connects_and_echoes() {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let port = listener.local_addr().unwrap().port();
+	let server = std::thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		let mut buffer = [0u8; 4];
+		std::io::Read::read_exact(&mut stream, &mut buffer).unwrap();
+		assert_eq!(&buffer, b"ping");
+		std::io::Write::write_all(&mut stream, b"pong").unwrap();
+	});
+
+	let connector = StdTcpConnector::with_timeout(Duration::from_secs(5));
+	let remote = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), port);
+	let reply = block_on(async {
+		let mut connection = connector.connect(remote).await?;
+		connection.write_all(b"ping").await?;
+		let mut buffer = [0u8; 4];
+		connection
+			.read_exact(&mut buffer)
+			.await
+			.map_err(|_| Error::Disconnected)?;
+		// The server has gone, so the next read is end-of-file
+		let eof = connection.read(&mut [0u8; 1]).await?;
+		Ok::<_, Error>((buffer, eof))
+	});
+
+	server.join().unwrap();
+	let (buffer, eof) = reply.unwrap();
+	assert_eq!(&buffer, b"pong");
+	assert_eq!(eof, 0);
+}
+
+#[test]
+fn refused_connection_is_disconnected() {
+	// Find a port nothing is listening on
+	let port = TcpListener::bind("127.0.0.1:0")
+		.unwrap()
+		.local_addr()
+		.unwrap()
+		.port();
+	let connector = StdTcpConnector::with_timeout(Duration::from_secs(5));
+	let remote = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), port);
+	let result = block_on(connector.connect(remote));
+	assert!(matches!(result, Err(Error::Disconnected)));
+}